@@ -12,6 +12,25 @@ pub(super) struct Combine<Index: Copy> {
     pub peers: Vec<Index>,
     pub delay: NtpDuration,
     pub leap_indicator: Option<NtpLeapIndicator>,
+    /// Spread of the selected peers around the combined offset, in seconds
+    pub jitter: f64,
+}
+
+/// Root mean square of the offsets of the selected peers relative to the
+/// combined `system_offset` (in seconds)
+pub(super) fn selection_jitter<Index: Copy>(
+    selection: &[PeerSnapshot<Index>],
+    system_offset: f64,
+) -> f64 {
+    if selection.is_empty() {
+        return 0.0;
+    }
+
+    let sum = selection
+        .iter()
+        .map(|snapshot| sqr(snapshot.offset() - system_offset))
+        .sum::<f64>();
+    (sum / selection.len() as f64).sqrt()
 }
 
 fn vote_leap<Index: Copy>(selection: &[PeerSnapshot<Index>]) -> Option<NtpLeapIndicator> {
@@ -39,40 +58,161 @@ fn vote_leap<Index: Copy>(selection: &[PeerSnapshot<Index>]) -> Option<NtpLeapIn
     }
 }
 
-pub(super) fn combine<Index: Copy>(
+/// Offset variance (in seconds squared) below which the variance of a peer is
+/// not trusted when limiting its weight. Scaling can't take weight away from a
+/// peer with a zero variance.
+const MIN_WEIGHTING_VARIANCE: f64 = 1e-30;
+
+/// Factors by which to scale the uncertainty of each peer, given the variance
+/// of its offset, such that none of them carries more than `max_weight` of the
+/// combined offset estimate. The weight taken from capped peers is spread over
+/// the others in proportion to their original weights.
+fn weight_cap_scales(offset_variances: &[f64], max_weight: f64) -> Vec<f64> {
+    let precisions: Vec<f64> = offset_variances
+        .iter()
+        .map(|v| 1.0 / v.max(MIN_WEIGHTING_VARIANCE))
+        .collect();
+    // The cap can't be met with too few peers, the best we can do then is
+    // weighing them equally
+    let max_weight = max_weight.max(1.0 / precisions.len() as f64);
+    let mut capped = vec![false; precisions.len()];
+
+    loop {
+        let free_precision: f64 = precisions
+            .iter()
+            .zip(&capped)
+            .filter(|(_, capped)| !**capped)
+            .map(|(precision, _)| precision)
+            .sum();
+        let free_weight = 1.0 - capped.iter().filter(|capped| **capped).count() as f64 * max_weight;
+
+        let mut changed = false;
+        for (precision, capped) in precisions.iter().zip(capped.iter_mut()) {
+            if !*capped && precision / free_precision * free_weight > max_weight {
+                *capped = true;
+                changed = true;
+            }
+        }
+
+        if capped.iter().all(|capped| *capped) {
+            // All peers end up with equal weights
+            let mean_precision = precisions.iter().sum::<f64>() / precisions.len() as f64;
+            return precisions
+                .iter()
+                .map(|precision| precision / mean_precision)
+                .collect();
+        }
+
+        if !changed {
+            let capped_precision = max_weight * free_precision / free_weight;
+            return precisions
+                .iter()
+                .zip(&capped)
+                .map(|(precision, capped)| {
+                    if *capped {
+                        precision / capped_precision
+                    } else {
+                        1.0
+                    }
+                })
+                .collect();
+        }
+    }
+}
+
+pub(super) fn combine<Index: Copy + PartialEq>(
     selection: &[PeerSnapshot<Index>],
     algo_config: &AlgorithmConfig,
+    system_peer: Option<Index>,
 ) -> Option<Combine<Index>> {
-    selection.first().map(|first| {
-        let mut estimate = first.state;
-        let mut uncertainty = if algo_config.ignore_server_dispersion {
-            first.uncertainty
+    // A selected preferred peer overrides the weighted combination
+    if let Some(preferred) = selection.iter().find(|snapshot| snapshot.prefer) {
+        let uncertainty = if algo_config.ignore_server_dispersion {
+            preferred.uncertainty
         } else {
-            first.uncertainty
-                + Matrix::new([[sqr(first.peer_uncertainty.to_seconds()), 0.], [0., 0.]])
+            preferred.uncertainty
+                + Matrix::new([[sqr(preferred.peer_uncertainty.to_seconds()), 0.], [0., 0.]])
         };
 
-        let mut used_peers = vec![(first.index, uncertainty.determinant())];
+        return Some(Combine {
+            estimate: preferred.state,
+            uncertainty,
+            peers: vec![preferred.index],
+            delay: NtpDuration::from_seconds(preferred.delay) + preferred.peer_delay,
+            leap_indicator: vote_leap(selection),
+            jitter: selection_jitter(selection, preferred.offset()),
+        });
+    }
 
-        for snapshot in selection.iter().skip(1) {
-            let peer_estimate = snapshot.state;
-            let peer_uncertainty = if algo_config.ignore_server_dispersion {
+    let peer_uncertainties: Vec<Matrix<2, 2>> = selection
+        .iter()
+        .map(|snapshot| {
+            if algo_config.ignore_server_dispersion {
                 snapshot.uncertainty
             } else {
                 snapshot.uncertainty
                     + Matrix::new([[sqr(snapshot.peer_uncertainty.to_seconds()), 0.], [0., 0.]])
-            };
+            }
+        })
+        .collect();
+
+    // Inflating the uncertainty of a peer lowers its weight when merging the
+    // estimates. The reported uncertainty is still that of the peers
+    // themselves, the cap doesn't make them any less accurate.
+    let peer_weightings: Vec<Matrix<2, 2>> = match algo_config.max_single_peer_weight {
+        Some(max_weight) if !selection.is_empty() => {
+            let floor = Matrix::new([[MIN_WEIGHTING_VARIANCE, 0.], [0., 0.]]);
+            let weightings: Vec<Matrix<2, 2>> = peer_uncertainties
+                .iter()
+                .map(|uncertainty| *uncertainty + floor)
+                .collect();
+            let variances: Vec<f64> = weightings
+                .iter()
+                .map(|weighting| weighting.entry(0, 0))
+                .collect();
+            weight_cap_scales(&variances, max_weight)
+                .into_iter()
+                .zip(weightings)
+                .map(|(scale, weighting)| scale * weighting)
+                .collect()
+        }
+        _ => peer_uncertainties.clone(),
+    };
+
+    selection.first().map(|first| {
+        let mut estimate = first.state;
+        let mut weighting = peer_weightings[0];
+        let mut uncertainty = peer_uncertainties[0];
+
+        let mut used_peers = vec![(first.index, peer_uncertainties[0].determinant())];
+
+        for (i, snapshot) in selection.iter().enumerate().skip(1) {
+            let peer_estimate = snapshot.state;
+            let peer_weighting = peer_weightings[i];
+            let peer_uncertainty = peer_uncertainties[i];
 
             used_peers.push((snapshot.index, peer_uncertainty.determinant()));
 
             // Merge measurements
+            let mixer = (weighting + peer_weighting).inverse();
+            estimate = estimate + weighting * mixer * (peer_estimate - estimate);
+            weighting = weighting * mixer * peer_weighting;
+
             let mixer = (uncertainty + peer_uncertainty).inverse();
-            estimate = estimate + uncertainty * mixer * (peer_estimate - estimate);
             uncertainty = uncertainty * mixer * peer_uncertainty;
         }
 
         used_peers.sort_by(|a, b| a.1.total_cmp(&b.1));
 
+        // Keep the current system peer first unless another peer is better by
+        // a clear margin, so that noise doesn't make it flap between peers.
+        if let Some(position) = used_peers.iter().position(|v| Some(v.0) == system_peer) {
+            if used_peers[position].1 <= used_peers[0].1 * algo_config.system_peer_hysteresis {
+                let current = used_peers.remove(position);
+                used_peers.insert(0, current);
+            }
+        }
+
         Combine {
             estimate,
             uncertainty,
@@ -83,6 +223,7 @@ pub(super) fn combine<Index: Copy>(
                 .min()
                 .unwrap_or(NtpDuration::from_seconds(first.delay) + first.peer_delay),
             leap_indicator: vote_leap(selection),
+            jitter: selection_jitter(selection, estimate.ventry(0)),
         }
     })
 }
@@ -106,15 +247,38 @@ mod tests {
             peer_uncertainty: NtpDuration::from_seconds(peer_uncertainty),
             peer_delay: NtpDuration::from_seconds(0.01),
             leap_indicator: NtpLeapIndicator::NoWarning,
+            prefer: false,
             last_update: NtpTimestamp::from_fixed_int(0),
         }
     }
 
+    #[test]
+    fn test_selection_jitter() {
+        let selected: Vec<_> = [5e-3, -5e-3, 0.0]
+            .into_iter()
+            .map(|offset| {
+                snapshot_for_state(
+                    Vector::new_vector([offset, 0.0]),
+                    Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                    1e-3,
+                )
+            })
+            .collect();
+
+        let expected = (50e-6f64 / 3.0).sqrt();
+        assert!((selection_jitter(&selected, 0.0) - expected).abs() < 1e-12);
+        assert_eq!(selection_jitter::<usize>(&[], 0.0), 0.0);
+
+        let algconfig = AlgorithmConfig::default();
+        let result = combine(&selected, &algconfig, None).unwrap();
+        assert!((result.jitter - expected).abs() < 1e-9);
+    }
+
     #[test]
     fn test_none() {
         let selected: Vec<PeerSnapshot<usize>> = vec![];
         let algconfig = AlgorithmConfig::default();
-        assert!(combine(&selected, &algconfig).is_none());
+        assert!(combine(&selected, &algconfig, None).is_none());
     }
 
     #[test]
@@ -128,7 +292,7 @@ mod tests {
         let algconfig = AlgorithmConfig {
             ..Default::default()
         };
-        let result = combine(&selected, &algconfig).unwrap();
+        let result = combine(&selected, &algconfig, None).unwrap();
         assert!((result.uncertainty.entry(0, 0) - 2e-6).abs() < 1e-12);
         assert!((result.uncertainty.entry(0, 0) - 2e-6).abs() < 1e-12);
 
@@ -136,7 +300,7 @@ mod tests {
             ignore_server_dispersion: true,
             ..Default::default()
         };
-        let result = combine(&selected, &algconfig).unwrap();
+        let result = combine(&selected, &algconfig, None).unwrap();
         assert!((result.uncertainty.entry(0, 0) - 1e-6).abs() < 1e-12);
     }
 
@@ -158,7 +322,7 @@ mod tests {
         let algconfig = AlgorithmConfig {
             ..Default::default()
         };
-        let result = combine(&selected, &algconfig).unwrap();
+        let result = combine(&selected, &algconfig, None).unwrap();
         assert!((result.estimate.ventry(0) - 5e-4).abs() < 1e-8);
         assert!(result.estimate.ventry(1).abs() < 1e-8);
         assert!((result.uncertainty.entry(0, 0) - 1e-6).abs() < 1e-12);
@@ -168,7 +332,7 @@ mod tests {
             ignore_server_dispersion: true,
             ..Default::default()
         };
-        let result = combine(&selected, &algconfig).unwrap();
+        let result = combine(&selected, &algconfig, None).unwrap();
         assert!((result.estimate.ventry(0) - 5e-4).abs() < 1e-8);
         assert!(result.estimate.ventry(1).abs() < 1e-8);
         assert!((result.uncertainty.entry(0, 0) - 5e-7).abs() < 1e-12);
@@ -195,7 +359,7 @@ mod tests {
         let algconfig = AlgorithmConfig {
             ..Default::default()
         };
-        let result = combine(&selected, &algconfig).unwrap();
+        let result = combine(&selected, &algconfig, None).unwrap();
         assert_eq!(result.peers, vec![0, 1]);
 
         let mut selected = vec![
@@ -216,10 +380,192 @@ mod tests {
         let algconfig = AlgorithmConfig {
             ..Default::default()
         };
-        let result = combine(&selected, &algconfig).unwrap();
+        let result = combine(&selected, &algconfig, None).unwrap();
+        assert_eq!(result.peers, vec![1, 0]);
+    }
+
+    #[test]
+    fn test_system_peer_hysteresis() {
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            ..Default::default()
+        };
+        let pair = |uncertainty_0: f64, uncertainty_1: f64| {
+            let mut selected = vec![
+                snapshot_for_state(
+                    Vector::new_vector([0.0, 0.0]),
+                    Matrix::new([[uncertainty_0, 0.0], [0.0, 1e-12]]),
+                    1e-3,
+                ),
+                snapshot_for_state(
+                    Vector::new_vector([0.0, 0.0]),
+                    Matrix::new([[uncertainty_1, 0.0], [0.0, 1e-12]]),
+                    1e-3,
+                ),
+            ];
+            selected[0].index = 0;
+            selected[1].index = 1;
+            selected
+        };
+
+        // Peers taking turns at being slightly better don't make the system peer flap
+        let mut system_peer = None;
+        for cycle in 0..6 {
+            let selected = if cycle % 2 == 0 {
+                pair(1.0e-6, 1.2e-6)
+            } else {
+                pair(1.2e-6, 1.0e-6)
+            };
+            let result = combine(&selected, &algconfig, system_peer).unwrap();
+            assert_eq!(result.peers[0], 0);
+            system_peer = Some(result.peers[0]);
+        }
+
+        // but a clearly better peer takes over
+        let result = combine(&pair(4e-6, 1e-6), &algconfig, system_peer).unwrap();
         assert_eq!(result.peers, vec![1, 0]);
     }
 
+    #[test]
+    fn test_prefer() {
+        let mut selected = vec![
+            snapshot_for_state(
+                Vector::new_vector([0.0, 0.0]),
+                Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                1e-3,
+            ),
+            snapshot_for_state(
+                Vector::new_vector([0.01, 0.0]),
+                Matrix::new([[1e-4, 0.0], [0.0, 1e-12]]),
+                1e-2,
+            ),
+        ];
+        selected[0].index = 0;
+        selected[1].index = 1;
+        selected[1].prefer = true;
+
+        let algconfig = AlgorithmConfig::default();
+        let result = combine(&selected, &algconfig, None).unwrap();
+        // The preferred peer wins, even though the other peer is more accurate
+        assert_eq!(result.estimate.ventry(0), 0.01);
+        assert_eq!(result.peers, vec![1]);
+
+        selected[1].prefer = false;
+        let result = combine(&selected, &algconfig, None).unwrap();
+        assert!(result.estimate.ventry(0) < 0.001);
+        assert_eq!(result.peers, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_max_single_peer_weight() {
+        // One excellent peer and two mediocre ones
+        let mut selected = vec![
+            snapshot_for_state(
+                Vector::new_vector([0.0, 0.0]),
+                Matrix::new([[1e-8, 0.0], [0.0, 1e-12]]),
+                1e-3,
+            ),
+            snapshot_for_state(
+                Vector::new_vector([1e-3, 0.0]),
+                Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                1e-3,
+            ),
+            snapshot_for_state(
+                Vector::new_vector([1e-3, 0.0]),
+                Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                1e-3,
+            ),
+        ];
+        for (index, snapshot) in selected.iter_mut().enumerate() {
+            snapshot.index = index;
+        }
+
+        // Without a cap the excellent peer nearly determines the estimate
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            ..Default::default()
+        };
+        let result = combine(&selected, &algconfig, None).unwrap();
+        assert!(result.estimate.ventry(0) < 2e-5);
+
+        // With a cap its weight is limited to the configured fraction
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            max_single_peer_weight: Some(0.6),
+            ..Default::default()
+        };
+        let capped = combine(&selected, &algconfig, None).unwrap();
+        assert!((capped.estimate.ventry(0) - 0.4 * 1e-3).abs() < 1e-9);
+        // it remains the best peer
+        assert_eq!(capped.peers[0], 0);
+        // and the reported uncertainty is still that of the peers themselves
+        assert!((capped.uncertainty.entry(0, 0) - result.uncertainty.entry(0, 0)).abs() < 1e-15);
+        assert!((capped.uncertainty.entry(1, 1) - result.uncertainty.entry(1, 1)).abs() < 1e-18);
+
+        // A cap that can't be met weighs all peers equally
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            max_single_peer_weight: Some(0.1),
+            ..Default::default()
+        };
+        let result = combine(&selected, &algconfig, None).unwrap();
+        assert!((result.estimate.ventry(0) - 2.0 / 3.0 * 1e-3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_weight_cap_scales() {
+        // Weights below the cap are left alone
+        assert_eq!(
+            weight_cap_scales(&[1.0, 1.0, 1.0], 0.5),
+            vec![1.0, 1.0, 1.0]
+        );
+
+        // Capping one peer may push another over the cap
+        let variances = [1.0, 2.0, 100.0, 100.0];
+        let scales = weight_cap_scales(&variances, 0.4);
+        let precisions: Vec<f64> = variances
+            .iter()
+            .zip(&scales)
+            .map(|(variance, scale)| 1.0 / (variance * scale))
+            .collect();
+        let total: f64 = precisions.iter().sum();
+        assert!((precisions[0] / total - 0.4).abs() < 1e-12);
+        assert!((precisions[1] / total - 0.4).abs() < 1e-12);
+        assert!((precisions[2] / total - 0.1).abs() < 1e-12);
+        assert!((precisions[3] / total - 0.1).abs() < 1e-12);
+
+        // A peer with a zero variance is capped as well
+        let scales = weight_cap_scales(&[0.0, 1.0, 1.0], 0.5);
+        assert!(scales.iter().all(|scale| scale.is_finite() && *scale > 0.0));
+    }
+
+    #[test]
+    fn test_max_single_peer_weight_zero_variance() {
+        let mut selected = vec![
+            snapshot_for_state(
+                Vector::new_vector([0.0, 0.0]),
+                Matrix::new([[0.0, 0.0], [0.0, 1e-12]]),
+                0.0,
+            ),
+            snapshot_for_state(
+                Vector::new_vector([1e-3, 0.0]),
+                Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                0.0,
+            ),
+        ];
+        for (index, snapshot) in selected.iter_mut().enumerate() {
+            snapshot.index = index;
+        }
+
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            max_single_peer_weight: Some(0.5),
+            ..Default::default()
+        };
+        let result = combine(&selected, &algconfig, None).unwrap();
+        assert!((result.estimate.ventry(0) - 0.5 * 1e-3).abs() < 1e-9);
+    }
+
     fn snapshot_for_leap(leap: NtpLeapIndicator) -> PeerSnapshot<usize> {
         PeerSnapshot {
             index: 0,
@@ -229,6 +575,7 @@ mod tests {
             peer_uncertainty: NtpDuration::from_seconds(0.0),
             peer_delay: NtpDuration::from_seconds(0.0),
             leap_indicator: leap,
+            prefer: false,
             last_update: NtpTimestamp::from_fixed_int(0),
         }
     }
@@ -242,7 +589,7 @@ mod tests {
             snapshot_for_leap(NtpLeapIndicator::NoWarning),
             snapshot_for_leap(NtpLeapIndicator::NoWarning),
         ];
-        let result = combine(&selected, &algconfig).unwrap();
+        let result = combine(&selected, &algconfig, None).unwrap();
         assert_eq!(result.leap_indicator, Some(NtpLeapIndicator::NoWarning));
 
         let selected = vec![
@@ -250,7 +597,7 @@ mod tests {
             snapshot_for_leap(NtpLeapIndicator::Leap59),
             snapshot_for_leap(NtpLeapIndicator::Leap59),
         ];
-        let result = combine(&selected, &algconfig).unwrap();
+        let result = combine(&selected, &algconfig, None).unwrap();
         assert_eq!(result.leap_indicator, Some(NtpLeapIndicator::Leap59));
 
         let selected = vec![
@@ -258,14 +605,14 @@ mod tests {
             snapshot_for_leap(NtpLeapIndicator::Leap61),
             snapshot_for_leap(NtpLeapIndicator::Leap61),
         ];
-        let result = combine(&selected, &algconfig).unwrap();
+        let result = combine(&selected, &algconfig, None).unwrap();
         assert_eq!(result.leap_indicator, Some(NtpLeapIndicator::Leap61));
 
         let selected = vec![
             snapshot_for_leap(NtpLeapIndicator::Leap61),
             snapshot_for_leap(NtpLeapIndicator::Leap59),
         ];
-        let result = combine(&selected, &algconfig).unwrap();
+        let result = combine(&selected, &algconfig, None).unwrap();
         assert_eq!(result.leap_indicator, None);
 
         let selected = vec![
@@ -273,7 +620,7 @@ mod tests {
             snapshot_for_leap(NtpLeapIndicator::Leap61),
             snapshot_for_leap(NtpLeapIndicator::Leap61),
         ];
-        let result = combine(&selected, &algconfig).unwrap();
+        let result = combine(&selected, &algconfig, None).unwrap();
         assert_eq!(result.leap_indicator, Some(NtpLeapIndicator::Leap61));
 
         let selected = vec![
@@ -281,7 +628,7 @@ mod tests {
             snapshot_for_leap(NtpLeapIndicator::Leap59),
             snapshot_for_leap(NtpLeapIndicator::Leap61),
         ];
-        let result = combine(&selected, &algconfig).unwrap();
+        let result = combine(&selected, &algconfig, None).unwrap();
         assert_eq!(result.leap_indicator, None);
     }
 }
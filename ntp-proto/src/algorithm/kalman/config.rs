@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{de, de::Unexpected, Deserialize, Deserializer};
 
 use crate::time_types::NtpDuration;
 
@@ -45,6 +45,12 @@ pub struct AlgorithmConfig {
     #[serde(default = "default_delay_outlier_threshold")]
     pub delay_outlier_threshold: f64,
 
+    /// Number of measurements after a peer (re)starts that only contribute
+    /// to its round trip time statistics, and not to its offset, as they
+    /// are often skewed by network setup. (count, 0+)
+    #[serde(default)]
+    pub discard_initial_samples: usize,
+
     /// Initial estimate of the clock wander of the combination
     /// of our local clock and that of the source. (s/s^2)
     #[serde(default = "default_initial_wander")]
@@ -67,6 +73,15 @@ pub struct AlgorithmConfig {
     /// ranges. (weight, 0-1)
     #[serde(default = "default_range_delay_weight")]
     pub range_delay_weight: f64,
+    /// Maximum number of peers considered during selection, the ones with
+    /// the smallest confidence intervals are kept. No maximum when not set.
+    #[serde(default)]
+    pub max_candidate_peers: Option<usize>,
+    /// Largest share any single peer may have in the combined offset
+    /// estimate, the other peers make up the rest. No maximum when not set.
+    /// (fraction, 0-1)
+    #[serde(default, deserialize_with = "deserialize_max_single_peer_weight")]
+    pub max_single_peer_weight: Option<f64>,
 
     /// How far from 0 (in multiples of the uncertainty) should
     /// the offset be before we correct. (standard deviations, 0+)
@@ -108,6 +123,24 @@ pub struct AlgorithmConfig {
     /// Threshold for detecting external clock meddling
     #[serde(default = "default_meddling_threshold")]
     pub meddling_threshold: NtpDuration,
+
+    /// Ignore measurements whose local time is further ahead of the previous
+    /// measurement of a peer than the monotonic clock advanced, by more than
+    /// this, or no limit when not set. Such measurements would make every
+    /// later one look like it is from the past.
+    #[serde(default)]
+    pub future_measurement_threshold: Option<NtpDuration>,
+
+    /// How many times smaller the uncertainty of another peer must
+    /// be before it replaces the current system peer. (ratio, 1+)
+    #[serde(default = "default_system_peer_hysteresis")]
+    pub system_peer_hysteresis: f64,
+
+    /// Weight of the newest offset estimate in the exponentially weighted
+    /// moving average reported as the smoothed offset of a peer, or no
+    /// smoothing when not set. This does not affect steering. (weight, 0-1)
+    #[serde(default)]
+    pub offset_smoothing: Option<f64>,
 }
 
 impl Default for AlgorithmConfig {
@@ -124,6 +157,7 @@ impl Default for AlgorithmConfig {
             poll_interval_step_threshold: default_poll_interval_step_threshold(),
 
             delay_outlier_threshold: default_delay_outlier_threshold(),
+            discard_initial_samples: 0,
 
             initial_wander: default_initial_wander(),
             initial_frequency_uncertainty: default_initial_frequency_uncertainty(),
@@ -131,6 +165,8 @@ impl Default for AlgorithmConfig {
             maximum_source_uncertainty: default_maximum_source_uncertainty(),
             range_statistical_weight: default_range_statistical_weight(),
             range_delay_weight: default_range_delay_weight(),
+            max_candidate_peers: None,
+            max_single_peer_weight: None,
 
             steer_offset_threshold: default_steer_offset_threshold(),
             steer_offset_leftover: default_steer_offset_leftover(),
@@ -145,10 +181,33 @@ impl Default for AlgorithmConfig {
             ignore_server_dispersion: false,
 
             meddling_threshold: default_meddling_threshold(),
+            future_measurement_threshold: None,
+
+            system_peer_hysteresis: default_system_peer_hysteresis(),
+
+            offset_smoothing: None,
         }
     }
 }
 
+fn deserialize_max_single_peer_weight<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    let weight = f64::deserialize(deserializer)?;
+    // Without any weight a peer can't contribute to the estimate at all
+    if weight.is_nan() || weight <= 0.0 {
+        return Err(de::Error::invalid_value(
+            Unexpected::Float(weight),
+            &"a positive weight",
+        ));
+    }
+    Ok(Some(weight))
+}
+
+fn default_system_peer_hysteresis() -> f64 {
+    2.0
+}
+
 fn default_precision_low_probability() -> f64 {
     1. / 3.
 }
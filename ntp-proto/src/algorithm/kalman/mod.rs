@@ -1,23 +1,31 @@
-use std::{collections::HashMap, fmt::Debug, hash::Hash, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    hash::Hash,
+    time::Duration,
+};
 
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, warn};
 
 use crate::{
     clock::NtpClock,
     config::{SourceDefaultsConfig, SynchronizationConfig},
     packet::NtpLeapIndicator,
-    peer::Measurement,
+    peer::{Measurement, MAX_SAVED_STATE_AGE},
     system::TimeSnapshot,
     time_types::{NtpDuration, NtpTimestamp},
 };
 
 use self::{
-    combiner::combine,
+    combiner::{combine, Combine},
     config::AlgorithmConfig,
     matrix::{Matrix, Vector},
     peer::PeerState,
 };
 
+pub use self::peer::SavedFilterState;
+pub use self::select::{ClockSelect, IntervalSelect, SelectionOutcome, SurvivorVerdict};
+
 use super::{ObservablePeerTimedata, StateUpdate, TimeSyncController};
 
 mod combiner;
@@ -30,8 +38,10 @@ fn sqr(x: f64) -> f64 {
     x * x
 }
 
+/// The state of a peer's filter at the moment peers are selected, as seen by a
+/// [`ClockSelect`] strategy
 #[derive(Debug, Clone)]
-struct PeerSnapshot<Index: Copy> {
+pub struct PeerSnapshot<Index: Copy> {
     index: Index,
     state: Vector<2>,
     uncertainty: Matrix<2, 2>,
@@ -40,23 +50,59 @@ struct PeerSnapshot<Index: Copy> {
     peer_uncertainty: NtpDuration,
     peer_delay: NtpDuration,
     leap_indicator: NtpLeapIndicator,
+    // Whether the operator marked this peer as preferred
+    prefer: bool,
 
     last_update: NtpTimestamp,
 }
 
 impl<Index: Copy> PeerSnapshot<Index> {
-    fn offset(&self) -> f64 {
+    /// Identifier of the peer
+    pub fn index(&self) -> Index {
+        self.index
+    }
+
+    /// Estimated offset of the local clock relative to the peer (s)
+    pub fn offset(&self) -> f64 {
         self.state.ventry(0)
     }
 
-    fn offset_uncertainty(&self) -> f64 {
+    /// Standard deviation of the estimated offset (s)
+    pub fn offset_uncertainty(&self) -> f64 {
         self.uncertainty.entry(0, 0).sqrt()
     }
 
+    /// Round trip delay of the peer (s)
+    pub fn delay(&self) -> f64 {
+        self.delay
+    }
+
+    /// Root delay reported by the peer
+    pub fn peer_delay(&self) -> NtpDuration {
+        self.peer_delay
+    }
+
+    /// Root dispersion reported by the peer
+    pub fn peer_uncertainty(&self) -> NtpDuration {
+        self.peer_uncertainty
+    }
+
+    /// Leap indicator reported by the peer
+    pub fn leap_indicator(&self) -> NtpLeapIndicator {
+        self.leap_indicator
+    }
+
+    /// Whether the operator marked this peer as preferred
+    pub fn prefer(&self) -> bool {
+        self.prefer
+    }
+
     fn observe(&self) -> ObservablePeerTimedata {
         ObservablePeerTimedata {
             offset: NtpDuration::from_seconds(self.offset()),
+            smoothed_offset: None,
             uncertainty: NtpDuration::from_seconds(self.offset_uncertainty()),
+            jitter: None,
             delay: NtpDuration::from_seconds(self.delay),
             remote_delay: self.peer_delay,
             remote_uncertainty: self.peer_uncertainty,
@@ -65,9 +111,47 @@ impl<Index: Copy> PeerSnapshot<Index> {
     }
 }
 
+/// Combined estimate of the system's time state, as produced by selecting
+/// and combining the currently usable peers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SystemUpdate {
+    /// Offset of the local clock relative to the combined peers
+    pub offset: NtpDuration,
+    /// Frequency offset of the local clock relative to the combined peers
+    pub frequency: f64,
+    /// Spread of the combined peers around the offset, in seconds
+    pub jitter: f64,
+    pub root_delay: NtpDuration,
+    pub root_dispersion: NtpDuration,
+    /// How the offset would be corrected
+    pub action: SteerAction,
+}
+
+/// Way in which an offset of the clock is corrected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SteerAction {
+    /// Jump the clock by the offset at once
+    Step,
+    /// Gradually correct the offset by running the clock faster or slower
+    Slew {
+        /// Frequency offset applied during the slew (s/s), never more than
+        /// the configured `slew_maximum_frequency_offset`
+        rate: f64,
+        /// How long it takes the slew to correct the offset
+        duration: Duration,
+    },
+}
+
+/// Clock controller based on a kalman filter per peer, selecting the peers
+/// to combine with the [`ClockSelect`] strategy `S`.
 #[derive(Debug, Clone)]
-pub struct KalmanClockController<C: NtpClock, PeerID: Hash + Eq + Copy + Debug> {
+pub struct KalmanClockController<
+    C: NtpClock,
+    PeerID: Hash + Eq + Copy + Debug,
+    S: ClockSelect = IntervalSelect,
+> {
     peers: HashMap<PeerID, (PeerState, bool)>,
+    preferred_peers: HashSet<PeerID>,
     clock: C,
     synchronization_config: SynchronizationConfig,
     peer_defaults_config: SourceDefaultsConfig,
@@ -76,9 +160,43 @@ pub struct KalmanClockController<C: NtpClock, PeerID: Hash + Eq + Copy + Debug>
     timedata: TimeSnapshot,
     desired_freq: f64,
     in_startup: bool,
+    system_peer: Option<PeerID>,
+    clock_select: S,
 }
 
-impl<C: NtpClock, PeerID: Hash + Eq + Copy + Debug> KalmanClockController<C, PeerID> {
+impl<C: NtpClock, PeerID: Hash + Eq + Copy + Debug, S: ClockSelect>
+    KalmanClockController<C, PeerID, S>
+{
+    /// Create a new clock controller controling the given clock, which
+    /// selects peers using `clock_select`
+    pub fn new_with_clock_select(
+        clock: C,
+        synchronization_config: SynchronizationConfig,
+        peer_defaults_config: SourceDefaultsConfig,
+        algo_config: AlgorithmConfig,
+        clock_select: S,
+    ) -> Result<Self, C::Error> {
+        // Setup clock
+        clock.disable_ntp_algorithm()?;
+        clock.status_update(NtpLeapIndicator::Unknown)?;
+        clock.set_frequency(0.0)?;
+
+        Ok(KalmanClockController {
+            peers: HashMap::new(),
+            preferred_peers: HashSet::new(),
+            clock,
+            synchronization_config,
+            peer_defaults_config,
+            algo_config,
+            freq_offset: 0.0,
+            desired_freq: 0.0,
+            timedata: TimeSnapshot::default(),
+            in_startup: true,
+            system_peer: None,
+            clock_select,
+        })
+    }
+
     #[instrument(skip(self))]
     fn update_peer(&mut self, id: PeerID, measurement: Measurement) -> bool {
         self.peers.get_mut(&id).map(|state| {
@@ -108,28 +226,15 @@ impl<C: NtpClock, PeerID: Hash + Eq + Copy + Debug> KalmanClockController<C, Pee
             state.progress_filtertime(time);
         }
 
-        let selection = select::select(
-            &self.synchronization_config,
-            &self.algo_config,
-            self.peers
-                .iter()
-                .filter_map(|(index, (state, usable))| {
-                    if *usable {
-                        state.snapshot(*index)
-                    } else {
-                        None
-                    }
-                })
-                .collect(),
-        );
-
-        if let Some(combined) = combine(&selection, &self.algo_config) {
+        let selection = self.select_and_combine();
+        if let Ok(combined) = selection {
             info!(
-                "Offset: {}+-{}ms, frequency: {}+-{}ppm",
+                "Offset: {}+-{}ms, frequency: {}+-{}ppm, jitter: {}ms",
                 combined.estimate.ventry(0) * 1e3,
                 combined.uncertainty.entry(0, 0).sqrt() * 1e3,
                 combined.estimate.ventry(1) * 1e6,
-                combined.uncertainty.entry(1, 1).sqrt() * 1e6
+                combined.uncertainty.entry(1, 1).sqrt() * 1e6,
+                combined.jitter * 1e3,
             );
 
             let freq_delta = combined.estimate.ventry(1) - self.desired_freq;
@@ -183,82 +288,199 @@ impl<C: NtpClock, PeerID: Hash + Eq + Copy + Debug> KalmanClockController<C, Pee
             // After a succesfull measurement we are out of startup.
             self.in_startup = false;
 
+            self.system_peer = combined.peers.first().copied();
+
             StateUpdate {
                 used_peers: Some(combined.peers),
                 time_snapshot: Some(self.timedata),
                 next_update,
             }
         } else {
-            info!("No consensus cluster found");
+            if let Err(SelectionOutcome::NoConsensus { candidate_count }) = selection {
+                warn!(
+                    candidate_count,
+                    "Peers disagree on the time, no consensus cluster found"
+                );
+            } else {
+                info!("No consensus cluster found");
+            }
             StateUpdate {
-                used_peers: None,
+                // Report losing our last time source, so it is no longer
+                // advertised as such
+                used_peers: self.system_peer.take().map(|_| vec![]),
                 time_snapshot: Some(self.timedata),
                 next_update: None,
             }
         }
     }
 
+    fn candidates(&self) -> Vec<PeerSnapshot<PeerID>> {
+        self.peers
+            .iter()
+            .filter_map(|(index, (state, usable))| {
+                if *usable {
+                    state.snapshot(*index).map(|snapshot| PeerSnapshot {
+                        prefer: self.preferred_peers.contains(index),
+                        ..snapshot
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn select_and_combine(&self) -> Result<Combine<PeerID>, SelectionOutcome<PeerID>> {
+        let selection = match self.clock_select.select(
+            &self.synchronization_config,
+            &self.algo_config,
+            self.candidates(),
+        ) {
+            SelectionOutcome::Selected(selection) => selection,
+            outcome => return Err(outcome),
+        };
+
+        // Not every selection strategy enforces a quorum itself, and without
+        // one a single falseticker could steer the clock.
+        if selection.len() < self.synchronization_config.minimum_agreeing_sources {
+            return Err(SelectionOutcome::NoConsensus {
+                candidate_count: selection.len(),
+            });
+        }
+
+        combine(&selection, &self.algo_config, self.system_peer)
+            .ok_or(SelectionOutcome::NoCandidates)
+    }
+
+    /// Select and combine the currently usable peers into a single estimate
+    /// of the offset the clock should be steered by, without steering it.
+    ///
+    /// Returns `None` when no consensus among the peers could be found.
+    pub fn system_update(&self) -> Option<SystemUpdate> {
+        self.select_and_combine().ok().map(|combined| SystemUpdate {
+            offset: NtpDuration::from_seconds(combined.estimate.ventry(0)),
+            frequency: combined.estimate.ventry(1),
+            jitter: combined.jitter,
+            root_delay: combined.delay,
+            root_dispersion: NtpDuration::from_seconds(combined.uncertainty.entry(0, 0).sqrt()),
+            action: self.steer_action(combined.estimate.ventry(0)),
+        })
+    }
+
+    /// Explain for every usable peer why it was or wasn't selected to
+    /// synchronize with
+    pub fn selection_audit(&self) -> Vec<(PeerID, SurvivorVerdict)> {
+        select::audit(
+            &self.synchronization_config,
+            &self.algo_config,
+            self.candidates(),
+        )
+    }
+
+    /// Smallest and largest recent round trip delay of a peer, if it has
+    /// produced any measurements yet
+    pub fn peer_delay_range(&self, id: PeerID) -> Option<(NtpDuration, NtpDuration)> {
+        self.peers.get(&id).and_then(|v| v.0.delay_range())
+    }
+
+    /// Filter state of a peer to persist, if its filter is past its initial
+    /// measurements
+    pub fn peer_export_state(&self, id: PeerID) -> Option<SavedFilterState> {
+        self.peers.get(&id).and_then(|v| v.0.export())
+    }
+
+    /// Continue the filter of a peer from a previously exported state at
+    /// local time `now`, unless that state has become too old. Returns
+    /// whether the state was restored.
+    pub fn peer_restore_state(
+        &mut self,
+        id: PeerID,
+        state: SavedFilterState,
+        now: NtpTimestamp,
+    ) -> bool {
+        let max_age = NtpDuration::from_seconds(MAX_SAVED_STATE_AGE);
+        self.peers
+            .get_mut(&id)
+            .map_or(false, |v| v.0.restore(state, now, max_age))
+    }
+
+    /// Whether the panic threshold for the current phase allows changing the
+    /// clock by `change` at once, be it by stepping or slewing
+    fn within_panic_threshold(&self, change: NtpDuration) -> bool {
+        let threshold = if self.in_startup {
+            &self.synchronization_config.startup_step_panic_threshold
+        } else {
+            &self.synchronization_config.single_step_panic_threshold
+        };
+        threshold.is_within(change)
+    }
+
     fn check_offset_steer(&mut self, change: f64) {
         let change = NtpDuration::from_seconds(change);
-        if self.in_startup {
-            if !self
-                .synchronization_config
-                .startup_step_panic_threshold
-                .is_within(change)
-            {
-                error!("Unusually large clock step suggested, please manually verify system clock and reference clock state and restart if appropriate.");
-                #[cfg(not(test))]
-                std::process::exit(crate::exitcode::SOFTWARE);
-                #[cfg(test)]
-                panic!("Threshold exceeded");
-            }
-        } else {
+        let mut accumulated_exceeded = false;
+        if !self.in_startup {
             self.timedata.accumulated_steps += change.abs();
-            if !self
+            accumulated_exceeded = self
                 .synchronization_config
-                .single_step_panic_threshold
-                .is_within(change)
-                || self
-                    .synchronization_config
-                    .accumulated_step_panic_threshold
-                    .map(|v| self.timedata.accumulated_steps > v)
-                    .unwrap_or(false)
-            {
-                error!("Unusually large clock step suggested, please manually verify system clock and reference clock state and restart if appropriate.");
-                #[cfg(not(test))]
-                std::process::exit(crate::exitcode::SOFTWARE);
-                #[cfg(test)]
-                panic!("Threshold exceeded");
-            }
+                .accumulated_step_panic_threshold
+                .map(|v| self.timedata.accumulated_steps > v)
+                .unwrap_or(false);
+        }
+
+        if !self.within_panic_threshold(change) || accumulated_exceeded {
+            offset_panic();
         }
     }
 
-    fn steer_offset(&mut self, change: f64, freq_delta: f64) -> Option<Duration> {
+    fn check_offset_slew(&self, change: f64) {
+        if !self.within_panic_threshold(NtpDuration::from_seconds(change)) {
+            offset_panic();
+        }
+    }
+
+    /// Decide whether an offset `change` (in seconds) should be stepped or
+    /// slewed, and at what rate
+    fn steer_action(&self, change: f64) -> SteerAction {
         if change.abs() > self.algo_config.step_threshold {
-            // jump
-            self.check_offset_steer(change);
-            self.clock
-                .step_clock(NtpDuration::from_seconds(change))
-                .expect("Cannot adjust clock");
-            for (state, _) in self.peers.values_mut() {
-                state.process_offset_steering(change);
-            }
-            info!("Jumped offset by {}ms", change * 1e3);
-            None
+            SteerAction::Step
         } else {
-            // start slew
-            let freq = self
+            let rate = self
                 .algo_config
                 .slew_maximum_frequency_offset
                 .min(change.abs() / self.algo_config.slew_minimum_duration);
-            let duration = Duration::from_secs_f64(change.abs() / freq);
-            info!(
-                "Slewing by {}ms over {}s",
-                change * 1e3,
-                duration.as_secs_f64(),
-            );
-            self.change_desired_frequency(-freq * change.signum(), freq_delta);
-            Some(duration)
+            // Nothing to correct when there is no offset at all
+            let duration = if rate > 0.0 {
+                Duration::from_secs_f64(change.abs() / rate)
+            } else {
+                Duration::ZERO
+            };
+            SteerAction::Slew { rate, duration }
+        }
+    }
+
+    fn steer_offset(&mut self, change: f64, freq_delta: f64) -> Option<Duration> {
+        match self.steer_action(change) {
+            SteerAction::Step => {
+                self.check_offset_steer(change);
+                self.clock
+                    .step_clock(NtpDuration::from_seconds(change))
+                    .expect("Cannot adjust clock");
+                for (state, _) in self.peers.values_mut() {
+                    state.process_offset_steering(change);
+                }
+                info!("Jumped offset by {}ms", change * 1e3);
+                None
+            }
+            SteerAction::Slew { rate, duration } => {
+                self.check_offset_slew(change);
+                info!(
+                    "Slewing by {}ms over {}s",
+                    change * 1e3,
+                    duration.as_secs_f64(),
+                );
+                self.change_desired_frequency(-rate * change.signum(), freq_delta);
+                Some(duration)
+            }
         }
     }
 
@@ -302,8 +524,8 @@ impl<C: NtpClock, PeerID: Hash + Eq + Copy + Debug> KalmanClockController<C, Pee
     }
 }
 
-impl<C: NtpClock, PeerID: Hash + Eq + Copy + Debug> TimeSyncController<C, PeerID>
-    for KalmanClockController<C, PeerID>
+impl<C: NtpClock, PeerID: Hash + Eq + Copy + Debug, S: ClockSelect + Default>
+    TimeSyncController<C, PeerID> for KalmanClockController<C, PeerID, S>
 {
     type AlgorithmConfig = AlgorithmConfig;
 
@@ -313,22 +535,13 @@ impl<C: NtpClock, PeerID: Hash + Eq + Copy + Debug> TimeSyncController<C, PeerID
         peer_defaults_config: SourceDefaultsConfig,
         algo_config: Self::AlgorithmConfig,
     ) -> Result<Self, C::Error> {
-        // Setup clock
-        clock.disable_ntp_algorithm()?;
-        clock.status_update(NtpLeapIndicator::Unknown)?;
-        clock.set_frequency(0.0)?;
-
-        Ok(KalmanClockController {
-            peers: HashMap::new(),
+        Self::new_with_clock_select(
             clock,
             synchronization_config,
             peer_defaults_config,
             algo_config,
-            freq_offset: 0.0,
-            desired_freq: 0.0,
-            timedata: TimeSnapshot::default(),
-            in_startup: true,
-        })
+            S::default(),
+        )
     }
 
     fn update_config(
@@ -348,6 +561,15 @@ impl<C: NtpClock, PeerID: Hash + Eq + Copy + Debug> TimeSyncController<C, PeerID
 
     fn peer_remove(&mut self, id: PeerID) {
         self.peers.remove(&id);
+        self.preferred_peers.remove(&id);
+    }
+
+    fn peer_prefer(&mut self, id: PeerID, prefer: bool) {
+        if prefer {
+            self.preferred_peers.insert(id);
+        } else {
+            self.preferred_peers.remove(&id);
+        }
     }
 
     fn peer_update(&mut self, id: PeerID, usable: bool) {
@@ -377,13 +599,20 @@ impl<C: NtpClock, PeerID: Hash + Eq + Copy + Debug> TimeSyncController<C, PeerID
     }
 
     fn peer_snapshot(&self, id: PeerID) -> Option<ObservablePeerTimedata> {
-        self.peers
-            .get(&id)
-            .and_then(|v| v.0.snapshot(id))
-            .map(|v| v.observe())
+        self.peers.get(&id).and_then(|v| v.0.statistics())
     }
 }
 
+/// Refuse to change the clock by an unreasonably large offset, as that is
+/// far more likely to be caused by a problem than to be correct
+fn offset_panic() -> ! {
+    error!("Unusually large clock step suggested, please manually verify system clock and reference clock state and restart if appropriate.");
+    #[cfg(not(test))]
+    std::process::exit(crate::exitcode::SOFTWARE);
+    #[cfg(test)]
+    panic!("Threshold exceeded");
+}
+
 #[cfg(test)]
 mod tests {
     use std::cell::RefCell;
@@ -441,7 +670,7 @@ mod tests {
         };
         let algo_config = AlgorithmConfig::default();
         let peer_defaults_config = SourceDefaultsConfig::default();
-        let mut algo = KalmanClockController::new(
+        let mut algo = KalmanClockController::<_, _>::new(
             TestClock {
                 has_steered: RefCell::new(false),
                 current_time: NtpTimestamp::from_fixed_int(0),
@@ -492,6 +721,248 @@ mod tests {
         assert_ne!(algo.timedata.root_dispersion, NtpDuration::ZERO);
     }
 
+    #[test]
+    fn test_system_update_combines_agreeing_peers() {
+        let synchronization_config = SynchronizationConfig::default();
+        let algo_config = AlgorithmConfig::default();
+        let peer_defaults_config = SourceDefaultsConfig::default();
+        let mut algo = KalmanClockController::<_, _>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            peer_defaults_config,
+            algo_config,
+        )
+        .unwrap();
+        let mut cur_instant = NtpInstant::now();
+
+        let offsets = [0.0100, 0.0101, 0.0102];
+        for id in 0..offsets.len() {
+            // Peers are not yet usable, so measurements don't steer the clock
+            algo.peer_add(id);
+        }
+
+        assert!(algo.system_update().is_none());
+
+        let mut noise = 1e-9;
+        for _ in 0..16 {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            noise *= -1.0;
+            for (id, offset) in offsets.iter().enumerate() {
+                algo.peer_measurement(
+                    id,
+                    Measurement {
+                        delay: NtpDuration::from_seconds(0.010 + noise),
+                        offset: NtpDuration::from_seconds(offset + noise),
+                        transmit_timestamp: Default::default(),
+                        receive_timestamp: Default::default(),
+                        localtime: algo.clock.current_time,
+                        monotime: cur_instant,
+
+                        stratum: 0,
+                        root_delay: NtpDuration::default(),
+                        root_dispersion: NtpDuration::default(),
+                        leap: NtpLeapIndicator::NoWarning,
+                        precision: 0,
+                    },
+                );
+            }
+        }
+
+        for id in 0..offsets.len() {
+            algo.peer_update(id, true);
+        }
+
+        let update = algo.system_update().unwrap();
+        assert!(update.offset >= NtpDuration::from_seconds(0.0100));
+        assert!(update.offset <= NtpDuration::from_seconds(0.0102));
+        assert!(update.root_delay > NtpDuration::ZERO);
+        assert!(update.root_dispersion > NtpDuration::ZERO);
+        // the peers disagree by a fraction of a millisecond
+        assert!(update.jitter > 0.0);
+        assert!(update.jitter < 0.001);
+        // Only computing the update must not steer the clock
+        assert!(algo.in_startup);
+    }
+
+    /// Selects only the peer furthest ahead of the local clock
+    #[derive(Debug, Clone, Default)]
+    struct LargestOffsetSelect;
+
+    impl ClockSelect for LargestOffsetSelect {
+        fn select<Index: Copy>(
+            &self,
+            _synchronization_config: &SynchronizationConfig,
+            _algo_config: &AlgorithmConfig,
+            candidates: Vec<PeerSnapshot<Index>>,
+        ) -> SelectionOutcome<Index> {
+            match candidates
+                .into_iter()
+                .max_by(|a, b| a.offset().total_cmp(&b.offset()))
+            {
+                Some(candidate) => SelectionOutcome::Selected(vec![candidate]),
+                None => SelectionOutcome::NoCandidates,
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_clock_select() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..SynchronizationConfig::default()
+        };
+        let algo_config = AlgorithmConfig::default();
+        let peer_defaults_config = SourceDefaultsConfig::default();
+        let mut algo = KalmanClockController::new_with_clock_select(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            peer_defaults_config,
+            algo_config,
+            LargestOffsetSelect,
+        )
+        .unwrap();
+        let mut cur_instant = NtpInstant::now();
+
+        let offsets = [0.0100, 0.0101, 0.0102];
+        for id in 0..offsets.len() {
+            algo.peer_add(id);
+        }
+
+        let mut noise = 1e-9;
+        for _ in 0..16 {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            noise *= -1.0;
+            for (id, offset) in offsets.iter().enumerate() {
+                algo.peer_measurement(
+                    id,
+                    Measurement {
+                        delay: NtpDuration::from_seconds(0.010 + noise),
+                        offset: NtpDuration::from_seconds(offset + noise),
+                        transmit_timestamp: Default::default(),
+                        receive_timestamp: Default::default(),
+                        localtime: algo.clock.current_time,
+                        monotime: cur_instant,
+
+                        stratum: 0,
+                        root_delay: NtpDuration::default(),
+                        root_dispersion: NtpDuration::default(),
+                        leap: NtpLeapIndicator::NoWarning,
+                        precision: 0,
+                    },
+                );
+            }
+        }
+
+        for id in 0..offsets.len() {
+            algo.peer_update(id, true);
+        }
+
+        // Only the peer furthest ahead is used, rather than all agreeing peers
+        let update = algo.system_update().unwrap();
+        assert!(update.offset > NtpDuration::from_seconds(0.01015));
+    }
+
+    #[test]
+    fn test_minimum_agreeing_sources() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 2,
+            ..SynchronizationConfig::default()
+        };
+        let algo_config = AlgorithmConfig::default();
+        let peer_defaults_config = SourceDefaultsConfig::default();
+        // A strategy that selects a single peer without checking whether
+        // enough peers agree with it
+        let mut algo = KalmanClockController::new_with_clock_select(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            peer_defaults_config,
+            algo_config,
+            LargestOffsetSelect,
+        )
+        .unwrap();
+        let mut cur_instant = NtpInstant::now();
+
+        let offsets = [0.0100, 0.0101, 0.0102];
+        for id in 0..offsets.len() {
+            algo.peer_add(id);
+        }
+
+        let mut noise = 1e-9;
+        for _ in 0..16 {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            noise *= -1.0;
+            for (id, offset) in offsets.iter().enumerate() {
+                algo.peer_measurement(
+                    id,
+                    Measurement {
+                        delay: NtpDuration::from_seconds(0.010 + noise),
+                        offset: NtpDuration::from_seconds(offset + noise),
+                        transmit_timestamp: Default::default(),
+                        receive_timestamp: Default::default(),
+                        localtime: algo.clock.current_time,
+                        monotime: cur_instant,
+
+                        stratum: 0,
+                        root_delay: NtpDuration::default(),
+                        root_dispersion: NtpDuration::default(),
+                        leap: NtpLeapIndicator::NoWarning,
+                        precision: 0,
+                    },
+                );
+            }
+        }
+
+        for id in 0..offsets.len() {
+            algo.peer_update(id, true);
+        }
+
+        // A single selected peer is not enough for a quorum of two
+        assert!(algo.system_update().is_none());
+
+        algo.synchronization_config.minimum_agreeing_sources = 1;
+        assert!(algo.system_update().is_some());
+    }
+
+    #[test]
+    fn steer_action_by_offset() {
+        let algo_config = AlgorithmConfig {
+            step_threshold: 0.5,
+            slew_maximum_frequency_offset: 200e-6,
+            ..Default::default()
+        };
+        let algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            algo_config,
+        )
+        .unwrap();
+
+        assert_eq!(algo.steer_action(0.6), SteerAction::Step);
+        assert_eq!(algo.steer_action(-0.6), SteerAction::Step);
+
+        let SteerAction::Slew { rate, duration } = algo.steer_action(0.005) else {
+            panic!("small offset should be slewed");
+        };
+        assert!(rate > 0.0 && rate <= 200e-6);
+        assert!((duration.as_secs_f64() * rate - 0.005).abs() < 1e-9);
+    }
+
     #[test]
     fn slews_dont_accumulate() {
         let synchronization_config = SynchronizationConfig {
@@ -523,6 +994,35 @@ mod tests {
         assert_eq!(algo.timedata.accumulated_steps, NtpDuration::ZERO);
     }
 
+    #[test]
+    #[should_panic]
+    fn large_slew_panics() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..SynchronizationConfig::default()
+        };
+        // An offset of 2000s is beyond the default panic threshold, even
+        // when it would be slewed rather than stepped
+        let algo_config = AlgorithmConfig {
+            step_threshold: 3600.0,
+            ..Default::default()
+        };
+        let peer_defaults_config = SourceDefaultsConfig::default();
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            peer_defaults_config,
+            algo_config,
+        )
+        .unwrap();
+
+        algo.in_startup = false;
+        algo.steer_offset(2000.0, 0.0);
+    }
+
     #[test]
     #[should_panic]
     fn jumps_add_absolutely() {
@@ -562,7 +1062,7 @@ mod tests {
         };
         let algo_config = AlgorithmConfig::default();
         let peer_defaults_config = SourceDefaultsConfig::default();
-        let mut algo = KalmanClockController::new(
+        let mut algo = KalmanClockController::<_, _>::new(
             TestClock {
                 has_steered: RefCell::new(false),
                 current_time: NtpTimestamp::from_fixed_int(0),
@@ -619,7 +1119,7 @@ mod tests {
         };
         let algo_config = AlgorithmConfig::default();
         let peer_defaults_config = SourceDefaultsConfig::default();
-        let mut algo = KalmanClockController::new(
+        let mut algo = KalmanClockController::<_, _>::new(
             TestClock {
                 has_steered: RefCell::new(false),
                 current_time: NtpTimestamp::from_fixed_int(0),
@@ -72,12 +72,14 @@
 /// If they are often too small, v is quartered, and if they are often too
 /// large, v is quadrupled (note, this corresponds with doubling/halving
 /// the more intuitive standard deviation).
+use serde::{Deserialize, Serialize};
 use tracing::{debug, info, trace};
 
 use crate::{
+    algorithm::ObservablePeerTimedata,
     config::SourceDefaultsConfig,
     peer::Measurement,
-    time_types::{NtpDuration, NtpTimestamp, PollInterval, PollIntervalLimits},
+    time_types::{NtpDuration, NtpInstant, NtpTimestamp, PollInterval, PollIntervalLimits},
 };
 
 use super::{
@@ -116,7 +118,12 @@ impl AveragingBuffer {
         self.data.iter().sum::<f64>() / (self.data.len() as f64)
     }
 
+    /// Sample variance of the buffer contents. The mean is estimated from the
+    /// same samples, hence the division by one less than the number of samples.
+    /// Where in the ring the most recent sample was inserted doesn't matter, as
+    /// every slot always holds a sample.
     fn variance(&self) -> f64 {
+        debug_assert!(self.data.len() > 1);
         let mean = self.mean();
         self.data.iter().map(|v| sqr(v - mean)).sum::<f64>() / ((self.data.len() - 1) as f64)
     }
@@ -134,16 +141,43 @@ struct InitialPeerFilter {
     last_measurement: Option<Measurement>,
 
     samples: i32,
+    // Number of measurements only used for the round trip time statistics
+    discarded: usize,
 }
 
 impl InitialPeerFilter {
-    fn update(&mut self, measurement: Measurement) {
+    fn new() -> Self {
+        InitialPeerFilter {
+            roundtriptime_stats: AveragingBuffer::default(),
+            init_offset: AveragingBuffer::default(),
+            last_measurement: None,
+            samples: 0,
+            discarded: 0,
+        }
+    }
+
+    /// Returns whether the measurement was used for the offset
+    fn update(&mut self, algo_config: &AlgorithmConfig, measurement: Measurement) -> bool {
         self.roundtriptime_stats
             .update(measurement.delay.to_seconds());
+        if self.discarded < algo_config.discard_initial_samples {
+            self.discarded += 1;
+            debug!(discarded = self.discarded, "Discarded initial peer update");
+            return false;
+        }
+
         self.init_offset.update(measurement.offset.to_seconds());
         self.samples += 1;
         self.last_measurement = Some(measurement);
         debug!(samples = self.samples, "Initial peer update");
+        true
+    }
+
+    /// The round trip times measured so far
+    fn roundtriptimes(&self) -> &[f64] {
+        let filled =
+            (self.samples as usize + self.discarded).min(self.roundtriptime_stats.data.len());
+        &self.roundtriptime_stats.data[..filled]
     }
 
     fn process_offset_steering(&mut self, steer: f64) {
@@ -153,6 +187,15 @@ impl InitialPeerFilter {
     }
 }
 
+/// Exponentially weighted moving average step, giving weight `alpha` to the
+/// newest offset
+fn smooth_offset(previous: Option<f64>, offset: f64, alpha: f64) -> f64 {
+    match previous {
+        Some(previous) => alpha * offset + (1.0 - alpha) * previous,
+        None => offset,
+    }
+}
+
 #[derive(Debug, Clone)]
 struct PeerFilter {
     state: Vector<2>,
@@ -172,6 +215,34 @@ struct PeerFilter {
     last_iter: NtpTimestamp,
     // Current time of the filter state.
     filter_time: NtpTimestamp,
+
+    // Moving average of the offset estimate, if smoothing is enabled
+    smoothed_offset: Option<f64>,
+}
+
+/// Filter state of a peer that can be persisted, so that the filter doesn't
+/// need to be rebuilt from scratch after a restart of the daemon. Only a
+/// filter that is past its initial measurements has state worth saving.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedFilterState {
+    state: [f64; 2],
+    uncertainty: [[f64; 2]; 2],
+    clock_wander: f64,
+
+    roundtriptimes: [f64; 8],
+    next_roundtriptime: usize,
+
+    precision_score: i32,
+    poll_score: i32,
+    desired_poll_interval: PollInterval,
+
+    last_measurement: Measurement,
+    prev_was_outlier: bool,
+
+    last_iter: NtpTimestamp,
+    filter_time: NtpTimestamp,
+
+    smoothed_offset: Option<f64>,
 }
 
 impl PeerFilter {
@@ -208,16 +279,23 @@ impl PeerFilter {
         trace!(?time, "Filter progressed");
     }
 
+    /// Variance of the offset of a single measurement, which is never allowed
+    /// to drop below the minimum dispersion, even when the delay is perfectly
+    /// stable.
+    fn measurement_variance(&self) -> f64 {
+        let delay_variance = self.roundtriptime_stats.variance();
+        (delay_variance / 4.).max(sqr(NtpDuration::MIN_DISPERSION.to_seconds()))
+    }
+
     /// Absorb knowledge from a measurement
     fn absorb_measurement(&mut self, measurement: Measurement) -> (f64, f64, f64) {
         // Measurement parameters
-        let delay_variance = self.roundtriptime_stats.variance();
         let m_delta_t = (measurement.localtime - self.last_measurement.localtime).to_seconds();
 
         // Kalman filter update
         let measurement_vec = Vector::new_vector([measurement.offset.to_seconds()]);
         let measurement_transform = Matrix::new([[1., 0.]]);
-        let measurement_noise = Matrix::new([[delay_variance / 4.]]);
+        let measurement_noise = Matrix::new([[self.measurement_variance()]]);
         let difference = measurement_vec - measurement_transform * self.state;
         let difference_covariance =
             measurement_transform * self.uncertainty * measurement_transform.transpose()
@@ -245,6 +323,8 @@ impl PeerFilter {
 
     /// Ensure we poll often enough to keep the filter well-fed with information, but
     /// not so much that each individual poll message gives us very little new information.
+    /// A measurement that is wildly inconsistent with our current estimate (a jitter or
+    /// offset spike) drops us back to the minimum poll interval immediately.
     fn update_desired_poll(
         &mut self,
         peer_defaults_config: &SourceDefaultsConfig,
@@ -357,6 +437,14 @@ impl PeerFilter {
 
         let (p, weight, measurement_period) = self.absorb_measurement(measurement);
 
+        if let Some(alpha) = algo_config.offset_smoothing {
+            self.smoothed_offset = Some(smooth_offset(
+                self.smoothed_offset,
+                self.state.ventry(0),
+                alpha,
+            ));
+        }
+
         self.update_wander_estimate(algo_config, p, weight);
         self.update_desired_poll(
             peer_defaults_config,
@@ -385,6 +473,9 @@ impl PeerFilter {
         self.last_measurement.offset -= NtpDuration::from_seconds(steer);
         self.last_measurement.localtime += NtpDuration::from_seconds(steer);
         self.filter_time += NtpDuration::from_seconds(steer);
+        if let Some(smoothed_offset) = &mut self.smoothed_offset {
+            *smoothed_offset -= steer;
+        }
     }
 
     fn process_frequency_steering(&mut self, time: NtpTimestamp, steer: f64) {
@@ -408,12 +499,7 @@ pub(super) struct PeerState(PeerStateInner);
 
 impl PeerState {
     pub fn new() -> Self {
-        PeerState(PeerStateInner::Initial(InitialPeerFilter {
-            roundtriptime_stats: AveragingBuffer::default(),
-            init_offset: AveragingBuffer::default(),
-            last_measurement: None,
-            samples: 0,
-        }))
+        PeerState(PeerStateInner::Initial(InitialPeerFilter::new()))
     }
 
     // Returs whether the clock may need adjusting.
@@ -425,7 +511,9 @@ impl PeerState {
     ) -> bool {
         match &mut self.0 {
             PeerStateInner::Initial(filter) => {
-                filter.update(measurement);
+                if !filter.update(algo_config, measurement) {
+                    return false;
+                }
                 if filter.samples == 8 {
                     *self = PeerState(PeerStateInner::Stable(PeerFilter {
                         state: Vector::new_vector([filter.init_offset.mean(), 0.]),
@@ -442,6 +530,7 @@ impl PeerState {
                         prev_was_outlier: false,
                         last_iter: measurement.localtime,
                         filter_time: measurement.localtime,
+                        smoothed_offset: None,
                     }));
                     debug!("Initial peer measurements complete");
                 }
@@ -458,18 +547,27 @@ impl PeerState {
                     .monotime
                     .abs_diff(filter.last_measurement.monotime);
 
-                if localtime_difference.abs_diff(monotime_difference)
+                // Only a local time that jumped further ahead than the
+                // monotonic clock is suspect, a long gap between measurements
+                // is not.
+                let too_far_ahead = algo_config
+                    .future_measurement_threshold
+                    .map_or(false, |threshold| {
+                        localtime_difference - monotime_difference > threshold
+                    });
+
+                if too_far_ahead {
+                    // Accepting it would move the filter time so far ahead
+                    // that all following measurements are ignored as old
+                    tracing::warn!("Ignoring measurement from implausibly far in the future");
+                    false
+                } else if localtime_difference.abs_diff(monotime_difference)
                     > algo_config.meddling_threshold
                 {
                     let msg = "Detected clock meddling. Has another process updated the clock?";
                     tracing::warn!(msg);
 
-                    *self = PeerState(PeerStateInner::Initial(InitialPeerFilter {
-                        roundtriptime_stats: AveragingBuffer::default(),
-                        init_offset: AveragingBuffer::default(),
-                        last_measurement: None,
-                        samples: 0,
-                    }));
+                    *self = PeerState(PeerStateInner::Initial(InitialPeerFilter::new()));
 
                     false
                 } else {
@@ -479,31 +577,46 @@ impl PeerState {
         }
     }
 
+    /// Current statistics of this peer, if it has produced any measurements yet
+    pub fn statistics(&self) -> Option<ObservablePeerTimedata> {
+        let mut timedata = self.snapshot(()).map(|snapshot| snapshot.observe())?;
+        if let PeerStateInner::Stable(filter) = &self.0 {
+            timedata.smoothed_offset = filter.smoothed_offset.map(NtpDuration::from_seconds);
+            timedata.jitter = Some(NtpDuration::from_seconds(
+                filter.measurement_variance().sqrt(),
+            ));
+        }
+        Some(timedata)
+    }
+
     pub fn snapshot<Index: Copy>(&self, index: Index) -> Option<PeerSnapshot<Index>> {
         match &self.0 {
-            PeerStateInner::Initial(InitialPeerFilter {
-                roundtriptime_stats,
-                init_offset,
-                last_measurement: Some(last_measurement),
-                samples,
-            }) if *samples > 0 => {
-                let max_roundtrip = roundtriptime_stats.data[..*samples as usize]
-                    .iter()
-                    .copied()
-                    .fold(None, |v1, v2| {
-                        if v2.is_nan() {
-                            v1
-                        } else if let Some(v1) = v1 {
-                            Some(v2.max(v1))
-                        } else {
-                            Some(v2)
-                        }
-                    })?;
+            PeerStateInner::Initial(filter) if filter.samples > 0 => {
+                let InitialPeerFilter {
+                    init_offset,
+                    last_measurement: Some(last_measurement),
+                    samples,
+                    ..
+                } = filter
+                else {
+                    return None;
+                };
+                let roundtriptimes = filter.roundtriptimes();
+                let max_roundtrip = roundtriptimes.iter().copied().fold(None, |v1, v2| {
+                    if v2.is_nan() {
+                        v1
+                    } else if let Some(v1) = v1 {
+                        Some(v2.max(v1))
+                    } else {
+                        Some(v2)
+                    }
+                })?;
                 Some(PeerSnapshot {
                     index,
                     peer_uncertainty: last_measurement.root_dispersion,
                     peer_delay: last_measurement.root_delay,
                     leap_indicator: last_measurement.leap,
+                    prefer: false,
                     last_update: last_measurement.localtime,
                     delay: max_roundtrip,
                     state: Vector::new_vector([
@@ -528,12 +641,107 @@ impl PeerState {
                 peer_uncertainty: filter.last_measurement.root_dispersion,
                 peer_delay: filter.last_measurement.root_delay,
                 leap_indicator: filter.last_measurement.leap,
+                prefer: false,
                 last_update: filter.last_iter,
             }),
             _ => None,
         }
     }
 
+    /// Smallest and largest round trip delay among the recent measurements.
+    /// A large spread between the two hints at congestion or asymmetric
+    /// network paths.
+    pub fn delay_range(&self) -> Option<(NtpDuration, NtpDuration)> {
+        let delays = match &self.0 {
+            PeerStateInner::Initial(filter) => filter.roundtriptimes(),
+            PeerStateInner::Stable(filter) => &filter.roundtriptime_stats.data[..],
+        };
+
+        let min = delays.iter().copied().reduce(f64::min)?;
+        let max = delays.iter().copied().reduce(f64::max)?;
+        Some((
+            NtpDuration::from_seconds(min),
+            NtpDuration::from_seconds(max),
+        ))
+    }
+
+    /// State of the filter to persist, if it is past its initial measurements
+    pub fn export(&self) -> Option<SavedFilterState> {
+        let PeerStateInner::Stable(filter) = &self.0 else {
+            return None;
+        };
+
+        Some(SavedFilterState {
+            state: [filter.state.ventry(0), filter.state.ventry(1)],
+            uncertainty: [
+                [
+                    filter.uncertainty.entry(0, 0),
+                    filter.uncertainty.entry(0, 1),
+                ],
+                [
+                    filter.uncertainty.entry(1, 0),
+                    filter.uncertainty.entry(1, 1),
+                ],
+            ],
+            clock_wander: filter.clock_wander,
+            roundtriptimes: filter.roundtriptime_stats.data,
+            next_roundtriptime: filter.roundtriptime_stats.next_idx,
+            precision_score: filter.precision_score,
+            poll_score: filter.poll_score,
+            desired_poll_interval: filter.desired_poll_interval,
+            last_measurement: filter.last_measurement,
+            prev_was_outlier: filter.prev_was_outlier,
+            last_iter: filter.last_iter,
+            filter_time: filter.filter_time,
+            smoothed_offset: filter.smoothed_offset,
+        })
+    }
+
+    /// Continue from a previously exported filter state at local time `now`.
+    /// A state from the future or older than `max_age` is ignored, the filter
+    /// then starts from scratch. Returns whether the state was restored.
+    pub fn restore(
+        &mut self,
+        state: SavedFilterState,
+        now: NtpTimestamp,
+        max_age: NtpDuration,
+    ) -> bool {
+        let age = now - state.filter_time;
+        if age < NtpDuration::ZERO || age > max_age {
+            return false;
+        }
+
+        // The monotonic time of the last measurement only has meaning within
+        // the process that took it. Place it as far in the past as the local
+        // time says, so the meddling detection keeps working.
+        let measurement_age =
+            std::cmp::max(now - state.last_measurement.localtime, NtpDuration::ZERO);
+        let mut last_measurement = state.last_measurement;
+        last_measurement.monotime = NtpInstant::now()
+            .checked_sub(measurement_age.to_std_duration_abs().1)
+            .unwrap_or_else(NtpInstant::now);
+
+        *self = PeerState(PeerStateInner::Stable(PeerFilter {
+            state: Vector::new_vector(state.state),
+            uncertainty: Matrix::new(state.uncertainty),
+            clock_wander: state.clock_wander,
+            roundtriptime_stats: AveragingBuffer {
+                data: state.roundtriptimes,
+                next_idx: state.next_roundtriptime % state.roundtriptimes.len(),
+            },
+            precision_score: state.precision_score,
+            poll_score: state.poll_score,
+            desired_poll_interval: state.desired_poll_interval,
+            last_measurement,
+            prev_was_outlier: state.prev_was_outlier,
+            last_iter: state.last_iter,
+            filter_time: state.filter_time,
+            smoothed_offset: state.smoothed_offset,
+        }));
+
+        true
+    }
+
     pub fn get_filtertime(&self) -> Option<NtpTimestamp> {
         match &self.0 {
             PeerStateInner::Initial(_) => None,
@@ -578,6 +786,224 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_averaging_buffer_variance() {
+        let mut buffer = AveragingBuffer::default();
+        for rtt in [1., 2., 3., 4., 5., 6., 7., 8.] {
+            buffer.update(rtt);
+        }
+        assert_eq!(buffer.next_idx, 0);
+        assert!((buffer.mean() - 4.5).abs() < 1e-12);
+        assert!((buffer.variance() - 6.0).abs() < 1e-12);
+
+        // The newest sample lands in the middle of the ring, replacing the
+        // oldest one, and is included exactly once
+        buffer.update(1.);
+        buffer.update(2.);
+        buffer.update(3.);
+        buffer.update(0.);
+        assert_eq!(buffer.next_idx, 4);
+        let expected = AveragingBuffer {
+            data: [0., 1., 2., 3., 5., 6., 7., 8.],
+            next_idx: 0,
+        };
+        assert!((buffer.mean() - expected.mean()).abs() < 1e-12);
+        assert!((buffer.variance() - expected.variance()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_minimum_dispersion() {
+        let base = NtpTimestamp::from_fixed_int(0);
+        let basei = NtpInstant::now();
+
+        let measurement = Measurement {
+            delay: NtpDuration::ZERO,
+            offset: NtpDuration::ZERO,
+            transmit_timestamp: Default::default(),
+            receive_timestamp: Default::default(),
+            localtime: base,
+            monotime: basei,
+
+            stratum: 0,
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        };
+
+        // A perfectly stable, zero delay peer
+        let mut filter = PeerFilter {
+            state: Vector::new_vector([0., 0.]),
+            uncertainty: Matrix::new([[1e-6, 0.], [0., 1e-8]]),
+            clock_wander: 1e-8,
+            roundtriptime_stats: AveragingBuffer {
+                data: [0.0; 8],
+                next_idx: 0,
+            },
+            precision_score: 0,
+            poll_score: 0,
+            desired_poll_interval: PollIntervalLimits::default().min,
+            last_measurement: measurement,
+            prev_was_outlier: false,
+            last_iter: base,
+            filter_time: base,
+            smoothed_offset: None,
+        };
+
+        assert!(filter.measurement_variance() >= sqr(NtpDuration::MIN_DISPERSION.to_seconds()));
+
+        let (_, weight, _) = filter.absorb_measurement(Measurement {
+            localtime: base + NtpDuration::from_seconds(1.0),
+            ..measurement
+        });
+        assert!(weight < 1.0);
+        assert!(filter.uncertainty.entry(0, 0) > 0.0);
+    }
+
+    #[test]
+    fn test_statistics() {
+        let base = NtpTimestamp::from_fixed_int(0);
+        let basei = NtpInstant::now();
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(0.0),
+            offset: NtpDuration::from_seconds(0e-3),
+            transmit_timestamp: Default::default(),
+            receive_timestamp: Default::default(),
+            localtime: base + NtpDuration::from_seconds(1000.0),
+            monotime: basei + std::time::Duration::from_secs(1000),
+
+            stratum: 0,
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        };
+
+        let mut peer = PeerState::new();
+        assert!(peer.statistics().is_none());
+
+        peer.update_self_using_measurement(
+            &SourceDefaultsConfig::default(),
+            &AlgorithmConfig::default(),
+            measurement,
+        );
+        let statistics = peer.statistics().unwrap();
+        assert_eq!(statistics, peer.snapshot(0_usize).unwrap().observe());
+
+        peer.update_self_using_measurement(
+            &SourceDefaultsConfig::default(),
+            &AlgorithmConfig::default(),
+            Measurement {
+                offset: NtpDuration::from_seconds(1e-3),
+                delay: NtpDuration::from_seconds(1e-3),
+                ..measurement
+            },
+        );
+        // The earlier copy is unaffected by the new measurement
+        assert_ne!(peer.statistics().unwrap(), statistics);
+        assert_eq!(statistics.offset, NtpDuration::from_seconds(0.0));
+    }
+
+    #[test]
+    fn test_delay_range() {
+        let base = NtpTimestamp::from_fixed_int(0);
+        let basei = NtpInstant::now();
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(0.0),
+            offset: NtpDuration::from_seconds(0e-3),
+            transmit_timestamp: Default::default(),
+            receive_timestamp: Default::default(),
+            localtime: base + NtpDuration::from_seconds(1000.0),
+            monotime: basei + std::time::Duration::from_secs(1000),
+
+            stratum: 0,
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        };
+
+        let mut peer = PeerState::new();
+        assert!(peer.delay_range().is_none());
+
+        for delay in [10e-3, 50e-3, 12e-3] {
+            peer.update_self_using_measurement(
+                &SourceDefaultsConfig::default(),
+                &AlgorithmConfig::default(),
+                Measurement {
+                    delay: NtpDuration::from_seconds(delay),
+                    ..measurement
+                },
+            );
+        }
+
+        let (min, max) = peer.delay_range().unwrap();
+        assert!((min.to_seconds() - 10e-3).abs() < 1e-9);
+        assert!((max.to_seconds() - 50e-3).abs() < 1e-9);
+        assert!(((max - min).to_seconds() - 40e-3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_discard_initial_samples() {
+        let base = NtpTimestamp::from_fixed_int(0);
+        let basei = NtpInstant::now();
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(0.0),
+            offset: NtpDuration::from_seconds(0e-3),
+            transmit_timestamp: Default::default(),
+            receive_timestamp: Default::default(),
+            localtime: base + NtpDuration::from_seconds(1000.0),
+            monotime: basei + std::time::Duration::from_secs(1000),
+
+            stratum: 0,
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        };
+        let algo_config = AlgorithmConfig {
+            discard_initial_samples: 2,
+            ..Default::default()
+        };
+
+        let mut peer = PeerState::new();
+
+        // The first two measurements are skewed, and not used for the offset
+        for delay in [80e-3, 60e-3] {
+            assert!(!peer.update_self_using_measurement(
+                &SourceDefaultsConfig::default(),
+                &algo_config,
+                Measurement {
+                    delay: NtpDuration::from_seconds(delay),
+                    offset: NtpDuration::from_seconds(0.5),
+                    ..measurement
+                },
+            ));
+            assert!(peer.snapshot(0_usize).is_none());
+        }
+
+        // But their round trip times are kept
+        let (min, max) = peer.delay_range().unwrap();
+        assert!((min.to_seconds() - 60e-3).abs() < 1e-9);
+        assert!((max.to_seconds() - 80e-3).abs() < 1e-9);
+
+        // Later measurements are used as usual
+        assert!(peer.update_self_using_measurement(
+            &SourceDefaultsConfig::default(),
+            &algo_config,
+            Measurement {
+                delay: NtpDuration::from_seconds(10e-3),
+                offset: NtpDuration::from_seconds(1e-3),
+                ..measurement
+            },
+        ));
+        let snapshot = peer.snapshot(0_usize).unwrap();
+        assert!((snapshot.state.ventry(0) - 1e-3).abs() < 1e-9);
+        assert!((snapshot.delay - 80e-3).abs() < 1e-9);
+        let (min, _) = peer.delay_range().unwrap();
+        assert!((min.to_seconds() - 10e-3).abs() < 1e-9);
+    }
+
     #[test]
     fn test_meddling_detection() {
         let base = NtpTimestamp::from_fixed_int(0);
@@ -611,6 +1037,7 @@ mod tests {
             prev_was_outlier: false,
             last_iter: base,
             filter_time: base,
+            smoothed_offset: None,
         }));
         peer.update_self_using_measurement(
             &SourceDefaultsConfig::default(),
@@ -660,6 +1087,7 @@ mod tests {
             prev_was_outlier: false,
             last_iter: base,
             filter_time: base,
+            smoothed_offset: None,
         }));
         peer.process_offset_steering(-1800.0);
         peer.update_self_using_measurement(
@@ -710,6 +1138,7 @@ mod tests {
             prev_was_outlier: false,
             last_iter: base,
             filter_time: base,
+            smoothed_offset: None,
         }));
         peer.process_offset_steering(1800.0);
         peer.update_self_using_measurement(
@@ -733,6 +1162,147 @@ mod tests {
         assert!(matches!(peer, PeerState(PeerStateInner::Stable(_))));
     }
 
+    #[test]
+    fn test_future_measurement() {
+        let base = NtpTimestamp::from_fixed_int(0);
+        let basei = NtpInstant::now();
+
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(0.0),
+            offset: NtpDuration::from_seconds(20e-3),
+            transmit_timestamp: Default::default(),
+            receive_timestamp: Default::default(),
+            localtime: base,
+            monotime: basei,
+
+            stratum: 0,
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        };
+        let mut peer = PeerState(PeerStateInner::Stable(PeerFilter {
+            state: Vector::new_vector([20e-3, 0.]),
+            uncertainty: Matrix::new([[1e-6, 0.], [0., 1e-8]]),
+            clock_wander: 1e-8,
+            roundtriptime_stats: AveragingBuffer {
+                data: [0.0, 0.0, 0.0, 0.0, 0.875e-6, 0.875e-6, 0.875e-6, 0.875e-6],
+                next_idx: 0,
+            },
+            precision_score: 0,
+            poll_score: 0,
+            desired_poll_interval: PollIntervalLimits::default().min,
+            last_measurement: measurement,
+            prev_was_outlier: false,
+            last_iter: base,
+            filter_time: base,
+            smoothed_offset: None,
+        }));
+        let algo_config = AlgorithmConfig {
+            future_measurement_threshold: Some(NtpDuration::from_seconds(3600.0)),
+            ..Default::default()
+        };
+
+        // A year ahead, while only a poll interval passed
+        assert!(!peer.update_self_using_measurement(
+            &SourceDefaultsConfig::default(),
+            &algo_config,
+            Measurement {
+                localtime: base + NtpDuration::from_seconds(365.0 * 86400.0),
+                monotime: basei + std::time::Duration::from_secs(16),
+                ..measurement
+            },
+        ));
+        assert_eq!(peer.get_filtertime(), Some(base));
+
+        // Regular measurements are still accepted afterwards
+        assert!(peer.update_self_using_measurement(
+            &SourceDefaultsConfig::default(),
+            &algo_config,
+            Measurement {
+                localtime: base + NtpDuration::from_seconds(1000.0),
+                monotime: basei + std::time::Duration::from_secs(1000),
+                ..measurement
+            },
+        ));
+        assert_eq!(
+            peer.get_filtertime(),
+            Some(base + NtpDuration::from_seconds(1000.0))
+        );
+    }
+
+    #[test]
+    fn test_export_restore() {
+        let base = NtpTimestamp::from_fixed_int(0);
+        let basei = NtpInstant::now();
+
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(0.0),
+            offset: NtpDuration::from_seconds(20e-3),
+            transmit_timestamp: Default::default(),
+            receive_timestamp: Default::default(),
+            localtime: base,
+            monotime: basei,
+
+            stratum: 0,
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        };
+        let peer = PeerState(PeerStateInner::Stable(PeerFilter {
+            state: Vector::new_vector([20e-3, 0.]),
+            uncertainty: Matrix::new([[1e-6, 0.], [0., 1e-8]]),
+            clock_wander: 1e-8,
+            roundtriptime_stats: AveragingBuffer {
+                data: [0.0, 0.0, 0.0, 0.0, 0.875e-6, 0.875e-6, 0.875e-6, 0.875e-6],
+                next_idx: 0,
+            },
+            precision_score: 0,
+            poll_score: 0,
+            desired_poll_interval: PollIntervalLimits::default().min,
+            last_measurement: measurement,
+            prev_was_outlier: false,
+            last_iter: base,
+            filter_time: base,
+            smoothed_offset: None,
+        }));
+        let max_age = NtpDuration::from_seconds(3600.0);
+
+        // A filter still in its initial phase has nothing worth saving
+        assert!(PeerState::new().export().is_none());
+
+        let state = peer.export().unwrap();
+        let now = base + NtpDuration::from_seconds(60.0);
+        let mut restored = PeerState::new();
+        assert!(restored.restore(state.clone(), now, max_age));
+        assert_eq!(restored.statistics(), peer.statistics());
+        assert_eq!(restored.get_filtertime(), Some(base));
+
+        // The next measurement of the new process is not mistaken for
+        // meddling with the clock
+        assert!(restored.update_self_using_measurement(
+            &SourceDefaultsConfig::default(),
+            &AlgorithmConfig::default(),
+            Measurement {
+                localtime: now + NtpDuration::from_seconds(16.0),
+                monotime: NtpInstant::now() + std::time::Duration::from_secs(16),
+                ..measurement
+            },
+        ));
+        assert!(matches!(restored, PeerState(PeerStateInner::Stable(_))));
+
+        // A state that is too old, or from the future, is ignored
+        let mut restored = PeerState::new();
+        assert!(!restored.restore(
+            state.clone(),
+            base + NtpDuration::from_seconds(7200.0),
+            max_age
+        ));
+        assert!(!restored.restore(state, base - NtpDuration::from_seconds(1.0), max_age));
+        assert!(restored.export().is_none());
+    }
+
     #[test]
     fn test_offset_steering_and_measurements() {
         let base = NtpTimestamp::from_fixed_int(0);
@@ -765,6 +1335,7 @@ mod tests {
             prev_was_outlier: false,
             last_iter: base,
             filter_time: base,
+            smoothed_offset: None,
         }));
 
         peer.process_offset_steering(20e-3);
@@ -803,6 +1374,7 @@ mod tests {
             prev_was_outlier: false,
             last_iter: base,
             filter_time: base,
+            smoothed_offset: None,
         }));
 
         peer.process_offset_steering(20e-3);
@@ -858,6 +1430,7 @@ mod tests {
             prev_was_outlier: false,
             last_iter: base,
             filter_time: base,
+            smoothed_offset: None,
         }));
 
         peer.process_offset_steering(-20e-3);
@@ -920,6 +1493,7 @@ mod tests {
             prev_was_outlier: false,
             last_iter: base,
             filter_time: base,
+            smoothed_offset: None,
         };
 
         peer.process_frequency_steering(base + NtpDuration::from_seconds(5.0), 200e-6);
@@ -959,6 +1533,7 @@ mod tests {
             prev_was_outlier: false,
             last_iter: base,
             filter_time: base,
+            smoothed_offset: None,
         }));
 
         peer.process_frequency_steering(base + NtpDuration::from_seconds(5.0), 200e-6);
@@ -1330,6 +1905,7 @@ mod tests {
             prev_was_outlier: false,
             last_iter: base,
             filter_time: base,
+            smoothed_offset: None,
         };
 
         let baseinterval = peer.desired_poll_interval.as_duration().to_seconds();
@@ -1419,6 +1995,142 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_poll_adapts_to_jitter() {
+        let config = SourceDefaultsConfig::default();
+        let algo_config = AlgorithmConfig {
+            poll_interval_hysteresis: 2,
+            ..Default::default()
+        };
+
+        let base = NtpTimestamp::from_fixed_int(0);
+        let basei = NtpInstant::now();
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(1e-3),
+            offset: NtpDuration::from_seconds(0.0),
+            transmit_timestamp: Default::default(),
+            receive_timestamp: Default::default(),
+            localtime: base,
+            monotime: basei,
+
+            stratum: 0,
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        };
+        let mut peer = PeerFilter {
+            state: Vector::new_vector([0.0, 0.]),
+            uncertainty: Matrix::new([[1e-6, 0.], [0., 1e-8]]),
+            clock_wander: 1e-16,
+            roundtriptime_stats: AveragingBuffer {
+                data: [
+                    0.9e-3, 1.1e-3, 0.9e-3, 1.1e-3, 0.9e-3, 1.1e-3, 0.9e-3, 1.1e-3,
+                ],
+                next_idx: 0,
+            },
+            precision_score: 0,
+            poll_score: 0,
+            desired_poll_interval: PollIntervalLimits::default().min,
+            last_measurement: measurement,
+            prev_was_outlier: false,
+            last_iter: base,
+            filter_time: base,
+            smoothed_offset: None,
+        };
+
+        // A stable peer gives us little new information per measurement, so we should poll less
+        let mut time = base;
+        for i in 0..16 {
+            time += peer.desired_poll_interval.as_duration();
+            let delay = if i % 2 == 0 { 0.9e-3 } else { 1.1e-3 };
+            peer.update(
+                &config,
+                &algo_config,
+                Measurement {
+                    delay: NtpDuration::from_seconds(delay),
+                    localtime: time,
+                    ..measurement
+                },
+            );
+        }
+        let stable_interval = peer.desired_poll_interval;
+        assert!(stable_interval > PollIntervalLimits::default().min);
+
+        // Jitter spikes in the offset should make us poll more often again
+        for i in 0..8 {
+            time += peer.desired_poll_interval.as_duration();
+            let offset = if i % 2 == 0 { 5e-3 } else { -5e-3 };
+            peer.update(
+                &config,
+                &algo_config,
+                Measurement {
+                    offset: NtpDuration::from_seconds(offset),
+                    localtime: time,
+                    ..measurement
+                },
+            );
+        }
+        assert!(peer.desired_poll_interval < stable_interval);
+    }
+
+    #[test]
+    fn test_era_rollover() {
+        // Just before the end of an ntp era
+        let base = NtpTimestamp::from_seconds_nanos_since_ntp_era(u32::MAX, 0);
+        let basei = NtpInstant::now();
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(1e-3),
+            offset: NtpDuration::from_seconds(0.0),
+            transmit_timestamp: Default::default(),
+            receive_timestamp: Default::default(),
+            localtime: base,
+            monotime: basei,
+
+            stratum: 0,
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        };
+        let mut peer = PeerFilter {
+            state: Vector::new_vector([0.0, 0.]),
+            uncertainty: Matrix::new([[1e-6, 0.], [0., 1e-8]]),
+            clock_wander: 1e-8,
+            roundtriptime_stats: AveragingBuffer {
+                data: [1e-3; 8],
+                next_idx: 0,
+            },
+            precision_score: 0,
+            poll_score: 0,
+            desired_poll_interval: PollIntervalLimits::default().min,
+            last_measurement: measurement,
+            prev_was_outlier: false,
+            last_iter: base,
+            filter_time: base,
+            smoothed_offset: None,
+        };
+
+        // Two seconds later, in the next era
+        let next = NtpTimestamp::from_seconds_nanos_since_ntp_era(1, 0);
+        assert!(base.is_before(next));
+        assert_eq!(next - base, NtpDuration::from_seconds(2.0));
+
+        assert!(peer.update(
+            &SourceDefaultsConfig::default(),
+            &AlgorithmConfig::default(),
+            Measurement {
+                localtime: next,
+                monotime: basei + std::time::Duration::from_secs(2),
+                ..measurement
+            },
+        ));
+        assert_eq!(peer.filter_time, next);
+        // Uncertainty only grew by two seconds of wander, not by ~136 years
+        assert!(peer.uncertainty.entry(0, 0) < 1e-6);
+        assert!(peer.uncertainty.entry(1, 1) < 1e-7);
+    }
+
     #[test]
     fn test_wander_estimation() {
         let algo_config = AlgorithmConfig {
@@ -1456,6 +2168,7 @@ mod tests {
             prev_was_outlier: false,
             last_iter: base,
             filter_time: base,
+            smoothed_offset: None,
         };
 
         peer.update_wander_estimate(&algo_config, 1.0, 0.0);
@@ -1494,4 +2207,27 @@ mod tests {
         assert_eq!(peer.precision_score, 0);
         assert!((peer.clock_wander - 1e-8).abs() < 1e-12);
     }
+
+    #[test]
+    fn test_offset_smoothing() {
+        fn variance(data: &[f64]) -> f64 {
+            let mean = data.iter().sum::<f64>() / data.len() as f64;
+            data.iter().map(|v| sqr(v - mean)).sum::<f64>() / data.len() as f64
+        }
+
+        let raw: Vec<f64> = (0..32)
+            .map(|i| if i % 2 == 0 { 0.01 } else { -0.01 })
+            .collect();
+
+        let mut smoothed = vec![];
+        let mut previous = None;
+        for offset in raw.iter().copied() {
+            let next = smooth_offset(previous, offset, 0.2);
+            smoothed.push(next);
+            previous = Some(next);
+        }
+
+        assert_eq!(smoothed[0], raw[0]);
+        assert!(variance(&smoothed) < variance(&raw));
+    }
 }
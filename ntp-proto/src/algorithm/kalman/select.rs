@@ -2,6 +2,67 @@ use crate::config::SynchronizationConfig;
 
 use super::{config::AlgorithmConfig, PeerSnapshot};
 
+/// Strategy for selecting the set of peers that agree well enough with each
+/// other to be combined into a single estimate of the time.
+pub trait ClockSelect {
+    fn select<Index: Copy>(
+        &self,
+        synchronization_config: &SynchronizationConfig,
+        algo_config: &AlgorithmConfig,
+        candidates: Vec<PeerSnapshot<Index>>,
+    ) -> SelectionOutcome<Index>;
+}
+
+/// Result of selecting peers. Having too few peers to synchronize with is
+/// distinguished from having peers that disagree on the time, as the latter
+/// may indicate that some of them are under attack.
+#[derive(Debug)]
+pub enum SelectionOutcome<Index: Copy> {
+    Selected(Vec<PeerSnapshot<Index>>),
+    /// There are fewer usable peers than needed to agree on the time
+    NoCandidates,
+    /// Enough peers are usable, but too few of them agree with each other
+    NoConsensus {
+        candidate_count: usize,
+    },
+}
+
+/// Why a candidate peer did or did not end up among the selected peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SurvivorVerdict {
+    /// The peer was selected
+    Survivor,
+    /// The peer is not synchronized, or its confidence interval is malformed
+    /// or wider than the maximum source uncertainty
+    Unusable,
+    /// The peer was left out because only the most precise candidates are
+    /// considered
+    NotConsidered,
+    /// There are fewer usable peers than needed to agree on the time
+    TooFewCandidates,
+    /// The confidence interval of the peer does not contain the offset most
+    /// peers agree on
+    OutsideInterval,
+    /// Too few peers agree with each other for any of them to be selected
+    NoConsensus,
+}
+
+/// The default selection strategy, selecting the largest set of peers with
+/// overlapping confidence intervals.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IntervalSelect;
+
+impl ClockSelect for IntervalSelect {
+    fn select<Index: Copy>(
+        &self,
+        synchronization_config: &SynchronizationConfig,
+        algo_config: &AlgorithmConfig,
+        candidates: Vec<PeerSnapshot<Index>>,
+    ) -> SelectionOutcome<Index> {
+        select(synchronization_config, algo_config, candidates)
+    }
+}
+
 enum BoundType {
     Start,
     End,
@@ -19,18 +80,95 @@ pub(super) fn select<Index: Copy>(
     synchronization_config: &SynchronizationConfig,
     algo_config: &AlgorithmConfig,
     candidates: Vec<PeerSnapshot<Index>>,
-) -> Vec<PeerSnapshot<Index>> {
-    let mut bounds: Vec<(f64, BoundType)> = Vec::with_capacity(2 * candidates.len());
+) -> SelectionOutcome<Index> {
+    select_with_verdicts(
+        synchronization_config,
+        algo_config,
+        candidates,
+        |snapshot| selection_radius(algo_config, snapshot),
+    )
+    .0
+}
 
-    for snapshot in candidates.iter() {
-        let radius = snapshot.offset_uncertainty() * algo_config.range_statistical_weight
-            + snapshot.delay * algo_config.range_delay_weight;
-        if radius > algo_config.maximum_source_uncertainty
-            || !snapshot.leap_indicator.is_synchronized()
-        {
-            continue;
+/// Explain for every candidate why it was or wasn't selected by [`select`]
+pub(super) fn audit<Index: Copy>(
+    synchronization_config: &SynchronizationConfig,
+    algo_config: &AlgorithmConfig,
+    candidates: Vec<PeerSnapshot<Index>>,
+) -> Vec<(Index, SurvivorVerdict)> {
+    select_with_verdicts(
+        synchronization_config,
+        algo_config,
+        candidates,
+        |snapshot| selection_radius(algo_config, snapshot),
+    )
+    .1
+}
+
+/// Radius of the confidence interval of a candidate
+fn selection_radius<Index: Copy>(
+    algo_config: &AlgorithmConfig,
+    snapshot: &PeerSnapshot<Index>,
+) -> f64 {
+    snapshot.offset_uncertainty() * algo_config.range_statistical_weight
+        + snapshot.delay * algo_config.range_delay_weight
+}
+
+fn select_with_verdicts<Index: Copy>(
+    synchronization_config: &SynchronizationConfig,
+    algo_config: &AlgorithmConfig,
+    candidates: Vec<PeerSnapshot<Index>>,
+    mut radius_of: impl FnMut(&PeerSnapshot<Index>) -> f64,
+) -> (SelectionOutcome<Index>, Vec<(Index, SurvivorVerdict)>) {
+    let mut verdicts = Vec::with_capacity(candidates.len());
+
+    // The radius of each candidate's confidence interval, computed once and
+    // reused for both finding the overlap and filtering the candidates.
+    let mut candidates: Vec<(PeerSnapshot<Index>, f64)> = candidates
+        .into_iter()
+        .filter_map(|snapshot| {
+            let radius = radius_of(&snapshot);
+            // A malformed snapshot (e.g. with a negative delay) yields an inverted
+            // interval whose end precedes its start, which would corrupt the
+            // overlap count below.
+            if radius.is_nan()
+                || radius < 0.0
+                || radius > algo_config.maximum_source_uncertainty
+                || !snapshot.leap_indicator.is_synchronized()
+            {
+                verdicts.push((snapshot.index, SurvivorVerdict::Unusable));
+                None
+            } else {
+                Some((snapshot, radius))
+            }
+        })
+        .collect();
+
+    // Bound the cost of selection with very many peers by only considering
+    // the most precise ones
+    if let Some(max_candidates) = algo_config.max_candidate_peers {
+        if candidates.len() > max_candidates {
+            candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+            verdicts.extend(
+                candidates
+                    .drain(max_candidates..)
+                    .map(|(snapshot, _)| (snapshot.index, SurvivorVerdict::NotConsidered)),
+            );
         }
+    }
 
+    if candidates.is_empty() || candidates.len() < synchronization_config.minimum_agreeing_sources {
+        verdicts.extend(
+            candidates
+                .iter()
+                .map(|(snapshot, _)| (snapshot.index, SurvivorVerdict::TooFewCandidates)),
+        );
+        return (SelectionOutcome::NoCandidates, verdicts);
+    }
+
+    let mut bounds: Vec<(f64, BoundType)> = Vec::with_capacity(2 * candidates.len());
+
+    for (snapshot, radius) in candidates.iter() {
         bounds.push((snapshot.offset() - radius, BoundType::Start));
         bounds.push((snapshot.offset() + radius, BoundType::End));
     }
@@ -53,20 +191,28 @@ pub(super) fn select<Index: Copy>(
     }
 
     if max >= synchronization_config.minimum_agreeing_sources && max * 4 > bounds.len() {
-        candidates
-            .iter()
-            .filter(|snapshot| {
-                let radius = snapshot.offset_uncertainty() * algo_config.range_statistical_weight
-                    + snapshot.delay * algo_config.range_delay_weight;
-                radius <= algo_config.maximum_source_uncertainty
-                    && snapshot.offset() - radius <= maxt
-                    && snapshot.offset() + radius >= maxt
-                    && snapshot.leap_indicator.is_synchronized()
-            })
-            .cloned()
-            .collect()
+        let mut selection = Vec::with_capacity(max);
+        for (snapshot, radius) in candidates {
+            if snapshot.offset() - radius <= maxt && snapshot.offset() + radius >= maxt {
+                verdicts.push((snapshot.index, SurvivorVerdict::Survivor));
+                selection.push(snapshot);
+            } else {
+                verdicts.push((snapshot.index, SurvivorVerdict::OutsideInterval));
+            }
+        }
+        (SelectionOutcome::Selected(selection), verdicts)
     } else {
-        vec![]
+        verdicts.extend(
+            candidates
+                .iter()
+                .map(|(snapshot, _)| (snapshot.index, SurvivorVerdict::NoConsensus)),
+        );
+        (
+            SelectionOutcome::NoConsensus {
+                candidate_count: candidates.len(),
+            },
+            verdicts,
+        )
     }
 }
 
@@ -84,6 +230,30 @@ mod tests {
 
     use super::*;
 
+    impl<Index: Copy> SelectionOutcome<Index> {
+        fn into_selection(self) -> Vec<PeerSnapshot<Index>> {
+            match self {
+                SelectionOutcome::Selected(selection) => selection,
+                SelectionOutcome::NoCandidates | SelectionOutcome::NoConsensus { .. } => vec![],
+            }
+        }
+    }
+
+    struct MedianSelect;
+
+    impl ClockSelect for MedianSelect {
+        fn select<Index: Copy>(
+            &self,
+            _synchronization_config: &SynchronizationConfig,
+            _algo_config: &AlgorithmConfig,
+            mut candidates: Vec<PeerSnapshot<Index>>,
+        ) -> SelectionOutcome<Index> {
+            candidates.sort_by(|a, b| a.offset().total_cmp(&b.offset()));
+            let median = candidates.len() / 2;
+            SelectionOutcome::Selected(candidates.into_iter().skip(median).take(1).collect())
+        }
+    }
+
     fn snapshot_for_range(center: f64, uncertainty: f64, delay: f64) -> PeerSnapshot<usize> {
         PeerSnapshot {
             index: 0,
@@ -93,6 +263,7 @@ mod tests {
             peer_uncertainty: NtpDuration::from_seconds(0.01),
             peer_delay: NtpDuration::from_seconds(0.01),
             leap_indicator: NtpLeapIndicator::NoWarning,
+            prefer: false,
             last_update: NtpTimestamp::from_fixed_int(0),
         }
     }
@@ -119,7 +290,7 @@ mod tests {
             ..Default::default()
         };
 
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let result = select(&sysconfig, &algconfig, candidates.clone()).into_selection();
         assert_eq!(result.len(), 0);
 
         let algconfig = AlgorithmConfig {
@@ -128,7 +299,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let result = select(&sysconfig, &algconfig, candidates.clone()).into_selection();
         assert_eq!(result.len(), 0);
 
         let algconfig = AlgorithmConfig {
@@ -137,7 +308,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates);
+        let result = select(&sysconfig, &algconfig, candidates).into_selection();
         assert_eq!(result.len(), 4);
     }
 
@@ -160,7 +331,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let result = select(&sysconfig, &algconfig, candidates.clone()).into_selection();
         assert_eq!(result.len(), 3);
 
         let algconfig = AlgorithmConfig {
@@ -169,7 +340,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let result = select(&sysconfig, &algconfig, candidates.clone()).into_selection();
         assert_eq!(result.len(), 2);
 
         let algconfig = AlgorithmConfig {
@@ -178,7 +349,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let result = select(&sysconfig, &algconfig, candidates.clone()).into_selection();
         assert_eq!(result.len(), 1);
 
         let algconfig = AlgorithmConfig {
@@ -187,10 +358,39 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates);
+        let result = select(&sysconfig, &algconfig, candidates).into_selection();
         assert_eq!(result.len(), 0);
     }
 
+    #[test]
+    fn test_inverted_interval() {
+        // A negative delay gives an interval with its end before its start
+        let candidates = vec![
+            snapshot_for_range(0.0, 0.01, -1.0),
+            snapshot_for_range(0.0, 0.01, 0.01),
+            snapshot_for_range(0.0, 0.01, 0.01),
+        ];
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..Default::default()
+        };
+        let algconfig = AlgorithmConfig {
+            maximum_source_uncertainty: 3.0,
+            range_statistical_weight: 1.0,
+            range_delay_weight: 1.0,
+            ..Default::default()
+        };
+
+        let result = select(&sysconfig, &algconfig, candidates).into_selection();
+        assert_eq!(result.len(), 2);
+        assert!(result.iter().all(|snapshot| snapshot.delay >= 0.0));
+
+        // On its own, it never produces a selection
+        let candidates = vec![snapshot_for_range(0.0, 0.01, -1.0)];
+        let result = select(&sysconfig, &algconfig, candidates).into_selection();
+        assert!(result.is_empty());
+    }
+
     #[test]
     fn test_min_survivors() {
         // Test that minimum number of survivors is correctly tested for.
@@ -212,14 +412,14 @@ mod tests {
             minimum_agreeing_sources: 3,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let result = select(&sysconfig, &algconfig, candidates.clone()).into_selection();
         assert_eq!(result.len(), 3);
 
         let sysconfig = SynchronizationConfig {
             minimum_agreeing_sources: 4,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates);
+        let result = select(&sysconfig, &algconfig, candidates).into_selection();
         assert_eq!(result.len(), 0);
     }
 
@@ -242,7 +442,208 @@ mod tests {
             minimum_agreeing_sources: 1,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates);
+        let result = select(&sysconfig, &algconfig, candidates).into_selection();
         assert_eq!(result.len(), 0);
     }
+
+    #[test]
+    fn test_no_consensus() {
+        // Three peers that are all reachable, but disagree with each other
+        let candidates = vec![
+            snapshot_for_range(0.0, 0.1, 0.1),
+            snapshot_for_range(0.5, 0.1, 0.1),
+            snapshot_for_range(1.0, 0.1, 0.1),
+        ];
+        let algconfig = AlgorithmConfig {
+            maximum_source_uncertainty: 3.0,
+            range_statistical_weight: 1.0,
+            range_delay_weight: 1.0,
+            ..Default::default()
+        };
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..Default::default()
+        };
+        let result = select(&sysconfig, &algconfig, candidates);
+        assert!(matches!(
+            result,
+            SelectionOutcome::NoConsensus { candidate_count: 3 }
+        ));
+
+        let result = select::<usize>(&sysconfig, &algconfig, vec![]);
+        assert!(matches!(result, SelectionOutcome::NoCandidates));
+
+        // Too few peers to ever reach the minimum is not a disagreement
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 2,
+            ..Default::default()
+        };
+        let candidates = vec![snapshot_for_range(0.0, 0.1, 0.1)];
+        let result = select(&sysconfig, &algconfig, candidates);
+        assert!(matches!(result, SelectionOutcome::NoCandidates));
+    }
+
+    #[test]
+    fn test_audit_outlier() {
+        // Three peers agree, the fourth is far off
+        let candidates: Vec<_> = [0.0, 0.01, -0.01, 1.0]
+            .into_iter()
+            .enumerate()
+            .map(|(index, offset)| PeerSnapshot {
+                index,
+                ..snapshot_for_range(offset, 0.05, 0.05)
+            })
+            .collect();
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 3,
+            ..Default::default()
+        };
+        let algconfig = AlgorithmConfig {
+            maximum_source_uncertainty: 3.0,
+            range_statistical_weight: 1.0,
+            range_delay_weight: 1.0,
+            ..Default::default()
+        };
+
+        let mut verdicts = audit(&sysconfig, &algconfig, candidates.clone());
+        verdicts.sort_by_key(|(index, _)| *index);
+        assert_eq!(
+            verdicts,
+            vec![
+                (0, SurvivorVerdict::Survivor),
+                (1, SurvivorVerdict::Survivor),
+                (2, SurvivorVerdict::Survivor),
+                (3, SurvivorVerdict::OutsideInterval),
+            ]
+        );
+
+        // The audit matches the selection
+        let mut selected: Vec<_> = select(&sysconfig, &algconfig, candidates.clone())
+            .into_selection()
+            .iter()
+            .map(|snapshot| snapshot.index)
+            .collect();
+        selected.sort();
+        assert_eq!(selected, vec![0, 1, 2]);
+
+        // Peers that are unusable on their own are reported as such
+        let mut candidates = candidates;
+        candidates[1].leap_indicator = NtpLeapIndicator::Unknown;
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 4,
+            ..Default::default()
+        };
+        let mut verdicts = audit(&sysconfig, &algconfig, candidates);
+        verdicts.sort_by_key(|(index, _)| *index);
+        assert_eq!(
+            verdicts,
+            vec![
+                (0, SurvivorVerdict::TooFewCandidates),
+                (1, SurvivorVerdict::Unusable),
+                (2, SurvivorVerdict::TooFewCandidates),
+                (3, SurvivorVerdict::TooFewCandidates),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_candidate_peers() {
+        // All peers agree, but the higher the index, the less precise
+        let candidates: Vec<_> = (0..100)
+            .map(|index| PeerSnapshot {
+                index,
+                ..snapshot_for_range(0.0, 0.01 + 0.001 * index as f64, 0.01)
+            })
+            .collect();
+        let sysconfig = SynchronizationConfig::default();
+        let algconfig = AlgorithmConfig {
+            maximum_source_uncertainty: 3.0,
+            ..Default::default()
+        };
+        let result = select(&sysconfig, &algconfig, candidates.clone()).into_selection();
+        assert_eq!(result.len(), 100);
+
+        let algconfig = AlgorithmConfig {
+            maximum_source_uncertainty: 3.0,
+            max_candidate_peers: Some(10),
+            ..Default::default()
+        };
+        // The order in which the peers are offered does not matter
+        let mut reversed = candidates;
+        reversed.reverse();
+        let result = select(&sysconfig, &algconfig, reversed).into_selection();
+        let mut indices: Vec<_> = result.iter().map(|snapshot| snapshot.index).collect();
+        indices.sort();
+        assert_eq!(indices, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_radius_computed_once() {
+        let candidates: Vec<_> = [0.0, 0.01, 0.02, 5.0, 0.03]
+            .into_iter()
+            .enumerate()
+            .map(|(index, offset)| PeerSnapshot {
+                index,
+                ..snapshot_for_range(offset, 0.01, 0.01)
+            })
+            .collect();
+        let sysconfig = SynchronizationConfig::default();
+        let algconfig = AlgorithmConfig {
+            max_candidate_peers: Some(4),
+            ..Default::default()
+        };
+
+        let mut computed = vec![0; candidates.len()];
+        let (outcome, verdicts) =
+            select_with_verdicts(&sysconfig, &algconfig, candidates.clone(), |snapshot| {
+                computed[snapshot.index] += 1;
+                selection_radius(&algconfig, snapshot)
+            });
+
+        // Every peer is considered exactly once, whatever its verdict
+        assert_eq!(computed, vec![1; candidates.len()]);
+        assert_eq!(verdicts.len(), candidates.len());
+        assert_eq!(
+            outcome
+                .into_selection()
+                .iter()
+                .map(|s| s.index)
+                .collect::<Vec<_>>(),
+            select(&sysconfig, &algconfig, candidates)
+                .into_selection()
+                .iter()
+                .map(|s| s.index)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_custom_select() {
+        let candidates: Vec<_> = [0.3, -0.1, 0.2, 0.0, 0.1]
+            .into_iter()
+            .enumerate()
+            .map(|(index, offset)| PeerSnapshot {
+                index,
+                ..snapshot_for_range(offset, 0.01, 0.01)
+            })
+            .collect();
+        let sysconfig = SynchronizationConfig::default();
+        let algconfig = AlgorithmConfig::default();
+
+        let result = MedianSelect
+            .select(&sysconfig, &algconfig, candidates.clone())
+            .into_selection();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].index, 4);
+
+        // The default strategy behaves like plain selection
+        let default = IntervalSelect
+            .select(&sysconfig, &algconfig, candidates.clone())
+            .into_selection();
+        let plain = select(&sysconfig, &algconfig, candidates).into_selection();
+        assert_eq!(
+            default.iter().map(|s| s.index).collect::<Vec<_>>(),
+            plain.iter().map(|s| s.index).collect::<Vec<_>>()
+        );
+    }
 }
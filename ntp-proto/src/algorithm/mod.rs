@@ -10,10 +10,17 @@ use crate::{
     time_types::{NtpDuration, NtpTimestamp},
 };
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
 pub struct ObservablePeerTimedata {
     pub offset: NtpDuration,
+    /// Moving average of the offset, when offset smoothing is enabled
+    #[serde(default)]
+    pub smoothed_offset: Option<NtpDuration>,
     pub uncertainty: NtpDuration,
+    /// Standard deviation of the offset of a single measurement, as estimated
+    /// by the filter once it has settled
+    #[serde(default)]
+    pub jitter: Option<NtpDuration>,
     pub delay: NtpDuration,
 
     pub remote_delay: NtpDuration,
@@ -66,6 +73,10 @@ pub trait TimeSyncController<C: NtpClock, PeerID: Hash + Eq + Copy + Debug>: Siz
     fn peer_add(&mut self, id: PeerID);
     /// Notify the controller that a previous peer has gone
     fn peer_remove(&mut self, id: PeerID);
+    /// Notify the controller whether a peer is preferred. A preferred peer
+    /// that is selected determines the time on its own. Controllers without
+    /// a notion of preferred peers can ignore this.
+    fn peer_prefer(&mut self, _id: PeerID, _prefer: bool) {}
     /// Notify the controller that the status of a peer (whether
     /// or not it is usable for synchronization) has changed.
     fn peer_update(&mut self, id: PeerID, usable: bool);
@@ -82,4 +93,7 @@ pub trait TimeSyncController<C: NtpClock, PeerID: Hash + Eq + Copy + Debug>: Siz
 mod kalman;
 
 pub use kalman::config::AlgorithmConfig;
-pub use kalman::KalmanClockController;
+pub use kalman::{
+    ClockSelect, IntervalSelect, KalmanClockController, PeerSnapshot as KalmanPeerSnapshot,
+    SavedFilterState, SelectionOutcome, SteerAction, SurvivorVerdict, SystemUpdate,
+};
@@ -37,3 +37,105 @@ pub trait NtpClock: Clone + Send + 'static {
     // the clocks synchronization status.
     fn status_update(&self, leap_status: NtpLeapIndicator) -> Result<(), Self::Error>;
 }
+
+/// Number of clock reads used to estimate the precision of a clock
+const PRECISION_MEASUREMENT_READS: usize = 128;
+
+/// Estimate the precision of a clock by reading it in a tight loop and taking
+/// the smallest nonzero difference between consecutive reads.
+///
+/// The result is the log2 of that difference in seconds, rounded up, as used
+/// in the precision field of NTP packets. It can be turned into the system
+/// precision with [`NtpDuration::from_exponent`]. Returns `None` if the clock
+/// never advanced during the measurement.
+pub fn measure_system_precision<C: NtpClock>(clock: &C) -> Result<Option<i8>, C::Error> {
+    let mut previous = clock.now()?;
+    let mut smallest_step: Option<NtpDuration> = None;
+
+    for _ in 0..PRECISION_MEASUREMENT_READS {
+        let current = clock.now()?;
+        let step = current - previous;
+        if step > NtpDuration::ZERO {
+            smallest_step = Some(smallest_step.map_or(step, |smallest| smallest.min(step)));
+        }
+        previous = current;
+    }
+
+    Ok(smallest_step.map(|step| {
+        let log = step.log2();
+        if NtpDuration::from_exponent(log) < step {
+            log.saturating_add(1)
+        } else {
+            log
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    struct SteppingClock {
+        current: Cell<NtpTimestamp>,
+        step: NtpDuration,
+    }
+
+    impl NtpClock for SteppingClock {
+        type Error = std::io::Error;
+
+        fn now(&self) -> Result<NtpTimestamp, Self::Error> {
+            let now = self.current.get();
+            self.current.set(now + self.step);
+            Ok(now)
+        }
+
+        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by measure_system_precision");
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by measure_system_precision");
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by measure_system_precision");
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by measure_system_precision");
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by measure_system_precision");
+        }
+    }
+
+    #[test]
+    fn test_measure_system_precision() {
+        let clock = SteppingClock {
+            current: Cell::new(NtpTimestamp::default()),
+            step: NtpDuration::from_exponent(-20),
+        };
+        assert_eq!(measure_system_precision(&clock).unwrap(), Some(-20));
+
+        // a granularity of 1 microsecond lies between 2^-20 and 2^-19
+        let clock = SteppingClock {
+            current: Cell::new(NtpTimestamp::default()),
+            step: NtpDuration::from_seconds(1e-6),
+        };
+        assert_eq!(measure_system_precision(&clock).unwrap(), Some(-19));
+
+        let clock = SteppingClock {
+            current: Cell::new(NtpTimestamp::default()),
+            step: NtpDuration::ZERO,
+        };
+        assert_eq!(measure_system_precision(&clock).unwrap(), None);
+    }
+}
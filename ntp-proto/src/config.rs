@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{fmt, net::IpAddr, time::Duration};
 
 use serde::{
     de::{self, MapAccess, Unexpected, Visitor},
@@ -208,6 +208,89 @@ pub struct SourceDefaultsConfig {
     /// Initial poll interval of the system
     #[serde(default = "default_initial_poll_interval")]
     pub initial_poll_interval: PollInterval,
+
+    /// Number of recent offset samples kept per source for monitoring.
+    /// Setting this to zero disables keeping a history.
+    #[serde(default = "default_offset_history_length")]
+    pub offset_history_length: usize,
+
+    /// Number of recent poll interval changes kept per source for debugging.
+    /// Setting this to zero disables keeping a history.
+    #[serde(default)]
+    pub poll_interval_history_length: usize,
+
+    /// How long to wait for a response to a poll before reporting it as
+    /// missed
+    #[serde(
+        default = "default_response_timeout",
+        deserialize_with = "deserialize_response_timeout"
+    )]
+    pub response_timeout: Duration,
+
+    /// How long to wait before retrying a source that asked us to stop
+    /// polling it with a RSTR Kiss-o'-Death
+    #[serde(
+        default = "default_restrict_cooldown",
+        deserialize_with = "deserialize_restrict_cooldown"
+    )]
+    pub restrict_cooldown: Duration,
+
+    /// Maximum number of polls per second sent to all sources combined, or
+    /// no limit when not set
+    #[serde(default, deserialize_with = "deserialize_poll_rate_limit")]
+    pub poll_rate_limit: Option<f64>,
+
+    /// Accept responses from the source's address regardless of their source
+    /// port, for networks that rewrite it.
+    #[serde(default)]
+    pub accept_any_response_port: bool,
+
+    /// Whether to lengthen each poll interval by a small random amount, to
+    /// make polls harder to predict
+    #[serde(default = "default_randomize_poll")]
+    pub randomize_poll: bool,
+
+    /// Minimum time between two polls, however they were triggered, except
+    /// for the packets of a burst. The minimum poll interval when not set.
+    #[serde(default)]
+    pub min_poll_gap: Option<PollInterval>,
+
+    /// Whether to resend an unanswered poll once shortly after sending it,
+    /// until the source has responded for the first time
+    #[serde(default = "default_retry_first_poll")]
+    pub retry_first_poll: bool,
+
+    /// Number of packets sent in each poll, one disables bursting
+    #[serde(default = "default_burst_count")]
+    pub burst_count: u8,
+
+    /// Interval between the packets of a burst
+    #[serde(default = "default_burst_interval")]
+    pub burst_interval: PollInterval,
+
+    /// Maximum time since a source's clock was last synchronized, as reported
+    /// by its reference timestamp, or no limit when not set
+    #[serde(default)]
+    pub max_reference_age: Option<NtpDuration>,
+
+    /// How far a source's reported reference timestamp may lie ahead of the
+    /// time we received its response, or no check when not set
+    #[serde(default)]
+    pub future_reference_tolerance: Option<NtpDuration>,
+
+    /// Responses with a root dispersion above this are ignored
+    #[serde(default = "default_reject_dispersion_above")]
+    pub reject_dispersion_above: NtpDuration,
+
+    /// Precision (log2 seconds) to advertise in outgoing polls, or the
+    /// system precision when not set
+    #[serde(default, deserialize_with = "deserialize_advertised_precision")]
+    pub advertised_precision: Option<i8>,
+
+    /// Whether to ask sources for interleaved responses, which carry the more
+    /// accurate transmit timestamp of the previous response
+    #[serde(default)]
+    pub interleaved: bool,
 }
 
 impl Default for SourceDefaultsConfig {
@@ -215,6 +298,22 @@ impl Default for SourceDefaultsConfig {
         Self {
             poll_interval_limits: Default::default(),
             initial_poll_interval: default_initial_poll_interval(),
+            offset_history_length: default_offset_history_length(),
+            poll_interval_history_length: 0,
+            response_timeout: default_response_timeout(),
+            restrict_cooldown: default_restrict_cooldown(),
+            poll_rate_limit: None,
+            accept_any_response_port: false,
+            randomize_poll: default_randomize_poll(),
+            min_poll_gap: None,
+            retry_first_poll: default_retry_first_poll(),
+            burst_count: default_burst_count(),
+            burst_interval: default_burst_interval(),
+            max_reference_age: None,
+            future_reference_tolerance: None,
+            reject_dispersion_above: default_reject_dispersion_above(),
+            advertised_precision: None,
+            interleaved: false,
         }
     }
 }
@@ -223,7 +322,85 @@ fn default_initial_poll_interval() -> PollInterval {
     PollIntervalLimits::default().min
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
+fn default_offset_history_length() -> usize {
+    8
+}
+
+fn default_burst_count() -> u8 {
+    1
+}
+
+fn default_burst_interval() -> PollInterval {
+    PollInterval::from_byte(1)
+}
+
+fn default_randomize_poll() -> bool {
+    true
+}
+
+fn default_retry_first_poll() -> bool {
+    true
+}
+
+fn default_reject_dispersion_above() -> NtpDuration {
+    NtpDuration::MAX_DISPERSION
+}
+
+fn default_response_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_restrict_cooldown() -> Duration {
+    Duration::from_secs(900)
+}
+
+fn deserialize_restrict_cooldown<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    Ok(Duration::from_secs(u64::deserialize(deserializer)?))
+}
+
+fn deserialize_advertised_precision<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<i8>, D::Error> {
+    let precision = i8::deserialize(deserializer)?;
+    // Packets with a precision outside this range are rejected as malformed
+    if !(-32..=0).contains(&precision) {
+        return Err(de::Error::invalid_value(
+            Unexpected::Signed(precision.into()),
+            &"a precision between -32 and 0",
+        ));
+    }
+    Ok(Some(precision))
+}
+
+fn deserialize_poll_rate_limit<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    let rate = f64::deserialize(deserializer)?;
+    // a rate of zero or less would silently mean no polls at all
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(de::Error::invalid_value(
+            Unexpected::Float(rate),
+            &"a positive number of polls per second",
+        ));
+    }
+    Ok(Some(rate))
+}
+
+fn deserialize_response_timeout<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    let seconds = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(seconds).map_err(|_| {
+        de::Error::invalid_value(
+            Unexpected::Float(seconds),
+            &"a non-negative number of seconds",
+        )
+    })
+}
+
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct SynchronizationConfig {
     /// Minimum number of survivors needed to be able to discipline the system clock.
@@ -267,6 +444,31 @@ pub struct SynchronizationConfig {
     #[serde(default = "default_local_stratum")]
     pub local_stratum: u8,
 
+    /// Highest stratum of sources we are willing to synchronize to
+    #[serde(default = "default_max_acceptable_stratum")]
+    pub max_acceptable_stratum: u8,
+
+    /// Highest jitter of sources we are willing to synchronize to, or no
+    /// limit when not set
+    #[serde(default)]
+    pub max_peer_jitter: Option<NtpDuration>,
+
+    /// Addresses of servers that synchronize to us, directly or through other
+    /// servers. Sources using one of them as their reference are rejected.
+    #[serde(default)]
+    pub downstream_servers: Vec<IpAddr>,
+
+    /// Stratum advertised while not synchronized to any source, at which
+    /// servers on an isolated network agree on a common time (orphan mode).
+    /// Orphan mode is disabled when not set.
+    #[serde(default)]
+    pub orphan_stratum: Option<u8>,
+
+    /// Whether to measure the precision of the system clock at startup,
+    /// instead of assuming a precision of 2^-18 seconds
+    #[serde(default)]
+    pub measure_precision: bool,
+
     #[serde(default)]
     pub algorithm: AlgorithmConfig,
 }
@@ -281,6 +483,11 @@ impl Default for SynchronizationConfig {
             accumulated_step_panic_threshold: None,
 
             local_stratum: default_local_stratum(),
+            max_acceptable_stratum: default_max_acceptable_stratum(),
+            max_peer_jitter: None,
+            downstream_servers: vec![],
+            orphan_stratum: None,
+            measure_precision: false,
             algorithm: Default::default(),
         }
     }
@@ -306,6 +513,11 @@ fn default_startup_step_panic_threshold() -> StepThreshold {
     }
 }
 
+fn default_max_acceptable_stratum() -> u8 {
+    // MAX_STRATUM is reserved for unsynchronized servers
+    15
+}
+
 fn default_local_stratum() -> u8 {
     16
 }
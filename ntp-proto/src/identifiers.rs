@@ -3,7 +3,7 @@ use std::net::IpAddr;
 use md5::{Digest, Md5};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct ReferenceId(u32);
 
 impl ReferenceId {
@@ -45,11 +45,19 @@ impl ReferenceId {
         *self == Self::KISS_NTSN
     }
 
+    /// Whether the reference id was left zero, which servers should not do
+    /// unless they are unsynchronized
+    pub(crate) fn is_unset(&self) -> bool {
+        self.0 == 0
+    }
+
     pub(crate) fn to_bytes(self) -> [u8; 4] {
         self.0.to_be_bytes()
     }
 
-    pub(crate) fn from_bytes(bits: [u8; 4]) -> ReferenceId {
+    /// Reference id from its four bytes, such as the ASCII name of a
+    /// reference clock like `*b"GPS\0"`
+    pub fn from_bytes(bits: [u8; 4]) -> ReferenceId {
         ReferenceId(u32::from_be_bytes(bits))
     }
 }
@@ -38,10 +38,11 @@ pub(crate) mod exitcode {
 
 mod exports {
     pub use super::algorithm::{
-        AlgorithmConfig, KalmanClockController, ObservablePeerTimedata, StateUpdate,
-        TimeSyncController,
+        AlgorithmConfig, ClockSelect, IntervalSelect, KalmanClockController, KalmanPeerSnapshot,
+        ObservablePeerTimedata, SavedFilterState, SelectionOutcome, StateUpdate, SteerAction,
+        SurvivorVerdict, SystemUpdate, TimeSyncController,
     };
-    pub use super::clock::NtpClock;
+    pub use super::clock::{measure_system_precision, NtpClock};
     pub use super::config::{SourceDefaultsConfig, StepThreshold, SynchronizationConfig};
     pub use super::identifiers::ReferenceId;
     #[cfg(feature = "__internal-fuzz")]
@@ -53,16 +54,19 @@ mod exports {
     #[cfg(feature = "__internal-fuzz")]
     pub use super::packet::ExtensionField;
     pub use super::packet::{
-        Cipher, CipherProvider, EncryptResult, ExtensionHeaderVersion, NoCipher,
-        NtpAssociationMode, NtpLeapIndicator, NtpPacket, PacketParsingError,
+        parse_read_variables_response, serialize_read_variables_request, Cipher, CipherProvider,
+        ControlMessageError, EncryptResult, ExtensionHeaderVersion, InvalidHeader, MacKey,
+        NoCipher, NtpAssociationMode, NtpLeapIndicator, NtpPacket, PacketDescription,
+        PacketParsingError,
     };
     #[cfg(feature = "__internal-fuzz")]
     pub use super::peer::fuzz_measurement_from_packet;
     #[cfg(feature = "__internal-test")]
     pub use super::peer::peer_snapshot;
     pub use super::peer::{
-        AcceptSynchronizationError, IgnoreReason, Measurement, Peer, PeerNtsData, PeerSnapshot,
-        PollError, ProtocolVersion, Reach, Update,
+        intervals_overlap, offset_and_delay, root_distance_from_parts, AcceptSynchronizationError,
+        IgnoreReason, Measurement, Peer, PeerNtsData, PeerSnapshot, PeerState, PollError,
+        ProtocolVersion, Reach, SampleProvenance, SavedPeerState, StepOutcome, Update,
     };
     pub use super::server::{
         FilterAction, FilterList, IpSubnet, Server, ServerAction, ServerConfig, ServerReason,
@@ -72,7 +76,8 @@ mod exports {
     #[cfg(feature = "__internal-fuzz")]
     pub use super::time_types::fuzz_duration_from_seconds;
     pub use super::time_types::{
-        FrequencyTolerance, NtpDuration, NtpInstant, NtpTimestamp, PollInterval, PollIntervalLimits,
+        FrequencyTolerance, NtpDuration, NtpInstant, NtpTimestamp, PollInterval,
+        PollIntervalLimits, Sign,
     };
 
     #[cfg(feature = "__internal-fuzz")]
@@ -0,0 +1,225 @@
+//! NTP control messages (mode 6), which tools such as ntpq use to query the
+//! variables of a server. Only reading the system variables is supported,
+//! and this is never part of synchronizing the clock.
+
+use std::{collections::HashMap, fmt::Display};
+
+const HEADER_LENGTH: usize = 12;
+
+// Control messages still use the version they were introduced with
+const CONTROL_VERSION: u8 = 2;
+const CONTROL_MODE: u8 = 6;
+
+const OPCODE_READ_VARIABLES: u8 = 2;
+
+const RESPONSE_BIT: u8 = 0x80;
+const ERROR_BIT: u8 = 0x40;
+const MORE_BIT: u8 = 0x20;
+const OPCODE_MASK: u8 = 0x1f;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ControlMessageError {
+    IncorrectLength,
+    InvalidMode(u8),
+    NotAResponse,
+    /// The server could not handle the request, with the given error code
+    ServerError(u8),
+    UnexpectedOpcode(u8),
+    UnexpectedSequence(u16),
+    /// The response spans multiple packets, which is not supported
+    Fragmented,
+    InvalidData,
+}
+
+impl Display for ControlMessageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IncorrectLength => f.write_str("Incorrect control message length"),
+            Self::InvalidMode(mode) => write!(f, "Invalid mode {mode} for control message"),
+            Self::NotAResponse => f.write_str("Control message is not a response"),
+            Self::ServerError(code) => write!(f, "Server responded with error code {code}"),
+            Self::UnexpectedOpcode(opcode) => write!(f, "Unexpected opcode {opcode}"),
+            Self::UnexpectedSequence(sequence) => {
+                write!(f, "Unexpected sequence number {sequence}")
+            }
+            Self::Fragmented => f.write_str("Fragmented control responses are not supported"),
+            Self::InvalidData => f.write_str("Control message data is not a variable list"),
+        }
+    }
+}
+
+impl std::error::Error for ControlMessageError {}
+
+/// Request for the system variables of a server. The `sequence` number is
+/// echoed in the response, to match it to this request.
+pub fn serialize_read_variables_request(sequence: u16) -> [u8; HEADER_LENGTH] {
+    let mut request = [0; HEADER_LENGTH];
+    request[0] = (CONTROL_VERSION << 3) | CONTROL_MODE;
+    request[1] = OPCODE_READ_VARIABLES;
+    request[2..4].copy_from_slice(&sequence.to_be_bytes());
+    // status, association id (0 for the system), offset and count are all zero
+    request
+}
+
+/// Parse the response to [`serialize_read_variables_request`] into the
+/// variables reported by the server, keyed by name. Quotes around string
+/// values are removed.
+pub fn parse_read_variables_response(
+    data: &[u8],
+    sequence: u16,
+) -> Result<HashMap<String, String>, ControlMessageError> {
+    if data.len() < HEADER_LENGTH {
+        return Err(ControlMessageError::IncorrectLength);
+    }
+
+    let mode = data[0] & 0x07;
+    if mode != CONTROL_MODE {
+        return Err(ControlMessageError::InvalidMode(mode));
+    }
+
+    let flags = data[1];
+    if flags & RESPONSE_BIT == 0 {
+        return Err(ControlMessageError::NotAResponse);
+    }
+    if flags & ERROR_BIT != 0 {
+        // the error code lives in the high byte of the status word
+        return Err(ControlMessageError::ServerError(data[4]));
+    }
+    if flags & MORE_BIT != 0 {
+        return Err(ControlMessageError::Fragmented);
+    }
+    if flags & OPCODE_MASK != OPCODE_READ_VARIABLES {
+        return Err(ControlMessageError::UnexpectedOpcode(flags & OPCODE_MASK));
+    }
+
+    let response_sequence = u16::from_be_bytes([data[2], data[3]]);
+    if response_sequence != sequence {
+        return Err(ControlMessageError::UnexpectedSequence(response_sequence));
+    }
+
+    let offset = u16::from_be_bytes([data[8], data[9]]);
+    let count = u16::from_be_bytes([data[10], data[11]]) as usize;
+    if offset != 0 {
+        return Err(ControlMessageError::Fragmented);
+    }
+
+    let payload = data
+        .get(HEADER_LENGTH..HEADER_LENGTH + count)
+        .ok_or(ControlMessageError::IncorrectLength)?;
+    let payload = std::str::from_utf8(payload).map_err(|_| ControlMessageError::InvalidData)?;
+
+    Ok(parse_variable_list(payload))
+}
+
+/// Split a list like `version="ntpd 4.2.8", stratum=2` into its variables.
+/// Commas inside quoted values do not separate variables.
+fn parse_variable_list(list: &str) -> HashMap<String, String> {
+    let mut variables = HashMap::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+
+    for (index, c) in list.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                insert_variable(&mut variables, &list[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    insert_variable(&mut variables, &list[start..]);
+
+    variables
+}
+
+fn insert_variable(variables: &mut HashMap<String, String>, variable: &str) {
+    let variable = variable.trim();
+    if variable.is_empty() {
+        return;
+    }
+
+    let (name, value) = variable.split_once('=').unwrap_or((variable, ""));
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+
+    variables.insert(name.trim().to_owned(), value.to_owned());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(sequence: u16, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![(CONTROL_VERSION << 3) | CONTROL_MODE, flags];
+        data.extend_from_slice(&sequence.to_be_bytes());
+        data.extend_from_slice(&[0x06, 0x18, 0, 0, 0, 0]);
+        data.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        data.extend_from_slice(payload);
+        // responses are padded to a multiple of 4 bytes
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        data
+    }
+
+    #[test]
+    fn test_read_variables_request() {
+        let request = serialize_read_variables_request(0x1234);
+        assert_eq!(request, [0x16, 0x02, 0x12, 0x34, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_parse_read_variables_response() {
+        let data = response(
+            7,
+            RESPONSE_BIT | OPCODE_READ_VARIABLES,
+            b"version=\"ntpd 4.2.8p15@1.3728-o, built\", processor=\"x86_64\",\r\n\
+              stratum=2, precision=-24, rootdelay=1.234, leap=00, refid=192.0.2.1\r\n",
+        );
+
+        let variables = parse_read_variables_response(&data, 7).unwrap();
+        assert_eq!(variables.len(), 7);
+        assert_eq!(variables["version"], "ntpd 4.2.8p15@1.3728-o, built");
+        assert_eq!(variables["processor"], "x86_64");
+        assert_eq!(variables["stratum"], "2");
+        assert_eq!(variables["precision"], "-24");
+        assert_eq!(variables["rootdelay"], "1.234");
+        assert_eq!(variables["leap"], "00");
+        assert_eq!(variables["refid"], "192.0.2.1");
+    }
+
+    #[test]
+    fn test_invalid_read_variables_response() {
+        let data = response(7, RESPONSE_BIT | OPCODE_READ_VARIABLES, b"stratum=2");
+        assert_eq!(
+            parse_read_variables_response(&data, 8),
+            Err(ControlMessageError::UnexpectedSequence(7))
+        );
+        assert_eq!(
+            parse_read_variables_response(&data[..8], 7),
+            Err(ControlMessageError::IncorrectLength)
+        );
+
+        let data = response(7, OPCODE_READ_VARIABLES, b"");
+        assert_eq!(
+            parse_read_variables_response(&data, 7),
+            Err(ControlMessageError::NotAResponse)
+        );
+
+        let data = response(7, RESPONSE_BIT | ERROR_BIT | OPCODE_READ_VARIABLES, b"");
+        assert_eq!(
+            parse_read_variables_response(&data, 7),
+            Err(ControlMessageError::ServerError(0x06))
+        );
+
+        let data = response(7, RESPONSE_BIT | MORE_BIT | OPCODE_READ_VARIABLES, b"");
+        assert_eq!(
+            parse_read_variables_response(&data, 7),
+            Err(ControlMessageError::Fragmented)
+        );
+    }
+}
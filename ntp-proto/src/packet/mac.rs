@@ -1,5 +1,7 @@
 use std::borrow::Cow;
 
+use md5::{Digest, Md5};
+
 use crate::io::NonBlockingWrite;
 
 use super::error::ParsingError;
@@ -43,6 +45,74 @@ impl<'a> Mac<'a> {
     }
 }
 
+/// Shared key for classic symmetric-key authentication (RFC5905), where a
+/// MAC of the packet is appended using the MD5 digest of the key followed by
+/// the packet contents.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MacKey {
+    key_id: u32,
+    secret: Vec<u8>,
+}
+
+impl std::fmt::Debug for MacKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MacKey")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MacKey {
+    const DIGEST_SIZE: usize = 16;
+
+    /// Size of the MAC appended to a packet: the key id and the digest
+    pub const MAC_SIZE: usize = 4 + Self::DIGEST_SIZE;
+
+    pub fn new(key_id: u32, secret: Vec<u8>) -> Self {
+        MacKey { key_id, secret }
+    }
+
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+
+    fn digest(&self, packet: &[u8]) -> [u8; Self::DIGEST_SIZE] {
+        let mut hasher = Md5::new();
+        hasher.update(&self.secret);
+        hasher.update(packet);
+        hasher.finalize().into()
+    }
+
+    /// Write the MAC of `packet` (header and extension fields)
+    pub fn sign(&self, packet: &[u8], mut w: impl std::io::Write) -> std::io::Result<()> {
+        w.write_all(&self.key_id.to_be_bytes())?;
+        w.write_all(&self.digest(packet))
+    }
+
+    /// Whether `data` ends in a MAC with our key id that matches the rest of
+    /// the packet
+    pub fn verify(&self, data: &[u8]) -> bool {
+        let Some(split) = data.len().checked_sub(Self::MAC_SIZE) else {
+            return false;
+        };
+        let (packet, mac) = data.split_at(split);
+
+        let Ok(Mac { keyid, mac }) = Mac::deserialize(mac) else {
+            return false;
+        };
+
+        // Compare without short-circuiting, to not leak how much of the
+        // digest was correct through timing
+        keyid == self.key_id
+            && self
+                .digest(packet)
+                .iter()
+                .zip(mac.iter())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -63,4 +133,28 @@ mod tests {
 
         assert_eq!(input, output);
     }
+
+    #[test]
+    fn sign_verify() {
+        let key = MacKey::new(7, b"secret".to_vec());
+        let packet = [0x23; 48];
+
+        let mut data = packet.to_vec();
+        key.sign(&packet, &mut data).unwrap();
+        assert_eq!(data.len(), 48 + MacKey::MAC_SIZE);
+        assert_eq!(&data[48..52], &7u32.to_be_bytes());
+        assert!(key.verify(&data));
+
+        // Another key, even with the same id, doesn't match
+        assert!(!MacKey::new(7, b"other".to_vec()).verify(&data));
+        assert!(!MacKey::new(8, b"secret".to_vec()).verify(&data));
+
+        // Neither does a modified packet
+        let mut modified = data.clone();
+        modified[10] ^= 1;
+        assert!(!key.verify(&modified));
+
+        // And a packet without a MAC never does
+        assert!(!key.verify(&packet));
+    }
 }
@@ -14,6 +14,7 @@ use crate::{
 
 use self::{error::ParsingError, extension_fields::ExtensionFieldData, mac::Mac};
 
+mod control;
 mod crypto;
 mod error;
 mod extension_fields;
@@ -22,12 +23,16 @@ mod mac;
 #[cfg(feature = "ntpv5")]
 pub mod v5;
 
+pub use control::{
+    parse_read_variables_response, serialize_read_variables_request, ControlMessageError,
+};
 pub use crypto::{
     AesSivCmac256, AesSivCmac512, Cipher, CipherHolder, CipherProvider, DecryptError,
     EncryptResult, NoCipher,
 };
 pub use error::PacketParsingError;
 pub use extension_fields::{ExtensionField, ExtensionHeaderVersion};
+pub use mac::MacKey;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NtpLeapIndicator {
@@ -126,6 +131,83 @@ pub enum NtpHeader {
     V5(v5::NtpHeaderV5),
 }
 
+/// Reason a server response header is internally inconsistent
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InvalidHeader {
+    /// The receive or transmit timestamp is not set
+    MissingTimestamp,
+    /// The precision claims a clock that only ticks every few seconds
+    Precision(i8),
+    /// The stratum is outside of the range used by synchronization sources
+    Stratum(u8),
+}
+
+impl NtpHeader {
+    /// Check that a response header, which is not a kiss code, is
+    /// internally consistent, so that a measurement can be derived from it
+    pub fn validate(&self) -> Result<(), InvalidHeader> {
+        let (stratum, precision, receive_timestamp, transmit_timestamp) = match self {
+            NtpHeader::V3(header) | NtpHeader::V4(header) => (
+                header.stratum,
+                header.precision,
+                header.receive_timestamp,
+                header.transmit_timestamp,
+            ),
+            #[cfg(feature = "ntpv5")]
+            NtpHeader::V5(header) => (
+                header.stratum,
+                header.precision,
+                header.receive_timestamp,
+                header.transmit_timestamp,
+            ),
+        };
+
+        if !(1..=crate::peer::MAX_STRATUM).contains(&stratum) {
+            return Err(InvalidHeader::Stratum(stratum));
+        }
+
+        // Precision is the log2 of the clock resolution in seconds
+        if !(-32..=0).contains(&precision) {
+            return Err(InvalidHeader::Precision(precision));
+        }
+
+        // A zero timestamp is used when the time is unknown
+        if receive_timestamp == NtpTimestamp::default()
+            || transmit_timestamp == NtpTimestamp::default()
+        {
+            return Err(InvalidHeader::MissingTimestamp);
+        }
+
+        Ok(())
+    }
+}
+
+/// Breakdown of the header of a packet in friendly units, for diagnostics
+#[derive(Debug, Clone, PartialEq)]
+pub struct PacketDescription {
+    pub leap: NtpLeapIndicator,
+    pub version: u8,
+    pub mode: NtpAssociationMode,
+    pub stratum: u8,
+    /// Unit: seconds
+    pub poll: f64,
+    /// Unit: seconds
+    pub precision: f64,
+    /// Unit: seconds
+    pub root_delay: f64,
+    /// Unit: seconds
+    pub root_dispersion: f64,
+    /// Kiss code or name of the reference clock for stratum 0 and 1, the
+    /// address of the server's time source otherwise
+    pub reference_id: String,
+    /// Not present in NTPv5 packets
+    pub reference_timestamp: Option<NtpTimestamp>,
+    /// Not present in NTPv5 packets, which carry a client cookie instead
+    pub origin_timestamp: Option<NtpTimestamp>,
+    pub receive_timestamp: NtpTimestamp,
+    pub transmit_timestamp: NtpTimestamp,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct NtpHeaderV3V4 {
     leap: NtpLeapIndicator,
@@ -623,6 +705,44 @@ impl<'a> NtpPacket<'a> {
         )
     }
 
+    /// Set the precision (log2 seconds) of the local clock advertised in
+    /// this packet
+    pub fn set_precision(&mut self, precision: i8) {
+        match &mut self.header {
+            NtpHeader::V3(ref mut header) => header.precision = precision,
+            NtpHeader::V4(ref mut header) => header.precision = precision,
+            #[cfg(feature = "ntpv5")]
+            NtpHeader::V5(ref mut header) => header.precision = precision,
+        }
+    }
+
+    /// Ask the server for an interleaved response, which carries the transmit
+    /// timestamp of its previous response instead of the current one. The
+    /// server recognizes that response by `previous_receive_timestamp`, the
+    /// time at which it received the request answered by it. Returns the
+    /// identifier of an interleaved response, or `None` when the protocol
+    /// version has no interleaved mode.
+    pub fn request_interleaved(
+        &mut self,
+        previous_receive_timestamp: NtpTimestamp,
+    ) -> Option<RequestIdentifier> {
+        match &mut self.header {
+            NtpHeader::V4(ref mut header) => {
+                // The server echoes our receive timestamp in the origin
+                // timestamp of an interleaved response, so like the transmit
+                // timestamp it is randomly generated.
+                let cookie = thread_rng().gen();
+                header.origin_timestamp = previous_receive_timestamp;
+                header.receive_timestamp = cookie;
+                Some(RequestIdentifier {
+                    expected_origin_timestamp: cookie,
+                    uid: None,
+                })
+            }
+            _ => None,
+        }
+    }
+
     #[cfg_attr(not(feature = "ntpv5"), allow(unused_mut))]
     pub fn timestamp_response<C: NtpClock>(
         system: &SystemSnapshot,
@@ -1152,6 +1272,17 @@ impl<'a> NtpPacket<'a> {
         }
     }
 
+    /// Time at which the server's clock was last set or corrected, if the
+    /// packet carries one
+    pub fn reference_timestamp(&self) -> Option<NtpTimestamp> {
+        match self.header {
+            NtpHeader::V3(header) => Some(header.reference_timestamp),
+            NtpHeader::V4(header) => Some(header.reference_timestamp),
+            #[cfg(feature = "ntpv5")]
+            NtpHeader::V5(_) => None,
+        }
+    }
+
     pub fn reference_id(&self) -> ReferenceId {
         match self.header {
             NtpHeader::V3(header) => header.reference_id,
@@ -1162,6 +1293,42 @@ impl<'a> NtpPacket<'a> {
         }
     }
 
+    /// Breakdown of the header of this packet, for diagnostics
+    pub fn describe(&self) -> PacketDescription {
+        let reference_bytes = self.reference_id().to_bytes();
+        let reference_id = if self.stratum() <= 1 {
+            reference_bytes
+                .iter()
+                .take_while(|byte| **byte != 0)
+                .map(|byte| *byte as char)
+                .collect()
+        } else {
+            std::net::Ipv4Addr::from(reference_bytes).to_string()
+        };
+
+        let origin_timestamp = match self.header {
+            NtpHeader::V3(header) | NtpHeader::V4(header) => Some(header.origin_timestamp),
+            #[cfg(feature = "ntpv5")]
+            NtpHeader::V5(_) => None,
+        };
+
+        PacketDescription {
+            leap: self.leap(),
+            version: self.version(),
+            mode: self.mode(),
+            stratum: self.stratum(),
+            poll: self.poll().as_system_duration().as_secs_f64(),
+            precision: 2f64.powi(self.precision() as i32),
+            root_delay: self.root_delay().to_seconds(),
+            root_dispersion: self.root_dispersion().to_seconds(),
+            reference_id,
+            reference_timestamp: self.reference_timestamp(),
+            origin_timestamp,
+            receive_timestamp: self.receive_timestamp(),
+            transmit_timestamp: self.transmit_timestamp(),
+        }
+    }
+
     fn kiss_code(&self) -> ReferenceId {
         match self.header {
             NtpHeader::V3(header) => header.reference_id,
@@ -1328,6 +1495,15 @@ impl<'a> NtpPacket<'a> {
         }
     }
 
+    pub fn set_reference_timestamp(&mut self, timestamp: NtpTimestamp) {
+        match &mut self.header {
+            NtpHeader::V3(ref mut header) => header.reference_timestamp = timestamp,
+            NtpHeader::V4(ref mut header) => header.reference_timestamp = timestamp,
+            #[cfg(feature = "ntpv5")]
+            NtpHeader::V5(_) => { /* No reference timestamp in V5 */ }
+        }
+    }
+
     pub fn set_receive_timestamp(&mut self, timestamp: NtpTimestamp) {
         match &mut self.header {
             NtpHeader::V3(ref mut header) => header.receive_timestamp = timestamp,
@@ -1337,12 +1513,12 @@ impl<'a> NtpPacket<'a> {
         }
     }
 
-    pub fn set_precision(&mut self, precision: i8) {
+    pub fn set_poll(&mut self, poll: PollInterval) {
         match &mut self.header {
-            NtpHeader::V3(ref mut header) => header.precision = precision,
-            NtpHeader::V4(ref mut header) => header.precision = precision,
+            NtpHeader::V3(ref mut header) => header.poll = poll,
+            NtpHeader::V4(ref mut header) => header.poll = poll,
             #[cfg(feature = "ntpv5")]
-            NtpHeader::V5(ref mut header) => header.precision = precision,
+            NtpHeader::V5(ref mut header) => header.poll = poll,
         }
     }
 
@@ -1563,6 +1739,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_describe() {
+        let packet = b"\x24\x02\x06\xe9\x00\x00\x02\x36\x00\x00\x03\xb7\xc0\x35\x67\x6c\xe5\xf6\x61\xfd\x6f\x16\x5f\x03\xe5\xf6\x63\xa8\x76\x19\xef\x40\xe5\xf6\x63\xa8\x79\x8c\x65\x81\xe5\xf6\x63\xa8\x79\x8e\xae\x2b";
+        let description = NtpPacket::deserialize(packet, &NoCipher)
+            .unwrap()
+            .0
+            .describe();
+
+        assert_eq!(description.leap, NtpLeapIndicator::NoWarning);
+        assert_eq!(description.version, 4);
+        assert_eq!(description.mode, NtpAssociationMode::Server);
+        assert_eq!(description.stratum, 2);
+        assert_eq!(description.poll, 64.0);
+        assert_eq!(description.precision, 2f64.powi(-23));
+        assert!((description.root_delay - 566.0 / 65536.0).abs() < 1e-9);
+        assert!((description.root_dispersion - 951.0 / 65536.0).abs() < 1e-9);
+        assert_eq!(description.reference_id, "192.53.103.108");
+        assert_eq!(
+            description.reference_timestamp,
+            Some(NtpTimestamp::from_fixed_int(0xe5f661fd6f165f03))
+        );
+        assert_eq!(
+            description.origin_timestamp,
+            Some(NtpTimestamp::from_fixed_int(0xe5f663a87619ef40))
+        );
+        assert_eq!(
+            description.receive_timestamp,
+            NtpTimestamp::from_fixed_int(0xe5f663a8798c6581)
+        );
+        assert_eq!(
+            description.transmit_timestamp,
+            NtpTimestamp::from_fixed_int(0xe5f663a8798eae2b)
+        );
+
+        // Stratum 1 servers name their reference clock
+        let mut packet = NtpPacket::test();
+        packet.set_stratum(1);
+        packet.set_reference_id(ReferenceId::from_bytes(*b"GPS\0"));
+        assert_eq!(packet.describe().reference_id, "GPS");
+    }
+
     #[test]
     fn test_version() {
         let packet = b"\x04\x02\x06\xe9\x00\x00\x02\x36\x00\x00\x03\xb7\xc0\x35\x67\x6c\xe5\xf6\x61\xfd\x6f\x16\x5f\x03\xe5\xf6\x63\xa8\x76\x19\xef\x40\xe5\xf6\x63\xa8\x79\x8c\x65\x81\xe5\xf6\x63\xa8\x79\x8e\xae\x2b";
@@ -4,24 +4,36 @@ use crate::packet::{
     ExtensionField, NtpHeader,
 };
 use crate::{
+    algorithm::SavedFilterState,
     config::SourceDefaultsConfig,
     cookiestash::CookieStash,
     identifiers::ReferenceId,
-    packet::{Cipher, NtpAssociationMode, NtpLeapIndicator, NtpPacket, RequestIdentifier},
+    packet::{Cipher, MacKey, NtpAssociationMode, NtpLeapIndicator, NtpPacket, RequestIdentifier},
     system::SystemSnapshot,
-    time_types::{NtpDuration, NtpInstant, NtpTimestamp, PollInterval},
+    time_types::{FrequencyTolerance, NtpDuration, NtpInstant, NtpTimestamp, PollInterval},
 };
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::VecDeque,
     fmt::Display,
     io::Cursor,
     net::{IpAddr, SocketAddr},
 };
 use tracing::{debug, info, instrument, trace, warn};
 
-const MAX_STRATUM: u8 = 16;
+pub(crate) const MAX_STRATUM: u8 = 16;
 const POLL_WINDOW: std::time::Duration = std::time::Duration::from_secs(5);
 const STARTUP_TRIES_THRESHOLD: usize = 3;
+/// Saved peer state older than this is not trusted on restore (unit: seconds)
+pub(crate) const MAX_SAVED_STATE_AGE: f64 = 3600.0;
+// Number of consecutive responses with an unchanged transmit timestamp after
+// which we consider the server's clock to be frozen
+const FROZEN_TRANSMIT_THRESHOLD: u32 = 2;
+// Number of unanswered earlier requests of which we still recognize responses
+// as late rather than bogus
+const LATE_RESPONSE_HISTORY: usize = 8;
+/// Rate at which the uncertainty of a measurement grows with its age (PHI)
+const FREQUENCY_TOLERANCE: FrequencyTolerance = FrequencyTolerance::ppm(15);
 
 #[derive(Debug)]
 pub enum NtsError {
@@ -69,6 +81,8 @@ impl std::fmt::Debug for PeerNtsData {
 #[derive(Debug)]
 pub struct Peer {
     nts: Option<Box<PeerNtsData>>,
+    // Key used to authenticate packets with a MAC, when not using NTS
+    mac_key: Option<MacKey>,
 
     // Poll interval dictated by unreachability backoff
     backoff_interval: PollInterval,
@@ -77,11 +91,27 @@ pub struct Peer {
     // The poll interval desired by the remove server.
     // Must be increased when the server sends the RATE kiss code.
     remote_min_poll_interval: PollInterval,
+    // Number of packets of the current burst still to be sent
+    burst_remaining: u8,
 
     // Identifier of the last request sent to the server. This is correlated
     // with any received response from the server to guard against replay
     // attacks and packet reordering.
     current_request_identifier: Option<(RequestIdentifier, NtpInstant)>,
+    // Identifiers of earlier requests that were never answered, most recent
+    // last, to recognize responses that arrive late.
+    unanswered_request_identifiers: VecDeque<RequestIdentifier>,
+    // Timestamps of the last completed exchange, which the next request can
+    // ask the server to complete with an interleaved response.
+    previous_exchange: Option<PreviousExchange>,
+    // Identifier of an interleaved response to the current request, and the
+    // exchange such a response completes.
+    interleaved_request: Option<(RequestIdentifier, PreviousExchange)>,
+    // The last accepted measurement, `None` until the first valid response
+    last_measurement: Option<Measurement>,
+    // The exchange the last accepted measurement was computed from, `None`
+    // when it came from a reference clock
+    last_provenance: Option<SampleProvenance>,
 
     stratum: u8,
     reference_id: ReferenceId,
@@ -90,8 +120,28 @@ pub struct Peer {
     source_id: ReferenceId,
     reach: Reach,
     tries: usize,
+    // Leap indicator of the last accepted measurement, `None` until the
+    // first valid response has been processed.
+    last_leap: Option<NtpLeapIndicator>,
+    // Recent (localtime, offset, jitter) samples for monitoring, bounded
+    // by the configured offset history length.
+    offset_history: VecDeque<(NtpTimestamp, f64, f64)>,
+    // Moments the poll interval changed together with the new interval,
+    // bounded by the configured poll interval history length.
+    poll_interval_history: VecDeque<(NtpInstant, PollInterval)>,
+    // Number of polls that went unanswered for longer than the response
+    // timeout
+    missed_responses: u64,
+    // Transmit timestamp of the last valid response, and the number of
+    // consecutive responses that repeated it unchanged.
+    last_transmit_timestamp: Option<NtpTimestamp>,
+    frozen_transmit_count: u32,
+    state: PeerState,
 
     peer_defaults_config: SourceDefaultsConfig,
+    // How much longer packets take to come back from the source than to
+    // reach it, half of which is added to every measured offset.
+    delay_asymmetry_correction: NtpDuration,
 
     protocol_version: ProtocolVersion,
 
@@ -100,13 +150,15 @@ pub struct Peer {
     bloom_filter: RemoteBloomFilter,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Measurement {
     pub delay: NtpDuration,
     pub offset: NtpDuration,
     pub transmit_timestamp: NtpTimestamp,
     pub receive_timestamp: NtpTimestamp,
     pub localtime: NtpTimestamp,
+    // only meaningful within this process
+    #[serde(skip, default = "NtpInstant::now")]
     pub monotime: NtpInstant,
 
     pub stratum: u8,
@@ -116,7 +168,16 @@ pub struct Measurement {
     pub precision: i8,
 }
 
+/// Timestamps of a completed exchange with the server
+#[derive(Debug, Clone, Copy)]
+struct PreviousExchange {
+    send_timestamp: NtpTimestamp,
+    server_receive_timestamp: NtpTimestamp,
+    recv_timestamp: NtpTimestamp,
+}
+
 impl Measurement {
+    #[cfg(any(test, feature = "__internal-fuzz"))]
     fn from_packet(
         packet: &NtpPacket,
         send_timestamp: NtpTimestamp,
@@ -124,15 +185,39 @@ impl Measurement {
         local_clock_time: NtpInstant,
         precision: NtpDuration,
     ) -> Self {
+        Self::from_timestamps(
+            packet,
+            send_timestamp,
+            packet.receive_timestamp(),
+            packet.transmit_timestamp(),
+            recv_timestamp,
+            local_clock_time,
+            precision,
+        )
+    }
+
+    fn from_timestamps(
+        packet: &NtpPacket,
+        send_timestamp: NtpTimestamp,
+        server_receive_timestamp: NtpTimestamp,
+        server_transmit_timestamp: NtpTimestamp,
+        recv_timestamp: NtpTimestamp,
+        local_clock_time: NtpInstant,
+        precision: NtpDuration,
+    ) -> Self {
+        let (offset, delay) = offset_and_delay(
+            send_timestamp,
+            server_receive_timestamp,
+            server_transmit_timestamp,
+            recv_timestamp,
+            precision,
+        );
+
         Self {
-            delay: ((recv_timestamp - send_timestamp)
-                - (packet.transmit_timestamp() - packet.receive_timestamp()))
-            .max(precision),
-            offset: ((packet.receive_timestamp() - send_timestamp)
-                + (packet.transmit_timestamp() - recv_timestamp))
-                / 2,
-            transmit_timestamp: packet.transmit_timestamp(),
-            receive_timestamp: packet.receive_timestamp(),
+            delay,
+            offset,
+            transmit_timestamp: server_transmit_timestamp,
+            receive_timestamp: server_receive_timestamp,
             localtime: send_timestamp + (recv_timestamp - send_timestamp) / 2,
             monotime: local_clock_time,
 
@@ -140,11 +225,53 @@ impl Measurement {
             root_delay: packet.root_delay(),
             root_dispersion: packet.root_dispersion(),
             leap: packet.leap(),
-            precision: packet.precision(),
+            // The precision is controlled by the remote, so bound it to the
+            // range of exponents that are meaningful for an NtpDuration.
+            precision: packet.precision().clamp(-32, 0),
         }
     }
 }
 
+/// Compute the offset and round trip delay from the four timestamps of a
+/// single request/response exchange: our send time `t1`, the server's receive
+/// time `t2`, the server's transmit time `t3` and our receive time `t4`.
+///
+/// The delay is never reported as smaller than `precision`, the precision of
+/// the clock with which `t1` and `t4` were taken. This also keeps the delay
+/// positive when our clock stepped backwards between `t1` and `t4`.
+pub fn offset_and_delay(
+    t1: NtpTimestamp,
+    t2: NtpTimestamp,
+    t3: NtpTimestamp,
+    t4: NtpTimestamp,
+    precision: NtpDuration,
+) -> (NtpDuration, NtpDuration) {
+    let offset = ((t2 - t1) + (t3 - t4)) / 2;
+    let delay = ((t4 - t1) - (t3 - t2)).max(precision);
+
+    (offset, delay)
+}
+
+/// Compute the root distance: the maximum error of the time of a peer
+/// relative to the primary reference, given the root delay and dispersion it
+/// reports, our delay and dispersion to it, its jitter (in seconds) and the
+/// age of the measurement. The error grows with the age as the clocks may
+/// drift apart in the meantime.
+pub fn root_distance_from_parts(
+    root_delay: NtpDuration,
+    root_dispersion: NtpDuration,
+    delay: NtpDuration,
+    dispersion: NtpDuration,
+    jitter_secs: f64,
+    age: NtpDuration,
+) -> NtpDuration {
+    (delay + root_delay) / 2
+        + root_dispersion
+        + dispersion
+        + NtpDuration::from_seconds(jitter_secs)
+        + age * FREQUENCY_TOLERANCE
+}
+
 /// Used to determine whether the server is reachable and the data are fresh
 ///
 /// This value is represented as an 8-bit shift register. The register is shifted left
@@ -152,7 +279,7 @@ impl Measurement {
 /// As valid packets arrive, the rightmost bit is set to one.
 /// If the register contains any nonzero bits, the server is considered reachable;
 /// otherwise, it is unreachable.
-#[derive(Default, Clone, Copy, Serialize, Deserialize)]
+#[derive(Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Reach(u8);
 
 impl std::fmt::Debug for Reach {
@@ -192,26 +319,117 @@ impl Reach {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IgnoreReason {
     /// The packet doesn't parse
     InvalidPacket,
+    /// The packet is not authenticated with the MAC of our shared key
+    InvalidMac,
     /// The association mode is not one that this peer supports
     InvalidMode,
     /// The NTP version is not one that this implementation supports
     InvalidVersion,
     /// The stratum of the server is too high
     InvalidStratum,
+    /// The root dispersion of the server is too high
+    ExcessiveDispersion,
     /// The send time on the received packet is not the time we sent it at
     InvalidPacketTime,
+    /// The packet answers one of our earlier requests rather than the
+    /// current one, for example because the network reordered packets
+    LateResponse,
     /// Received a Kiss-o'-Death https://datatracker.ietf.org/doc/html/rfc5905#section-7.4
     KissIgnore,
-    /// Received a DENY or RSTR Kiss-o'-Death, and must demobilize the association
+    /// Received a DENY Kiss-o'-Death, and must demobilize the association
     KissDemobilize,
+    /// Received a RSTR Kiss-o'-Death, and must demobilize the association
+    /// for now, but may retry after a cooldown
+    KissRestrict,
     /// Received a matching NTS-Nack, no further action needed.
     KissNtsNack,
+    /// The local receive timestamp lies before the local send timestamp, for
+    /// example because the clock was stepped in between
+    InvalidTimestampOrder,
+    /// The server keeps sending the same transmit timestamp, indicating its
+    /// clock is frozen
+    FrozenTransmitTimestamp,
+    /// The header of the response is internally inconsistent
+    MalformedHeader,
+    /// The server's clock was last synchronized too long ago
+    StaleReference,
+    /// The server claims to have last synchronized its clock at a time that
+    /// is still in our future
+    FutureReference,
     /// The best packet is older than the peer's current time
     TooOld,
+    /// The association was already demobilized
+    Demobilized,
+}
+
+impl IgnoreReason {
+    /// All reasons a packet can be ignored for, e.g. to pre-register metrics
+    pub const ALL: &'static [IgnoreReason] = &[
+        IgnoreReason::InvalidPacket,
+        IgnoreReason::InvalidMac,
+        IgnoreReason::InvalidMode,
+        IgnoreReason::InvalidVersion,
+        IgnoreReason::InvalidStratum,
+        IgnoreReason::ExcessiveDispersion,
+        IgnoreReason::InvalidPacketTime,
+        IgnoreReason::LateResponse,
+        IgnoreReason::KissIgnore,
+        IgnoreReason::KissDemobilize,
+        IgnoreReason::KissRestrict,
+        IgnoreReason::KissNtsNack,
+        IgnoreReason::InvalidTimestampOrder,
+        IgnoreReason::FrozenTransmitTimestamp,
+        IgnoreReason::MalformedHeader,
+        IgnoreReason::StaleReference,
+        IgnoreReason::FutureReference,
+        IgnoreReason::TooOld,
+        IgnoreReason::Demobilized,
+    ];
+
+    /// Stable label for this reason, suitable for use in logs and metrics
+    pub const fn label(&self) -> &'static str {
+        match self {
+            IgnoreReason::InvalidPacket => "invalid_packet",
+            IgnoreReason::InvalidMac => "invalid_mac",
+            IgnoreReason::InvalidMode => "invalid_mode",
+            IgnoreReason::InvalidVersion => "invalid_version",
+            IgnoreReason::InvalidStratum => "invalid_stratum",
+            IgnoreReason::ExcessiveDispersion => "excessive_dispersion",
+            IgnoreReason::InvalidPacketTime => "invalid_packet_time",
+            IgnoreReason::LateResponse => "late_response",
+            IgnoreReason::KissIgnore => "kiss_ignore",
+            IgnoreReason::KissDemobilize => "kiss_demobilize",
+            IgnoreReason::KissRestrict => "kiss_restrict",
+            IgnoreReason::KissNtsNack => "kiss_nts_nack",
+            IgnoreReason::InvalidTimestampOrder => "invalid_timestamp_order",
+            IgnoreReason::FrozenTransmitTimestamp => "frozen_transmit_timestamp",
+            IgnoreReason::MalformedHeader => "malformed_header",
+            IgnoreReason::StaleReference => "stale_reference",
+            IgnoreReason::FutureReference => "future_reference",
+            IgnoreReason::TooOld => "too_old",
+            IgnoreReason::Demobilized => "demobilized",
+        }
+    }
+}
+
+/// The exchange a measurement was computed from, and what the server
+/// reported about its own synchronization in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleProvenance {
+    /// Local time the request was sent (t1)
+    pub send_timestamp: NtpTimestamp,
+    /// Server time the request was received (t2)
+    pub server_receive_timestamp: NtpTimestamp,
+    /// Server time the response was sent (t3)
+    pub server_transmit_timestamp: NtpTimestamp,
+    /// Local time the response was received (t4)
+    pub recv_timestamp: NtpTimestamp,
+    pub stratum: u8,
+    pub reference_id: ReferenceId,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -228,6 +446,16 @@ pub struct PeerSnapshot {
 
     pub protocol_version: ProtocolVersion,
 
+    /// Local time of the last accepted measurement of the peer
+    pub last_measurement_time: Option<NtpTimestamp>,
+    /// Exchange the last accepted measurement of the peer was computed from
+    pub provenance: Option<SampleProvenance>,
+    /// Number of polls that went unanswered for longer than the response
+    /// timeout
+    pub missed_responses: u64,
+
+    pub state: PeerState,
+
     #[cfg(feature = "ntpv5")]
     pub bloom_filter: Option<BloomFilter>,
 }
@@ -236,7 +464,9 @@ impl PeerSnapshot {
     pub fn accept_synchronization(
         &self,
         local_stratum: u8,
+        max_acceptable_stratum: u8,
         local_ips: &[IpAddr],
+        downstream_ids: &[ReferenceId],
         #[cfg_attr(not(feature = "ntpv5"), allow(unused_variables))] system: &SystemSnapshot,
     ) -> Result<(), AcceptSynchronizationError> {
         use AcceptSynchronizationError::*;
@@ -250,6 +480,26 @@ impl PeerSnapshot {
             return Err(Stratum);
         }
 
+        if self.stratum > max_acceptable_stratum {
+            info!(
+                peer_stratum = self.stratum,
+                max_acceptable_stratum,
+                "Peer rejected due to a stratum above the configured maximum",
+            );
+            return Err(Stratum);
+        }
+
+        // A server above stratum 1 should name the server it synchronizes to
+        // in its reference id. When it leaves it zero, the loop checks below
+        // can't tell whether that server is us.
+        if self.stratum > 1 && self.reference_id.is_unset() {
+            info!(
+                peer_stratum = self.stratum,
+                "Peer rejected because it does not report its reference id, so a synchronization loop can't be ruled out",
+            );
+            return Err(UnsetReferenceId);
+        }
+
         // Detect whether the remote uses us as their main time reference.
         // if so, we shouldn't sync to them as that would create a loop.
         // Note, this can only ever be an issue if the peer is not using
@@ -264,6 +514,13 @@ impl PeerSnapshot {
             return Err(Loop);
         }
 
+        // Servers known to synchronize to us, possibly through other servers,
+        // must not be our time reference either, which catches longer loops.
+        if self.stratum != 1 && downstream_ids.contains(&self.reference_id) {
+            info!("Peer rejected because it synchronizes to a downstream server");
+            return Err(Loop);
+        }
+
         #[cfg(feature = "ntpv5")]
         match self.bloom_filter {
             Some(filter) if filter.contains_id(&system.server_id) => {
@@ -282,6 +539,12 @@ impl PeerSnapshot {
         Ok(())
     }
 
+    /// How long before the local time `now` the last accepted measurement of
+    /// the peer was taken, if any
+    pub fn time_since_last_measurement(&self, now: NtpTimestamp) -> Option<NtpDuration> {
+        self.last_measurement_time.map(|time| now - time)
+    }
+
     pub fn from_peer(peer: &Peer) -> Self {
         Self {
             source_addr: peer.source_addr,
@@ -291,6 +554,12 @@ impl PeerSnapshot {
             reach: peer.reach,
             poll_interval: peer.last_poll_interval,
             protocol_version: peer.protocol_version,
+            last_measurement_time: peer
+                .last_measurement
+                .map(|measurement| measurement.localtime),
+            provenance: peer.last_provenance,
+            missed_responses: peer.missed_responses,
+            state: peer.state,
             #[cfg(feature = "ntpv5")]
             bloom_filter: peer.bloom_filter.full_filter().copied(),
         }
@@ -313,11 +582,31 @@ pub fn peer_snapshot() -> PeerSnapshot {
         reach,
         poll_interval: crate::time_types::PollIntervalLimits::default().min,
         protocol_version: Default::default(),
+        last_measurement_time: None,
+        provenance: None,
+        missed_responses: 0,
+        state: Default::default(),
         #[cfg(feature = "ntpv5")]
         bloom_filter: None,
     }
 }
 
+/// State of a peer that can be persisted, so that it can be restored after
+/// a restart of the daemon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedPeerState {
+    /// Local time at which the state was saved
+    pub time: NtpTimestamp,
+    pub reach: Reach,
+    pub backoff_interval: PollInterval,
+    pub remote_min_poll_interval: PollInterval,
+    pub offset_history: Vec<(NtpTimestamp, f64, f64)>,
+    /// State of the filter of the clock controller, which the peer itself
+    /// doesn't keep, see [`System::export_peer_state`](crate::System::export_peer_state)
+    #[serde(default)]
+    pub filter: Option<SavedFilterState>,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum AcceptSynchronizationError {
@@ -325,6 +614,8 @@ pub enum AcceptSynchronizationError {
     Loop,
     Distance,
     Stratum,
+    /// The peer synchronizes to another server, but doesn't say which
+    UnsetReferenceId,
 }
 
 #[derive(Debug)]
@@ -333,6 +624,19 @@ pub enum Update {
     NewMeasurement(PeerSnapshot, Measurement),
 }
 
+/// Result of a single [`Peer::step`], mirroring what the daemon acts on
+#[derive(Debug)]
+pub enum StepOutcome {
+    /// A poll message that should be sent to the peer
+    Poll(Vec<u8>, PeerSnapshot),
+    /// The incoming packet was accepted
+    Update(Update),
+    /// The incoming packet was ignored
+    Ignored(IgnoreReason),
+    /// No poll message could be generated
+    PollFailed(PollError),
+}
+
 #[derive(Debug)]
 pub enum PollError {
     Io(std::io::Error),
@@ -356,6 +660,23 @@ impl From<std::io::Error> for PollError {
     }
 }
 
+/// Where a peer is in its lifecycle
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerState {
+    /// No response has been received yet
+    #[default]
+    Initializing,
+    /// Sending the packets of a burst
+    Bursting,
+    /// Recent polls were answered
+    Reachable,
+    /// None of the recent polls were answered
+    Unreachable,
+    /// The association was torn down, no more packets are processed
+    Demobilized,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ProtocolVersion {
     V4,
@@ -400,21 +721,36 @@ impl Peer {
     ) -> Self {
         Self {
             nts: None,
+            mac_key: None,
 
             last_poll_interval: peer_defaults_config.poll_interval_limits.min,
             backoff_interval: peer_defaults_config.poll_interval_limits.min,
             remote_min_poll_interval: peer_defaults_config.poll_interval_limits.min,
 
+            burst_remaining: 0,
             current_request_identifier: None,
+            unanswered_request_identifiers: VecDeque::new(),
+            previous_exchange: None,
+            interleaved_request: None,
+            last_measurement: None,
+            last_provenance: None,
             source_id: ReferenceId::from_ip(source_addr.ip()),
             source_addr,
             reach: Default::default(),
             tries: 0,
+            last_leap: None,
+            offset_history: VecDeque::new(),
+            poll_interval_history: VecDeque::new(),
+            missed_responses: 0,
+            last_transmit_timestamp: None,
+            frozen_transmit_count: 0,
+            state: PeerState::Initializing,
 
             stratum: 16,
             reference_id: ReferenceId::NONE,
 
             peer_defaults_config,
+            delay_asymmetry_correction: NtpDuration::ZERO,
 
             protocol_version, // TODO make this configurable
 
@@ -436,6 +772,25 @@ impl Peer {
         }
     }
 
+    #[instrument]
+    pub fn new_with_mac_key(
+        source_addr: SocketAddr,
+        peer_defaults_config: SourceDefaultsConfig,
+        protocol_version: ProtocolVersion,
+        mac_key: MacKey,
+    ) -> Self {
+        Self {
+            mac_key: Some(mac_key),
+            ..Self::new(source_addr, peer_defaults_config, protocol_version)
+        }
+    }
+
+    /// Set how much longer packets take to come back from the source than to
+    /// reach it, half of which is added to every following measured offset
+    pub fn set_delay_asymmetry_correction(&mut self, correction: NtpDuration) {
+        self.delay_asymmetry_correction = correction;
+    }
+
     pub fn current_poll_interval(&self, system: SystemSnapshot) -> PollInterval {
         system
             .time_snapshot
@@ -444,13 +799,269 @@ impl Peer {
             .max(self.remote_min_poll_interval)
     }
 
-    #[cfg_attr(not(feature = "ntpv5"), allow(unused_mut))]
+    /// Interval until the next poll, which is the burst interval while
+    /// still sending the packets of a burst.
+    pub fn next_poll_interval(&self, system: SystemSnapshot) -> PollInterval {
+        if self.burst_remaining > 0 {
+            self.peer_defaults_config.burst_interval
+        } else {
+            self.current_poll_interval(system)
+        }
+    }
+
+    /// Same as [`Peer::next_poll_interval`], in seconds. The daemon may
+    /// lengthen the actual wait by a small random amount.
+    pub fn poll_interval_seconds(&self, system: SystemSnapshot) -> f64 {
+        self.next_poll_interval(system)
+            .as_system_duration()
+            .as_secs_f64()
+    }
+
+    /// Local time at which the next poll is due, when the last poll was sent
+    /// at `last_poll`.
+    ///
+    /// The daemon schedules polls with its own monotonic timer, which may
+    /// lengthen the interval by a small random amount, so this is the
+    /// earliest time at which the next poll is sent. It is meant for
+    /// reporting, the timer remains the source of truth for scheduling.
+    pub fn next_poll_deadline(
+        &self,
+        system: SystemSnapshot,
+        last_poll: NtpTimestamp,
+    ) -> NtpTimestamp {
+        last_poll + self.next_poll_interval(system).as_duration()
+    }
+
+    /// Whether this peer is fit to be considered for synchronization.
+    ///
+    /// A peer is a candidate only once it is reachable, has produced at least
+    /// one valid measurement and that measurement reports a synchronized
+    /// leap indicator. A freshly created peer is never a candidate. Callers
+    /// must filter on this before offering a peer's measurements to the
+    /// clock selection.
+    pub fn is_candidate(&self) -> bool {
+        self.reach.is_reachable() && self.leap_vote().is_synchronized()
+    }
+
+    /// The leap indicator announced in the last accepted response of this
+    /// peer, used to vote on upcoming leap seconds.
+    ///
+    /// Reports [`NtpLeapIndicator::Unknown`] until a response has been
+    /// accepted.
+    pub fn leap_vote(&self) -> NtpLeapIndicator {
+        self.last_leap.unwrap_or(NtpLeapIndicator::Unknown)
+    }
+
+    /// Recent `(localtime, offset, jitter)` samples of this peer in
+    /// chronological order, with offset and jitter in seconds. The jitter of
+    /// a sample is its offset difference with the preceding sample.
+    pub fn offset_history(&self) -> &VecDeque<(NtpTimestamp, f64, f64)> {
+        &self.offset_history
+    }
+
+    /// Recent changes of the poll interval of this peer in chronological
+    /// order, each with the moment of the first poll at the new interval.
+    pub fn poll_interval_history(&self) -> &VecDeque<(NtpInstant, PollInterval)> {
+        &self.poll_interval_history
+    }
+
+    /// Export the persistable part of the state of this peer at local time
+    /// `now`. The filter state is kept by the clock controller, so it is left
+    /// empty here; [`System::export_peer_state`](crate::System::export_peer_state)
+    /// includes it.
+    pub fn export_state(&self, now: NtpTimestamp) -> SavedPeerState {
+        SavedPeerState {
+            time: now,
+            reach: self.reach,
+            backoff_interval: self.backoff_interval,
+            remote_min_poll_interval: self.remote_min_poll_interval,
+            offset_history: self.offset_history.iter().copied().collect(),
+            filter: None,
+        }
+    }
+
+    /// Restore previously exported state at local time `now`.
+    ///
+    /// Offset samples that have become too old are dropped, and when the
+    /// saved state as a whole is too old only the still valid samples are
+    /// kept, as the reachability and poll information is no longer relevant.
+    pub fn restore_state(&mut self, state: SavedPeerState, now: NtpTimestamp) {
+        let max_age = NtpDuration::from_seconds(MAX_SAVED_STATE_AGE);
+        let is_fresh = |time: NtpTimestamp| {
+            let age = now - time;
+            age >= NtpDuration::ZERO && age <= max_age
+        };
+
+        if is_fresh(state.time) {
+            let limits = self.peer_defaults_config.poll_interval_limits;
+            self.reach = state.reach;
+            self.backoff_interval = state.backoff_interval.max(limits.min).min(limits.max);
+            self.remote_min_poll_interval = state
+                .remote_min_poll_interval
+                .max(limits.min)
+                .min(limits.max);
+        }
+
+        let max_length = self.peer_defaults_config.offset_history_length;
+        let mut history: VecDeque<_> = state
+            .offset_history
+            .into_iter()
+            .filter(|(time, _, _)| is_fresh(*time))
+            .collect();
+        while history.len() > max_length {
+            history.pop_front();
+        }
+        self.offset_history = history;
+    }
+
+    fn record_poll_interval(&mut self, poll_interval: PollInterval) {
+        let max_length = self.peer_defaults_config.poll_interval_history_length;
+        if max_length == 0 {
+            self.poll_interval_history.clear();
+            return;
+        }
+
+        if let Some((_, last)) = self.poll_interval_history.back() {
+            if *last == poll_interval {
+                return;
+            }
+        }
+
+        while self.poll_interval_history.len() >= max_length {
+            self.poll_interval_history.pop_front();
+        }
+        self.poll_interval_history
+            .push_back((NtpInstant::now(), poll_interval));
+    }
+
+    fn record_offset(&mut self, measurement: &Measurement) {
+        let max_length = self.peer_defaults_config.offset_history_length;
+        if max_length == 0 {
+            self.offset_history.clear();
+            return;
+        }
+
+        let offset = measurement.offset.to_seconds();
+        let jitter = self
+            .offset_history
+            .back()
+            .map(|(_, previous, _)| (offset - previous).abs())
+            .unwrap_or(0.0);
+
+        while self.offset_history.len() >= max_length {
+            self.offset_history.pop_front();
+        }
+        self.offset_history
+            .push_back((measurement.localtime, offset, jitter));
+    }
+
+    /// Tear down the association. Any packet handled afterwards, for example
+    /// one that was already queued, is ignored.
+    pub fn demobilize(&mut self) {
+        self.state = PeerState::Demobilized;
+        self.current_request_identifier = None;
+        self.unanswered_request_identifiers.clear();
+        self.previous_exchange = None;
+        self.interleaved_request = None;
+    }
+
+    /// Forget which of the recent polls were answered, for example when the
+    /// server is known to be back after maintenance. The peer counts as
+    /// unreachable until it answers again, but its measurements and history
+    /// are kept.
+    pub fn reset_reachability(&mut self) {
+        self.reach = Reach::default();
+        // Give the peer the same number of tries as at startup before it is
+        // given up on
+        self.tries = 0;
+
+        if !self.is_demobilized() {
+            self.state = if self.last_measurement.is_none() {
+                PeerState::Initializing
+            } else {
+                PeerState::Unreachable
+            };
+        }
+    }
+
+    /// Timestamps of the exchange the most recent measurement was computed
+    /// from: our send time, the server's receive and transmit times and our
+    /// receive time, in that order.
+    pub fn last_exchange(
+        &self,
+    ) -> Option<(NtpTimestamp, NtpTimestamp, NtpTimestamp, NtpTimestamp)> {
+        self.last_provenance.map(|provenance| {
+            (
+                provenance.send_timestamp,
+                provenance.server_receive_timestamp,
+                provenance.server_transmit_timestamp,
+                provenance.recv_timestamp,
+            )
+        })
+    }
+
+    /// Whether the last measurement of this peer was taken at most `max_age`
+    /// before the local time `now`, so that it is still recent enough to
+    /// steer by. A peer without measurements is never fresh.
+    pub fn is_measurement_fresh(&self, now: NtpTimestamp, max_age: NtpDuration) -> bool {
+        // Measurements are taken halfway through the exchange
+        self.last_exchange().map_or(false, |(t1, _, _, t4)| {
+            now - (t1 + (t4 - t1) / 2) <= max_age
+        })
+    }
+
+    /// Interval around the last measured offset that should contain the true
+    /// offset at local time `now`: the offset plus or minus the root distance,
+    /// which grows as the measurement ages.
+    fn correctness_interval(&self, now: NtpTimestamp) -> Option<(NtpDuration, NtpDuration)> {
+        let measurement = self.last_measurement?;
+        let age = std::cmp::max(now - measurement.localtime, NtpDuration::ZERO);
+        let root_distance = root_distance_from_parts(
+            measurement.root_delay,
+            measurement.root_dispersion,
+            measurement.delay,
+            NtpDuration::ZERO,
+            0.0,
+            age,
+        );
+
+        Some((
+            measurement.offset - root_distance,
+            measurement.offset + root_distance,
+        ))
+    }
+
+    /// How long before the local time `now` the last accepted measurement of
+    /// this peer was taken, which is `None` until the peer has provided one
+    pub fn time_since_last_measurement(&self, now: NtpTimestamp) -> Option<NtpDuration> {
+        self.last_measurement
+            .map(|measurement| now - measurement.localtime)
+    }
+
+    pub fn is_demobilized(&self) -> bool {
+        self.state == PeerState::Demobilized
+    }
+
+    pub fn state(&self) -> PeerState {
+        self.state
+    }
+
+    /// Count a poll that was not answered within the response timeout
+    pub fn record_missed_response(&mut self) {
+        self.missed_responses += 1;
+    }
+
+    pub fn missed_responses(&self) -> u64 {
+        self.missed_responses
+    }
+
     pub fn generate_poll_message<'a>(
         &mut self,
         buf: &'a mut [u8],
         system: SystemSnapshot,
     ) -> Result<(&'a [u8], PeerSnapshot), PollError> {
         if !self.reach.is_reachable() && self.tries >= STARTUP_TRIES_THRESHOLD {
+            self.state = PeerState::Unreachable;
             return Err(PollError::PeerUnreachable);
         }
 
@@ -491,8 +1102,51 @@ impl Peer {
                 ProtocolVersion::V5 => NtpPacket::poll_message_v5(poll_interval),
             },
         };
+        packet.set_precision(
+            self.peer_defaults_config
+                .advertised_precision
+                .unwrap_or_else(|| system.time_snapshot.precision.log2().clamp(-32, 0)),
+        );
+
+        // The previous request was not answered, but its response may
+        // still arrive
+        if let Some((unanswered, _)) = self.current_request_identifier.take() {
+            if self.unanswered_request_identifiers.len() == LATE_RESPONSE_HISTORY {
+                self.unanswered_request_identifiers.pop_front();
+            }
+            self.unanswered_request_identifiers.push_back(unanswered);
+        }
         self.current_request_identifier = Some((identifier, NtpInstant::now() + POLL_WINDOW));
 
+        // A completed exchange can only be completed by an interleaved
+        // response to the request directly following it. Interleaved mode is
+        // not supported together with NTS.
+        self.interleaved_request = match self.previous_exchange.take() {
+            Some(previous) if self.peer_defaults_config.interleaved && self.nts.is_none() => packet
+                .request_interleaved(previous.server_receive_timestamp)
+                .map(|identifier| (identifier, previous)),
+            _ => None,
+        };
+
+        // Either continue the current burst, or start a new one
+        self.burst_remaining = match self.burst_remaining {
+            0 => self.peer_defaults_config.burst_count.saturating_sub(1),
+            remaining => remaining - 1,
+        };
+
+        if !self.is_demobilized() {
+            self.state = if self.burst_remaining > 0 {
+                PeerState::Bursting
+            } else if self.reach.is_reachable() {
+                PeerState::Reachable
+            } else if self.last_measurement.is_none() {
+                PeerState::Initializing
+            } else {
+                // The last response has been shifted out of the reach register
+                PeerState::Unreachable
+            };
+        }
+
         // Ensure we don't spam the remote with polls if it is not reachable
         self.backoff_interval = poll_interval.inc(self.peer_defaults_config.poll_interval_limits);
 
@@ -509,15 +1163,53 @@ impl Peer {
             &self.nts.as_ref().map(|nts| nts.c2s.as_ref()),
             None,
         )?;
-        let used = cursor.position();
-        let result = &cursor.into_inner()[..used as usize];
+        let mut used = cursor.position() as usize;
+        let buf = cursor.into_inner();
+
+        if let Some(mac_key) = &self.mac_key {
+            let (packet, rest) = buf.split_at_mut(used);
+            let mut cursor = Cursor::new(rest);
+            mac_key.sign(packet, &mut cursor)?;
+            used += cursor.position() as usize;
+        }
+        let result = &buf[..used];
 
         // update the poll interval
         self.last_poll_interval = poll_interval;
+        self.record_poll_interval(poll_interval);
 
         Ok((result, PeerSnapshot::from_peer(self)))
     }
 
+    /// Drive the peer without any sockets or timers: generate a poll message
+    /// when there is no `incoming` packet, and otherwise process `incoming`
+    /// as the response to a poll sent at `send_time` and received at
+    /// `recv_time`.
+    pub fn step(
+        &mut self,
+        system: SystemSnapshot,
+        now: NtpInstant,
+        incoming: Option<&[u8]>,
+        send_time: NtpTimestamp,
+        recv_time: NtpTimestamp,
+    ) -> StepOutcome {
+        match incoming {
+            None => {
+                let mut buf = [0; 1024];
+                match self.generate_poll_message(&mut buf, system) {
+                    Ok((packet, snapshot)) => StepOutcome::Poll(packet.to_vec(), snapshot),
+                    Err(error) => StepOutcome::PollFailed(error),
+                }
+            }
+            Some(message) => {
+                match self.handle_incoming(system, message, now, send_time, recv_time) {
+                    Ok(update) => StepOutcome::Update(update),
+                    Err(reason) => StepOutcome::Ignored(reason),
+                }
+            }
+        }
+    }
+
     #[instrument(skip(self, system), fields(peer = debug(self.source_id)))]
     pub fn handle_incoming(
         &mut self,
@@ -527,6 +1219,18 @@ impl Peer {
         send_time: NtpTimestamp,
         recv_time: NtpTimestamp,
     ) -> Result<Update, IgnoreReason> {
+        if self.is_demobilized() {
+            debug!("Received packet after demobilization, ignoring");
+            return Err(IgnoreReason::Demobilized);
+        }
+
+        if let Some(mac_key) = &self.mac_key {
+            if !mac_key.verify(message) {
+                warn!("received packet without a valid MAC");
+                return Err(IgnoreReason::InvalidMac);
+            }
+        }
+
         let message =
             match NtpPacket::deserialize(message, &self.nts.as_ref().map(|nts| nts.s2c.as_ref())) {
                 Ok((packet, _)) => packet,
@@ -544,10 +1248,7 @@ impl Peer {
             Some((next_expected_origin, validity)) if validity >= NtpInstant::now() => {
                 next_expected_origin
             }
-            _ => {
-                debug!("Received old/unexpected packet from peer");
-                return Err(IgnoreReason::InvalidPacketTime);
-            }
+            _ => return Err(self.unexpected_response_reason(&message)),
         };
 
         #[cfg(feature = "ntpv5")]
@@ -567,15 +1268,27 @@ impl Peer {
             }
         }
 
-        if !message.valid_server_response(request_identifier, self.nts.is_some()) {
+        // An interleaved response to the current request echoes the
+        // identifier from the receive timestamp of the request instead.
+        let interleaved = match self.interleaved_request {
+            Some((identifier, previous))
+                if message.valid_server_response(identifier, self.nts.is_some()) =>
+            {
+                Some(previous)
+            }
+            _ => None,
+        };
+
+        if !message.valid_server_response(request_identifier, self.nts.is_some())
+            && interleaved.is_none()
+        {
             // Packets should be a response to a previous request from us,
             // if not just ignore. Note that this might also happen when
             // we reset between sending the request and receiving the response.
             // We do this as the first check since accepting even a KISS
             // packet that is not a response will leave us vulnerable
             // to denial of service attacks.
-            debug!("Received old/unexpected packet from peer");
-            Err(IgnoreReason::InvalidPacketTime)
+            Err(self.unexpected_response_reason(&message))
         } else if message.is_kiss_rate() {
             // KISS packets may not have correct timestamps at all, handle them anyway
             self.remote_min_poll_interval = Ord::max(
@@ -583,12 +1296,17 @@ impl Peer {
                     .inc(self.peer_defaults_config.poll_interval_limits),
                 self.last_poll_interval,
             );
+            self.burst_remaining = 0;
             warn!(?self.remote_min_poll_interval, "Peer requested rate limit");
             Err(IgnoreReason::KissIgnore)
-        } else if message.is_kiss_rstr() || message.is_kiss_deny() {
+        } else if message.is_kiss_deny() {
             warn!("Peer denied service");
             // KISS packets may not have correct timestamps at all, handle them anyway
             Err(IgnoreReason::KissDemobilize)
+        } else if message.is_kiss_rstr() {
+            warn!("Peer restricted service");
+            // KISS packets may not have correct timestamps at all, handle them anyway
+            Err(IgnoreReason::KissRestrict)
         } else if message.is_kiss_ntsn() {
             warn!("Received nts not-acknowledge");
             // as these can be easily faked, we dont immediately give up on receiving
@@ -608,13 +1326,104 @@ impl Peer {
                 message.stratum()
             );
             Err(IgnoreReason::InvalidStratum)
+        } else if message.root_dispersion() > self.peer_defaults_config.reject_dispersion_above {
+            warn!(
+                root_dispersion = ?message.root_dispersion(),
+                "Received message from server with excessive root dispersion"
+            );
+            Err(IgnoreReason::ExcessiveDispersion)
         } else if message.mode() != NtpAssociationMode::Server {
             // we currently only support a client <-> server association
             warn!("Received packet with invalid mode");
             Err(IgnoreReason::InvalidMode)
+        } else if let Err(error) = message.header().validate() {
+            warn!(?error, "Received packet with malformed header");
+            Err(IgnoreReason::MalformedHeader)
+        } else if recv_time - send_time < NtpDuration::ZERO - system.time_snapshot.precision {
+            // Our clock went backwards between sending and receiving, any
+            // measurement derived from these timestamps would be nonsense
+            warn!("Received packet before it was sent, ignoring");
+            Err(IgnoreReason::InvalidTimestampOrder)
+        } else if self.reference_too_old(&message) {
+            warn!("Server has not synchronized its clock for too long, ignoring");
+            Err(IgnoreReason::StaleReference)
+        } else if self.reference_in_future(&message, recv_time) {
+            warn!("Server reference timestamp lies in our future, ignoring");
+            Err(IgnoreReason::FutureReference)
+        } else if self.transmit_timestamp_frozen(message.transmit_timestamp()) {
+            // A server echoing the same transmit timestamp over and over is
+            // broken (or spoofed), its offsets are meaningless
+            warn!("Server transmit timestamp is not advancing, ignoring");
+            Err(IgnoreReason::FrozenTransmitTimestamp)
+        } else {
+            Ok(self.process_message(
+                system,
+                message,
+                interleaved,
+                local_clock_time,
+                send_time,
+                recv_time,
+            ))
+        }
+    }
+
+    /// Reason to ignore a packet that is not a response to our current
+    /// request: it may still answer one of our earlier requests, as happens
+    /// on networks that reorder or delay packets.
+    fn unexpected_response_reason(&self, message: &NtpPacket) -> IgnoreReason {
+        let nts = self.nts.is_some();
+        let late = self
+            .unanswered_request_identifiers
+            .iter()
+            .chain(self.current_request_identifier.iter().map(|(id, _)| id))
+            .any(|identifier| message.valid_server_response(*identifier, nts));
+
+        if late {
+            debug!("Received late response to an earlier request");
+            IgnoreReason::LateResponse
+        } else {
+            debug!("Received old/unexpected packet from peer");
+            IgnoreReason::InvalidPacketTime
+        }
+    }
+
+    fn reference_too_old(&self, message: &NtpPacket) -> bool {
+        let Some(max_age) = self.peer_defaults_config.max_reference_age else {
+            return false;
+        };
+
+        match message.reference_timestamp() {
+            // A zero reference timestamp means the server doesn't report one
+            Some(reference) if reference != NtpTimestamp::default() => {
+                message.transmit_timestamp() - reference > max_age
+            }
+            _ => false,
+        }
+    }
+
+    fn reference_in_future(&self, message: &NtpPacket, recv_time: NtpTimestamp) -> bool {
+        let Some(tolerance) = self.peer_defaults_config.future_reference_tolerance else {
+            return false;
+        };
+
+        match message.reference_timestamp() {
+            // A zero reference timestamp means the server doesn't report one
+            Some(reference) if reference != NtpTimestamp::default() => {
+                reference - recv_time > tolerance
+            }
+            _ => false,
+        }
+    }
+
+    fn transmit_timestamp_frozen(&mut self, transmit_timestamp: NtpTimestamp) -> bool {
+        if self.last_transmit_timestamp == Some(transmit_timestamp) {
+            self.frozen_transmit_count += 1;
         } else {
-            Ok(self.process_message(system, message, local_clock_time, send_time, recv_time))
+            self.frozen_transmit_count = 0;
+            self.last_transmit_timestamp = Some(transmit_timestamp);
         }
+
+        self.frozen_transmit_count >= FROZEN_TRANSMIT_THRESHOLD
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -622,6 +1431,7 @@ impl Peer {
         &mut self,
         system: SystemSnapshot,
         message: NtpPacket,
+        interleaved: Option<PreviousExchange>,
         local_clock_time: NtpInstant,
         send_time: NtpTimestamp,
         recv_time: NtpTimestamp,
@@ -633,8 +1443,14 @@ impl Peer {
         // Got a response, so no need for unreachability backoff
         self.backoff_interval = self.peer_defaults_config.poll_interval_limits.min;
 
+        // Bursting continues until the last packet of the burst is sent
+        if self.burst_remaining == 0 {
+            self.state = PeerState::Reachable;
+        }
+
         // we received this packet, and don't want to accept future ones with this next_expected_origin
         self.current_request_identifier = None;
+        self.interleaved_request = None;
 
         // Update stratum and reference id
         self.stratum = message.stratum();
@@ -642,8 +1458,11 @@ impl Peer {
 
         #[cfg(feature = "ntpv5")]
         if let NtpHeader::V5(header) = message.header() {
-            // Handle new requested poll interval
-            let requested_poll = message.poll();
+            // Handle new requested poll interval, bounded by our own limits
+            // as the value is fully controlled by the remote
+            let requested_poll = message
+                .poll()
+                .min(self.peer_defaults_config.poll_interval_limits.max);
             if requested_poll > self.remote_min_poll_interval {
                 debug!(
                     ?requested_poll,
@@ -682,14 +1501,51 @@ impl Peer {
             }
         }
 
-        // generate a measurement
-        let measurement = Measurement::from_packet(
+        // generate a measurement. In interleaved mode the server sends the
+        // (more accurate) transmit timestamp of its previous response, so the
+        // measurement describes the previous exchange.
+        let (t1, t2, t4) = match interleaved {
+            Some(previous) => {
+                trace!("Processing interleaved response");
+                (
+                    previous.send_timestamp,
+                    previous.server_receive_timestamp,
+                    previous.recv_timestamp,
+                )
+            }
+            None => (send_time, message.receive_timestamp(), recv_time),
+        };
+        let t3 = message.transmit_timestamp();
+        let mut measurement = Measurement::from_timestamps(
             &message,
-            send_time,
-            recv_time,
+            t1,
+            t2,
+            t3,
+            t4,
             local_clock_time,
             system.time_snapshot.precision,
         );
+        // The offset assumes both directions take equally long, a known
+        // difference between them shifts it by half that difference.
+        measurement.offset += self.delay_asymmetry_correction / 2;
+        self.previous_exchange = Some(PreviousExchange {
+            send_timestamp: send_time,
+            server_receive_timestamp: message.receive_timestamp(),
+            recv_timestamp: recv_time,
+        });
+        let lost_synchronization =
+            self.leap_vote().is_synchronized() && !measurement.leap.is_synchronized();
+        self.last_leap = Some(measurement.leap);
+        self.record_offset(&measurement);
+        self.last_measurement = Some(measurement);
+        self.last_provenance = Some(SampleProvenance {
+            send_timestamp: t1,
+            server_receive_timestamp: t2,
+            server_transmit_timestamp: t3,
+            recv_timestamp: t4,
+            stratum: message.stratum(),
+            reference_id: message.reference_id(),
+        });
 
         // Process new cookies
         if let Some(nts) = self.nts.as_mut() {
@@ -698,31 +1554,93 @@ impl Peer {
             }
         }
 
+        // The server lost its own time source. Rather than handing over a
+        // measurement, let the system reconsider this peer, which is no
+        // longer a candidate.
+        if lost_synchronization {
+            warn!("Peer is no longer synchronized");
+            return Update::BareUpdate(PeerSnapshot::from_peer(self));
+        }
+
         Update::NewMeasurement(PeerSnapshot::from_peer(self), measurement)
     }
 
+    /// Process a measurement taken from a reference clock attached to this
+    /// machine instead of through a packet exchange. The reference clock
+    /// determines the stratum of this peer, and `reference_id` identifies it.
+    pub fn handle_refclock_measurement(
+        &mut self,
+        reference_id: ReferenceId,
+        measurement: Measurement,
+    ) -> Result<Update, IgnoreReason> {
+        if self.is_demobilized() {
+            debug!("Received measurement after demobilization, ignoring");
+            return Err(IgnoreReason::Demobilized);
+        }
+
+        if measurement.stratum > MAX_STRATUM {
+            warn!(
+                "Received measurement from reference clock with excessive stratum {}",
+                measurement.stratum
+            );
+            return Err(IgnoreReason::InvalidStratum);
+        }
+
+        // Every measurement is both the poll and its response
+        self.reach.poll();
+        self.reach.received_packet();
+        self.state = PeerState::Reachable;
+
+        self.stratum = measurement.stratum;
+        self.reference_id = reference_id;
+        self.last_leap = Some(measurement.leap);
+        self.record_offset(&measurement);
+        self.last_measurement = Some(measurement);
+        self.last_provenance = None;
+
+        Ok(Update::NewMeasurement(
+            PeerSnapshot::from_peer(self),
+            measurement,
+        ))
+    }
+
     #[cfg(test)]
     pub(crate) fn test_peer() -> Self {
         use std::net::Ipv4Addr;
 
         Peer {
             nts: None,
+            mac_key: None,
 
             last_poll_interval: PollInterval::default(),
             backoff_interval: PollInterval::default(),
             remote_min_poll_interval: PollInterval::default(),
 
+            burst_remaining: 0,
             current_request_identifier: None,
+            unanswered_request_identifiers: VecDeque::new(),
+            previous_exchange: None,
+            interleaved_request: None,
+            last_measurement: None,
+            last_provenance: None,
 
             source_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
             source_id: ReferenceId::from_int(0),
             reach: Reach::default(),
             tries: 0,
+            last_leap: None,
+            offset_history: VecDeque::new(),
+            poll_interval_history: VecDeque::new(),
+            missed_responses: 0,
+            last_transmit_timestamp: None,
+            frozen_transmit_count: 0,
+            state: PeerState::Initializing,
 
             stratum: 0,
             reference_id: ReferenceId::from_int(0),
 
             peer_defaults_config: SourceDefaultsConfig::default(),
+            delay_asymmetry_correction: NtpDuration::ZERO,
 
             protocol_version: Default::default(),
 
@@ -732,6 +1650,16 @@ impl Peer {
     }
 }
 
+/// Whether the correctness intervals of two peers overlap at local time
+/// `now`, i.e. whether their last measurements agree on the time. Peers
+/// without a measurement agree with nobody.
+pub fn intervals_overlap(a: &Peer, b: &Peer, now: NtpTimestamp) -> bool {
+    match (a.correctness_interval(now), b.correctness_interval(now)) {
+        (Some((a_low, a_high)), Some((b_low, b_high))) => a_low <= b_high && b_low <= a_high,
+        _ => false,
+    }
+}
+
 #[cfg(feature = "__internal-fuzz")]
 pub fn fuzz_measurement_from_packet(
     client: u64,
@@ -767,8 +1695,7 @@ mod test {
     use super::*;
     #[cfg(feature = "ntpv5")]
     use crate::packet::v5::server_reference_id::ServerId;
-    #[cfg(feature = "ntpv5")]
-    use rand::thread_rng;
+    use rand::{thread_rng, Rng};
     use std::time::Duration;
 
     #[derive(Debug, Clone, Default)]
@@ -812,6 +1739,47 @@ mod test {
         }
     }
 
+    /// Let `peer` poll, and build the response of a stratum 1 server to that
+    /// poll, after `respond` had the chance to adjust it
+    fn response_to_poll<'a>(
+        peer: &mut Peer,
+        system: SystemSnapshot,
+        respond: impl FnOnce(&NtpPacket, &mut NtpPacket<'a>),
+    ) -> NtpPacket<'a> {
+        let mut buf = [0; 1024];
+        let poll = peer.generate_poll_message(&mut buf, system).unwrap().0;
+        let poll = NtpPacket::deserialize(poll, &NoCipher).unwrap().0;
+
+        let mut response = NtpPacket::test();
+        response.set_stratum(1);
+        response.set_mode(NtpAssociationMode::Server);
+        response.set_origin_timestamp(poll.transmit_timestamp());
+        response.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
+        response.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+        respond(&poll, &mut response);
+        response
+    }
+
+    /// Let `peer` poll and handle the response built by `respond`, as if the
+    /// poll was sent at `send_time` and the response received at `recv_time`
+    fn exchange(
+        peer: &mut Peer,
+        system: SystemSnapshot,
+        now: NtpInstant,
+        send_time: NtpTimestamp,
+        recv_time: NtpTimestamp,
+        respond: impl FnOnce(&NtpPacket, &mut NtpPacket),
+    ) -> Result<Update, IgnoreReason> {
+        let response = response_to_poll(peer, system, respond);
+        peer.handle_incoming(
+            system,
+            &response.serialize_without_encryption_vec(None).unwrap(),
+            now,
+            send_time,
+            recv_time,
+        )
+    }
+
     #[test]
     fn test_measurement_from_packet() {
         let instant = NtpInstant::now();
@@ -852,10 +1820,175 @@ mod test {
         );
         assert_eq!(result.offset, NtpDuration::from_fixed_int(1));
         assert_eq!(result.delay, NtpDuration::from_fixed_int(1));
+
+        // our clock stepped backwards between sending and receiving, the
+        // round trip must not become negative
+        packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(1));
+        packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(1));
+        let result = Measurement::from_packet(
+            &packet,
+            NtpTimestamp::from_fixed_int(5),
+            NtpTimestamp::from_fixed_int(3),
+            instant,
+            NtpDuration::from_exponent(-32),
+        );
+        assert_eq!(result.delay, NtpDuration::from_exponent(-32));
+        assert!(result.delay >= NtpDuration::ZERO);
     }
 
     #[test]
-    fn reachability() {
+    fn test_measurement_serialize() {
+        use serde_test::{assert_ser_tokens, Token};
+
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(0.5),
+            offset: NtpDuration::from_seconds(-0.25),
+            transmit_timestamp: NtpTimestamp::from_fixed_int(1),
+            receive_timestamp: NtpTimestamp::from_fixed_int(2),
+            localtime: NtpTimestamp::from_fixed_int(3),
+            monotime: NtpInstant::now(),
+            stratum: 2,
+            root_delay: NtpDuration::from_seconds(0.125),
+            root_dispersion: NtpDuration::from_seconds(1.0),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: -20,
+        };
+
+        // durations are in seconds, the monotonic time is left out
+        assert_ser_tokens(
+            &measurement,
+            &[
+                Token::Struct {
+                    name: "Measurement",
+                    len: 10,
+                },
+                Token::Str("delay"),
+                Token::F64(measurement.delay.to_seconds()),
+                Token::Str("offset"),
+                Token::F64(measurement.offset.to_seconds()),
+                Token::Str("transmit_timestamp"),
+                Token::Struct {
+                    name: "NtpTimestamp",
+                    len: 1,
+                },
+                Token::Str("timestamp"),
+                Token::U64(1),
+                Token::StructEnd,
+                Token::Str("receive_timestamp"),
+                Token::Struct {
+                    name: "NtpTimestamp",
+                    len: 1,
+                },
+                Token::Str("timestamp"),
+                Token::U64(2),
+                Token::StructEnd,
+                Token::Str("localtime"),
+                Token::Struct {
+                    name: "NtpTimestamp",
+                    len: 1,
+                },
+                Token::Str("timestamp"),
+                Token::U64(3),
+                Token::StructEnd,
+                Token::Str("stratum"),
+                Token::U8(2),
+                Token::Str("root_delay"),
+                Token::F64(measurement.root_delay.to_seconds()),
+                Token::Str("root_dispersion"),
+                Token::F64(measurement.root_dispersion.to_seconds()),
+                Token::Str("leap"),
+                Token::UnitVariant {
+                    name: "NtpLeapIndicator",
+                    variant: "NoWarning",
+                },
+                Token::Str("precision"),
+                Token::I8(-20),
+                Token::StructEnd,
+            ],
+        );
+
+        // reach is its bit pattern
+        let mut reach = Reach::default();
+        reach.received_packet();
+        reach.poll();
+        assert_ser_tokens(
+            &reach,
+            &[Token::NewtypeStruct { name: "Reach" }, Token::U8(0b10)],
+        );
+    }
+
+    #[test]
+    fn test_offset_and_delay() {
+        let precision = NtpDuration::from_exponent(-32);
+
+        let (offset, delay) = offset_and_delay(
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(1),
+            NtpTimestamp::from_fixed_int(2),
+            NtpTimestamp::from_fixed_int(3),
+            precision,
+        );
+        assert_eq!(offset, NtpDuration::from_fixed_int(0));
+        assert_eq!(delay, NtpDuration::from_fixed_int(2));
+
+        let (offset, delay) = offset_and_delay(
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(2),
+            NtpTimestamp::from_fixed_int(3),
+            NtpTimestamp::from_fixed_int(3),
+            precision,
+        );
+        assert_eq!(offset, NtpDuration::from_fixed_int(1));
+        assert_eq!(delay, NtpDuration::from_fixed_int(2));
+
+        let (offset, delay) = offset_and_delay(
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(5),
+            NtpTimestamp::from_fixed_int(3),
+            precision,
+        );
+        assert_eq!(offset, NtpDuration::from_fixed_int(1));
+        assert_eq!(delay, NtpDuration::from_fixed_int(1));
+
+        // a server claiming to take longer than the round trip doesn't give a negative delay
+        let (_, delay) = offset_and_delay(
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(10),
+            NtpTimestamp::from_fixed_int(3),
+            precision,
+        );
+        assert_eq!(delay, precision);
+    }
+
+    #[test]
+    fn test_root_distance_from_parts() {
+        // (10ms + 20ms) / 2 + 5ms + 1ms + 2ms + 1000s * 15ppm
+        let root_distance = root_distance_from_parts(
+            NtpDuration::from_seconds(0.020),
+            NtpDuration::from_seconds(0.005),
+            NtpDuration::from_seconds(0.010),
+            NtpDuration::from_seconds(0.001),
+            0.002,
+            NtpDuration::from_seconds(1000.0),
+        );
+        assert!((root_distance.to_seconds() - 0.038).abs() < 1e-9);
+
+        // a fresh measurement only has the delays and dispersions
+        let root_distance = root_distance_from_parts(
+            NtpDuration::from_seconds(0.020),
+            NtpDuration::from_seconds(0.005),
+            NtpDuration::from_seconds(0.010),
+            NtpDuration::ZERO,
+            0.0,
+            NtpDuration::ZERO,
+        );
+        assert!((root_distance.to_seconds() - 0.020).abs() < 1e-9);
+    }
+
+    #[test]
+    fn reachability() {
         let mut reach = Reach::default();
 
         // the default reach register value is 0, and hence not reachable
@@ -901,7 +2034,13 @@ mod test {
         macro_rules! accept {
             () => {{
                 let snapshot = PeerSnapshot::from_peer(&peer);
-                snapshot.accept_synchronization(16, &["127.0.0.1".parse().unwrap()], &system)
+                snapshot.accept_synchronization(
+                    16,
+                    15,
+                    &["127.0.0.1".parse().unwrap()],
+                    &[],
+                    &system,
+                )
             }};
         }
 
@@ -919,6 +2058,181 @@ mod test {
         assert_eq!(accept!(), Err(Stratum));
     }
 
+    #[test]
+    fn test_max_acceptable_stratum() {
+        let mut peer = Peer::test_peer();
+        peer.source_id = ReferenceId::from_ip("127.0.1.1".parse().unwrap());
+        peer.reference_id = ReferenceId::from_ip("10.0.0.1".parse().unwrap());
+        peer.reach.received_packet();
+        peer.stratum = 4;
+
+        #[cfg_attr(not(feature = "ntpv5"), allow(unused_mut))]
+        let mut system = SystemSnapshot::default();
+
+        #[cfg(feature = "ntpv5")]
+        {
+            system.server_id = ServerId::new(&mut thread_rng());
+        }
+
+        let local_ips = ["127.0.0.1".parse().unwrap()];
+        let config = crate::config::SynchronizationConfig::default();
+        let snapshot = PeerSnapshot::from_peer(&peer);
+
+        assert_eq!(
+            snapshot.accept_synchronization(
+                config.local_stratum,
+                config.max_acceptable_stratum,
+                &local_ips,
+                &[],
+                &system
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            snapshot.accept_synchronization(config.local_stratum, 3, &local_ips, &[], &system),
+            Err(AcceptSynchronizationError::Stratum)
+        );
+    }
+
+    #[test]
+    fn test_downstream_loop() {
+        let mut peer = Peer::test_peer();
+        peer.source_id = ReferenceId::from_ip("127.0.1.1".parse().unwrap());
+        peer.reference_id = ReferenceId::from_ip("10.0.0.3".parse().unwrap());
+        peer.reach.received_packet();
+        peer.stratum = 3;
+
+        #[cfg_attr(not(feature = "ntpv5"), allow(unused_mut))]
+        let mut system = SystemSnapshot::default();
+
+        #[cfg(feature = "ntpv5")]
+        {
+            system.server_id = ServerId::new(&mut thread_rng());
+        }
+
+        let local_ips = ["127.0.0.1".parse().unwrap()];
+        let snapshot = PeerSnapshot::from_peer(&peer);
+
+        // the peer does not use us directly
+        assert_eq!(
+            snapshot.accept_synchronization(16, 15, &local_ips, &[], &system),
+            Ok(())
+        );
+
+        // but it does use a server that gets its time from us
+        let downstream_ids = [
+            ReferenceId::from_ip("10.0.0.2".parse().unwrap()),
+            ReferenceId::from_ip("10.0.0.3".parse().unwrap()),
+        ];
+        assert_eq!(
+            snapshot.accept_synchronization(16, 15, &local_ips, &downstream_ids, &system),
+            Err(AcceptSynchronizationError::Loop)
+        );
+    }
+
+    #[test]
+    fn test_unset_reference_id() {
+        let mut peer = Peer::test_peer();
+        peer.source_id = ReferenceId::from_ip("127.0.1.1".parse().unwrap());
+        peer.reference_id = ReferenceId::from_int(0);
+        peer.reach.received_packet();
+        peer.stratum = 3;
+
+        #[cfg_attr(not(feature = "ntpv5"), allow(unused_mut))]
+        let mut system = SystemSnapshot::default();
+
+        #[cfg(feature = "ntpv5")]
+        {
+            system.server_id = ServerId::new(&mut thread_rng());
+        }
+
+        let local_ips = ["127.0.0.1".parse().unwrap()];
+
+        // A stratum 3 server that doesn't say what it synchronizes to
+        let snapshot = PeerSnapshot::from_peer(&peer);
+        assert_eq!(
+            snapshot.accept_synchronization(16, 15, &local_ips, &[], &system),
+            Err(AcceptSynchronizationError::UnsetReferenceId)
+        );
+
+        // A stratum 1 server names its reference clock instead
+        peer.stratum = 1;
+        let snapshot = PeerSnapshot::from_peer(&peer);
+        assert_eq!(
+            snapshot.accept_synchronization(16, 15, &local_ips, &[], &system),
+            Ok(())
+        );
+
+        peer.stratum = 3;
+        peer.reference_id = ReferenceId::from_ip("10.0.0.1".parse().unwrap());
+        let snapshot = PeerSnapshot::from_peer(&peer);
+        assert_eq!(
+            snapshot.accept_synchronization(16, 15, &local_ips, &[], &system),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_burst() {
+        let mut peer = Peer::test_peer();
+        peer.peer_defaults_config.burst_count = 3;
+        peer.peer_defaults_config.burst_interval = PollInterval::test_new(1);
+        let system = SystemSnapshot::default();
+        let mut buf = [0; 1024];
+
+        // Two more packets follow the first at the burst interval
+        for _ in 0..2 {
+            peer.generate_poll_message(&mut buf, system).unwrap();
+            assert_eq!(peer.next_poll_interval(system), PollInterval::test_new(1));
+        }
+
+        // after which we return to the regular poll interval
+        peer.generate_poll_message(&mut buf, system).unwrap();
+        assert_eq!(
+            peer.next_poll_interval(system),
+            peer.current_poll_interval(system)
+        );
+        assert!(peer.next_poll_interval(system) > PollInterval::test_new(1));
+    }
+
+    #[test]
+    fn test_poll_interval_seconds() {
+        let peer = Peer::test_peer();
+        let mut system = SystemSnapshot::default();
+        system.time_snapshot.poll_interval = PollInterval::test_new(6);
+
+        assert_eq!(peer.poll_interval_seconds(system), 64.0);
+    }
+
+    #[test]
+    fn test_next_poll_deadline() {
+        let mut peer = Peer::test_peer();
+        peer.peer_defaults_config.burst_count = 2;
+        peer.peer_defaults_config.burst_interval = PollInterval::test_new(1);
+        let system = SystemSnapshot::default();
+        let mut buf = [0; 1024];
+        let sent = NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0);
+
+        // The second packet of the burst follows at the burst interval
+        peer.generate_poll_message(&mut buf, system).unwrap();
+        assert_eq!(
+            peer.next_poll_deadline(system, sent),
+            sent + PollInterval::test_new(1).as_duration()
+        );
+
+        // after which the poll interval applies, clamped to the limits
+        peer.generate_poll_message(&mut buf, system).unwrap();
+        let poll_interval = system
+            .time_snapshot
+            .poll_interval
+            .max(peer.backoff_interval)
+            .max(peer.remote_min_poll_interval);
+        assert_eq!(
+            peer.next_poll_deadline(system, sent),
+            sent + poll_interval.as_duration()
+        );
+    }
+
     #[test]
     fn test_poll_interval() {
         let base = NtpInstant::now();
@@ -933,93 +2247,1131 @@ mod test {
         assert!(peer.current_poll_interval(system) >= peer.remote_min_poll_interval);
         assert!(peer.current_poll_interval(system) >= system.time_snapshot.poll_interval);
 
-        system.time_snapshot.poll_interval = PollIntervalLimits::default().min;
-        peer.remote_min_poll_interval = PollIntervalLimits::default().max;
+        system.time_snapshot.poll_interval = PollIntervalLimits::default().min;
+        peer.remote_min_poll_interval = PollIntervalLimits::default().max;
+
+        assert!(peer.current_poll_interval(system) >= peer.remote_min_poll_interval);
+        assert!(peer.current_poll_interval(system) >= system.time_snapshot.poll_interval);
+
+        peer.remote_min_poll_interval = PollIntervalLimits::default().min;
+
+        let prev = peer.current_poll_interval(system);
+        let response = response_to_poll(&mut peer, system, |_, _| {});
+        assert!(peer.current_poll_interval(system) > prev);
+        assert!(peer
+            .handle_incoming(
+                system,
+                &response.serialize_without_encryption_vec(None).unwrap(),
+                base,
+                NtpTimestamp::default(),
+                NtpTimestamp::default()
+            )
+            .is_ok());
+        assert_eq!(peer.current_poll_interval(system), prev);
+
+        let prev = peer.current_poll_interval(system);
+        let response = response_to_poll(&mut peer, system, |_, response| {
+            response.set_stratum(0);
+            response.set_reference_id(ReferenceId::KISS_RATE);
+        });
+        assert!(peer.current_poll_interval(system) > prev);
+        assert!(peer
+            .handle_incoming(
+                system,
+                &response.serialize_without_encryption_vec(None).unwrap(),
+                base,
+                NtpTimestamp::default(),
+                NtpTimestamp::default()
+            )
+            .is_err());
+        assert!(peer.current_poll_interval(system) > prev);
+        assert!(peer.remote_min_poll_interval > prev);
+    }
+
+    #[test]
+    fn test_handle_incoming() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+
+        let system = SystemSnapshot::default();
+        let response = response_to_poll(&mut peer, system, |_, _| {})
+            .serialize_without_encryption_vec(None)
+            .unwrap();
+
+        assert!(peer
+            .handle_incoming(
+                system,
+                &response,
+                base + Duration::from_secs(1),
+                NtpTimestamp::from_fixed_int(0),
+                NtpTimestamp::from_fixed_int(400)
+            )
+            .is_ok());
+        // the same response is not accepted twice
+        assert!(peer
+            .handle_incoming(
+                system,
+                &response,
+                base + Duration::from_secs(1),
+                NtpTimestamp::from_fixed_int(0),
+                NtpTimestamp::from_fixed_int(500)
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_is_candidate() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        let system = SystemSnapshot::default();
+
+        // a fresh peer has no measurements yet
+        assert!(!peer.is_candidate());
+
+        // being reachable alone is not enough
+        peer.reach.received_packet();
+        assert!(!peer.is_candidate());
+
+        assert!(exchange(
+            &mut peer,
+            system,
+            base + Duration::from_secs(1),
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(400),
+            |_, _| {}
+        )
+        .is_ok());
+        assert!(peer.is_candidate());
+
+        // an unsynchronized peer is not a candidate
+        peer.last_leap = Some(NtpLeapIndicator::Unknown);
+        assert!(!peer.is_candidate());
+    }
+
+    #[test]
+    fn test_leap_vote() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        let system = SystemSnapshot::default();
+
+        assert_eq!(peer.leap_vote(), NtpLeapIndicator::Unknown);
+
+        assert!(exchange(
+            &mut peer,
+            system,
+            base + Duration::from_secs(1),
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(400),
+            |_, response| response.set_leap(NtpLeapIndicator::Leap61)
+        )
+        .is_ok());
+        assert_eq!(peer.leap_vote(), NtpLeapIndicator::Leap61);
+    }
+
+    #[test]
+    fn test_lost_synchronization() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        let system = SystemSnapshot::default();
+
+        let respond = |peer: &mut Peer, leap| {
+            exchange(
+                peer,
+                system,
+                base + Duration::from_secs(1),
+                NtpTimestamp::from_fixed_int(0),
+                NtpTimestamp::from_fixed_int(400),
+                |_, response| {
+                    response.set_stratum(2);
+                    response.set_leap(leap);
+                },
+            )
+        };
+
+        assert!(matches!(
+            respond(&mut peer, NtpLeapIndicator::NoWarning),
+            Ok(Update::NewMeasurement(_, _))
+        ));
+        assert!(peer.is_candidate());
+
+        // the server lost its upstream
+        assert!(matches!(
+            respond(&mut peer, NtpLeapIndicator::Unknown),
+            Ok(Update::BareUpdate(_))
+        ));
+        assert!(!peer.is_candidate());
+    }
+
+    #[test]
+    fn test_offset_history() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        let system = SystemSnapshot::default();
+
+        assert!(peer.offset_history().is_empty());
+
+        for i in 0..3 {
+            assert!(exchange(
+                &mut peer,
+                system,
+                base + Duration::from_secs(1),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(100 * i, 0),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(100 * i, 0),
+                |_, response| {
+                    let server_time =
+                        NtpTimestamp::from_seconds_nanos_since_ntp_era(100 * i + 1, 0);
+                    response.set_receive_timestamp(server_time);
+                    response.set_transmit_timestamp(server_time);
+                },
+            )
+            .is_ok());
+        }
+
+        let history = peer.offset_history();
+        assert_eq!(history.len(), 3);
+        assert!(history[0].0 < history[1].0);
+        assert!(history[1].0 < history[2].0);
+        for (_, offset, _) in history {
+            assert!((offset - 1.0).abs() < 1e-9);
+        }
+        assert_eq!(history[0].2, 0.0);
+    }
+
+    #[test]
+    fn test_poll_interval_history() {
+        let mut peer = Peer::test_peer();
+        peer.peer_defaults_config.poll_interval_limits = PollIntervalLimits {
+            min: PollInterval::test_new(4),
+            max: PollInterval::test_new(10),
+        };
+        let mut system = SystemSnapshot::default();
+        let mut buf = [0; 1024];
+
+        // disabled by default
+        system.time_snapshot.poll_interval = PollInterval::test_new(4);
+        peer.generate_poll_message(&mut buf, system).unwrap();
+        assert!(peer.poll_interval_history().is_empty());
+
+        peer.peer_defaults_config.poll_interval_history_length = 3;
+        for log2 in [4, 4, 5, 6, 6, 7] {
+            system.time_snapshot.poll_interval = PollInterval::test_new(log2);
+            peer.generate_poll_message(&mut buf, system).unwrap();
+            // as if the poll was answered
+            peer.reach.received_packet();
+            peer.backoff_interval = PollInterval::test_new(4);
+        }
+
+        // only changes are recorded, and only the most recent ones are kept
+        let history = peer.poll_interval_history();
+        let intervals: Vec<_> = history.iter().map(|(_, interval)| *interval).collect();
+        assert_eq!(
+            intervals,
+            [
+                PollInterval::test_new(5),
+                PollInterval::test_new(6),
+                PollInterval::test_new(7)
+            ]
+        );
+        assert!(history[0].0 <= history[1].0 && history[1].0 <= history[2].0);
+    }
+
+    #[test]
+    fn test_offset_history_bounded() {
+        let mut peer = Peer::test_peer();
+        peer.peer_defaults_config.offset_history_length = 2;
+
+        let mut measurement = Measurement::from_packet(
+            &NtpPacket::test(),
+            NtpTimestamp::default(),
+            NtpTimestamp::default(),
+            NtpInstant::now(),
+            NtpDuration::from_exponent(-32),
+        );
+        for i in 0..4 {
+            measurement.offset = NtpDuration::from_seconds(i as f64);
+            measurement.localtime = NtpTimestamp::from_seconds_nanos_since_ntp_era(i, 0);
+            peer.record_offset(&measurement);
+        }
+
+        let history = peer.offset_history();
+        assert_eq!(history.len(), 2);
+        assert!((history[0].1 - 2.0).abs() < 1e-9);
+        assert!((history[1].1 - 3.0).abs() < 1e-9);
+        assert!((history[1].2 - 1.0).abs() < 1e-9);
+
+        peer.peer_defaults_config.offset_history_length = 0;
+        peer.record_offset(&measurement);
+        assert!(peer.offset_history().is_empty());
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let now = NtpTimestamp::from_seconds_nanos_since_ntp_era(10_000, 0);
+
+        let mut peer = Peer::test_peer();
+        peer.reach.received_packet();
+        peer.backoff_interval = PollIntervalLimits::default().max;
+        let mut measurement = Measurement::from_packet(
+            &NtpPacket::test(),
+            NtpTimestamp::default(),
+            NtpTimestamp::default(),
+            NtpInstant::now(),
+            NtpDuration::from_exponent(-32),
+        );
+        for i in 0..3 {
+            measurement.offset = NtpDuration::from_seconds(0.001 * i as f64);
+            measurement.localtime = NtpTimestamp::from_seconds_nanos_since_ntp_era(9_900 + i, 0);
+            peer.record_offset(&measurement);
+        }
+
+        let state = peer.export_state(now);
+
+        let mut restored = Peer::test_peer();
+        restored.restore_state(state.clone(), now + NtpDuration::from_seconds(60.0));
+        assert!(restored.reach.is_reachable());
+        assert_eq!(restored.backoff_interval, PollIntervalLimits::default().max);
+        assert_eq!(restored.export_state(now), state);
+    }
+
+    #[test]
+    fn test_restore_drops_stale_state() {
+        let now = NtpTimestamp::from_seconds_nanos_since_ntp_era(100_000, 0);
+
+        let mut peer = Peer::test_peer();
+        peer.reach.received_packet();
+        let mut measurement = Measurement::from_packet(
+            &NtpPacket::test(),
+            NtpTimestamp::default(),
+            NtpTimestamp::default(),
+            NtpInstant::now(),
+            NtpDuration::from_exponent(-32),
+        );
+        // one sample well before the state was saved, one just before
+        measurement.localtime = now - NtpDuration::from_seconds(MAX_SAVED_STATE_AGE);
+        peer.record_offset(&measurement);
+        measurement.localtime = now - NtpDuration::from_seconds(1.0);
+        peer.record_offset(&measurement);
+
+        let state = peer.export_state(now);
+
+        let mut restored = Peer::test_peer();
+        restored.restore_state(state.clone(), now + NtpDuration::from_seconds(60.0));
+        assert!(restored.reach.is_reachable());
+        assert_eq!(restored.offset_history().len(), 1);
+
+        let mut restored = Peer::test_peer();
+        restored.restore_state(
+            state,
+            now + NtpDuration::from_seconds(2.0 * MAX_SAVED_STATE_AGE),
+        );
+        assert!(!restored.reach.is_reachable());
+        assert!(restored.offset_history().is_empty());
+    }
+
+    #[test]
+    fn test_timestamp_order() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+
+        let system = SystemSnapshot::default();
+        assert!(matches!(
+            exchange(
+                &mut peer,
+                system,
+                base + Duration::from_secs(1),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(200, 0),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0),
+                |_, response| {
+                    response.set_receive_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                        100, 0,
+                    ));
+                    response.set_transmit_timestamp(
+                        NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0),
+                    );
+                },
+            ),
+            Err(IgnoreReason::InvalidTimestampOrder)
+        ));
+        // the sample must not have been processed
+        assert!(!peer.reach.is_reachable());
+    }
+
+    #[test]
+    fn test_stale_reference() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        peer.peer_defaults_config.max_reference_age = Some(NtpDuration::from_seconds(86400.0));
+
+        let system = SystemSnapshot::default();
+        let mut response = response_to_poll(&mut peer, system, |_, response| {
+            response
+                .set_receive_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(200_000, 0));
+            response
+                .set_transmit_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(200_000, 0));
+            // Last synchronized 48 hours before responding
+            response.set_reference_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                200_000 - 48 * 3600,
+                0,
+            ));
+        });
+
+        assert!(matches!(
+            peer.handle_incoming(
+                system,
+                &response.serialize_without_encryption_vec(None).unwrap(),
+                base + Duration::from_secs(1),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(200_000, 0),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(200_000, 0)
+            ),
+            Err(IgnoreReason::StaleReference)
+        ));
+        assert!(!peer.reach.is_reachable());
+
+        // A recently synchronized server is fine
+        response.set_reference_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+            200_000 - 3600,
+            0,
+        ));
+        assert!(peer
+            .handle_incoming(
+                system,
+                &response.serialize_without_encryption_vec(None).unwrap(),
+                base + Duration::from_secs(1),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(200_000, 0),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(200_000, 0)
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_future_reference() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        peer.peer_defaults_config.future_reference_tolerance = Some(NtpDuration::from_seconds(1.0));
+
+        let system = SystemSnapshot::default();
+        let mut response = response_to_poll(&mut peer, system, |_, response| {
+            response
+                .set_receive_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(200_000, 0));
+            response
+                .set_transmit_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(200_000, 0));
+            // Synchronized 10 seconds after we received the response
+            response.set_reference_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                200_010, 0,
+            ));
+        });
+
+        assert!(matches!(
+            peer.handle_incoming(
+                system,
+                &response.serialize_without_encryption_vec(None).unwrap(),
+                base + Duration::from_secs(1),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(200_000, 0),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(200_000, 0)
+            ),
+            Err(IgnoreReason::FutureReference)
+        ));
+        assert!(!peer.reach.is_reachable());
+
+        // A reference timestamp in the past is fine
+        response.set_reference_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+            200_000 - 10,
+            0,
+        ));
+        assert!(peer
+            .handle_incoming(
+                system,
+                &response.serialize_without_encryption_vec(None).unwrap(),
+                base + Duration::from_secs(1),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(200_000, 0),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(200_000, 0)
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_intervals_overlap() {
+        let localtime = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 0);
+        let measured_peer = |offset: f64| {
+            let mut peer = Peer::test_peer();
+            peer.last_measurement = Some(Measurement {
+                delay: NtpDuration::from_seconds(0.02),
+                offset: NtpDuration::from_seconds(offset),
+                transmit_timestamp: NtpTimestamp::default(),
+                receive_timestamp: NtpTimestamp::default(),
+                localtime,
+                monotime: NtpInstant::now(),
+                stratum: 1,
+                root_delay: NtpDuration::ZERO,
+                root_dispersion: NtpDuration::from_seconds(0.005),
+                leap: NtpLeapIndicator::NoWarning,
+                precision: 0,
+            });
+            peer
+        };
+
+        // Both peers have a root distance of 15ms
+        let a = measured_peer(0.0);
+        let b = measured_peer(0.029);
+        let c = measured_peer(0.031);
+
+        assert!(intervals_overlap(&a, &b, localtime));
+        assert!(intervals_overlap(&b, &a, localtime));
+        assert!(!intervals_overlap(&a, &c, localtime));
+        assert!(!intervals_overlap(&c, &a, localtime));
+
+        // The intervals widen as the measurements age
+        let later = localtime + NtpDuration::from_seconds(100.0);
+        assert!(intervals_overlap(&a, &c, later));
+
+        // A peer without measurements agrees with nobody
+        assert!(!intervals_overlap(&a, &Peer::test_peer(), localtime));
+    }
+
+    #[test]
+    fn test_refclock_measurement() {
+        let mut peer = Peer::test_peer();
+        let gps = ReferenceId::from_bytes(*b"GPS\0");
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(1e-6),
+            offset: NtpDuration::from_seconds(0.001),
+            transmit_timestamp: NtpTimestamp::default(),
+            receive_timestamp: NtpTimestamp::default(),
+            localtime: NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 0),
+            monotime: NtpInstant::now(),
+            stratum: 0,
+            root_delay: NtpDuration::ZERO,
+            root_dispersion: NtpDuration::ZERO,
+            leap: NtpLeapIndicator::NoWarning,
+            precision: -20,
+        };
+
+        let Ok(Update::NewMeasurement(snapshot, result)) =
+            peer.handle_refclock_measurement(gps, measurement)
+        else {
+            panic!("measurement should be accepted");
+        };
+        assert_eq!(result.offset, measurement.offset);
+        assert_eq!(snapshot.stratum, 0);
+        assert_eq!(snapshot.reference_id, gps);
+        assert!(snapshot.reach.is_reachable());
+
+        peer.demobilize();
+        assert!(matches!(
+            peer.handle_refclock_measurement(gps, measurement),
+            Err(IgnoreReason::Demobilized)
+        ));
+    }
+
+    #[test]
+    fn test_late_response() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        let system = SystemSnapshot::default();
+        let time = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 0);
+
+        let mut response = response_to_poll(&mut peer, system, |_, response| {
+            response.set_receive_timestamp(time);
+            response.set_transmit_timestamp(time);
+        });
+        let mut buf = [0; 1024];
+        peer.generate_poll_message(&mut buf, system).unwrap();
+        peer.generate_poll_message(&mut buf, system).unwrap();
+
+        // A response to an earlier poll is late, not bogus
+        assert!(matches!(
+            peer.handle_incoming(
+                system,
+                &response.serialize_without_encryption_vec(None).unwrap(),
+                base,
+                time,
+                time
+            ),
+            Err(IgnoreReason::LateResponse)
+        ));
+
+        // while a response to a poll we never sent is
+        response.set_origin_timestamp(NtpTimestamp::from_fixed_int(42));
+        assert!(matches!(
+            peer.handle_incoming(
+                system,
+                &response.serialize_without_encryption_vec(None).unwrap(),
+                base,
+                time,
+                time
+            ),
+            Err(IgnoreReason::InvalidPacketTime)
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_provenance() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        let system = SystemSnapshot::default();
+        let send_time = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 0);
+        let server_receive_time = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 100);
+        let server_transmit_time = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 200);
+        let recv_time = NtpTimestamp::from_seconds_nanos_since_ntp_era(1001, 0);
+        let reference_id = ReferenceId::from_ip("10.0.0.1".parse().unwrap());
+
+        assert!(PeerSnapshot::from_peer(&peer).provenance.is_none());
+
+        let update = exchange(
+            &mut peer,
+            system,
+            base,
+            send_time,
+            recv_time,
+            |_, response| {
+                response.set_stratum(2);
+                response.set_reference_id(reference_id);
+                response.set_receive_timestamp(server_receive_time);
+                response.set_transmit_timestamp(server_transmit_time);
+            },
+        )
+        .unwrap();
+        let Update::NewMeasurement(snapshot, _) = update else {
+            panic!("Expected a measurement");
+        };
+
+        assert_eq!(
+            snapshot.provenance,
+            Some(SampleProvenance {
+                send_timestamp: send_time,
+                server_receive_timestamp: server_receive_time,
+                server_transmit_timestamp: server_transmit_time,
+                recv_timestamp: recv_time,
+                stratum: 2,
+                reference_id,
+            })
+        );
+
+        // A measurement from a reference clock comes from no exchange, and
+        // does not keep reporting the previous one
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(1e-6),
+            offset: NtpDuration::from_seconds(0.001),
+            transmit_timestamp: NtpTimestamp::default(),
+            receive_timestamp: NtpTimestamp::default(),
+            localtime: NtpTimestamp::from_seconds_nanos_since_ntp_era(1002, 0),
+            monotime: NtpInstant::now(),
+            stratum: 0,
+            root_delay: NtpDuration::ZERO,
+            root_dispersion: NtpDuration::ZERO,
+            leap: NtpLeapIndicator::NoWarning,
+            precision: -20,
+        };
+        let Ok(Update::NewMeasurement(snapshot, _)) =
+            peer.handle_refclock_measurement(ReferenceId::from_bytes(*b"GPS\0"), measurement)
+        else {
+            panic!("Expected a measurement");
+        };
+        assert_eq!(snapshot.stratum, 0);
+        assert!(snapshot.provenance.is_none());
+    }
+
+    #[test]
+    fn test_reset_reachability() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        let system = SystemSnapshot::default();
+        let time = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 0);
+
+        let respond = |peer: &mut Peer| {
+            exchange(peer, system, base, time, time, |_, response| {
+                response.set_receive_timestamp(time);
+                response.set_transmit_timestamp(time);
+            })
+            .unwrap();
+        };
+
+        respond(&mut peer);
+        assert!(peer.reach.is_reachable());
+        assert_eq!(peer.state(), PeerState::Reachable);
+
+        let offset_history = peer.offset_history().clone();
+        let last_offset = peer.last_measurement.map(|measurement| measurement.offset);
+        assert!(last_offset.is_some());
+
+        peer.reset_reachability();
+        assert!(!peer.reach.is_reachable());
+        assert!(!peer.is_candidate());
+        assert_eq!(peer.state(), PeerState::Unreachable);
+        assert_eq!(peer.offset_history(), &offset_history);
+        assert_eq!(
+            peer.last_measurement.map(|measurement| measurement.offset),
+            last_offset
+        );
+
+        // Polling alone doesn't make it reachable again, nor does it give up
+        // on the peer right away
+        let mut buf = [0; 1024];
+        assert!(peer.generate_poll_message(&mut buf, system).is_ok());
+        assert!(!peer.reach.is_reachable());
+
+        respond(&mut peer);
+        assert!(peer.reach.is_reachable());
+    }
+
+    #[test]
+    fn test_time_since_last_measurement() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        let system = SystemSnapshot::default();
+        let time = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 0);
+
+        assert!(peer.time_since_last_measurement(time).is_none());
+
+        let update = exchange(&mut peer, system, base, time, time, |_, response| {
+            response.set_receive_timestamp(time);
+            response.set_transmit_timestamp(time);
+        })
+        .unwrap();
+        let Update::NewMeasurement(snapshot, _) = update else {
+            panic!("Expected a measurement");
+        };
+
+        for elapsed in [0.0, 1.0, 45.0, 3600.0] {
+            let now = time + NtpDuration::from_seconds(elapsed);
+            let staleness = peer.time_since_last_measurement(now).unwrap();
+            assert!((staleness.to_seconds() - elapsed).abs() < 1e-6);
+            assert_eq!(snapshot.time_since_last_measurement(now), Some(staleness));
+        }
+    }
+
+    #[test]
+    fn test_peer_state() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        peer.peer_defaults_config.burst_count = 2;
+        let system = SystemSnapshot::default();
+        let mut buf = [0; 1024];
+
+        assert_eq!(peer.state(), PeerState::Initializing);
+
+        let response = response_to_poll(&mut peer, system, |_, _| {});
+        assert_eq!(peer.state(), PeerState::Bursting);
+
+        let update = peer
+            .handle_incoming(
+                system,
+                &response.serialize_without_encryption_vec(None).unwrap(),
+                base,
+                NtpTimestamp::from_fixed_int(0),
+                NtpTimestamp::from_fixed_int(300),
+            )
+            .unwrap();
+        // The burst is not over yet
+        assert_eq!(peer.state(), PeerState::Bursting);
+        let Update::NewMeasurement(snapshot, _) = update else {
+            panic!("Expected a measurement");
+        };
+        assert_eq!(snapshot.state, PeerState::Bursting);
+
+        peer.generate_poll_message(&mut buf, system).unwrap();
+        assert_eq!(peer.state(), PeerState::Reachable);
+
+        // Polls, now without bursts, go unanswered until the response is
+        // shifted out of the reach register
+        peer.peer_defaults_config.burst_count = 1;
+        while peer.reach.is_reachable() {
+            assert_eq!(peer.state(), PeerState::Reachable);
+            peer.generate_poll_message(&mut buf, system).unwrap();
+        }
+        assert_eq!(peer.state(), PeerState::Unreachable);
+        assert_eq!(PeerSnapshot::from_peer(&peer).state, PeerState::Unreachable);
+
+        peer.demobilize();
+        assert_eq!(peer.state(), PeerState::Demobilized);
+        assert!(peer.is_demobilized());
+    }
+
+    #[test]
+    fn test_malformed_header() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+
+        let system = SystemSnapshot::default();
+        let mut response =
+            response_to_poll(&mut peer, system, |_, response| response.set_precision(5));
+        assert_eq!(
+            response.header().validate(),
+            Err(crate::packet::InvalidHeader::Precision(5))
+        );
+
+        assert!(matches!(
+            peer.handle_incoming(
+                system,
+                &response.serialize_without_encryption_vec(None).unwrap(),
+                base,
+                NtpTimestamp::default(),
+                NtpTimestamp::default()
+            ),
+            Err(IgnoreReason::MalformedHeader)
+        ));
+
+        response.set_precision(-20);
+        response.set_transmit_timestamp(NtpTimestamp::default());
+        assert_eq!(
+            response.header().validate(),
+            Err(crate::packet::InvalidHeader::MissingTimestamp)
+        );
+        assert!(matches!(
+            peer.handle_incoming(
+                system,
+                &response.serialize_without_encryption_vec(None).unwrap(),
+                base,
+                NtpTimestamp::default(),
+                NtpTimestamp::default()
+            ),
+            Err(IgnoreReason::MalformedHeader)
+        ));
+    }
+
+    #[test]
+    fn test_header_validation_fuzz() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        let system = SystemSnapshot::default();
+        let mut rng = thread_rng();
+
+        for _ in 0..1000 {
+            let mut buf = [0; 1024];
+            let outgoing = peer.generate_poll_message(&mut buf, system).unwrap().0;
+            let outgoing_transmit: [u8; 8] = outgoing[40..48].try_into().unwrap();
+
+            let mut data = [0u8; 48];
+            rng.fill(&mut data[..]);
+            // Keep the leap indicator, but make it a version 4 server response
+            // to our latest request, so the header itself gets inspected
+            data[0] = (data[0] & 0xC0) | 0x24;
+            data[24..32].copy_from_slice(&outgoing_transmit);
+
+            let result = peer.handle_incoming(
+                system,
+                &data,
+                base,
+                NtpTimestamp::default(),
+                NtpTimestamp::default(),
+            );
+
+            let packet = NtpPacket::deserialize(&data, &NoCipher).unwrap().0;
+            if packet.header().validate().is_err() {
+                assert!(result.is_err());
+            }
+
+            // Prevent the peer from backing off or becoming unreachable
+            peer.reach.received_packet();
+            peer.backoff_interval = peer.peer_defaults_config.poll_interval_limits.min;
+        }
+    }
+
+    #[test]
+    fn test_frozen_transmit_timestamp() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+
+        let system = SystemSnapshot::default();
+
+        let respond = |peer: &mut Peer| {
+            exchange(
+                peer,
+                system,
+                base + Duration::from_secs(1),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 1000),
+                |_, response| {
+                    response.set_receive_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                        100, 0,
+                    ));
+                    response.set_transmit_timestamp(
+                        NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0),
+                    );
+                },
+            )
+        };
+
+        assert!(respond(&mut peer).is_ok());
+        assert!(respond(&mut peer).is_ok());
+        assert!(matches!(
+            respond(&mut peer),
+            Err(IgnoreReason::FrozenTransmitTimestamp)
+        ));
+    }
+
+    #[test]
+    fn test_interleaved_response() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        peer.peer_defaults_config.interleaved = true;
+
+        let system = SystemSnapshot::default();
+
+        let origin_of = |poll: &NtpPacket| {
+            let poll = poll.serialize_without_encryption_vec(None).unwrap();
+            NtpTimestamp::from_bits(poll[24..32].try_into().unwrap())
+        };
+
+        // A basic exchange, with an inaccurate server transmit timestamp
+        let first = response_to_poll(&mut peer, system, |poll, response| {
+            assert_eq!(origin_of(poll), NtpTimestamp::default());
+            response.set_receive_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                100,
+                500_000_000,
+            ));
+            response.set_transmit_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                100,
+                700_000_000,
+            ));
+        })
+        .serialize_without_encryption_vec(None)
+        .unwrap();
+        let Ok(Update::NewMeasurement(_, measurement)) = peer.handle_incoming(
+            system,
+            &first,
+            base + Duration::from_secs(1),
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0),
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(101, 0),
+        ) else {
+            panic!("Expected a measurement");
+        };
+        assert!((measurement.offset.to_seconds() - 0.1).abs() < 1e-6);
+
+        // The next request asks for an interleaved response, identifying the
+        // first exchange by the time the server received its request
+        let second = response_to_poll(&mut peer, system, |poll, response| {
+            assert_eq!(
+                origin_of(poll),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 500_000_000)
+            );
+            response.set_origin_timestamp(poll.receive_timestamp());
+            response.set_receive_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                102,
+                500_000_000,
+            ));
+            response.set_transmit_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                100,
+                600_000_000,
+            ));
+        });
+
+        // A duplicate response to the already answered first request is
+        // still rejected
+        assert!(peer
+            .handle_incoming(
+                system,
+                &first,
+                base + Duration::from_secs(3),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(102, 0),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(103, 0),
+            )
+            .is_err());
+
+        // The interleaved response carries the accurate transmit timestamp of
+        // the first response, so the measurement describes the first exchange
+        let Ok(Update::NewMeasurement(_, measurement)) = peer.handle_incoming(
+            system,
+            &second.serialize_without_encryption_vec(None).unwrap(),
+            base + Duration::from_secs(3),
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(102, 0),
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(103, 0),
+        ) else {
+            panic!("Expected a measurement");
+        };
+        assert!((measurement.offset.to_seconds() - 0.05).abs() < 1e-6);
+        assert!((measurement.delay.to_seconds() - 0.9).abs() < 1e-6);
+
+        // Only the request directly following an exchange can complete it,
+        // and a server answering in basic mode is still understood
+        let Ok(Update::NewMeasurement(_, measurement)) = exchange(
+            &mut peer,
+            system,
+            base + Duration::from_secs(5),
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(104, 0),
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(105, 0),
+            |poll, response| {
+                assert_eq!(
+                    origin_of(poll),
+                    NtpTimestamp::from_seconds_nanos_since_ntp_era(102, 500_000_000)
+                );
+                response.set_receive_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                    104,
+                    500_000_000,
+                ));
+                response.set_transmit_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                    104,
+                    700_000_000,
+                ));
+            },
+        ) else {
+            panic!("Expected a measurement");
+        };
+        assert!((measurement.offset.to_seconds() - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_interleaved_disabled() {
+        let mut peer = Peer::test_peer();
+        let system = SystemSnapshot::default();
+
+        assert!(exchange(
+            &mut peer,
+            system,
+            NtpInstant::now(),
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0),
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(101, 0),
+            |_, response| {
+                response
+                    .set_receive_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0));
+                response
+                    .set_transmit_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0));
+            },
+        )
+        .is_ok());
+
+        // Without interleaved mode, requests never identify an earlier exchange
+        let mut buf = [0; 1024];
+        let outgoingbuf = peer.generate_poll_message(&mut buf, system).unwrap().0;
+        assert_eq!(&outgoingbuf[24..40], &[0; 16]);
+    }
+
+    #[test]
+    fn test_last_exchange() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        assert_eq!(peer.last_exchange(), None);
+
+        let system = SystemSnapshot::default();
+
+        let t1 = NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0);
+        let t2 = NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 500_000_000);
+        let t3 = NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 700_000_000);
+        let t4 = NtpTimestamp::from_seconds_nanos_since_ntp_era(101, 0);
+
+        assert!(exchange(
+            &mut peer,
+            system,
+            base + Duration::from_secs(1),
+            t1,
+            t4,
+            |_, response| {
+                response.set_receive_timestamp(t2);
+                response.set_transmit_timestamp(t3);
+            },
+        )
+        .is_ok());
+        assert_eq!(peer.last_exchange(), Some((t1, t2, t3, t4)));
+    }
+
+    #[test]
+    fn test_delay_asymmetry_correction() {
+        let measure = |correction: f64| {
+            let base = NtpInstant::now();
+            let mut peer = Peer::test_peer();
+            peer.set_delay_asymmetry_correction(NtpDuration::from_seconds(correction));
+
+            let system = SystemSnapshot::default();
+            let Ok(Update::NewMeasurement(_, measurement)) = exchange(
+                &mut peer,
+                system,
+                base + Duration::from_secs(1),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0),
+                NtpTimestamp::from_seconds_nanos_since_ntp_era(101, 0),
+                |_, response| {
+                    response.set_receive_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                        100,
+                        500_000_000,
+                    ));
+                    response.set_transmit_timestamp(
+                        NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 700_000_000),
+                    );
+                },
+            ) else {
+                panic!("Expected a measurement");
+            };
+            measurement.offset.to_seconds()
+        };
+
+        let uncorrected = measure(0.0);
+        let corrected = measure(0.010);
+        assert!((corrected - uncorrected - 0.005).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_step() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
+        let system = SystemSnapshot::default();
+
+        for cycle in 0..2u32 {
+            let send_time = NtpTimestamp::from_seconds_nanos_since_ntp_era(100 + 16 * cycle, 0);
+            let recv_time = send_time + NtpDuration::from_seconds(0.1);
 
-        assert!(peer.current_poll_interval(system) >= peer.remote_min_poll_interval);
-        assert!(peer.current_poll_interval(system) >= system.time_snapshot.poll_interval);
+            let StepOutcome::Poll(poll, _) = peer.step(system, base, None, send_time, recv_time)
+            else {
+                panic!("Expected a poll message");
+            };
+            let poll = NtpPacket::deserialize(&poll, &NoCipher).unwrap().0;
 
-        peer.remote_min_poll_interval = PollIntervalLimits::default().min;
+            let mut response = NtpPacket::test();
+            response.set_stratum(1);
+            response.set_mode(NtpAssociationMode::Server);
+            response.set_origin_timestamp(poll.transmit_timestamp());
+            response.set_receive_timestamp(send_time + NtpDuration::from_seconds(0.05));
+            response.set_transmit_timestamp(send_time + NtpDuration::from_seconds(0.05));
 
-        let prev = peer.current_poll_interval(system);
-        let mut buf = [0; 1024];
-        let packetbuf = peer.generate_poll_message(&mut buf, system).unwrap().0;
-        let packet = NtpPacket::deserialize(packetbuf, &NoCipher).unwrap().0;
-        assert!(peer.current_poll_interval(system) > prev);
-        let mut response = NtpPacket::test();
-        response.set_mode(NtpAssociationMode::Server);
-        response.set_stratum(1);
-        response.set_origin_timestamp(packet.transmit_timestamp());
-        assert!(peer
-            .handle_incoming(
+            let outcome = peer.step(
                 system,
-                &response.serialize_without_encryption_vec(None).unwrap(),
-                base,
-                NtpTimestamp::default(),
-                NtpTimestamp::default()
-            )
-            .is_ok());
-        assert_eq!(peer.current_poll_interval(system), prev);
+                base + Duration::from_secs(16 * cycle as u64 + 1),
+                Some(&response.serialize_without_encryption_vec(None).unwrap()),
+                send_time,
+                recv_time,
+            );
+            assert!(matches!(
+                outcome,
+                StepOutcome::Update(Update::NewMeasurement(_, _))
+            ));
+        }
 
-        let prev = peer.current_poll_interval(system);
-        let mut buf = [0; 1024];
-        let packetbuf = peer.generate_poll_message(&mut buf, system).unwrap().0;
-        let packet = NtpPacket::deserialize(packetbuf, &NoCipher).unwrap().0;
-        assert!(peer.current_poll_interval(system) > prev);
-        let mut response = NtpPacket::test();
-        response.set_mode(NtpAssociationMode::Server);
-        response.set_stratum(0);
-        response.set_origin_timestamp(packet.transmit_timestamp());
-        response.set_reference_id(ReferenceId::KISS_RATE);
-        assert!(peer
-            .handle_incoming(
-                system,
-                &response.serialize_without_encryption_vec(None).unwrap(),
-                base,
-                NtpTimestamp::default(),
-                NtpTimestamp::default()
-            )
-            .is_err());
-        assert!(peer.current_poll_interval(system) > prev);
-        assert!(peer.remote_min_poll_interval > prev);
+        // A packet that doesn't answer a poll is ignored
+        let outcome = peer.step(
+            system,
+            base,
+            Some(&[0; 48]),
+            NtpTimestamp::default(),
+            NtpTimestamp::default(),
+        );
+        assert!(matches!(outcome, StepOutcome::Ignored(_)));
     }
 
     #[test]
-    fn test_handle_incoming() {
+    fn test_measurement_freshness() {
         let base = NtpInstant::now();
         let mut peer = Peer::test_peer();
+        let now = NtpTimestamp::from_seconds_nanos_since_ntp_era(110, 0);
+        assert!(!peer.is_measurement_fresh(now, NtpDuration::from_seconds(1000.0)));
 
         let system = SystemSnapshot::default();
-        let mut buf = [0; 1024];
-        let outgoingbuf = peer.generate_poll_message(&mut buf, system).unwrap().0;
-        let outgoing = NtpPacket::deserialize(outgoingbuf, &NoCipher).unwrap().0;
-        let mut packet = NtpPacket::test();
-        let system = SystemSnapshot::default();
-        packet.set_stratum(1);
-        packet.set_mode(NtpAssociationMode::Server);
-        packet.set_origin_timestamp(outgoing.transmit_timestamp());
-        packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
-        packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
 
-        assert!(peer
-            .handle_incoming(
-                system,
-                &packet.serialize_without_encryption_vec(None).unwrap(),
-                base + Duration::from_secs(1),
-                NtpTimestamp::from_fixed_int(0),
-                NtpTimestamp::from_fixed_int(400)
-            )
-            .is_ok());
-        //assert_eq!(peer.timestate.last_packet, packet);
-        assert!(peer
-            .handle_incoming(
-                system,
-                &packet.serialize_without_encryption_vec(None).unwrap(),
-                base + Duration::from_secs(1),
-                NtpTimestamp::from_fixed_int(0),
-                NtpTimestamp::from_fixed_int(500)
-            )
-            .is_err());
+        // measured at 100.5 seconds
+        assert!(exchange(
+            &mut peer,
+            system,
+            base + Duration::from_secs(1),
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0),
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(101, 0),
+            |_, response| {
+                response
+                    .set_receive_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0));
+                response
+                    .set_transmit_timestamp(NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0));
+            },
+        )
+        .is_ok());
+
+        assert!(peer.is_measurement_fresh(now, NtpDuration::from_seconds(16.0)));
+        assert!(!peer.is_measurement_fresh(now, NtpDuration::from_seconds(8.0)));
     }
 
     #[test]
@@ -1042,26 +3394,17 @@ mod test {
         let mut peer = Peer::test_peer();
 
         let system = SystemSnapshot::default();
-        let mut buf = [0; 1024];
-        let outgoingbuf = peer.generate_poll_message(&mut buf, system).unwrap().0;
-        let outgoing = NtpPacket::deserialize(outgoingbuf, &NoCipher).unwrap().0;
-        let mut packet = NtpPacket::test();
-        let system = SystemSnapshot::default();
-        packet.set_stratum(1);
-        packet.set_mode(NtpAssociationMode::Server);
-        packet.set_origin_timestamp(outgoing.transmit_timestamp());
-        packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
-        packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
-        assert!(peer
-            .handle_incoming(
-                system,
-                &packet.serialize_without_encryption_vec(None).unwrap(),
-                base + Duration::from_secs(1),
-                NtpTimestamp::from_fixed_int(0),
-                NtpTimestamp::from_fixed_int(400)
-            )
-            .is_ok());
+        assert!(exchange(
+            &mut peer,
+            system,
+            base + Duration::from_secs(1),
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(400),
+            |_, _| {}
+        )
+        .is_ok());
 
+        let mut buf = [0; 1024];
         assert!(peer.generate_poll_message(&mut buf, system).is_ok());
         assert!(peer.generate_poll_message(&mut buf, system).is_ok());
         assert!(peer.generate_poll_message(&mut buf, system).is_ok());
@@ -1082,31 +3425,24 @@ mod test {
         let mut peer = Peer::test_peer();
 
         let system = SystemSnapshot::default();
-        let mut buf = [0; 1024];
-        let outgoingbuf = peer.generate_poll_message(&mut buf, system).unwrap().0;
-        let outgoing = NtpPacket::deserialize(outgoingbuf, &NoCipher).unwrap().0;
-        let mut packet = NtpPacket::test();
-        let system = SystemSnapshot::default();
-        packet.set_stratum(MAX_STRATUM + 1);
-        packet.set_mode(NtpAssociationMode::Server);
-        packet.set_origin_timestamp(outgoing.transmit_timestamp());
-        packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
-        packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+        let mut response = response_to_poll(&mut peer, system, |_, response| {
+            response.set_stratum(MAX_STRATUM + 1)
+        });
         assert!(peer
             .handle_incoming(
                 system,
-                &packet.serialize_without_encryption_vec(None).unwrap(),
+                &response.serialize_without_encryption_vec(None).unwrap(),
                 base + Duration::from_secs(1),
                 NtpTimestamp::from_fixed_int(0),
                 NtpTimestamp::from_fixed_int(500)
             )
             .is_err());
 
-        packet.set_stratum(0);
+        response.set_stratum(0);
         assert!(peer
             .handle_incoming(
                 system,
-                &packet.serialize_without_encryption_vec(None).unwrap(),
+                &response.serialize_without_encryption_vec(None).unwrap(),
                 base + Duration::from_secs(1),
                 NtpTimestamp::from_fixed_int(0),
                 NtpTimestamp::from_fixed_int(500)
@@ -1115,111 +3451,190 @@ mod test {
     }
 
     #[test]
-    fn test_handle_kod() {
+    fn test_ignore_reason_labels() {
+        // Adding a variant breaks this match, as a reminder to also add it to ALL
+        for reason in IgnoreReason::ALL {
+            match reason {
+                IgnoreReason::InvalidPacket
+                | IgnoreReason::InvalidMac
+                | IgnoreReason::InvalidMode
+                | IgnoreReason::InvalidVersion
+                | IgnoreReason::InvalidStratum
+                | IgnoreReason::ExcessiveDispersion
+                | IgnoreReason::InvalidPacketTime
+                | IgnoreReason::LateResponse
+                | IgnoreReason::KissIgnore
+                | IgnoreReason::KissDemobilize
+                | IgnoreReason::KissRestrict
+                | IgnoreReason::KissNtsNack
+                | IgnoreReason::InvalidTimestampOrder
+                | IgnoreReason::FrozenTransmitTimestamp
+                | IgnoreReason::MalformedHeader
+                | IgnoreReason::StaleReference
+                | IgnoreReason::FutureReference
+                | IgnoreReason::TooOld
+                | IgnoreReason::Demobilized => {}
+            }
+        }
+        assert_eq!(IgnoreReason::ALL.len(), 19);
+
+        let labels: std::collections::HashSet<_> =
+            IgnoreReason::ALL.iter().map(IgnoreReason::label).collect();
+        assert_eq!(labels.len(), IgnoreReason::ALL.len());
+
+        // Labels are used as metric names, and must not change
+        assert_eq!(IgnoreReason::InvalidPacket.label(), "invalid_packet");
+        assert_eq!(IgnoreReason::KissNtsNack.label(), "kiss_nts_nack");
+        assert_eq!(IgnoreReason::TooOld.label(), "too_old");
+    }
+
+    #[test]
+    fn test_mode_checks() {
         let base = NtpInstant::now();
         let mut peer = Peer::test_peer();
 
-        let mut packet = NtpPacket::test();
         let system = SystemSnapshot::default();
-        packet.set_reference_id(ReferenceId::KISS_RSTR);
-        packet.set_mode(NtpAssociationMode::Server);
-        assert!(!matches!(
+        let mut response = response_to_poll(&mut peer, system, |_, response| {
+            response.set_mode(NtpAssociationMode::Client)
+        });
+
+        // A "response" in client mode is not an answer to our request
+        assert!(matches!(
             peer.handle_incoming(
                 system,
-                &packet.serialize_without_encryption_vec(None).unwrap(),
+                &response.serialize_without_encryption_vec(None).unwrap(),
                 base + Duration::from_secs(1),
                 NtpTimestamp::from_fixed_int(0),
-                NtpTimestamp::from_fixed_int(100)
+                NtpTimestamp::from_fixed_int(500)
             ),
-            Err(IgnoreReason::KissDemobilize)
+            Err(IgnoreReason::InvalidMode)
         ));
 
-        let mut packet = NtpPacket::test();
-        let system = SystemSnapshot::default();
-        let mut buf = [0; 1024];
-        let outgoingbuf = peer.generate_poll_message(&mut buf, system).unwrap().0;
-        let outgoing = NtpPacket::deserialize(outgoingbuf, &NoCipher).unwrap().0;
-        packet.set_reference_id(ReferenceId::KISS_RSTR);
-        packet.set_origin_timestamp(outgoing.transmit_timestamp());
-        packet.set_mode(NtpAssociationMode::Server);
-        assert!(matches!(
-            peer.handle_incoming(
+        // and rejecting it doesn't consume the request, the real response still gets through
+        response.set_mode(NtpAssociationMode::Server);
+        assert!(peer
+            .handle_incoming(
                 system,
-                &packet.serialize_without_encryption_vec(None).unwrap(),
+                &response.serialize_without_encryption_vec(None).unwrap(),
                 base + Duration::from_secs(1),
                 NtpTimestamp::from_fixed_int(0),
-                NtpTimestamp::from_fixed_int(100)
-            ),
-            Err(IgnoreReason::KissDemobilize)
-        ));
+                NtpTimestamp::from_fixed_int(500)
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_demobilize() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
 
-        let mut packet = NtpPacket::test();
         let system = SystemSnapshot::default();
-        packet.set_reference_id(ReferenceId::KISS_DENY);
-        packet.set_mode(NtpAssociationMode::Server);
-        assert!(!matches!(
+        let response = response_to_poll(&mut peer, system, |_, _| {});
+
+        assert!(!peer.is_demobilized());
+        peer.demobilize();
+        assert!(peer.is_demobilized());
+
+        // A valid response that was already on its way is not processed
+        assert!(matches!(
             peer.handle_incoming(
                 system,
-                &packet.serialize_without_encryption_vec(None).unwrap(),
+                &response.serialize_without_encryption_vec(None).unwrap(),
                 base + Duration::from_secs(1),
                 NtpTimestamp::from_fixed_int(0),
-                NtpTimestamp::from_fixed_int(100)
+                NtpTimestamp::from_fixed_int(400)
             ),
-            Err(IgnoreReason::KissDemobilize)
+            Err(IgnoreReason::Demobilized)
         ));
+        assert!(!peer.reach.is_reachable());
+    }
 
-        let mut packet = NtpPacket::test();
+    #[test]
+    fn test_dispersion_checks() {
+        let base = NtpInstant::now();
         let system = SystemSnapshot::default();
-        let outgoingbuf = peer.generate_poll_message(&mut buf, system).unwrap().0;
-        let outgoing = NtpPacket::deserialize(outgoingbuf, &NoCipher).unwrap().0;
-        packet.set_reference_id(ReferenceId::KISS_DENY);
-        packet.set_origin_timestamp(outgoing.transmit_timestamp());
-        packet.set_mode(NtpAssociationMode::Server);
-        assert!(matches!(
-            peer.handle_incoming(
+
+        let respond = |peer: &mut Peer| {
+            exchange(
+                peer,
                 system,
-                &packet.serialize_without_encryption_vec(None).unwrap(),
                 base + Duration::from_secs(1),
                 NtpTimestamp::from_fixed_int(0),
-                NtpTimestamp::from_fixed_int(100)
-            ),
-            Err(IgnoreReason::KissDemobilize)
+                NtpTimestamp::from_fixed_int(500),
+                |_, response| response.set_root_dispersion(NtpDuration::from_seconds(2.0)),
+            )
+        };
+
+        // Accepted with the default threshold
+        let mut peer = Peer::test_peer();
+        assert!(respond(&mut peer).is_ok());
+
+        // but not with a stricter one
+        let mut peer = Peer::test_peer();
+        peer.peer_defaults_config.reject_dispersion_above = NtpDuration::from_seconds(1.0);
+        assert!(matches!(
+            respond(&mut peer),
+            Err(IgnoreReason::ExcessiveDispersion)
         ));
+    }
 
-        let old_remote_interval = peer.remote_min_poll_interval;
-        let mut packet = NtpPacket::test();
+    #[test]
+    fn test_handle_kod() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer();
         let system = SystemSnapshot::default();
-        packet.set_reference_id(ReferenceId::KISS_RATE);
-        packet.set_mode(NtpAssociationMode::Server);
-        assert!(peer
-            .handle_incoming(
+
+        // A kiss code is only acted upon when it answers our request
+        let kiss = |peer: &mut Peer, code| {
+            exchange(
+                peer,
                 system,
-                &packet.serialize_without_encryption_vec(None).unwrap(),
                 base + Duration::from_secs(1),
                 NtpTimestamp::from_fixed_int(0),
-                NtpTimestamp::from_fixed_int(100)
+                NtpTimestamp::from_fixed_int(100),
+                |_, response| {
+                    response.set_stratum(0);
+                    response.set_reference_id(code);
+                },
             )
-            .is_err());
-        assert_eq!(peer.remote_min_poll_interval, old_remote_interval);
-
-        let old_remote_interval = peer.remote_min_poll_interval;
-        let mut packet = NtpPacket::test();
-        let system = SystemSnapshot::default();
-        let mut buf = [0; 1024];
-        let outgoingbuf = peer.generate_poll_message(&mut buf, system).unwrap().0;
-        let outgoing = NtpPacket::deserialize(outgoingbuf, &NoCipher).unwrap().0;
-        packet.set_reference_id(ReferenceId::KISS_RATE);
-        packet.set_origin_timestamp(outgoing.transmit_timestamp());
-        packet.set_mode(NtpAssociationMode::Server);
-        assert!(peer
-            .handle_incoming(
+        };
+        let unsolicited_kiss = |peer: &mut Peer, code| {
+            let mut packet = NtpPacket::test();
+            packet.set_reference_id(code);
+            packet.set_mode(NtpAssociationMode::Server);
+            peer.handle_incoming(
                 system,
                 &packet.serialize_without_encryption_vec(None).unwrap(),
                 base + Duration::from_secs(1),
                 NtpTimestamp::from_fixed_int(0),
-                NtpTimestamp::from_fixed_int(100)
+                NtpTimestamp::from_fixed_int(100),
             )
-            .is_err());
+        };
+
+        assert!(!matches!(
+            unsolicited_kiss(&mut peer, ReferenceId::KISS_RSTR),
+            Err(IgnoreReason::KissRestrict)
+        ));
+        assert!(matches!(
+            kiss(&mut peer, ReferenceId::KISS_RSTR),
+            Err(IgnoreReason::KissRestrict)
+        ));
+
+        assert!(!matches!(
+            unsolicited_kiss(&mut peer, ReferenceId::KISS_DENY),
+            Err(IgnoreReason::KissDemobilize)
+        ));
+        assert!(matches!(
+            kiss(&mut peer, ReferenceId::KISS_DENY),
+            Err(IgnoreReason::KissDemobilize)
+        ));
+
+        let old_remote_interval = peer.remote_min_poll_interval;
+        assert!(unsolicited_kiss(&mut peer, ReferenceId::KISS_RATE).is_err());
+        assert_eq!(peer.remote_min_poll_interval, old_remote_interval);
+
+        let old_remote_interval = peer.remote_min_poll_interval;
+        assert!(kiss(&mut peer, ReferenceId::KISS_RATE).is_err());
         assert!(peer.remote_min_poll_interval >= old_remote_interval);
     }
 
@@ -1245,7 +3660,7 @@ mod test {
             assert!(poll.is_upgrade());
 
             let response =
-                NtpPacket::timestamp_response(&system, poll, NtpTimestamp::default(), &clock);
+                NtpPacket::timestamp_response(&system, poll, clock.now().unwrap(), &clock);
             let mut response = response
                 .serialize_without_encryption_vec(Some(poll_len))
                 .unwrap();
@@ -1289,8 +3704,7 @@ mod test {
         assert_eq!(poll.version(), 4);
         assert!(poll.is_upgrade());
 
-        let response =
-            NtpPacket::timestamp_response(&system, poll, NtpTimestamp::default(), &clock);
+        let response = NtpPacket::timestamp_response(&system, poll, clock.now().unwrap(), &clock);
         let response = response
             .serialize_without_encryption_vec(Some(poll_len))
             .unwrap();
@@ -1312,6 +3726,78 @@ mod test {
         assert_eq!(poll.version(), 5);
     }
 
+    #[test]
+    fn test_advertised_precision() {
+        let mut peer = Peer::test_peer();
+        let system = SystemSnapshot::default();
+
+        let mut buf = [0; 1024];
+        let poll = peer.generate_poll_message(&mut buf, system).unwrap().0;
+        let (poll, _) = NtpPacket::deserialize(poll, &NoCipher).unwrap();
+        assert_eq!(poll.precision(), system.time_snapshot.precision.log2());
+
+        peer.peer_defaults_config.advertised_precision = Some(-10);
+        let mut buf = [0; 1024];
+        let poll = peer.generate_poll_message(&mut buf, system).unwrap().0;
+        let (poll, _) = NtpPacket::deserialize(poll, &NoCipher).unwrap();
+        assert_eq!(poll.precision(), -10);
+    }
+
+    #[test]
+    fn test_measurement_precision_bounded() {
+        let mut packet = NtpPacket::test();
+        packet.set_precision(127);
+        let measurement = Measurement::from_packet(
+            &packet,
+            NtpTimestamp::default(),
+            NtpTimestamp::default(),
+            NtpInstant::now(),
+            NtpDuration::from_exponent(-32),
+        );
+        assert_eq!(measurement.precision, 0);
+
+        packet.set_precision(-128);
+        let measurement = Measurement::from_packet(
+            &packet,
+            NtpTimestamp::default(),
+            NtpTimestamp::default(),
+            NtpInstant::now(),
+            NtpDuration::from_exponent(-32),
+        );
+        assert_eq!(measurement.precision, -32);
+    }
+
+    #[cfg(feature = "ntpv5")]
+    #[test]
+    fn test_requested_poll_bounded() {
+        let mut peer = Peer::test_peer();
+        peer.protocol_version = ProtocolVersion::V5;
+
+        let clock = TestClock::default();
+        let system = SystemSnapshot::default();
+
+        let mut buf = [0; 1024];
+        let req = peer.generate_poll_message(&mut buf, system).unwrap().0;
+        let (req, _) = NtpPacket::deserialize(req, &NoCipher).unwrap();
+        let mut response =
+            NtpPacket::timestamp_response(&system, req, clock.now().unwrap(), &clock);
+        response.set_poll(PollInterval::from_byte(127));
+        let resp_bytes = response.serialize_without_encryption_vec(None).unwrap();
+
+        peer.handle_incoming(
+            system,
+            &resp_bytes,
+            NtpInstant::now(),
+            NtpTimestamp::default(),
+            NtpTimestamp::default(),
+        )
+        .unwrap();
+
+        let max = peer.peer_defaults_config.poll_interval_limits.max;
+        assert_eq!(peer.remote_min_poll_interval, max);
+        assert_eq!(peer.current_poll_interval(system), max);
+    }
+
     #[cfg(feature = "ntpv5")]
     #[test]
     fn bloom_filters_will_synchronize_at_some_point() {
@@ -1337,7 +3823,7 @@ mod test {
 
             let (req, _) = NtpPacket::deserialize(req, &NoCipher).unwrap();
             let response =
-                NtpPacket::timestamp_response(&server_system, req, NtpTimestamp::default(), &clock);
+                NtpPacket::timestamp_response(&server_system, req, clock.now().unwrap(), &clock);
             let resp_bytes = response.serialize_without_encryption_vec(None).unwrap();
 
             client
@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::Duration;
@@ -11,14 +11,17 @@ use crate::packet::v5::server_reference_id::{BloomFilter, ServerId};
 use crate::peer::ProtocolVersion;
 use crate::{
     algorithm::{KalmanClockController, ObservablePeerTimedata, StateUpdate, TimeSyncController},
-    clock::NtpClock,
+    clock::{measure_system_precision, NtpClock},
     config::{SourceDefaultsConfig, SynchronizationConfig},
     identifiers::ReferenceId,
     packet::NtpLeapIndicator,
-    peer::{Measurement, PeerSnapshot},
-    time_types::{NtpDuration, PollInterval},
+    peer::{Measurement, Peer, PeerSnapshot, SavedPeerState},
+    time_types::{NtpDuration, NtpTimestamp, PollInterval},
 };
 
+/// Number of clock selection cycles over which survivor churn is averaged
+const SURVIVOR_CHURN_WINDOW: usize = 8;
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TimeSnapshot {
     /// Desired poll interval
@@ -71,6 +74,14 @@ pub struct SystemSnapshot {
 }
 
 impl SystemSnapshot {
+    /// Advertise ourselves as a synchronized orphan at `stratum`, so other
+    /// orphans can pick us as their orphan parent
+    fn become_orphan(&mut self, stratum: u8) {
+        self.stratum = stratum;
+        self.reference_id = ReferenceId::NONE;
+        self.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
+    }
+
     pub fn update_timedata(&mut self, timedata: TimeSnapshot, config: &SynchronizationConfig) {
         self.time_snapshot = timedata;
         self.accumulated_steps_threshold = config.accumulated_step_panic_threshold;
@@ -118,8 +129,16 @@ pub struct System<C: NtpClock, PeerId: Hash + Eq + Copy + Debug> {
     peer_defaults_config: SourceDefaultsConfig,
     system: SystemSnapshot,
     ip_list: Arc<[IpAddr]>,
+    // Reference ids of the configured downstream servers
+    downstream_ids: Vec<ReferenceId>,
 
     peers: HashMap<PeerId, Option<PeerSnapshot>>,
+    // Peer with the highest weight in the last combine, if any
+    system_peer: Option<PeerId>,
+    // Peers used in the last combine, `None` before the first one
+    survivors: Option<Vec<PeerId>>,
+    // Number of peers added to or removed from the survivors in recent cycles
+    survivor_changes: VecDeque<usize>,
 
     clock: C,
     controller: Option<KalmanClockController<C, PeerId>>,
@@ -143,12 +162,44 @@ impl<C: NtpClock, PeerId: Hash + Eq + Copy + Debug> System<C, PeerId> {
             system.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
         }
 
+        if let Some(orphan_stratum) = synchronization_config.orphan_stratum {
+            system.become_orphan(orphan_stratum.min(synchronization_config.local_stratum));
+        }
+
+        if synchronization_config.measure_precision {
+            match measure_system_precision(&clock) {
+                Ok(Some(precision)) => {
+                    tracing::info!(precision, "Measured precision of the system clock");
+                    system.time_snapshot.precision = NtpDuration::from_exponent(precision);
+                }
+                Ok(None) => {
+                    tracing::warn!("System clock did not advance, keeping the default precision");
+                }
+                Err(error) => {
+                    tracing::warn!(
+                        ?error,
+                        "Could not measure the precision of the system clock"
+                    );
+                }
+            }
+        }
+
+        let downstream_ids = synchronization_config
+            .downstream_servers
+            .iter()
+            .map(|ip| ReferenceId::from_ip(*ip))
+            .collect();
+
         System {
             synchronization_config,
             peer_defaults_config,
             system,
             ip_list,
+            downstream_ids,
             peers: Default::default(),
+            system_peer: None,
+            survivors: None,
+            survivor_changes: VecDeque::with_capacity(SURVIVOR_CHURN_WINDOW),
             clock,
             controller: None,
         }
@@ -163,7 +214,7 @@ impl<C: NtpClock, PeerId: Hash + Eq + Copy + Debug> System<C, PeerId> {
             Some(controller) => controller,
             None => KalmanClockController::new(
                 self.clock.clone(),
-                self.synchronization_config,
+                self.synchronization_config.clone(),
                 self.peer_defaults_config,
                 self.synchronization_config.algorithm,
             )?,
@@ -171,8 +222,10 @@ impl<C: NtpClock, PeerId: Hash + Eq + Copy + Debug> System<C, PeerId> {
         Ok(self.controller.insert(controller))
     }
 
-    pub fn handle_peer_create(&mut self, id: PeerId) -> Result<(), C::Error> {
-        self.clock_controller()?.peer_add(id);
+    pub fn handle_peer_create(&mut self, id: PeerId, prefer: bool) -> Result<(), C::Error> {
+        let controller = self.clock_controller()?;
+        controller.peer_add(id);
+        controller.peer_prefer(id, prefer);
         self.peers.insert(id, None);
         Ok(())
     }
@@ -180,26 +233,79 @@ impl<C: NtpClock, PeerId: Hash + Eq + Copy + Debug> System<C, PeerId> {
     pub fn handle_peer_remove(&mut self, id: PeerId) -> Result<(), C::Error> {
         self.clock_controller()?.peer_remove(id);
         self.peers.remove(&id);
+        self.stop_using_peer(id);
         Ok(())
     }
 
+    /// Stop advertising a peer that can no longer be synchronized to as our
+    /// time source. The remaining survivors stay in use until the next
+    /// selection.
+    fn stop_using_peer(&mut self, id: PeerId) {
+        if let Some(survivors) = &self.survivors {
+            if survivors.contains(&id) {
+                let remaining = survivors.iter().copied().filter(|v| *v != id).collect();
+                self.handle_algorithm_state_update(StateUpdate {
+                    used_peers: Some(remaining),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
     pub fn handle_peer_snapshot(
         &mut self,
         id: PeerId,
         snapshot: PeerSnapshot,
     ) -> Result<(), C::Error> {
-        let usable = snapshot
-            .accept_synchronization(
-                self.synchronization_config.local_stratum,
-                self.ip_list.as_ref(),
-                &self.system,
-            )
-            .is_ok();
-        self.clock_controller()?.peer_update(id, usable);
         *self.peers.get_mut(&id).unwrap() = Some(snapshot);
+        let usable = if self.synchronization_config.orphan_stratum == Some(snapshot.stratum) {
+            // Orphans synchronizing to each other would form a loop, so only
+            // the orphan parent is used
+            self.orphan_parent() == Some(id)
+        } else {
+            snapshot
+                .accept_synchronization(
+                    self.synchronization_config.local_stratum,
+                    self.synchronization_config.max_acceptable_stratum,
+                    self.ip_list.as_ref(),
+                    &self.downstream_ids,
+                    &self.system,
+                )
+                .is_ok()
+                && self.accept_jitter(id)?
+        };
+        self.clock_controller()?.peer_update(id, usable);
+        if !usable {
+            self.stop_using_peer(id);
+        }
         Ok(())
     }
 
+    /// Whether the jitter the clock controller estimates for a peer is within
+    /// the configured maximum. Peers whose filter hasn't settled yet have no
+    /// jitter estimate and are accepted.
+    fn accept_jitter(&mut self, id: PeerId) -> Result<bool, C::Error> {
+        let Some(max_peer_jitter) = self.synchronization_config.max_peer_jitter else {
+            return Ok(true);
+        };
+
+        let jitter = self
+            .clock_controller()?
+            .peer_snapshot(id)
+            .and_then(|timedata| timedata.jitter);
+        match jitter {
+            Some(jitter) if jitter > max_peer_jitter => {
+                tracing::info!(
+                    peer_jitter = jitter.to_seconds(),
+                    max_peer_jitter = max_peer_jitter.to_seconds(),
+                    "Peer rejected due to a jitter above the configured maximum",
+                );
+                Ok(false)
+            }
+            _ => Ok(true),
+        }
+    }
+
     pub fn handle_peer_measurement(
         &mut self,
         id: PeerId,
@@ -216,6 +322,9 @@ impl<C: NtpClock, PeerId: Hash + Eq + Copy + Debug> System<C, PeerId> {
 
     fn handle_algorithm_state_update(&mut self, update: StateUpdate<PeerId>) -> Option<Duration> {
         if let Some(ref used_peers) = update.used_peers {
+            // used peers are ordered by decreasing weight in the combined estimate
+            self.system_peer = used_peers.first().copied();
+            self.track_survivors(used_peers);
             self.system.update_used_peers(used_peers.iter().map(|v| {
                 self.peers.get(v).and_then(|snapshot| *snapshot).expect(
                     "Critical error: Peer used for synchronization that is not known to system",
@@ -223,12 +332,55 @@ impl<C: NtpClock, PeerId: Hash + Eq + Copy + Debug> System<C, PeerId> {
             }));
         }
         if let Some(time_snapshot) = update.time_snapshot {
+            // The precision is a property of the clock, which the controller
+            // doesn't know about
+            let time_snapshot = TimeSnapshot {
+                precision: self.system.time_snapshot.precision,
+                ..time_snapshot
+            };
             self.system
                 .update_timedata(time_snapshot, &self.synchronization_config);
         }
+        // Without a time source, we fall back to being an orphan
+        if let Some(orphan_stratum) = self.synchronization_config.orphan_stratum {
+            if self.system_peer.is_none() {
+                self.system
+                    .become_orphan(orphan_stratum.min(self.synchronization_config.local_stratum));
+            }
+        }
         update.next_update
     }
 
+    fn track_survivors(&mut self, used_peers: &[PeerId]) {
+        if let Some(previous) = &self.survivors {
+            let added = used_peers
+                .iter()
+                .filter(|id| !previous.contains(id))
+                .count();
+            let removed = previous
+                .iter()
+                .filter(|id| !used_peers.contains(id))
+                .count();
+
+            if self.survivor_changes.len() == SURVIVOR_CHURN_WINDOW {
+                self.survivor_changes.pop_front();
+            }
+            self.survivor_changes.push_back(added + removed);
+        }
+        self.survivors = Some(used_peers.to_vec());
+    }
+
+    /// Average number of peers added to or removed from the set of peers
+    /// used for synchronization per cycle, over the last few cycles. A high
+    /// value indicates network instability or an attack.
+    pub fn survivor_churn(&self) -> f64 {
+        if self.survivor_changes.is_empty() {
+            return 0.0;
+        }
+
+        self.survivor_changes.iter().sum::<usize>() as f64 / self.survivor_changes.len() as f64
+    }
+
     pub fn handle_timer(&mut self) -> Option<Duration> {
         tracing::debug!("Timer expired");
         // note: local needed for borrow checker
@@ -252,6 +404,74 @@ impl<C: NtpClock, PeerId: Hash + Eq + Copy + Debug> System<C, PeerId> {
         }
     }
 
+    /// Export the persistable state of a peer at local time `now`, including
+    /// the state of its filter, which is kept here rather than by the peer
+    pub fn export_peer_state(&self, id: PeerId, peer: &Peer, now: NtpTimestamp) -> SavedPeerState {
+        SavedPeerState {
+            filter: self
+                .controller
+                .as_ref()
+                .and_then(|controller| controller.peer_export_state(id)),
+            ..peer.export_state(now)
+        }
+    }
+
+    /// Restore the state of a peer exported by [`System::export_peer_state`]
+    /// at local time `now`. State that has become too old is dropped.
+    pub fn restore_peer_state(
+        &mut self,
+        id: PeerId,
+        peer: &mut Peer,
+        state: SavedPeerState,
+        now: NtpTimestamp,
+    ) {
+        if let (Some(filter), Some(controller)) = (state.filter.clone(), self.controller.as_mut()) {
+            controller.peer_restore_state(id, filter, now);
+        }
+        peer.restore_state(state, now);
+    }
+
+    /// Reference id of the peer currently driving the system clock, if any
+    pub fn system_peer(&self) -> Option<ReferenceId> {
+        self.system_peer
+            .and_then(|id| self.peers.get(&id).copied().flatten())
+            .map(|snapshot| snapshot.source_id)
+    }
+
+    /// Peer to synchronize to in orphan mode (RFC5905 section 5.3.4), used
+    /// while we are not synchronized to a peer below the orphan stratum. Of
+    /// all reachable peers at the orphan stratum and ourselves, the one with
+    /// the lowest reference id is the orphan parent. `None` when orphan mode
+    /// is disabled or when we are the orphan parent ourselves.
+    pub fn orphan_parent(&self) -> Option<PeerId> {
+        let orphan_stratum = self.synchronization_config.orphan_stratum?;
+        let system_peer = self
+            .system_peer
+            .and_then(|id| self.peers.get(&id).copied().flatten());
+        if matches!(system_peer, Some(snapshot) if snapshot.stratum < orphan_stratum) {
+            return None;
+        }
+
+        let (id, parent) = self
+            .peers
+            .iter()
+            .filter_map(|(id, snapshot)| snapshot.map(|snapshot| (*id, snapshot)))
+            .filter(|(_, snapshot)| {
+                snapshot.reach.is_reachable() && snapshot.stratum == orphan_stratum
+            })
+            .min_by_key(|(_, snapshot)| snapshot.source_id)?;
+
+        let own_id = self
+            .ip_list
+            .iter()
+            .map(|ip| ReferenceId::from_ip(*ip))
+            .min();
+        match own_id {
+            Some(own_id) if own_id < parent.source_id => None,
+            _ => Some(id),
+        }
+    }
+
     pub fn update_ip_list(&mut self, ip_list: Arc<[IpAddr]>) {
         self.ip_list = ip_list;
     }
@@ -261,7 +481,10 @@ impl<C: NtpClock, PeerId: Hash + Eq + Copy + Debug> System<C, PeerId> {
 mod tests {
     use std::net::{Ipv4Addr, SocketAddr};
 
-    use crate::time_types::PollIntervalLimits;
+    use crate::{
+        peer::Reach,
+        time_types::{NtpInstant, NtpTimestamp, PollIntervalLimits},
+    };
 
     use super::*;
 
@@ -290,6 +513,10 @@ mod tests {
                     stratum: 2,
                     reference_id: ReferenceId::NONE,
                     protocol_version: Default::default(),
+                    last_measurement_time: None,
+                    provenance: None,
+                    missed_responses: 0,
+                    state: Default::default(),
                     #[cfg(feature = "ntpv5")]
                     bloom_filter: None,
                 },
@@ -301,6 +528,10 @@ mod tests {
                     stratum: 3,
                     reference_id: ReferenceId::NONE,
                     protocol_version: Default::default(),
+                    last_measurement_time: None,
+                    provenance: None,
+                    missed_responses: 0,
+                    state: Default::default(),
                     #[cfg(feature = "ntpv5")]
                     bloom_filter: None,
                 },
@@ -338,4 +569,606 @@ mod tests {
             Some(new_accumulated_threshold),
         );
     }
+
+    #[derive(Debug, Clone, Default)]
+    struct TestClock {}
+
+    impl NtpClock for TestClock {
+        type Error = std::time::SystemTimeError;
+
+        fn now(&self) -> std::result::Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+    }
+
+    /// Clock that advances by a fixed step on every read
+    #[derive(Debug, Clone)]
+    struct SteppingClock {
+        current: std::cell::Cell<NtpTimestamp>,
+        step: NtpDuration,
+    }
+
+    impl NtpClock for SteppingClock {
+        type Error = std::time::SystemTimeError;
+
+        fn now(&self) -> std::result::Result<NtpTimestamp, Self::Error> {
+            self.current.set(self.current.get() + self.step);
+            Ok(self.current.get())
+        }
+
+        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+    }
+
+    #[test]
+    fn test_measure_precision() {
+        let clock = SteppingClock {
+            current: std::cell::Cell::new(NtpTimestamp::from_fixed_int(0)),
+            step: NtpDuration::from_exponent(-20),
+        };
+
+        // Without measuring, the default precision is used
+        let system: System<_, usize> = System::new(
+            clock.clone(),
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+        assert_eq!(
+            system.system_snapshot().time_snapshot.precision,
+            TimeSnapshot::default().precision
+        );
+
+        let mut system: System<_, usize> = System::new(
+            clock,
+            SynchronizationConfig {
+                measure_precision: true,
+                ..Default::default()
+            },
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+        let measured = NtpDuration::from_exponent(-20);
+        assert_eq!(system.system_snapshot().time_snapshot.precision, measured);
+
+        // Updates from the controller keep the measured precision
+        system.handle_algorithm_state_update(StateUpdate {
+            time_snapshot: Some(TimeSnapshot {
+                root_delay: NtpDuration::from_seconds(1.0),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let snapshot = system.system_snapshot().time_snapshot;
+        assert_eq!(snapshot.root_delay, NtpDuration::from_seconds(1.0));
+        assert_eq!(snapshot.precision, measured);
+    }
+
+    #[test]
+    fn test_system_peer() {
+        let mut system: System<_, usize> = System::new(
+            TestClock {},
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+        assert_eq!(system.system_peer(), None);
+
+        for (id, source_id) in [
+            ReferenceId::KISS_DENY,
+            ReferenceId::KISS_RATE,
+            ReferenceId::KISS_RSTR,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            system.peers.insert(
+                id,
+                Some(PeerSnapshot {
+                    source_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+                    source_id,
+                    poll_interval: PollIntervalLimits::default().max,
+                    reach: Default::default(),
+                    stratum: 2,
+                    reference_id: ReferenceId::NONE,
+                    protocol_version: Default::default(),
+                    last_measurement_time: None,
+                    provenance: None,
+                    missed_responses: 0,
+                    state: Default::default(),
+                    #[cfg(feature = "ntpv5")]
+                    bloom_filter: None,
+                }),
+            );
+        }
+
+        // The combiner orders the used peers by increasing uncertainty
+        system.handle_algorithm_state_update(StateUpdate {
+            used_peers: Some(vec![1, 2, 0]),
+            ..Default::default()
+        });
+        assert_eq!(system.system_peer(), Some(ReferenceId::KISS_RATE));
+
+        system.handle_algorithm_state_update(StateUpdate {
+            used_peers: Some(vec![]),
+            ..Default::default()
+        });
+        assert_eq!(system.system_peer(), None);
+    }
+
+    #[test]
+    fn test_orphan_parent() {
+        let synchronization_config = SynchronizationConfig {
+            orphan_stratum: Some(10),
+            ..Default::default()
+        };
+        let mut system: System<_, usize> = System::new(
+            TestClock {},
+            synchronization_config,
+            SourceDefaultsConfig::default(),
+            Arc::new(["10.0.0.5".parse().unwrap()]),
+        );
+        assert_eq!(system.system_snapshot().stratum, 10);
+        assert_eq!(
+            system.system_snapshot().time_snapshot.leap_indicator,
+            NtpLeapIndicator::NoWarning
+        );
+        assert_eq!(system.orphan_parent(), None);
+
+        // None of the peers is synchronized, so all of them are orphans,
+        // except for the one with a lower stratum
+        for (id, (ip, stratum)) in [
+            ("10.0.0.3", 10),
+            ("10.0.0.1", 11),
+            ("10.0.0.2", 10),
+            ("10.0.0.4", 10),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut reach = Reach::default();
+            reach.received_packet();
+            system.peers.insert(
+                id,
+                Some(PeerSnapshot {
+                    source_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+                    source_id: ReferenceId::from_ip(ip.parse().unwrap()),
+                    poll_interval: PollIntervalLimits::default().max,
+                    reach,
+                    stratum,
+                    reference_id: ReferenceId::NONE,
+                    protocol_version: Default::default(),
+                    last_measurement_time: None,
+                    provenance: None,
+                    missed_responses: 0,
+                    state: Default::default(),
+                    #[cfg(feature = "ntpv5")]
+                    bloom_filter: None,
+                }),
+            );
+        }
+        assert_eq!(system.orphan_parent(), Some(2));
+
+        // We are the parent ourselves when our reference id is lower
+        system.update_ip_list(Arc::new(["10.0.0.0".parse().unwrap()]));
+        assert_eq!(system.orphan_parent(), None);
+
+        // Synchronizing to the orphan parent keeps it the orphan parent
+        system.update_ip_list(Arc::new(["10.0.0.5".parse().unwrap()]));
+        system.handle_algorithm_state_update(StateUpdate {
+            used_peers: Some(vec![2]),
+            ..Default::default()
+        });
+        assert_eq!(system.orphan_parent(), Some(2));
+        assert_eq!(system.system_snapshot().stratum, 11);
+
+        // No orphan parent while synchronized to a source below the orphans
+        system.handle_algorithm_state_update(StateUpdate {
+            used_peers: Some(vec![1]),
+            ..Default::default()
+        });
+        system.peers.get_mut(&1).unwrap().as_mut().unwrap().stratum = 9;
+        assert_eq!(system.orphan_parent(), None);
+
+        // Losing all sources makes us an orphan again
+        system.handle_algorithm_state_update(StateUpdate {
+            used_peers: Some(vec![]),
+            ..Default::default()
+        });
+        assert_eq!(system.system_snapshot().stratum, 10);
+        assert_eq!(system.system_snapshot().reference_id, ReferenceId::NONE);
+        assert_eq!(system.orphan_parent(), Some(2));
+    }
+
+    /// Clock that is never steered, of which the tests set the current time
+    #[derive(Debug, Clone, Default)]
+    struct FreeRunningClock {
+        now: Arc<std::sync::Mutex<NtpTimestamp>>,
+    }
+
+    impl NtpClock for FreeRunningClock {
+        type Error = std::time::SystemTimeError;
+
+        fn now(&self) -> std::result::Result<NtpTimestamp, Self::Error> {
+            Ok(*self.now.lock().unwrap())
+        }
+
+        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            self.now()
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            self.now()
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_orphan_mode() {
+        let synchronization_config = SynchronizationConfig {
+            orphan_stratum: Some(10),
+            minimum_agreeing_sources: 1,
+            ..Default::default()
+        };
+        let parent_ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        // Two servers on an isolated network, of which the parent has the
+        // lowest reference id
+        let parent: System<_, usize> = System::new(
+            FreeRunningClock::default(),
+            synchronization_config.clone(),
+            SourceDefaultsConfig::default(),
+            Arc::new([parent_ip]),
+        );
+        let clock = FreeRunningClock::default();
+        let mut child: System<_, usize> = System::new(
+            clock.clone(),
+            synchronization_config,
+            SourceDefaultsConfig::default(),
+            Arc::new(["10.0.0.2".parse().unwrap()]),
+        );
+        child.handle_peer_create(0, false).unwrap();
+
+        let peer_snapshot = |reach| PeerSnapshot {
+            source_addr: SocketAddr::new(parent_ip, 123),
+            source_id: ReferenceId::from_ip(parent_ip),
+            poll_interval: PollIntervalLimits::default().min,
+            reach,
+            stratum: parent.system_snapshot().stratum,
+            reference_id: parent.system_snapshot().reference_id,
+            protocol_version: Default::default(),
+            last_measurement_time: None,
+            provenance: None,
+            missed_responses: 0,
+            state: Default::default(),
+            #[cfg(feature = "ntpv5")]
+            bloom_filter: None,
+        };
+
+        // The child synchronizes to the time advertised by the parent
+        let base = NtpInstant::now();
+        let mut reach = Reach::default();
+        for i in 1..=32 {
+            reach.received_packet();
+            let localtime = NtpTimestamp::default() + NtpDuration::from_seconds(i as f64);
+            *clock.now.lock().unwrap() = localtime;
+            child
+                .handle_peer_measurement(
+                    0,
+                    peer_snapshot(reach),
+                    Measurement {
+                        delay: NtpDuration::from_seconds(0.001),
+                        offset: NtpDuration::from_seconds(1e-6 * (i % 3) as f64),
+                        transmit_timestamp: Default::default(),
+                        receive_timestamp: Default::default(),
+                        localtime,
+                        monotime: base + Duration::from_secs(i),
+                        stratum: parent.system_snapshot().stratum,
+                        root_delay: NtpDuration::ZERO,
+                        root_dispersion: NtpDuration::ZERO,
+                        leap: parent.system_snapshot().time_snapshot.leap_indicator,
+                        precision: 0,
+                    },
+                )
+                .unwrap();
+        }
+        assert_eq!(child.system_peer(), Some(ReferenceId::from_ip(parent_ip)));
+        assert_eq!(child.system_snapshot().stratum, 11);
+        assert_eq!(
+            child.system_snapshot().reference_id,
+            ReferenceId::from_ip(parent_ip)
+        );
+
+        // Once the parent is gone, the child is an orphan again
+        child
+            .handle_peer_snapshot(0, peer_snapshot(Reach::default()))
+            .unwrap();
+        assert_eq!(child.system_peer(), None);
+        assert_eq!(child.system_snapshot().stratum, 10);
+        assert_eq!(
+            child.system_snapshot().time_snapshot.leap_indicator,
+            NtpLeapIndicator::NoWarning
+        );
+    }
+
+    #[test]
+    fn test_peer_state_includes_filter() {
+        let clock = FreeRunningClock::default();
+        let mut system: System<_, usize> = System::new(
+            clock.clone(),
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+        system.handle_peer_create(0, false).unwrap();
+
+        let mut reach = Reach::default();
+        reach.received_packet();
+        let peer_snapshot = PeerSnapshot {
+            source_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 123),
+            source_id: ReferenceId::from_ip(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            poll_interval: PollIntervalLimits::default().min,
+            reach,
+            stratum: 1,
+            reference_id: ReferenceId::NONE,
+            protocol_version: Default::default(),
+            last_measurement_time: None,
+            provenance: None,
+            missed_responses: 0,
+            state: Default::default(),
+            #[cfg(feature = "ntpv5")]
+            bloom_filter: None,
+        };
+
+        let base = NtpInstant::now();
+        let mut localtime = NtpTimestamp::default();
+        for i in 1..=16 {
+            localtime = NtpTimestamp::default() + NtpDuration::from_seconds(i as f64);
+            *clock.now.lock().unwrap() = localtime;
+            system
+                .handle_peer_measurement(
+                    0,
+                    peer_snapshot,
+                    Measurement {
+                        delay: NtpDuration::from_seconds(0.001),
+                        offset: NtpDuration::from_seconds(1e-3 + 1e-6 * (i % 3) as f64),
+                        transmit_timestamp: Default::default(),
+                        receive_timestamp: Default::default(),
+                        localtime,
+                        monotime: base + Duration::from_secs(i),
+                        stratum: 1,
+                        root_delay: NtpDuration::ZERO,
+                        root_dispersion: NtpDuration::ZERO,
+                        leap: NtpLeapIndicator::NoWarning,
+                        precision: 0,
+                    },
+                )
+                .unwrap();
+        }
+        let timedata = system.controller.as_ref().unwrap().peer_snapshot(0);
+        assert!(timedata.is_some());
+
+        let state = system.export_peer_state(0, &Peer::test_peer(), localtime);
+        assert!(state.filter.is_some());
+
+        // After a restart, the filter continues where it was
+        let mut restarted: System<_, usize> = System::new(
+            clock.clone(),
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+        restarted.handle_peer_create(0, false).unwrap();
+        let later = localtime + NtpDuration::from_seconds(60.0);
+        restarted.restore_peer_state(0, &mut Peer::test_peer(), state.clone(), later);
+        assert_eq!(
+            restarted.controller.as_ref().unwrap().peer_snapshot(0),
+            timedata
+        );
+
+        // but not when it has been down for too long
+        let mut restarted: System<_, usize> = System::new(
+            clock,
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+        restarted.handle_peer_create(0, false).unwrap();
+        let much_later =
+            localtime + NtpDuration::from_seconds(2.0 * crate::peer::MAX_SAVED_STATE_AGE);
+        restarted.restore_peer_state(0, &mut Peer::test_peer(), state, much_later);
+        assert_eq!(
+            restarted.controller.as_ref().unwrap().peer_snapshot(0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_max_peer_jitter() {
+        let clock = FreeRunningClock::default();
+        let mut system: System<_, usize> = System::new(
+            clock.clone(),
+            SynchronizationConfig {
+                minimum_agreeing_sources: 1,
+                max_peer_jitter: Some(NtpDuration::from_seconds(0.001)),
+                ..SynchronizationConfig::default()
+            },
+            // The jitter doesn't depend on the offset history kept for monitoring
+            SourceDefaultsConfig {
+                offset_history_length: 0,
+                ..SourceDefaultsConfig::default()
+            },
+            Arc::new([]),
+        );
+        system.handle_peer_create(0, false).unwrap();
+
+        let mut reach = Reach::default();
+        reach.received_packet();
+        let peer_snapshot = PeerSnapshot {
+            source_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 123),
+            source_id: ReferenceId::from_ip(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            poll_interval: PollIntervalLimits::default().min,
+            reach,
+            stratum: 1,
+            reference_id: ReferenceId::NONE,
+            protocol_version: Default::default(),
+            last_measurement_time: None,
+            provenance: None,
+            missed_responses: 0,
+            state: Default::default(),
+            #[cfg(feature = "ntpv5")]
+            bloom_filter: None,
+        };
+
+        let base = NtpInstant::now();
+        for i in 1..=16 {
+            let localtime = NtpTimestamp::default() + NtpDuration::from_seconds(i as f64);
+            *clock.now.lock().unwrap() = localtime;
+            system
+                .handle_peer_measurement(
+                    0,
+                    peer_snapshot,
+                    Measurement {
+                        // a delay this unstable makes for noisy offsets
+                        delay: NtpDuration::from_seconds(0.001 + 0.02 * (i % 2) as f64),
+                        offset: NtpDuration::from_seconds(1e-3),
+                        transmit_timestamp: Default::default(),
+                        receive_timestamp: Default::default(),
+                        localtime,
+                        monotime: base + Duration::from_secs(i),
+                        stratum: 1,
+                        root_delay: NtpDuration::ZERO,
+                        root_dispersion: NtpDuration::ZERO,
+                        leap: NtpLeapIndicator::NoWarning,
+                        precision: 0,
+                    },
+                )
+                .unwrap();
+        }
+
+        let controller = system.controller.as_ref().unwrap();
+        let jitter = controller.peer_snapshot(0).unwrap().jitter.unwrap();
+        assert!(jitter > NtpDuration::from_seconds(0.001));
+        assert!(controller.system_update().is_none());
+
+        system.synchronization_config.max_peer_jitter = Some(NtpDuration::from_seconds(1.0));
+        system.handle_peer_snapshot(0, peer_snapshot).unwrap();
+        assert!(system
+            .controller
+            .as_ref()
+            .unwrap()
+            .system_update()
+            .is_some());
+    }
+
+    #[test]
+    fn test_survivor_churn() {
+        let mut system: System<_, usize> = System::new(
+            TestClock {},
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            Arc::new([]),
+        );
+        for id in 0..4 {
+            system.peers.insert(
+                id,
+                Some(PeerSnapshot {
+                    source_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+                    source_id: ReferenceId::NONE,
+                    poll_interval: PollIntervalLimits::default().max,
+                    reach: Default::default(),
+                    stratum: 2,
+                    reference_id: ReferenceId::NONE,
+                    protocol_version: Default::default(),
+                    last_measurement_time: None,
+                    provenance: None,
+                    missed_responses: 0,
+                    state: Default::default(),
+                    #[cfg(feature = "ntpv5")]
+                    bloom_filter: None,
+                }),
+            );
+        }
+        assert_eq!(system.survivor_churn(), 0.0);
+
+        // A stable set of survivors, even if ordered differently
+        for used_peers in [vec![0, 1], vec![1, 0], vec![0, 1]] {
+            system.handle_algorithm_state_update(StateUpdate {
+                used_peers: Some(used_peers),
+                ..Default::default()
+            });
+        }
+        assert_eq!(system.survivor_churn(), 0.0);
+
+        // Alternating between two disjoint sets replaces every peer each cycle
+        for _ in 0..SURVIVOR_CHURN_WINDOW {
+            for used_peers in [vec![2, 3], vec![0, 1]] {
+                system.handle_algorithm_state_update(StateUpdate {
+                    used_peers: Some(used_peers),
+                    ..Default::default()
+                });
+            }
+        }
+        assert_eq!(system.survivor_churn(), 4.0);
+    }
 }
@@ -43,6 +43,19 @@ impl NtpInstant {
     pub fn elapsed(&self) -> std::time::Duration {
         self.instant.elapsed()
     }
+
+    /// Time passed from `earlier` until `self`, or zero when `earlier` is
+    /// later than `self`
+    pub fn saturating_duration_since(self, earlier: Self) -> Duration {
+        self.instant.saturating_duration_since(earlier.instant)
+    }
+
+    /// The instant `rhs` before `self`, if it can be represented
+    pub fn checked_sub(self, rhs: Duration) -> Option<Self> {
+        self.instant
+            .checked_sub(rhs)
+            .map(|instant| Self { instant })
+    }
 }
 
 impl Add<Duration> for NtpInstant {
@@ -190,11 +203,22 @@ impl SubAssign<NtpDuration> for NtpTimestamp {
 /// A negative duration interval is interpreted to mean that the first
 /// timestamp used to define the interval represents a point in time after
 /// the second timestamp.
+///
+/// Durations are stored in a fixed-point integer representation, and are
+/// ordered by that representation. This is a total order, so durations can
+/// safely be sorted with either [`Ord::cmp`] or [`PartialOrd::partial_cmp`].
 #[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Default)]
 pub struct NtpDuration {
     duration: i64,
 }
 
+/// Direction of an [`NtpDuration`], zero counting as positive
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Sign {
+    Positive,
+    Negative,
+}
+
 impl std::fmt::Debug for NtpDuration {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "NtpDuration({} ms)", self.to_seconds() * 1e3)
@@ -204,6 +228,14 @@ impl std::fmt::Debug for NtpDuration {
 impl NtpDuration {
     pub const ZERO: Self = Self { duration: 0 };
 
+    /// Lower bound on the dispersion of a single measurement (about 60ns),
+    /// so that no peer can claim to be perfectly accurate
+    pub const MIN_DISPERSION: Self = Self { duration: 1 << 8 };
+
+    /// Maximum dispersion (16 seconds), beyond which a source is considered
+    /// useless (MAXDISP in RFC5905)
+    pub const MAX_DISPERSION: Self = Self { duration: 16 << 32 };
+
     pub(crate) const fn from_bits(bits: [u8; 8]) -> Self {
         Self {
             duration: i64::from_be_bytes(bits),
@@ -305,6 +337,24 @@ impl NtpDuration {
         )
     }
 
+    /// Split into a sign and the length of this duration, e.g. for logging.
+    /// Unlike [`Duration`] conversions that expect a positive duration, this
+    /// never panics: any `NtpDuration`, including the most negative one, fits.
+    pub const fn to_std_duration_abs(self) -> (Sign, Duration) {
+        let sign = if self.duration < 0 {
+            Sign::Negative
+        } else {
+            Sign::Positive
+        };
+
+        let abs = self.duration.unsigned_abs();
+        let seconds = abs >> 32;
+        // round to the nearest nanosecond, a carry into the seconds is handled by Duration::new
+        let nanos = ((abs & 0xFFFFFFFF) * 1_000_000_000 + (1 << 31)) >> 32;
+
+        (sign, Duration::new(seconds, nanos as u32))
+    }
+
     /// Interpret an exponent `k` as `2^k` seconds, expressed as an NtpDuration
     pub fn from_exponent(input: i8) -> Self {
         Self {
@@ -537,7 +587,7 @@ pub struct PollIntervalLimits {
 
 // here we follow the spec (the code skeleton and ntpd repository use different values)
 // with the exception that we have lowered the MAX value, which is needed because
-// we don't support bursting, and hence using a larger poll interval gives issues
+// we don't burst by default, and hence using a larger poll interval gives issues
 // with the responsiveness of the client to environmental changes
 impl Default for PollIntervalLimits {
     fn default() -> Self {
@@ -710,6 +760,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_duration_total_order() {
+        let durations = [
+            NtpDuration::from_fixed_int(i64::MAX),
+            NtpDuration::from_seconds(-1.5),
+            NtpDuration::ZERO,
+            NtpDuration::from_fixed_int(i64::MIN),
+            NtpDuration::from_fixed_int(1),
+            NtpDuration::from_seconds(1e9),
+            NtpDuration::from_fixed_int(-1),
+            NtpDuration::from_seconds(-1e9),
+        ];
+
+        let mut by_cmp = durations.to_vec();
+        by_cmp.sort();
+        let mut by_partial_cmp = durations.to_vec();
+        by_partial_cmp.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut by_seconds = durations.to_vec();
+        by_seconds.sort_by(|a, b| a.to_seconds().total_cmp(&b.to_seconds()));
+
+        assert_eq!(by_cmp, by_partial_cmp);
+        assert_eq!(by_cmp, by_seconds);
+        assert_eq!(by_cmp[0], NtpDuration::from_fixed_int(i64::MIN));
+        assert_eq!(by_cmp[7], NtpDuration::from_fixed_int(i64::MAX));
+    }
+
     #[test]
     fn test_duration_math() {
         let mut a = NtpDuration::from_fixed_int(5);
@@ -748,6 +824,20 @@ mod tests {
     ntp_duration_scaling_test!(ntp_duration_scaling_u16, u16);
     ntp_duration_scaling_test!(ntp_duration_scaling_u32, u32);
 
+    #[test]
+    fn ntp_duration_scaling_saturates() {
+        // Scaling a large distance by a stratum (at most 16) must never wrap around
+        let stratum: u8 = 16;
+        let large = NtpDuration::from_seconds(1e9);
+        assert_eq!(large * stratum, NtpDuration::from_fixed_int(i64::MAX));
+        assert_eq!(stratum * large, NtpDuration::from_fixed_int(i64::MAX));
+        assert_eq!(-large * stratum, NtpDuration::from_fixed_int(i64::MIN));
+
+        let mut a = large;
+        a *= stratum;
+        assert_eq!(a, NtpDuration::from_fixed_int(i64::MAX));
+    }
+
     macro_rules! assert_eq_epsilon {
         ($a:expr, $b:expr, $epsilon:expr) => {
             assert!(
@@ -767,6 +857,30 @@ mod tests {
         assert_eq_epsilon!(NtpDuration::from_seconds(2.0).to_seconds(), 2.0, 1e-9);
     }
 
+    #[test]
+    fn duration_to_std_duration_abs() {
+        let (sign, duration) = NtpDuration::from_seconds(-0.012345).to_std_duration_abs();
+        assert_eq!(sign, Sign::Negative);
+        assert!((duration.as_secs_f64() - 0.012345).abs() < 1e-9);
+
+        let (sign, duration) = NtpDuration::from_seconds(1.5).to_std_duration_abs();
+        assert_eq!(sign, Sign::Positive);
+        assert_eq!(duration, Duration::from_millis(1500));
+
+        let (sign, duration) = NtpDuration::ZERO.to_std_duration_abs();
+        assert_eq!(sign, Sign::Positive);
+        assert_eq!(duration, Duration::ZERO);
+
+        // The extremes don't overflow
+        let (sign, duration) = NtpDuration::from_fixed_int(i64::MIN).to_std_duration_abs();
+        assert_eq!(sign, Sign::Negative);
+        assert_eq!(duration, Duration::from_secs(1 << 31));
+
+        let (sign, duration) = NtpDuration::from_fixed_int(i64::MAX).to_std_duration_abs();
+        assert_eq!(sign, Sign::Positive);
+        assert_eq!(duration, Duration::from_secs(1 << 31));
+    }
+
     #[test]
     fn duration_from_exponent() {
         assert_eq_epsilon!(NtpDuration::from_exponent(0).to_seconds(), 1.0, 1e-9);
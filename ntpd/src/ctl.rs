@@ -1,11 +1,20 @@
-use std::{path::PathBuf, process::ExitCode};
-
-use crate::daemon::{config::CliArg, tracing::LogLevel, Config, ObservableState};
+use std::{net::SocketAddr, path::PathBuf, process::ExitCode};
+
+use crate::daemon::{
+    config::CliArg,
+    observer::{ManagementRequest, ReadVariablesResult},
+    tracing::LogLevel,
+    Config, NtpClockWrapper, ObservableState,
+};
+use ntp_proto::NtpClock;
 use tracing_subscriber::util::SubscriberInitExt;
 
 const USAGE_MSG: &str = "\
 usage: ntp-ctl validate [-c PATH]
        ntp-ctl status [-f FORMAT] [-c PATH]
+       ntp-ctl reset-reachability ADDRESS [-c PATH]
+       ntp-ctl read-variables ADDRESS [-c PATH]
+       ntp-ctl poll-now [-c PATH]
        ntp-ctl -h | ntp-ctl -v";
 
 const DESCRIPTOR: &str = "ntp-ctl - ntp-daemon monitoring";
@@ -34,6 +43,9 @@ pub enum NtpCtlAction {
     Version,
     Validate,
     Status,
+    ResetReachability(SocketAddr),
+    ReadVariables(SocketAddr),
+    PollNow,
 }
 
 #[derive(Debug, Default)]
@@ -44,6 +56,9 @@ pub(crate) struct NtpCtlOptions {
     version: bool,
     validate: bool,
     status: bool,
+    reset_reachability: Option<SocketAddr>,
+    read_variables: Option<SocketAddr>,
+    poll_now: bool,
     action: NtpCtlAction,
 }
 
@@ -93,10 +108,10 @@ impl NtpCtlOptions {
                     }
                 },
                 CliArg::Rest(rest) => {
-                    if rest.len() > 1 {
-                        eprintln!("Warning: Too many commands provided.")
-                    }
-                    for command in rest {
+                    let mut commands = rest.into_iter();
+                    let mut count = 0;
+                    while let Some(command) = commands.next() {
+                        count += 1;
                         match command.as_str() {
                             "validate" => {
                                 options.validate = true;
@@ -104,11 +119,35 @@ impl NtpCtlOptions {
                             "status" => {
                                 options.status = true;
                             }
+                            "reset-reachability" => {
+                                let address = commands
+                                    .next()
+                                    .ok_or("reset-reachability expects the address of a source")?;
+                                let address = address
+                                    .parse()
+                                    .map_err(|_| format!("invalid source address: {address}"))?;
+                                options.reset_reachability = Some(address);
+                            }
+                            "read-variables" => {
+                                let address = commands
+                                    .next()
+                                    .ok_or("read-variables expects the address of a source")?;
+                                let address = address
+                                    .parse()
+                                    .map_err(|_| format!("invalid source address: {address}"))?;
+                                options.read_variables = Some(address);
+                            }
+                            "poll-now" => {
+                                options.poll_now = true;
+                            }
                             unknown => {
                                 eprintln!("Warning: Unknown command {unknown}");
                             }
                         }
                     }
+                    if count > 1 {
+                        eprintln!("Warning: Too many commands provided.")
+                    }
                 }
             }
         }
@@ -129,6 +168,12 @@ impl NtpCtlOptions {
             self.action = NtpCtlAction::Validate;
         } else if self.status {
             self.action = NtpCtlAction::Status;
+        } else if let Some(address) = self.reset_reachability {
+            self.action = NtpCtlAction::ResetReachability(address);
+        } else if let Some(address) = self.read_variables {
+            self.action = NtpCtlAction::ReadVariables(address);
+        } else if self.poll_now {
+            self.action = NtpCtlAction::PollNow;
         } else {
             self.action = NtpCtlAction::Help;
         }
@@ -191,6 +236,97 @@ pub async fn main() -> std::io::Result<ExitCode> {
                 Format::Prometheus => print_state(Format::Prometheus, observation).await,
             }
         }
+        NtpCtlAction::ResetReachability(address) => {
+            let management = management_path(options.config).await;
+            match send_request(management, ManagementRequest::ResetReachability(address)).await {
+                Some(_) => Ok(ExitCode::SUCCESS),
+                None => Ok(ExitCode::FAILURE),
+            }
+        }
+        NtpCtlAction::ReadVariables(address) => {
+            let management = management_path(options.config).await;
+            print_variables(management, address).await
+        }
+        NtpCtlAction::PollNow => {
+            let management = management_path(options.config).await;
+            match send_request(management, ManagementRequest::PollNow).await {
+                Some(_) => Ok(ExitCode::SUCCESS),
+                None => Ok(ExitCode::FAILURE),
+            }
+        }
+    }
+}
+
+/// Path of the management socket according to the configuration
+async fn management_path(config: Option<PathBuf>) -> PathBuf {
+    let config = Config::from_args(config, vec![], vec![]).await;
+
+    if let Err(ref e) = config {
+        println!("Warning: Unable to load configuration file: {e}");
+    }
+
+    config
+        .unwrap_or_default()
+        .observability
+        .management_path
+        .unwrap_or_else(|| PathBuf::from("/var/run/ntpd-rs/manage"))
+}
+
+/// Send a request to the management socket, returning the stream on which
+/// any response arrives, or `None` when sending failed
+async fn send_request(
+    management_socket: PathBuf,
+    request: ManagementRequest,
+) -> Option<tokio::net::UnixStream> {
+    let mut stream = match tokio::net::UnixStream::connect(&management_socket).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!(
+                "Could not open socket at {}: {e}",
+                management_socket.display(),
+            );
+            return None;
+        }
+    };
+
+    if let Err(e) = crate::daemon::sockets::write_json(&mut stream, &request).await {
+        eprintln!("Failed to send request to management socket: {e}");
+        return None;
+    }
+
+    Some(stream)
+}
+
+async fn print_variables(
+    management_socket: PathBuf,
+    address: SocketAddr,
+) -> Result<ExitCode, std::io::Error> {
+    let Some(mut stream) =
+        send_request(management_socket, ManagementRequest::ReadVariables(address)).await
+    else {
+        return Ok(ExitCode::FAILURE);
+    };
+
+    let mut msg = Vec::with_capacity(16 * 1024);
+    let result =
+        crate::daemon::sockets::read_json::<ReadVariablesResult>(&mut stream, &mut msg).await;
+    match result {
+        Ok(Ok(variables)) => {
+            let mut variables: Vec<_> = variables.into_iter().collect();
+            variables.sort();
+            for (name, value) in variables {
+                println!("{name}={value}");
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Ok(Err(e)) => {
+            eprintln!("Could not read variables of {address}: {e}");
+            Ok(ExitCode::FAILURE)
+        }
+        Err(e) => {
+            eprintln!("Failed to read response from management socket: {e}");
+            Ok(ExitCode::FAILURE)
+        }
     }
 }
 
@@ -241,6 +377,8 @@ async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode,
             println!("Stratum: {}", output.system.stratum);
             println!();
             println!("Sources:");
+            // Peer times are in the daemon's local time, which is this clock
+            let now = NtpClockWrapper::default().now().ok();
             for peer in &output.sources {
                 match peer {
                     crate::daemon::ObservablePeerState::Nothing => {}
@@ -252,6 +390,10 @@ async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode,
                             name: address,
                             address: ip,
                             id,
+                            last_error,
+                            last_measurement,
+                            missed_responses: _,
+                            state: _,
                         },
                     ) => {
                         println!(
@@ -271,6 +413,15 @@ async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode,
                             timedata.remote_uncertainty.to_seconds(),
                             timedata.remote_delay.to_seconds(),
                         );
+                        if let (Some(now), Some(time)) = (now, last_measurement) {
+                            println!(
+                                "    last measurement: {:.0}s ago",
+                                (now - *time).to_seconds()
+                            );
+                        }
+                        if let Some((_, error)) = last_error {
+                            println!("    last error: {error}");
+                        }
                     }
                 }
             }
@@ -450,4 +601,43 @@ mod tests {
         let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
         assert_eq!(err, "invalid format option provided: yaml");
     }
+
+    #[test]
+    fn cli_reset_reachability() {
+        let arguments = &[BINARY, "reset-reachability", "127.0.0.3:123"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(
+            options.action,
+            NtpCtlAction::ResetReachability("127.0.0.3:123".parse().unwrap())
+        );
+
+        let arguments = &[BINARY, "reset-reachability"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "reset-reachability expects the address of a source");
+
+        let arguments = &[BINARY, "reset-reachability", "example.com"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "invalid source address: example.com");
+    }
+
+    #[test]
+    fn cli_read_variables() {
+        let arguments = &[BINARY, "read-variables", "127.0.0.3:123"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(
+            options.action,
+            NtpCtlAction::ReadVariables("127.0.0.3:123".parse().unwrap())
+        );
+
+        let arguments = &[BINARY, "read-variables"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "read-variables expects the address of a source");
+    }
+
+    #[test]
+    fn cli_poll_now() {
+        let arguments = &[BINARY, "poll-now"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::PollNow);
+    }
 }
@@ -289,6 +289,20 @@ impl TimestampMode {
     }
 }
 
+/// Which receive timestamps are accepted for incoming packets.
+#[derive(Default, Debug, Clone, Copy, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecvTimestampPolicy {
+    /// Use the kernel or hardware timestamp, falling back to reading the
+    /// clock when the packet doesn't have one
+    #[default]
+    Prefer,
+    /// Ignore packets without a kernel or hardware timestamp
+    Require,
+    /// Always read the clock on receiving a packet
+    SoftwareOnly,
+}
+
 #[derive(Deserialize, Debug, Copy, Clone, Default)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct ClockConfig {
@@ -297,6 +311,8 @@ pub struct ClockConfig {
     #[serde(deserialize_with = "deserialize_interface", default)]
     pub interface: Option<InterfaceName>,
     pub timestamp_mode: TimestampMode,
+    #[serde(default)]
+    pub recv_timestamp_policy: RecvTimestampPolicy,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -308,8 +324,14 @@ pub struct ObservabilityConfig {
     pub observation_path: Option<PathBuf>,
     #[serde(default = "default_observation_permissions")]
     pub observation_permissions: u32,
+    #[serde(default)]
+    pub management_path: Option<PathBuf>,
+    #[serde(default = "default_management_permissions")]
+    pub management_permissions: u32,
     #[serde(default = "default_metrics_exporter_listen")]
     pub metrics_exporter_listen: SocketAddr,
+    #[serde(default)]
+    pub measurement_log_path: Option<PathBuf>,
 }
 
 impl Default for ObservabilityConfig {
@@ -318,7 +340,10 @@ impl Default for ObservabilityConfig {
             log_level: Default::default(),
             observation_path: Default::default(),
             observation_permissions: default_observation_permissions(),
+            management_path: Default::default(),
+            management_permissions: default_management_permissions(),
             metrics_exporter_listen: default_metrics_exporter_listen(),
+            measurement_log_path: Default::default(),
         }
     }
 }
@@ -327,6 +352,10 @@ const fn default_observation_permissions() -> u32 {
     0o666
 }
 
+const fn default_management_permissions() -> u32 {
+    0o600
+}
+
 fn default_metrics_exporter_listen() -> SocketAddr {
     "127.0.0.1:9975".parse().unwrap()
 }
@@ -423,6 +452,7 @@ impl Config {
                 PeerConfig::Standard(_) => count += 1,
                 PeerConfig::Nts(_) => count += 1,
                 PeerConfig::Pool(config) => count += config.max_peers,
+                PeerConfig::Sock(_) => count += 1,
                 #[cfg(feature = "unstable_nts-pool")]
                 PeerConfig::NtsPool(config) => count += config.max_peers,
             }
@@ -497,6 +527,10 @@ mod tests {
             config.sources,
             vec![PeerConfig::Standard(StandardPeerConfig {
                 address: NormalizedAddress::new_unchecked("example.com", 123).into(),
+                prefer: false,
+                key: None,
+                source_port: None,
+                delay_asymmetry_correction: NtpDuration::ZERO,
             })]
         );
         assert!(config.observability.log_level.is_none());
@@ -510,6 +544,10 @@ mod tests {
             config.sources,
             vec![PeerConfig::Standard(StandardPeerConfig {
                 address: NormalizedAddress::new_unchecked("example.com", 123).into(),
+                prefer: false,
+                key: None,
+                source_port: None,
+                delay_asymmetry_correction: NtpDuration::ZERO,
             })]
         );
 
@@ -521,6 +559,10 @@ mod tests {
             config.sources,
             vec![PeerConfig::Standard(StandardPeerConfig {
                 address: NormalizedAddress::new_unchecked("example.com", 123).into(),
+                prefer: false,
+                key: None,
+                source_port: None,
+                delay_asymmetry_correction: NtpDuration::ZERO,
             })]
         );
         assert_eq!(
@@ -540,6 +582,10 @@ mod tests {
             config.sources,
             vec![PeerConfig::Standard(StandardPeerConfig {
                 address: NormalizedAddress::new_unchecked("example.com", 123).into(),
+                prefer: false,
+                key: None,
+                source_port: None,
+                delay_asymmetry_correction: NtpDuration::ZERO,
             })]
         );
         assert!(config
@@ -561,10 +607,14 @@ mod tests {
             [source-defaults]
             poll-interval-limits = { min = 5, max = 9 }
             initial-poll-interval = 5
+            advertised-precision = -10
+            restrict-cooldown = 60
+            response-timeout = 0.5
             [observability]
             log-level = "info"
             observation-path = "/foo/bar/observe"
             observation-permissions = 0o567
+            management-path = "/foo/bar/manage"
             "#,
         )
         .unwrap();
@@ -575,11 +625,20 @@ mod tests {
             Some(PathBuf::from("/foo/bar/observe"))
         );
         assert_eq!(config.observability.observation_permissions, 0o567);
+        assert_eq!(
+            config.observability.management_path,
+            Some(PathBuf::from("/foo/bar/manage"))
+        );
+        assert_eq!(config.observability.management_permissions, 0o600);
 
         assert_eq!(
             config.sources,
             vec![PeerConfig::Standard(StandardPeerConfig {
                 address: NormalizedAddress::new_unchecked("example.com", 123).into(),
+                prefer: false,
+                key: None,
+                source_port: None,
+                delay_asymmetry_correction: NtpDuration::ZERO,
             })]
         );
 
@@ -588,6 +647,15 @@ mod tests {
         assert_eq!(poll_interval_limits.max.as_log(), 9);
 
         assert_eq!(config.source_defaults.initial_poll_interval.as_log(), 5);
+        assert_eq!(config.source_defaults.advertised_precision, Some(-10));
+        assert_eq!(
+            config.source_defaults.restrict_cooldown,
+            std::time::Duration::from_secs(60)
+        );
+        assert_eq!(
+            config.source_defaults.response_timeout,
+            std::time::Duration::from_millis(500)
+        );
     }
 
     #[test]
@@ -645,6 +713,18 @@ mod tests {
         assert!(config.is_err());
     }
 
+    #[test]
+    fn toml_poll_rate_limit() {
+        let config: SourceDefaultsConfig = toml::from_str("poll-rate-limit = 2.5").unwrap();
+        assert_eq!(config.poll_rate_limit, Some(2.5));
+
+        for rate in ["0", "0.0", "-1.0", "nan", "inf"] {
+            let config: Result<SourceDefaultsConfig, _> =
+                toml::from_str(&format!("poll-rate-limit = {rate}"));
+            assert!(config.is_err(), "{rate} should be rejected");
+        }
+    }
+
     #[test]
     fn toml_allow_no_peers() {
         let config: Result<Config, _> = toml::from_str(
@@ -737,6 +817,25 @@ mod tests {
         assert!(error.to_string().contains("expected a positive number"));
     }
 
+    #[test]
+    fn max_single_peer_weight_positive() {
+        let config: SynchronizationConfig = toml::from_str(
+            r#"
+            [algorithm]
+            max-single-peer-weight = 0.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.algorithm.max_single_peer_weight, Some(0.5));
+
+        for weight in ["0.0", "-0.5", "nan"] {
+            let config: Result<SynchronizationConfig, _> =
+                toml::from_str(&format!("[algorithm]\nmax-single-peer-weight = {weight}"));
+            let error = config.unwrap_err();
+            assert!(error.to_string().contains("expected a positive weight"));
+        }
+    }
+
     #[test]
     fn deny_unknown_fields() {
         let config: Result<SynchronizationConfig, _> = toml::from_str(
@@ -764,5 +863,15 @@ mod tests {
         assert_eq!(config.interface, Some(expected));
 
         assert_eq!(config.timestamp_mode, TimestampMode::Software);
+        assert_eq!(config.recv_timestamp_policy, RecvTimestampPolicy::Prefer);
+
+        let config: ClockConfig = toml::from_str(
+            r#"
+            timestamp-mode = "kernel-recv"
+            recv-timestamp-policy = "require"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.recv_timestamp_policy, RecvTimestampPolicy::Require);
     }
 }
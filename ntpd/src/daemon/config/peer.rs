@@ -6,6 +6,7 @@ use std::{
     sync::{Arc, Mutex},
 };
 
+use ntp_proto::{MacKey, NtpDuration, ReferenceId};
 use rustls::pki_types::CertificateDer;
 use serde::{de, Deserialize, Deserializer};
 
@@ -15,6 +16,58 @@ use super::super::keyexchange::certificates_from_file;
 #[serde(deny_unknown_fields)]
 pub struct StandardPeerConfig {
     pub address: NtpAddress,
+    #[serde(default)]
+    pub prefer: bool,
+    /// Shared key with which to authenticate packets through a MAC
+    #[serde(default, deserialize_with = "deserialize_mac_key")]
+    pub key: Option<MacKey>,
+    /// Local port from which to send polls, or an ephemeral port chosen by
+    /// the operating system when not set
+    #[serde(default, rename = "source-port")]
+    pub source_port: Option<u16>,
+    /// How much longer packets take to come back from the source than to
+    /// reach it. Half of this is added to every measured offset.
+    #[serde(default, rename = "delay-asymmetry-correction")]
+    pub delay_asymmetry_correction: NtpDuration,
+}
+
+fn deserialize_mac_key<'de, D>(deserializer: D) -> Result<Option<MacKey>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct KeyConfig {
+        id: u32,
+        secret: String,
+    }
+
+    let KeyConfig { id, secret } = KeyConfig::deserialize(deserializer)?;
+
+    // Don't echo the secret in the error, it would end up in the logs
+    let is_hex = secret.bytes().all(|b| b.is_ascii_hexdigit());
+    if secret.is_empty() || secret.len() % 2 != 0 || !is_hex {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Other("key secret"),
+            &"a non-empty hex string",
+        ));
+    }
+
+    let secret = secret
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| (hex_value(pair[0]) << 4) | hex_value(pair[1]))
+        .collect();
+
+    Ok(Some(MacKey::new(id, secret)))
+}
+
+fn hex_value(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
 }
 
 #[derive(Debug, Deserialize, PartialEq, Eq, Clone)]
@@ -65,6 +118,44 @@ fn max_peers_default() -> usize {
     4
 }
 
+/// A reference clock fed over a unix socket using the SOCK protocol of chrony
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SockPeerConfig {
+    /// Path of the unix socket on which samples are received
+    pub path: PathBuf,
+    /// Reference id advertised for the clock
+    #[serde(
+        rename = "reference-id",
+        default = "default_refclock_reference_id",
+        deserialize_with = "deserialize_refclock_reference_id"
+    )]
+    pub reference_id: ReferenceId,
+    #[serde(default)]
+    pub prefer: bool,
+}
+
+fn default_refclock_reference_id() -> ReferenceId {
+    ReferenceId::from_bytes(*b"GPS\0")
+}
+
+fn deserialize_refclock_reference_id<'de, D>(deserializer: D) -> Result<ReferenceId, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    if name.is_empty() || name.len() > 4 || !name.is_ascii() {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Str(&name),
+            &"one to four ASCII characters",
+        ));
+    }
+
+    let mut bytes = [0; 4];
+    bytes[..name.len()].copy_from_slice(name.as_bytes());
+    Ok(ReferenceId::from_bytes(bytes))
+}
+
 #[cfg(feature = "unstable_nts-pool")]
 #[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
 #[serde(deny_unknown_fields)]
@@ -90,6 +181,8 @@ pub enum PeerConfig {
     Nts(NtsPeerConfig),
     #[serde(rename = "pool")]
     Pool(PoolPeerConfig),
+    #[serde(rename = "sock")]
+    Sock(SockPeerConfig),
     // Consul(ConsulPeerConfig),
     #[cfg(feature = "unstable_nts-pool")]
     #[serde(rename = "nts-pool")]
@@ -255,7 +348,6 @@ impl NormalizedAddress {
         }
     }
 
-    #[cfg(test)]
     pub(crate) fn new_unchecked(server_name: &str, port: u16) -> Self {
         Self {
             server_name: server_name.to_string(),
@@ -312,6 +404,10 @@ impl TryFrom<&str> for StandardPeerConfig {
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         Ok(Self {
             address: NormalizedAddress::from_string_ntp(value.to_string())?.into(),
+            prefer: false,
+            key: None,
+            source_port: None,
+            delay_asymmetry_correction: NtpDuration::ZERO,
         })
     }
 }
@@ -333,6 +429,7 @@ mod tests {
             PeerConfig::Standard(c) => c.address.to_string(),
             PeerConfig::Nts(c) => c.address.to_string(),
             PeerConfig::Pool(c) => c.addr.to_string(),
+            PeerConfig::Sock(c) => c.path.display().to_string(),
             #[cfg(feature = "unstable_nts-pool")]
             PeerConfig::NtsPool(c) => c.addr.to_string(),
         }
@@ -367,6 +464,63 @@ mod tests {
         assert_eq!(peer_addr(&test.peer), "example.com:5678");
         assert!(matches!(test.peer, PeerConfig::Standard(_)));
 
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            mode = "server"
+            address = "example.com"
+            prefer = true
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(
+            test.peer,
+            PeerConfig::Standard(StandardPeerConfig { prefer: true, .. })
+        ));
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            mode = "server"
+            address = "example.com"
+            key = { id = 42, secret = "00ff10" }
+            "#,
+        )
+        .unwrap();
+        let PeerConfig::Standard(config) = test.peer else {
+            panic!("Expected a standard peer");
+        };
+        assert_eq!(config.key, Some(MacKey::new(42, vec![0x00, 0xff, 0x10])));
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            mode = "server"
+            address = "example.com"
+            source-port = 123
+            delay-asymmetry-correction = 0.01
+            "#,
+        )
+        .unwrap();
+        let PeerConfig::Standard(config) = test.peer else {
+            panic!("Expected a standard peer");
+        };
+        assert_eq!(config.source_port, Some(123));
+        assert_eq!(
+            config.delay_asymmetry_correction,
+            NtpDuration::from_seconds(0.01)
+        );
+
+        assert!(toml::from_str::<TestConfig>(
+            r#"
+            [peer]
+            mode = "server"
+            address = "example.com"
+            key = { id = 42, secret = "not hex" }
+            "#,
+        )
+        .is_err());
+
         let test: TestConfig = toml::from_str(
             r#"
             [peer]
@@ -464,6 +618,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_deserialize_sock_peer() {
+        #[derive(Deserialize, Debug)]
+        struct TestConfig {
+            peer: PeerConfig,
+        }
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            mode = "sock"
+            path = "/run/ntpd-rs/gps.sock"
+            "#,
+        )
+        .unwrap();
+        let PeerConfig::Sock(config) = test.peer else {
+            panic!("Expected a sock peer");
+        };
+        assert_eq!(config.path, PathBuf::from("/run/ntpd-rs/gps.sock"));
+        assert_eq!(config.reference_id, ReferenceId::from_bytes(*b"GPS\0"));
+        assert!(!config.prefer);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            mode = "sock"
+            path = "/run/ntpd-rs/pps.sock"
+            reference-id = "PPS"
+            prefer = true
+            "#,
+        )
+        .unwrap();
+        let PeerConfig::Sock(config) = test.peer else {
+            panic!("Expected a sock peer");
+        };
+        assert_eq!(config.reference_id, ReferenceId::from_bytes(*b"PPS\0"));
+        assert!(config.prefer);
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [peer]
+            mode = "sock"
+            path = "/run/ntpd-rs/gps.sock"
+            reference-id = "TOOLONG"
+            "#,
+        );
+        assert!(test.is_err());
+    }
+
     #[test]
     fn test_peer_from_string() {
         let peer = PeerConfig::try_from("example.com").unwrap();
@@ -0,0 +1,97 @@
+use std::{net::SocketAddr, path::Path};
+
+use ntp_proto::Measurement;
+use serde::Serialize;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
+use tracing::{debug, warn};
+
+/// Number of measurements that can wait to be written before new ones are
+/// dropped
+const RECORD_BUFFER_SIZE: usize = 256;
+
+/// Sink shared between all peer tasks, appending every measurement as a line
+/// of JSON to a file, for offline analysis
+#[derive(Debug, Clone)]
+pub struct MeasurementRecorder {
+    sender: Option<mpsc::Sender<(SocketAddr, Measurement)>>,
+}
+
+#[derive(Serialize)]
+struct MeasurementRecord<'a> {
+    peer: SocketAddr,
+    #[serde(flatten)]
+    measurement: &'a Measurement,
+}
+
+impl MeasurementRecorder {
+    /// A recorder that drops all measurements
+    pub fn disabled() -> Self {
+        MeasurementRecorder { sender: None }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_sender(sender: mpsc::Sender<(SocketAddr, Measurement)>) -> Self {
+        MeasurementRecorder {
+            sender: Some(sender),
+        }
+    }
+
+    /// Create a recorder appending to the file at `path`, or a disabled one
+    /// when `path` is `None`
+    pub async fn spawn(path: Option<&Path>) -> std::io::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::disabled());
+        };
+
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+
+        let (sender, receiver) = mpsc::channel(RECORD_BUFFER_SIZE);
+        tokio::spawn(write_records(receiver, file));
+
+        Ok(MeasurementRecorder {
+            sender: Some(sender),
+        })
+    }
+
+    /// Record a measurement of `peer`. This never waits for the file, when
+    /// writing lags behind the measurement is dropped instead.
+    pub fn record(&self, peer: SocketAddr, measurement: Measurement) {
+        if let Some(sender) = &self.sender {
+            if sender.try_send((peer, measurement)).is_err() {
+                debug!("measurement recording lags behind, dropping measurement");
+            }
+        }
+    }
+}
+
+pub(crate) async fn write_records(
+    mut receiver: mpsc::Receiver<(SocketAddr, Measurement)>,
+    mut writer: impl AsyncWrite + Unpin,
+) {
+    while let Some((peer, measurement)) = receiver.recv().await {
+        let record = MeasurementRecord {
+            peer,
+            measurement: &measurement,
+        };
+
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(error) => {
+                warn!(?error, "could not serialize measurement");
+                continue;
+            }
+        };
+        line.push(b'\n');
+
+        if let Err(error) = writer.write_all(&line).await {
+            warn!(?error, "could not record measurement");
+        }
+    }
+}
@@ -2,9 +2,12 @@ mod clock;
 pub mod config;
 pub mod keyexchange;
 mod local_ip_provider;
+mod measurement_log;
 pub mod nts_key_provider;
 pub mod observer;
 mod peer;
+mod poll_limiter;
+pub mod refclock;
 mod server;
 pub mod sockets;
 pub mod spawn;
@@ -15,6 +18,7 @@ mod util;
 use std::{error::Error, path::PathBuf};
 
 use ::tracing::info;
+pub(crate) use clock::NtpClockWrapper;
 pub use config::Config;
 pub use observer::{ObservablePeerState, ObservableState, ObservedPeerState};
 pub use system::spawn;
@@ -99,6 +103,11 @@ async fn run(options: NtpDaemonOptions) -> Result<(), Box<dyn Error>> {
     #[cfg(not(feature = "hardware-timestamping"))]
     let clock_config = config::ClockConfig::default();
 
+    let measurement_recorder = measurement_log::MeasurementRecorder::spawn(
+        config.observability.measurement_log_path.as_deref(),
+    )
+    .await?;
+
     ::tracing::debug!("Configuration loaded, spawning daemon jobs");
     let (main_loop_handle, channels) = spawn(
         config.synchronization,
@@ -107,6 +116,7 @@ async fn run(options: NtpDaemonOptions) -> Result<(), Box<dyn Error>> {
         &config.sources,
         &config.servers,
         keyset.clone(),
+        measurement_recorder,
     )
     .await?;
 
@@ -122,6 +132,14 @@ async fn run(options: NtpDaemonOptions) -> Result<(), Box<dyn Error>> {
     )
     .await;
 
+    observer::spawn_management(
+        &config.observability,
+        channels.reset_reachability_sender,
+        channels.read_variables_sender,
+        channels.poll_now_sender,
+    )
+    .await;
+
     Ok(main_loop_handle.await??)
 }
 
@@ -131,12 +149,6 @@ pub(crate) mod exitcode {
     /// errors as possible.
     pub const SOFTWARE: i32 = 70;
 
-    /// You did not have sufficient permission to perform
-    /// the operation.  This is not intended for file system
-    /// problems, which should use `NOINPUT` or `CANTCREAT`,
-    /// but rather for higher level permissions.
-    pub const NOPERM: i32 = 77;
-
     /// Something was found in an unconfigured or misconfigured state.
     pub const CONFIG: i32 = 78;
 }
@@ -1,12 +1,18 @@
+use super::peer::ReadVariablesRequest;
 use super::server::ServerStats;
-use super::sockets::create_unix_socket_with_permissions;
+use super::sockets::{create_unix_socket_with_permissions, read_json, write_json};
 use super::spawn::PeerId;
 use super::system::ServerData;
-use ntp_proto::{ObservablePeerTimedata, PollInterval, SystemSnapshot};
+use ntp_proto::{NtpTimestamp, ObservablePeerTimedata, PeerState, PollInterval, SystemSnapshot};
 use std::os::unix::fs::PermissionsExt;
-use std::{net::SocketAddr, time::Instant};
-use tokio::task::JoinHandle;
-use tracing::warn;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{net::UnixStream, task::JoinHandle};
+use tracing::{info, warn};
 
 use serde::{Deserialize, Serialize};
 
@@ -76,6 +82,17 @@ pub struct ObservedPeerState {
     pub name: String,
     pub address: String,
     pub id: PeerId,
+    /// Most recent error of the peer, and when it occurred
+    #[serde(default)]
+    pub last_error: Option<(NtpTimestamp, String)>,
+    /// Local time of the last accepted measurement of the peer
+    #[serde(default)]
+    pub last_measurement: Option<NtpTimestamp>,
+    /// Number of polls the peer did not answer within the response timeout
+    #[serde(default)]
+    pub missed_responses: u64,
+    #[serde(default)]
+    pub state: PeerState,
 }
 
 pub async fn spawn(
@@ -126,7 +143,137 @@ async fn observer(
             servers: server_reader.borrow().iter().map(|s| s.into()).collect(),
         };
 
-        super::sockets::write_json(&mut stream, &observe).await?;
+        write_json(&mut stream, &observe).await?;
+    }
+}
+
+/// Request the daemon accepts on its management socket
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ManagementRequest {
+    /// Forget the reachability history of the source with the given address
+    ResetReachability(SocketAddr),
+    /// Read the variables of the source with the given address using a
+    /// control message, answered with a [`ReadVariablesResult`]
+    ReadVariables(SocketAddr),
+    /// Poll all sources now, as far as their minimum poll gap allows
+    PollNow,
+}
+
+/// Variables of a source read with a control message, or why reading them
+/// failed
+pub type ReadVariablesResult = Result<HashMap<String, String>, String>;
+
+/// How long to wait for a source to answer a request to read its variables,
+/// which includes waiting for the server to respond
+const READ_VARIABLES_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn spawn_management(
+    config: &super::config::ObservabilityConfig,
+    reset_reachability_sender: tokio::sync::broadcast::Sender<SocketAddr>,
+    read_variables_sender: tokio::sync::watch::Sender<Option<ReadVariablesRequest>>,
+    poll_now_sender: tokio::sync::watch::Sender<()>,
+) -> JoinHandle<std::io::Result<()>> {
+    let config = config.clone();
+    tokio::spawn(async move {
+        let result = management(
+            config,
+            reset_reachability_sender,
+            read_variables_sender,
+            poll_now_sender,
+        )
+        .await;
+        if let Err(ref e) = result {
+            warn!("Abnormal termination of the management socket: {e}");
+            warn!("The daemon can not be managed through its socket");
+        }
+        result
+    })
+}
+
+async fn management(
+    config: super::config::ObservabilityConfig,
+    reset_reachability_sender: tokio::sync::broadcast::Sender<SocketAddr>,
+    read_variables_sender: tokio::sync::watch::Sender<Option<ReadVariablesRequest>>,
+    poll_now_sender: tokio::sync::watch::Sender<()>,
+) -> std::io::Result<()> {
+    let path = match config.management_path {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    // unlike observing, managing the daemon is restricted to its own user by default
+    let permissions: std::fs::Permissions =
+        PermissionsExt::from_mode(config.management_permissions);
+
+    let listener = create_unix_socket_with_permissions(&path, permissions)?;
+
+    let senders = Arc::new(ManagementSenders {
+        reset_reachability: reset_reachability_sender,
+        read_variables: read_variables_sender,
+        poll_now: poll_now_sender,
+    });
+
+    loop {
+        let (stream, _addr) = listener.accept().await?;
+
+        // a slow request (e.g. reading variables) must not hold up other clients
+        let senders = senders.clone();
+        tokio::spawn(async move { handle_management_request(stream, &senders).await });
+    }
+}
+
+struct ManagementSenders {
+    reset_reachability: tokio::sync::broadcast::Sender<SocketAddr>,
+    read_variables: tokio::sync::watch::Sender<Option<ReadVariablesRequest>>,
+    poll_now: tokio::sync::watch::Sender<()>,
+}
+
+async fn handle_management_request(mut stream: UnixStream, senders: &ManagementSenders) {
+    let mut msg = Vec::with_capacity(1024);
+
+    match read_json::<ManagementRequest>(&mut stream, &mut msg).await {
+        Ok(ManagementRequest::ResetReachability(addr)) => {
+            info!(%addr, "Resetting reachability of source on request");
+            // fails only when no peers are running, so there is nothing to reset
+            let _ = senders.reset_reachability.send(addr);
+        }
+        Ok(ManagementRequest::ReadVariables(addr)) => {
+            info!(%addr, "Reading variables of source on request");
+            let (reply, mut response) = tokio::sync::mpsc::channel(1);
+            senders
+                .read_variables
+                .send_replace(Some(ReadVariablesRequest {
+                    addr,
+                    reply: reply.clone(),
+                }));
+
+            // Only a source with the requested address answers
+            let result: ReadVariablesResult =
+                match tokio::time::timeout(READ_VARIABLES_TIMEOUT, response.recv()).await {
+                    Ok(Some(result)) => result,
+                    _ => Err(format!("no response from a source with address {addr}")),
+                };
+            // Peers started later should not act on this request anymore, but
+            // a request from another client that replaced it must stay in place
+            senders
+                .read_variables
+                .send_if_modified(|current| match current {
+                    Some(request) if request.reply.same_channel(&reply) => {
+                        *current = None;
+                        true
+                    }
+                    _ => false,
+                });
+
+            if let Err(e) = write_json(&mut stream, &result).await {
+                warn!("Could not send variables to management client: {e}");
+            }
+        }
+        Ok(ManagementRequest::PollNow) => {
+            info!("Polling all sources on request");
+            senders.poll_now.send_replace(());
+        }
+        Err(e) => warn!("Ignoring invalid management request: {e}"),
     }
 }
 
@@ -142,7 +289,7 @@ mod tests {
         NtpClock, NtpDuration, NtpLeapIndicator, NtpTimestamp, PollIntervalLimits, Reach,
         ReferenceId, TimeSnapshot,
     };
-    use tokio::{io::AsyncReadExt, net::UnixStream};
+    use tokio::io::AsyncReadExt;
 
     use super::*;
 
@@ -202,6 +349,10 @@ mod tests {
                 name: "127.0.0.3:123".into(),
                 address: "127.0.0.3:123".into(),
                 id: PeerId::new(),
+                last_error: None,
+                last_measurement: None,
+                missed_responses: 0,
+                state: PeerState::Reachable,
             }),
         ]);
 
@@ -272,6 +423,10 @@ mod tests {
                 name: "127.0.0.3:123".into(),
                 address: "127.0.0.3:123".into(),
                 id: PeerId::new(),
+                last_error: None,
+                last_measurement: None,
+                missed_responses: 0,
+                state: PeerState::Reachable,
             }),
         ]);
 
@@ -319,4 +474,188 @@ mod tests {
 
         handle.abort();
     }
+
+    #[tokio::test]
+    async fn test_management_reset_reachability() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-12");
+        let config = super::super::config::ObservabilityConfig {
+            management_path: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let (reset_sender, mut reset_receiver) = tokio::sync::broadcast::channel(4);
+        let (read_variables_sender, _) = tokio::sync::watch::channel(None);
+        let (poll_now_sender, _) = tokio::sync::watch::channel(());
+
+        let handle = tokio::spawn(async move {
+            management(config, reset_sender, read_variables_sender, poll_now_sender)
+                .await
+                .unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // Invalid requests are ignored
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        super::super::sockets::write_json(&mut stream, &42u32)
+            .await
+            .unwrap();
+        drop(stream);
+
+        // Resets of different peers in quick succession all arrive
+        let addrs: [SocketAddr; 2] = [
+            "127.0.0.3:123".parse().unwrap(),
+            "127.0.0.4:123".parse().unwrap(),
+        ];
+        for addr in addrs {
+            let mut stream = UnixStream::connect(&path).await.unwrap();
+            super::super::sockets::write_json(
+                &mut stream,
+                &ManagementRequest::ResetReachability(addr),
+            )
+            .await
+            .unwrap();
+            drop(stream);
+        }
+
+        // requests are handled concurrently, so their order is not fixed
+        let mut received = Vec::new();
+        for _ in addrs {
+            let addr = tokio::time::timeout(Duration::from_secs(1), reset_receiver.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            received.push(addr);
+        }
+        received.sort();
+        assert_eq!(received, addrs);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_management_read_variables() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-13");
+        let config = super::super::config::ObservabilityConfig {
+            management_path: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let (reset_sender, _) = tokio::sync::broadcast::channel(1);
+        let (read_variables_sender, mut read_variables_receiver) =
+            tokio::sync::watch::channel::<Option<ReadVariablesRequest>>(None);
+        let (poll_now_sender, _) = tokio::sync::watch::channel(());
+
+        let handle = tokio::spawn(async move {
+            management(config, reset_sender, read_variables_sender, poll_now_sender)
+                .await
+                .unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let addr: SocketAddr = "127.0.0.3:123".parse().unwrap();
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        write_json(&mut stream, &ManagementRequest::ReadVariables(addr))
+            .await
+            .unwrap();
+
+        // The peer with the requested address answers
+        read_variables_receiver.changed().await.unwrap();
+        let request = read_variables_receiver.borrow().clone().unwrap();
+        assert_eq!(request.addr, addr);
+        let variables = HashMap::from([("stratum".into(), "2".into())]);
+        request.reply.send(Ok(variables.clone())).await.unwrap();
+
+        let mut msg = Vec::with_capacity(1024);
+        let result: ReadVariablesResult = read_json(&mut stream, &mut msg).await.unwrap();
+        assert_eq!(result, Ok(variables));
+
+        // after which the request is withdrawn
+        read_variables_receiver.changed().await.unwrap();
+        assert!(read_variables_receiver.borrow().is_none());
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_management_poll_now() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-14");
+        let config = super::super::config::ObservabilityConfig {
+            management_path: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let (reset_sender, _) = tokio::sync::broadcast::channel(1);
+        let (read_variables_sender, _) = tokio::sync::watch::channel(None);
+        let (poll_now_sender, mut poll_now_receiver) = tokio::sync::watch::channel(());
+
+        let handle = tokio::spawn(async move {
+            management(config, reset_sender, read_variables_sender, poll_now_sender)
+                .await
+                .unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        write_json(&mut stream, &ManagementRequest::PollNow)
+            .await
+            .unwrap();
+        drop(stream);
+
+        tokio::time::timeout(Duration::from_secs(1), poll_now_receiver.changed())
+            .await
+            .unwrap()
+            .unwrap();
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_management_not_blocked_by_read_variables() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-15");
+        let config = super::super::config::ObservabilityConfig {
+            management_path: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let (reset_sender, _) = tokio::sync::broadcast::channel(1);
+        let (read_variables_sender, mut read_variables_receiver) =
+            tokio::sync::watch::channel::<Option<ReadVariablesRequest>>(None);
+        let (poll_now_sender, mut poll_now_receiver) = tokio::sync::watch::channel(());
+
+        let handle = tokio::spawn(async move {
+            management(config, reset_sender, read_variables_sender, poll_now_sender)
+                .await
+                .unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // No peer answers this request, so it stays pending until it times out
+        let addr: SocketAddr = "127.0.0.3:123".parse().unwrap();
+        let mut read_stream = UnixStream::connect(&path).await.unwrap();
+        write_json(&mut read_stream, &ManagementRequest::ReadVariables(addr))
+            .await
+            .unwrap();
+        read_variables_receiver.changed().await.unwrap();
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        write_json(&mut stream, &ManagementRequest::PollNow)
+            .await
+            .unwrap();
+        drop(stream);
+
+        tokio::time::timeout(Duration::from_secs(1), poll_now_receiver.changed())
+            .await
+            .unwrap()
+            .unwrap();
+
+        handle.abort();
+    }
 }
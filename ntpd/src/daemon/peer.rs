@@ -1,21 +1,104 @@
-use std::{future::Future, marker::PhantomData, net::SocketAddr, pin::Pin};
+use std::{
+    collections::HashMap,
+    future::Future,
+    marker::PhantomData,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    time::Duration,
+};
 
 use ntp_proto::{
-    IgnoreReason, Measurement, NtpClock, NtpInstant, NtpTimestamp, Peer, PeerNtsData, PeerSnapshot,
-    PollError, ProtocolVersion, SourceDefaultsConfig, SystemSnapshot, Update,
+    parse_read_variables_response, serialize_read_variables_request, ControlMessageError,
+    IgnoreReason, MacKey, Measurement, NtpClock, NtpDuration, NtpInstant, NtpTimestamp, Peer,
+    PeerNtsData, PeerSnapshot, PeerState, PollError, ProtocolVersion, SourceDefaultsConfig,
+    SystemSnapshot, Update,
 };
 use rand::{thread_rng, Rng};
 #[cfg(target_os = "linux")]
 use timestamped_socket::socket::open_interface_udp;
 use timestamped_socket::{
     interface::InterfaceName,
-    socket::{connect_address, Connected, RecvResult, Socket},
+    socket::{connect_address, open_ip, Connected, Open, RecvResult, Socket, Timestamp},
 };
-use tracing::{debug, error, info, instrument, warn, Instrument, Span};
+use tracing::{debug, error, info, instrument, trace, warn, Instrument, Span};
 
 use tokio::time::{Instant, Sleep};
 
-use super::{config::TimestampMode, exitcode, spawn::PeerId, util::convert_net_timestamp};
+use super::{
+    config::{NormalizedAddress, RecvTimestampPolicy, SockPeerConfig, TimestampMode},
+    measurement_log::MeasurementRecorder,
+    observer::ReadVariablesResult,
+    poll_limiter::PollRateLimiter,
+    refclock::{RefClockDriver, SockRefClock},
+    spawn::PeerId,
+    util::convert_net_timestamp,
+};
+
+/// Tracing target under which the raw bytes of every sent and received
+/// packet are logged at trace level
+const WIRE_TRACE_TARGET: &str = "ntpd::wire";
+
+/// Idle time after which the first poll is resent once if it goes unanswered
+const LONG_IDLE_THRESHOLD: Duration = Duration::from_secs(300);
+
+/// How long to wait for a response before resending after a long idle period,
+/// or before the peer first responded
+const IDLE_RETRY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long we keep waiting for the response to a poll before sending the
+/// next one (matches the validity window of requests in ntp-proto)
+const RESPONSE_WINDOW: Duration = Duration::from_secs(5);
+
+/// How often we try to open a socket before giving up, as opening can fail
+/// transiently, e.g. right after an interface comes up
+const SOCKET_SETUP_TRIES: usize = 3;
+
+/// How long to wait between two attempts at opening a socket
+const SOCKET_SETUP_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// How long to wait before reading a reference clock again after it failed,
+/// doubling on every consecutive failure up to the maximum
+const REFCLOCK_RETRY_DELAY_MIN: Duration = Duration::from_secs(1);
+const REFCLOCK_RETRY_DELAY_MAX: Duration = Duration::from_secs(64);
+
+/// Read the system variables of the server at `addr` with a control message,
+/// giving up when there is no response within `timeout`
+async fn read_variables(
+    addr: SocketAddr,
+    timeout: Duration,
+) -> std::io::Result<HashMap<String, String>> {
+    let local_ip = match addr {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = tokio::net::UdpSocket::bind(SocketAddr::new(local_ip, 0)).await?;
+    socket.connect(addr).await?;
+
+    let sequence = thread_rng().gen();
+    socket
+        .send(&serialize_read_variables_request(sequence))
+        .await?;
+
+    let receive = async {
+        let mut buf = [0; 1024];
+        loop {
+            let size = socket.recv(&mut buf).await?;
+            match parse_read_variables_response(&buf[..size], sequence) {
+                Ok(variables) => return Ok(variables),
+                Err(ControlMessageError::UnexpectedSequence(sequence)) => {
+                    debug!(sequence, "ignoring control response to another request");
+                }
+                Err(error) => {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+                }
+            }
+        }
+    };
+
+    tokio::time::timeout(timeout, receive)
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "no response"))?
+}
 
 /// Trait needed to allow injecting of futures other than `tokio::time::Sleep` for testing
 pub trait Wait: Future<Output = ()> {
@@ -32,10 +115,18 @@ impl Wait for Sleep {
 pub enum MsgForSystem {
     /// Received a Kiss-o'-Death and must demobilize
     MustDemobilize(PeerId),
+    /// Received a RSTR Kiss-o'-Death and must demobilize, but may be
+    /// restarted once the cooldown has passed
+    DemobilizeTemporary(PeerId, Duration),
     /// Experienced a network issue and must be restarted
     NetworkIssue(PeerId),
     /// Source is unreachable, and should be restarted with new resolved addr.
     Unreachable(PeerId),
+    /// Could not read the local clock, so no poll could be sent
+    ClockError(PeerId),
+    /// Ran into an error at the given time, to be shown on the management
+    /// interface. Any action needed is signalled by a separate message.
+    Error(PeerId, NtpTimestamp, String),
     /// Received an acceptable packet and made a new peer snapshot
     /// A new measurement should try to trigger a clock select
     NewMeasurement(PeerId, PeerSnapshot, Measurement),
@@ -44,10 +135,104 @@ pub enum MsgForSystem {
     UpdatedSnapshot(PeerId, PeerSnapshot),
 }
 
+/// Request to read the variables of the source with the given address using
+/// a control message (mode 6), answered on `reply`
 #[derive(Debug, Clone)]
+pub struct ReadVariablesRequest {
+    pub addr: SocketAddr,
+    pub reply: tokio::sync::mpsc::Sender<ReadVariablesResult>,
+}
+
+#[derive(Debug)]
 pub struct PeerChannels {
     pub msg_for_system_sender: tokio::sync::mpsc::Sender<MsgForSystem>,
     pub system_snapshot_receiver: tokio::sync::watch::Receiver<SystemSnapshot>,
+    pub poll_limiter: PollRateLimiter,
+    /// Signals that peers should poll right away, outside their normal schedule
+    pub poll_now_receiver: tokio::sync::watch::Receiver<()>,
+    /// Signals that the peer with the given address should forget which of
+    /// its recent polls were answered
+    pub reset_reachability_receiver: tokio::sync::broadcast::Receiver<SocketAddr>,
+    /// Requests to read the variables of the peer with the given address
+    pub read_variables_receiver: tokio::sync::watch::Receiver<Option<ReadVariablesRequest>>,
+    /// Records raw measurements for offline analysis, when enabled
+    pub measurement_recorder: MeasurementRecorder,
+}
+
+impl Clone for PeerChannels {
+    fn clone(&self) -> Self {
+        Self {
+            msg_for_system_sender: self.msg_for_system_sender.clone(),
+            system_snapshot_receiver: self.system_snapshot_receiver.clone(),
+            poll_limiter: self.poll_limiter.clone(),
+            poll_now_receiver: self.poll_now_receiver.clone(),
+            // a new peer only acts on resets requested after it was created
+            reset_reachability_receiver: self.reset_reachability_receiver.resubscribe(),
+            read_variables_receiver: self.read_variables_receiver.clone(),
+            measurement_recorder: self.measurement_recorder.clone(),
+        }
+    }
+}
+
+/// Socket used to communicate with a peer
+enum PeerSocket {
+    /// Connected to the peer, only receiving packets from its address and port
+    Connected(Socket<SocketAddr, Connected>),
+    /// Unconnected, accepting packets from the peer's address on any port
+    Unconnected(Socket<SocketAddr, Open>, SocketAddr),
+}
+
+impl PeerSocket {
+    /// Send a single datagram. Datagrams are sent atomically, so there are no
+    /// short writes to handle: either all of `buf` is sent or an error is
+    /// returned.
+    async fn send(&mut self, buf: &[u8]) -> std::io::Result<Option<Timestamp>> {
+        match self {
+            PeerSocket::Connected(socket) => socket.send(buf).await,
+            PeerSocket::Unconnected(socket, addr) => socket.send_to(buf, *addr).await,
+        }
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> std::io::Result<RecvResult<SocketAddr>> {
+        match self {
+            PeerSocket::Connected(socket) => socket.recv(buf).await,
+            PeerSocket::Unconnected(socket, _) => socket.recv(buf).await,
+        }
+    }
+
+    /// Address packets must come from that is not already checked by the
+    /// kernel. Without a connected socket we have to verify the sender
+    /// ourselves.
+    fn unverified_source(&self) -> Option<SocketAddr> {
+        match self {
+            PeerSocket::Connected(_) => None,
+            PeerSocket::Unconnected(_, addr) => Some(*addr),
+        }
+    }
+}
+
+/// Convert a deadline on the clock of the NTP service to one for tokio's
+/// timers. The two clocks share no common reference, and tokio's clock may be
+/// paused or advanced in tests, so the conversion goes through the time left
+/// until the deadline. Deadlines that have passed convert to right now.
+fn tokio_deadline(deadline: NtpInstant) -> Instant {
+    Instant::now() + deadline.saturating_duration_since(NtpInstant::now())
+}
+
+/// Wait until `deadline` passes, or forever when there is none
+async fn sleep_until_deadline(deadline: Option<NtpInstant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(tokio_deadline(deadline)).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Map IPv4-mapped IPv6 addresses to their IPv4 equivalent
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        IpAddr::V4(_) => ip,
+    }
 }
 
 pub(crate) struct PeerTask<C: 'static + NtpClock + Send, T: Wait> {
@@ -56,8 +241,18 @@ pub(crate) struct PeerTask<C: 'static + NtpClock + Send, T: Wait> {
     clock: C,
     interface: Option<InterfaceName>,
     timestamp_mode: TimestampMode,
+    recv_timestamp_policy: RecvTimestampPolicy,
     source_addr: SocketAddr,
-    socket: Option<Socket<SocketAddr, Connected>>,
+    socket: Option<PeerSocket>,
+    /// Whether to accept responses from any port on the peer's address
+    accept_any_port: bool,
+    /// Local port to send from, or an ephemeral one when `None`
+    source_port: Option<u16>,
+    /// Name the peer's address was resolved from, to look up again after a
+    /// long idle period in case the address changed
+    resolve_after_idle: Option<NormalizedAddress>,
+    /// Whether to add a random amount to each poll interval
+    randomize_poll: bool,
     channels: PeerChannels,
 
     peer: Peer,
@@ -70,7 +265,33 @@ pub(crate) struct PeerTask<C: 'static + NtpClock + Send, T: Wait> {
     last_send_timestamp: Option<NtpTimestamp>,
 
     /// Instant last poll message was sent (used for timing the wait)
-    last_poll_sent: Instant,
+    last_poll_sent: NtpInstant,
+    /// Minimum time between two polls, whatever triggered them
+    min_poll_gap: Duration,
+    /// Whether a poll was requested before the minimum gap had passed, and
+    /// is waiting for it to pass
+    poll_deferred: bool,
+
+    /// Poll message to resend once, and when to do so, if the first poll
+    /// after a long idle period goes unanswered
+    idle_retry: Option<(Vec<u8>, NtpInstant)>,
+    /// Whether unanswered polls are still resent once, which is the case
+    /// until the first response when enabled
+    retry_until_response: bool,
+
+    /// How long to wait for a response to a poll before reporting it missed
+    response_timeout: Duration,
+    /// How long to wait before retrying after a RSTR Kiss-o'-Death
+    restrict_cooldown: Duration,
+    /// When the outstanding poll is reported missed, if we still await one
+    response_deadline: Option<NtpInstant>,
+    /// Number of packets dropped because they came from another address
+    /// than the peer's
+    unexpected_source_packets: u64,
+
+    /// Reference clock to take measurements from instead of polling over
+    /// the network
+    refclock: Option<Box<dyn RefClockDriver>>,
 }
 
 #[derive(Debug)]
@@ -78,6 +299,19 @@ enum PollResult {
     Ok,
     NetworkGone,
     Unreachable,
+    ClockError,
+}
+
+impl PollResult {
+    /// Message telling the system why this peer stopped, if it did
+    fn failure_message(self, index: PeerId) -> Option<MsgForSystem> {
+        match self {
+            PollResult::Ok => None,
+            PollResult::NetworkGone => Some(MsgForSystem::NetworkIssue(index)),
+            PollResult::Unreachable => Some(MsgForSystem::Unreachable(index)),
+            PollResult::ClockError => Some(MsgForSystem::ClockError(index)),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -101,36 +335,83 @@ where
     fn update_poll_wait(&self, poll_wait: &mut Pin<&mut T>, system_snapshot: SystemSnapshot) {
         let poll_interval = self
             .peer
-            .current_poll_interval(system_snapshot)
+            .next_poll_interval(system_snapshot)
             .as_system_duration();
 
         // randomize the poll interval a little to make it harder to predict poll requests
-        let poll_interval = poll_interval.mul_f64(thread_rng().gen_range(1.01..=1.05));
+        let poll_interval = if self.randomize_poll {
+            poll_interval.mul_f64(thread_rng().gen_range(1.01..=1.05))
+        } else {
+            poll_interval
+        };
+
+        let mut deadline = self.last_poll_sent + poll_interval;
+        if self.poll_deferred {
+            deadline = deadline.min(self.last_poll_sent + self.min_poll_gap);
+        }
+        if self.poll_in_flight() {
+            deadline = deadline.max(self.last_poll_sent + RESPONSE_WINDOW);
+        }
+        if let Some((_, retry_deadline)) = self.idle_retry {
+            deadline = deadline.min(retry_deadline);
+        }
+
+        poll_wait.as_mut().reset(tokio_deadline(deadline));
+    }
 
-        poll_wait
-            .as_mut()
-            .reset(self.last_poll_sent + poll_interval);
+    /// Whether the last poll is still awaiting a response within its window
+    fn poll_in_flight(&self) -> bool {
+        // The socket is dropped once a response has been received
+        self.socket.is_some() && self.last_poll_sent.elapsed() < RESPONSE_WINDOW
     }
 
     async fn handle_poll(&mut self, poll_wait: &mut Pin<&mut T>) -> PollResult {
         let system_snapshot = *self.channels.system_snapshot_receiver.borrow();
 
+        // Sending a new poll would invalidate the outstanding one, and we
+        // only track the send timestamp of the latest poll. Poll once it is
+        // done instead, so that an immediate poll request isn't lost.
+        if self.poll_in_flight() {
+            debug!("previous poll still awaiting response, deferring");
+            self.poll_deferred = true;
+            self.update_poll_wait(poll_wait, system_snapshot);
+            return PollResult::Ok;
+        }
+
+        // However the poll was triggered, don't poll faster than the minimum
+        // gap allows, except within a burst. The first poll is never early.
+        let early = NtpInstant::now() < self.last_poll_sent + self.min_poll_gap;
+        if early && self.last_send_timestamp.is_some() && self.peer.state() != PeerState::Bursting {
+            debug!("poll requested before the minimum gap has passed, deferring");
+            self.poll_deferred = true;
+            self.update_poll_wait(poll_wait, system_snapshot);
+            return PollResult::Ok;
+        }
+        self.poll_deferred = false;
+
+        // Pace the polls of all peers combined
+        self.channels.poll_limiter.acquire().await;
+
         let mut buf = [0; 1024];
         let (packet, snapshot) = match self.peer.generate_poll_message(&mut buf, system_snapshot) {
             Ok(result) => result,
             Err(PollError::Io(e)) => {
                 warn!(error = ?e, "Could not generate poll message");
+                self.report_error(format!("could not generate poll message: {e}"))
+                    .await;
                 // not exactly a network gone situation, but needs the same response
                 return PollResult::NetworkGone;
             }
             Err(PollError::PeerUnreachable) => {
                 warn!("Peer is no longer reachable over network, restarting");
+                self.report_error("peer is no longer reachable").await;
                 return PollResult::Unreachable;
             }
         };
 
         // Sent a poll, so update waiting to match deadline of next
-        self.last_poll_sent = Instant::now();
+        let idle_time = self.last_poll_sent.elapsed();
+        self.last_poll_sent = NtpInstant::now();
         self.update_poll_wait(poll_wait, system_snapshot);
 
         // the last_send_timestamp is only None at startup
@@ -144,26 +425,75 @@ where
             self.channels.msg_for_system_sender.send(msg).await.ok();
         }
 
-        match self.clock.now() {
-            Err(e) => {
-                // we cannot determine the origin_timestamp
-                error!(error = ?e, "There was an error retrieving the current time");
+        if idle_time >= LONG_IDLE_THRESHOLD && !self.address_still_current().await {
+            info!("address of peer changed while idle, restarting");
+            self.report_error("address of peer changed").await;
+            return PollResult::Unreachable;
+        }
 
-                // report as no permissions, since this seems the most likely
-                std::process::exit(exitcode::NOPERM);
-            }
-            Ok(ts) => {
-                self.last_send_timestamp = Some(ts);
-            }
+        if matches!(self.setup_socket().await, SocketResult::Abort) {
+            return PollResult::NetworkGone;
+        }
+
+        // Stateful firewalls and NAT may have forgotten about us after a long
+        // idle period, in which case our first packet can get lost. Resend it
+        // once soon after, before counting it as a missed poll. The same holds
+        // at startup, where losing a packet would otherwise mean a full poll
+        // interval of silence.
+        if idle_time >= LONG_IDLE_THRESHOLD || self.retry_until_response {
+            self.idle_retry = Some((packet.to_vec(), NtpInstant::now() + IDLE_RETRY_TIMEOUT));
+            self.update_poll_wait(poll_wait, system_snapshot);
         }
 
+        self.send_packet(packet).await
+    }
+
+    async fn handle_idle_retry(
+        &mut self,
+        poll_wait: &mut Pin<&mut T>,
+        packet: &[u8],
+    ) -> PollResult {
+        // Continue with the regular poll schedule afterwards
+        let system_snapshot = *self.channels.system_snapshot_receiver.borrow();
+        self.update_poll_wait(poll_wait, system_snapshot);
+
+        debug!("No response to first poll, resending it");
+
+        // The mapping of the old socket may be what got lost, so resend from a
+        // fresh one
         if matches!(self.setup_socket().await, SocketResult::Abort) {
             return PollResult::NetworkGone;
         }
 
+        // The resent packet is identical to the original, so a response to
+        // either carries the original transmit timestamp. Measuring against
+        // the send time of the copy would make the first one look too fast.
+        let original_send_timestamp = self.last_send_timestamp;
+        let result = self.send_packet(packet).await;
+        self.last_send_timestamp = original_send_timestamp;
+        result
+    }
+
+    async fn send_packet(&mut self, packet: &[u8]) -> PollResult {
+        let send_timestamp = match self.clock.now() {
+            Err(e) => {
+                // we cannot determine the origin_timestamp, leave it to the
+                // system to decide what to do with this peer
+                error!(error = ?e, "There was an error retrieving the current time");
+                self.report_error(format!("could not read the clock: {e}"))
+                    .await;
+                return PollResult::ClockError;
+            }
+            Ok(ts) => ts,
+        };
+
+        trace!(target: WIRE_TRACE_TARGET, bytes = %HexDump(packet), "sending packet");
+
         match self.socket.as_mut().unwrap().send(packet).await {
             Err(error) => {
                 warn!(?error, "poll message could not be sent");
+                self.report_error(format!("poll message could not be sent: {error}"))
+                    .await;
 
                 match error.raw_os_error() {
                     Some(libc::EHOSTDOWN)
@@ -174,10 +504,14 @@ where
                 }
             }
             Ok(opt_send_timestamp) => {
-                // update the last_send_timestamp with the one given by the kernel, if available
-                self.last_send_timestamp = opt_send_timestamp
-                    .map(convert_net_timestamp)
-                    .or(self.last_send_timestamp);
+                // only a packet that was actually sent has a send timestamp,
+                // preferably the one given by the kernel
+                self.last_send_timestamp = Some(
+                    opt_send_timestamp
+                        .map(convert_net_timestamp)
+                        .unwrap_or(send_timestamp),
+                );
+                self.response_deadline = Some(NtpInstant::now() + self.response_timeout);
             }
         }
 
@@ -202,6 +536,13 @@ where
             recv_timestamp,
         );
 
+        // Got a response, so there is no need to resend our poll
+        if result.is_ok() {
+            self.idle_retry = None;
+            self.retry_until_response = false;
+            self.response_deadline = None;
+        }
+
         // Handle incoming may have changed poll interval based on message, respect that change
         self.update_poll_wait(poll_wait, system_snapshot);
 
@@ -211,23 +552,29 @@ where
 
                 // NOTE: fitness check is not performed here, but by System
 
-                let msg = match update {
-                    Update::BareUpdate(update) => MsgForSystem::UpdatedSnapshot(self.index, update),
-                    Update::NewMeasurement(update, measurement) => {
-                        MsgForSystem::NewMeasurement(self.index, update, measurement)
-                    }
-                };
-                self.channels.msg_for_system_sender.send(msg).await.ok();
+                self.send_update(update).await;
                 // No longer needed since we don't expect any more packets
                 self.socket = None;
             }
             Err(IgnoreReason::KissDemobilize) => {
                 info!("Demobilizing peer connection on request of remote.");
+                self.peer.demobilize();
                 let msg = MsgForSystem::MustDemobilize(self.index);
                 self.channels.msg_for_system_sender.send(msg).await.ok();
 
                 return PacketResult::Demobilize;
             }
+            Err(IgnoreReason::KissRestrict) => {
+                info!(
+                    cooldown = ?self.restrict_cooldown,
+                    "Temporarily demobilizing peer connection on request of remote."
+                );
+                self.peer.demobilize();
+                let msg = MsgForSystem::DemobilizeTemporary(self.index, self.restrict_cooldown);
+                self.channels.msg_for_system_sender.send(msg).await.ok();
+
+                return PacketResult::Demobilize;
+            }
             Err(ignore_reason) => {
                 debug!(?ignore_reason, "packet ignored");
             }
@@ -236,25 +583,58 @@ where
         PacketResult::Ok
     }
 
+    /// Whether the name the peer was created from still resolves to its
+    /// address. Lookup failures are not a reason to give up on the address.
+    async fn address_still_current(&self) -> bool {
+        let Some(name) = &self.resolve_after_idle else {
+            return true;
+        };
+
+        match name.lookup_host().await {
+            Ok(addresses) => {
+                let addresses: Vec<_> = addresses.collect();
+                addresses.is_empty() || addresses.contains(&self.source_addr)
+            }
+            Err(error) => {
+                warn!(
+                    ?error,
+                    "could not resolve peer address again, keeping the old one"
+                );
+                true
+            }
+        }
+    }
+
     async fn setup_socket(&mut self) -> SocketResult {
-        let socket_res = match self.interface {
-            #[cfg(target_os = "linux")]
-            Some(interface) => {
-                open_interface_udp(
-                    interface,
-                    0, /*lets os choose*/
-                    self.timestamp_mode.as_interface_mode(),
-                    None,
-                )
-                .and_then(|socket| socket.connect(self.source_addr))
+        // Close the previous socket first, it may be bound to the same port
+        self.socket = None;
+
+        let socket_res = retry_socket_setup(SOCKET_SETUP_RETRY_DELAY, || {
+            if self.accept_any_port {
+                self.open_socket()
+                    .map(|socket| PeerSocket::Unconnected(socket, self.source_addr))
+            } else {
+                match self.interface {
+                    #[cfg(target_os = "linux")]
+                    Some(_) => self
+                        .open_socket()
+                        .and_then(|socket| socket.connect(self.source_addr)),
+                    _ if self.source_port.is_some() => self
+                        .open_socket()
+                        .and_then(|socket| socket.connect(self.source_addr)),
+                    _ => connect_address(self.source_addr, self.timestamp_mode.as_general_mode()),
+                }
+                .map(PeerSocket::Connected)
             }
-            _ => connect_address(self.source_addr, self.timestamp_mode.as_general_mode()),
-        };
+        })
+        .await;
 
         self.socket = match socket_res {
             Ok(socket) => Some(socket),
             Err(error) => {
                 warn!(?error, "Could not open socket");
+                self.report_error(format!("could not open socket: {error}"))
+                    .await;
                 return SocketResult::Abort;
             }
         };
@@ -262,29 +642,152 @@ where
         SocketResult::Ok
     }
 
+    /// Let the system know about an error, so it can be shown on the
+    /// management interface
+    async fn report_error(&mut self, error: impl Into<String>) {
+        // the error may well be that the clock can't be read
+        let timestamp = self.clock.now().unwrap_or_default();
+        let msg = MsgForSystem::Error(self.index, timestamp, error.into());
+        self.channels.msg_for_system_sender.send(msg).await.ok();
+    }
+
+    fn open_socket(&self) -> std::io::Result<Socket<SocketAddr, Open>> {
+        // port 0 lets the os choose
+        let port = self.source_port.unwrap_or(0);
+
+        match self.interface {
+            #[cfg(target_os = "linux")]
+            Some(interface) => open_interface_udp(
+                interface,
+                port,
+                self.timestamp_mode.as_interface_mode(),
+                None,
+            ),
+            _ => {
+                let local_ip = match self.source_addr {
+                    SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                };
+                open_ip(
+                    SocketAddr::new(local_ip, port),
+                    self.timestamp_mode.as_general_mode(),
+                )
+            }
+        }
+    }
+
+    /// Read the variables of the peer with a control message. This uses a
+    /// socket of its own and runs in the background, so it doesn't hold up
+    /// synchronization.
+    fn handle_read_variables(&self, request: ReadVariablesRequest) {
+        info!("reading variables of peer");
+        let addr = self.source_addr;
+        let timeout = self.response_timeout;
+        tokio::spawn(
+            async move {
+                let result = read_variables(addr, timeout)
+                    .await
+                    .map_err(|e| e.to_string());
+                if let Err(ref error) = result {
+                    warn!(?error, "could not read variables of peer");
+                }
+                request.reply.send(result).await.ok();
+            }
+            .instrument(Span::current()),
+        );
+    }
+
+    async fn handle_response_timeout(&mut self) {
+        self.response_deadline = None;
+        self.peer.record_missed_response();
+        warn!(
+            peer = ?self.source_addr,
+            timeout = ?self.response_timeout,
+            missed = self.peer.missed_responses(),
+            "peer did not respond within timeout"
+        );
+        self.send_update(Update::BareUpdate(PeerSnapshot::from_peer(&self.peer)))
+            .await;
+    }
+
+    /// Let the system know why this peer stops
+    async fn stop(&mut self, msg: MsgForSystem) {
+        self.channels.msg_for_system_sender.send(msg).await.ok();
+    }
+
+    async fn send_update(&mut self, update: Update) {
+        let msg = match update {
+            Update::BareUpdate(update) => MsgForSystem::UpdatedSnapshot(self.index, update),
+            Update::NewMeasurement(update, measurement) => {
+                self.channels
+                    .measurement_recorder
+                    .record(self.source_addr, measurement);
+                MsgForSystem::NewMeasurement(self.index, update, measurement)
+            }
+        };
+        self.channels.msg_for_system_sender.send(msg).await.ok();
+    }
+
+    /// Feed the measurements of a reference clock to the peer, in place of
+    /// polling over the network
+    async fn run_refclock(&mut self, mut driver: Box<dyn RefClockDriver>) {
+        let reference_id = driver.reference_id();
+        let mut retry_delay = REFCLOCK_RETRY_DELAY_MIN;
+        loop {
+            let measurement = match driver.next_measurement().await {
+                Ok(measurement) => {
+                    retry_delay = REFCLOCK_RETRY_DELAY_MIN;
+                    measurement
+                }
+                Err(error) => {
+                    // errors are often transient (e.g. the device is briefly
+                    // unavailable), so keep trying rather than giving up
+                    warn!(
+                        ?error,
+                        ?retry_delay,
+                        "Could not read reference clock, retrying"
+                    );
+                    self.report_error(format!("could not read reference clock: {error}"))
+                        .await;
+                    tokio::time::sleep(retry_delay).await;
+                    retry_delay = (retry_delay * 2).min(REFCLOCK_RETRY_DELAY_MAX);
+                    continue;
+                }
+            };
+
+            match self
+                .peer
+                .handle_refclock_measurement(reference_id, measurement)
+            {
+                Ok(update) => self.send_update(update).await,
+                Err(reason) => debug!(?reason, "ignoring reference clock measurement"),
+            }
+        }
+    }
+
     async fn run(&mut self, mut poll_wait: Pin<&mut T>) {
+        if let Some(driver) = self.refclock.take() {
+            self.run_refclock(driver).await;
+            return;
+        }
+
         loop {
             let mut buf = [0_u8; 1024];
+            let response_deadline = self.response_deadline;
+            let unverified_source = self.socket.as_ref().and_then(PeerSocket::unverified_source);
 
             tokio::select! {
-                () = &mut poll_wait => {
-                    tracing::debug!("wait completed");
-                    match self.handle_poll(&mut poll_wait).await {
-                        PollResult::Ok => {},
-                        PollResult::NetworkGone => {
-                            self.channels.msg_for_system_sender.send(MsgForSystem::NetworkIssue(self.index)).await.ok();
-                            break;
-                        }
-                        PollResult::Unreachable => {
-                            self.channels.msg_for_system_sender.send(MsgForSystem::Unreachable(self.index)).await.ok();
-                            break;
-                        }
-                    }
-                },
+                // Drain a packet that is already waiting before polling again:
+                // a new poll replaces the send timestamp that the waiting
+                // response should be matched against.
+                biased;
+
                 result = async { if let Some(ref mut socket) = self.socket { socket.recv(&mut buf).await } else { std::future::pending().await }} => {
                     tracing::debug!("accept packet");
-                    match accept_packet(result, &buf, &self.clock) {
+                    match accept_packet(result, &buf, &self.clock, self.recv_timestamp_policy, unverified_source) {
                         AcceptResult::Accept(packet, recv_timestamp) => {
+                            trace!(target: WIRE_TRACE_TARGET, bytes = %HexDump(packet), "received packet");
+
                             let send_timestamp = match self.last_send_timestamp {
                                 Some(ts) => ts,
                                 None => {
@@ -299,12 +802,54 @@ where
                             }
                         },
                         AcceptResult::NetworkGone => {
-                            self.channels.msg_for_system_sender.send(MsgForSystem::NetworkIssue(self.index)).await.ok();
+                            self.report_error("could not receive packet: network is unreachable").await;
+                            self.stop(MsgForSystem::NetworkIssue(self.index)).await;
                             break;
                         },
+                        AcceptResult::UnexpectedSource(remote_addr) => {
+                            self.unexpected_source_packets += 1;
+                            debug!(?remote_addr, count = self.unexpected_source_packets, "ignoring packet from unexpected address");
+                        },
                         AcceptResult::Ignore => {},
                     }
                 },
+                () = &mut poll_wait => {
+                    tracing::debug!("wait completed");
+                    let result = match self.idle_retry.take() {
+                        Some((packet, _)) => self.handle_idle_retry(&mut poll_wait, &packet).await,
+                        None => self.handle_poll(&mut poll_wait).await,
+                    };
+                    if let Some(msg) = result.failure_message(self.index) {
+                        self.stop(msg).await;
+                        break;
+                    }
+                },
+                Ok(()) = self.channels.poll_now_receiver.changed() => {
+                    tracing::debug!("immediate poll requested");
+                    // A pending idle retry is superseded by the new poll
+                    self.idle_retry = None;
+                    let result = self.handle_poll(&mut poll_wait).await;
+                    if let Some(msg) = result.failure_message(self.index) {
+                        self.stop(msg).await;
+                        break;
+                    }
+                },
+                Ok(()) = self.channels.read_variables_receiver.changed() => {
+                    let request = self.channels.read_variables_receiver.borrow().clone();
+                    if let Some(request) = request.filter(|request| request.addr == self.source_addr) {
+                        self.handle_read_variables(request);
+                    }
+                },
+                Ok(requested) = self.channels.reset_reachability_receiver.recv() => {
+                    if requested == self.source_addr {
+                        info!("resetting reachability");
+                        self.peer.reset_reachability();
+                        self.send_update(Update::BareUpdate(PeerSnapshot::from_peer(&self.peer))).await;
+                    }
+                },
+                () = sleep_until_deadline(response_deadline) => {
+                    self.handle_response_timeout().await;
+                },
             }
         }
     }
@@ -322,18 +867,27 @@ where
         interface: Option<InterfaceName>,
         clock: C,
         timestamp_mode: TimestampMode,
+        recv_timestamp_policy: RecvTimestampPolicy,
         channels: PeerChannels,
         protocol_version: ProtocolVersion,
         config_snapshot: SourceDefaultsConfig,
         nts: Option<Box<PeerNtsData>>,
+        mac_key: Option<MacKey>,
+        source_port: Option<u16>,
+        delay_asymmetry_correction: NtpDuration,
+        resolve_after_idle: Option<NormalizedAddress>,
+        refclock: Option<SockPeerConfig>,
     ) -> tokio::task::JoinHandle<()> {
         tokio::spawn(
             (async move {
-                let peer = if let Some(nts) = nts {
+                let mut peer = if let Some(nts) = nts {
                     Peer::new_nts(source_addr, config_snapshot, protocol_version, nts)
+                } else if let Some(mac_key) = mac_key {
+                    Peer::new_with_mac_key(source_addr, config_snapshot, protocol_version, mac_key)
                 } else {
                     Peer::new(source_addr, config_snapshot, protocol_version)
                 };
+                peer.set_delay_asymmetry_correction(delay_asymmetry_correction);
 
                 let poll_wait = tokio::time::sleep(std::time::Duration::default());
                 tokio::pin!(poll_wait);
@@ -345,11 +899,31 @@ where
                     channels,
                     interface,
                     timestamp_mode,
+                    recv_timestamp_policy,
                     source_addr,
                     socket: None,
+                    accept_any_port: config_snapshot.accept_any_response_port,
+                    source_port,
+                    resolve_after_idle,
+                    randomize_poll: config_snapshot.randomize_poll,
                     peer,
                     last_send_timestamp: None,
-                    last_poll_sent: Instant::now(),
+                    last_poll_sent: NtpInstant::now(),
+                    min_poll_gap: config_snapshot
+                        .min_poll_gap
+                        .unwrap_or(config_snapshot.poll_interval_limits.min)
+                        .as_system_duration(),
+                    poll_deferred: false,
+                    idle_retry: None,
+                    retry_until_response: config_snapshot.retry_first_poll,
+                    response_timeout: config_snapshot.response_timeout,
+                    restrict_cooldown: config_snapshot.restrict_cooldown,
+                    response_deadline: None,
+                    unexpected_source_packets: 0,
+                    refclock: refclock.map(|config| {
+                        Box::new(SockRefClock::new(config.path, config.reference_id))
+                            as Box<dyn RefClockDriver>
+                    }),
                 };
 
                 process.run(poll_wait).await;
@@ -359,10 +933,26 @@ where
     }
 }
 
+/// Lazily formats a buffer as hex, so nothing is formatted unless the
+/// wire trace target is actually enabled
+struct HexDump<'a>(&'a [u8]);
+
+impl std::fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 enum AcceptResult<'a> {
     Accept(&'a [u8], NtpTimestamp),
     Ignore,
+    /// Received a packet from another address than expected, which must
+    /// be ignored
+    UnexpectedSource(SocketAddr),
     NetworkGone,
 }
 
@@ -370,21 +960,51 @@ fn accept_packet<'a, C: NtpClock>(
     result: Result<RecvResult<SocketAddr>, std::io::Error>,
     buf: &'a [u8],
     clock: &C,
+    policy: RecvTimestampPolicy,
+    expected_source: Option<SocketAddr>,
 ) -> AcceptResult<'a> {
     match result {
         Ok(RecvResult {
             bytes_read: size,
+            remote_addr,
             timestamp,
-            ..
         }) => {
-            let recv_timestamp = timestamp.map(convert_net_timestamp).unwrap_or_else(|| {
-                if let Ok(now) = clock.now() {
-                    debug!(?size, "received a packet without a timestamp, substituting");
-                    now
-                } else {
-                    panic!("Received packet without timestamp and couldn't substitute");
+            if let Some(expected_source) = expected_source {
+                if canonical_ip(remote_addr.ip()) != canonical_ip(expected_source.ip()) {
+                    return AcceptResult::UnexpectedSource(remote_addr);
                 }
-            });
+            }
+
+            let timestamp = match policy {
+                RecvTimestampPolicy::SoftwareOnly => None,
+                RecvTimestampPolicy::Prefer | RecvTimestampPolicy::Require => timestamp,
+            };
+
+            let recv_timestamp = match timestamp {
+                Some(timestamp) => convert_net_timestamp(timestamp),
+                None if policy == RecvTimestampPolicy::Require => {
+                    warn!(
+                        ?size,
+                        "received a packet without a kernel timestamp, ignoring"
+                    );
+                    return AcceptResult::Ignore;
+                }
+                None => match clock.now() {
+                    Ok(now) => {
+                        if policy == RecvTimestampPolicy::Prefer {
+                            debug!(?size, "received a packet without a timestamp, substituting");
+                        }
+                        now
+                    }
+                    Err(error) => {
+                        warn!(
+                            ?error,
+                            "received a packet without a timestamp and couldn't substitute, ignoring"
+                        );
+                        return AcceptResult::Ignore;
+                    }
+                },
+            };
 
             // Note: packets are allowed to be bigger when including extensions.
             // we don't expect them, but the server may still send them. The
@@ -412,15 +1032,36 @@ fn accept_packet<'a, C: NtpClock>(
     }
 }
 
+/// Run `open` until it succeeds, at most [`SOCKET_SETUP_TRIES`] times and
+/// waiting `retry_delay` between attempts
+async fn retry_socket_setup<S>(
+    retry_delay: Duration,
+    mut open: impl FnMut() -> std::io::Result<S>,
+) -> std::io::Result<S> {
+    let mut tries = 1;
+    loop {
+        match open() {
+            Ok(socket) => return Ok(socket),
+            Err(error) if tries < SOCKET_SETUP_TRIES => {
+                debug!(?error, tries, "Could not open socket, retrying");
+                tries += 1;
+                tokio::time::sleep(retry_delay).await;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{io::Cursor, net::Ipv4Addr, sync::Arc, time::Duration};
 
-    use ntp_proto::{NoCipher, NtpDuration, NtpLeapIndicator, NtpPacket, TimeSnapshot};
+    use ntp_proto::{NoCipher, NtpLeapIndicator, NtpPacket, ReferenceId, TimeSnapshot};
     use timestamped_socket::socket::{open_ip, GeneralTimestampMode, Open};
     use tokio::sync::mpsc;
+    use tracing::instrument::WithSubscriber;
 
-    use crate::daemon::util::EPOCH_OFFSET;
+    use crate::daemon::{measurement_log::write_records, util::EPOCH_OFFSET};
 
     use super::*;
 
@@ -429,6 +1070,11 @@ mod tests {
     }
 
     impl TestWaitSender {
+        /// Deadline the wait was last reset to
+        fn deadline(&self) -> Option<Instant> {
+            self.state.lock().unwrap().deadline
+        }
+
         fn notify(&self) {
             let mut state = self.state.lock().unwrap();
             state.pending = true;
@@ -445,6 +1091,7 @@ mod tests {
     struct TestWaitState {
         waker: Option<std::task::Waker>,
         pending: bool,
+        deadline: Option<Instant>,
     }
 
     impl Future for TestWait {
@@ -468,7 +1115,9 @@ mod tests {
     }
 
     impl Wait for TestWait {
-        fn reset(self: Pin<&mut Self>, _deadline: Instant) {}
+        fn reset(self: Pin<&mut Self>, deadline: Instant) {
+            self.state.lock().unwrap().deadline = Some(deadline);
+        }
     }
 
     impl Drop for TestWait {
@@ -482,6 +1131,7 @@ mod tests {
             let state = Arc::new(std::sync::Mutex::new(TestWaitState {
                 waker: None,
                 pending: false,
+                deadline: None,
             }));
 
             (
@@ -493,8 +1143,11 @@ mod tests {
         }
     }
 
+    /// Clock following the system time, shifted by `offset`
     #[derive(Debug, Clone, Default)]
-    struct TestClock {}
+    struct TestClock {
+        offset: NtpDuration,
+    }
 
     impl NtpClock for TestClock {
         type Error = std::time::SystemTimeError;
@@ -506,7 +1159,42 @@ mod tests {
             Ok(NtpTimestamp::from_seconds_nanos_since_ntp_era(
                 EPOCH_OFFSET.wrapping_add(cur.as_secs() as u32),
                 cur.subsec_nanos(),
-            ))
+            ) + self.offset)
+        }
+
+        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by peer");
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by peer");
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by peer");
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by peer");
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by peer");
+        }
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct FailingClock {}
+
+    impl NtpClock for FailingClock {
+        type Error = std::io::Error;
+
+        fn now(&self) -> std::result::Result<NtpTimestamp, Self::Error> {
+            Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
         }
 
         fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
@@ -540,6 +1228,17 @@ mod tests {
         PeerTask<TestClock, T>,
         Socket<SocketAddr, Open>,
         mpsc::Receiver<MsgForSystem>,
+    ) {
+        test_startup_with_clock(port_base, TestClock::default()).await
+    }
+
+    async fn test_startup_with_clock<C: NtpClock, T: Wait>(
+        port_base: u16,
+        clock: C,
+    ) -> (
+        PeerTask<C, T>,
+        Socket<SocketAddr, Open>,
+        mpsc::Receiver<MsgForSystem>,
     ) {
         // Note: Ports must be unique among tests to deal with parallelism, hence
         // port_base
@@ -551,6 +1250,9 @@ mod tests {
 
         let (_, system_snapshot_receiver) = tokio::sync::watch::channel(SystemSnapshot::default());
         let (msg_for_system_sender, msg_for_system_receiver) = mpsc::channel(1);
+        let (_, poll_now_receiver) = tokio::sync::watch::channel(());
+        let (_, reset_reachability_receiver) = tokio::sync::broadcast::channel(1);
+        let (_, read_variables_receiver) = tokio::sync::watch::channel(None);
 
         let peer = Peer::new(
             SocketAddr::from((Ipv4Addr::LOCALHOST, port_base)),
@@ -561,18 +1263,37 @@ mod tests {
         let process = PeerTask {
             _wait: PhantomData,
             index: PeerId::new(),
-            clock: TestClock {},
+            clock,
             channels: PeerChannels {
                 msg_for_system_sender,
                 system_snapshot_receiver,
+                poll_limiter: PollRateLimiter::new(None),
+                poll_now_receiver,
+                reset_reachability_receiver,
+                read_variables_receiver,
+                measurement_recorder: MeasurementRecorder::disabled(),
             },
             source_addr: SocketAddr::from((Ipv4Addr::LOCALHOST, port_base)),
             interface: None,
             timestamp_mode: TimestampMode::KernelRecv,
+            recv_timestamp_policy: RecvTimestampPolicy::Prefer,
             socket: None,
+            accept_any_port: false,
+            source_port: None,
+            resolve_after_idle: None,
+            randomize_poll: true,
             peer,
             last_send_timestamp: None,
-            last_poll_sent: Instant::now(),
+            last_poll_sent: NtpInstant::now(),
+            min_poll_gap: Duration::ZERO,
+            poll_deferred: false,
+            idle_retry: None,
+            retry_until_response: false,
+            response_timeout: SourceDefaultsConfig::default().response_timeout,
+            restrict_cooldown: SourceDefaultsConfig::default().restrict_cooldown,
+            response_deadline: None,
+            unexpected_source_packets: 0,
+            refclock: None,
         };
 
         (process, test_socket, msg_for_system_receiver)
@@ -599,71 +1320,1052 @@ mod tests {
         handle.abort();
     }
 
-    fn serialize_packet_unencryped(send_packet: &NtpPacket) -> [u8; 48] {
-        let mut buf = [0; 48];
-        let mut cursor = Cursor::new(buf.as_mut_slice());
-        send_packet.serialize(&mut cursor, &NoCipher, None).unwrap();
+    #[tokio::test]
+    async fn test_poll_now() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, _) = test_startup(8024).await;
 
-        assert_eq!(cursor.position(), 48);
+        let (poll_now_sender, poll_now_receiver) = tokio::sync::watch::channel(());
+        process.channels.poll_now_receiver = poll_now_receiver;
 
-        buf
+        // The regular poll schedule never fires in this test
+        let (poll_wait, _poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_now_sender.send(()).unwrap();
+
+        let mut buf = [0; 48];
+        let network = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(network.bytes_read, 48);
+
+        handle.abort();
     }
 
     #[tokio::test]
-    async fn test_timeroundtrip() {
+    async fn test_poll_now_while_in_flight() {
         // Note: Ports must be unique among tests to deal with parallelism
-        let (mut process, mut socket, mut msg_recv) = test_startup(8008).await;
+        let (mut process, socket, _) = test_startup(8053).await;
 
-        let system = SystemSnapshot {
-            time_snapshot: TimeSnapshot {
-                leap_indicator: NtpLeapIndicator::NoWarning,
-                ..Default::default()
-            },
-            ..Default::default()
-        };
+        let (poll_now_sender, poll_now_receiver) = tokio::sync::watch::channel(());
+        process.channels.poll_now_receiver = poll_now_receiver;
 
         let (poll_wait, poll_send) = TestWait::new();
-        let clock = TestClock {};
 
         let handle = tokio::spawn(async move {
             tokio::pin!(poll_wait);
             process.run(poll_wait).await;
         });
 
-        poll_send.notify();
+        poll_now_sender.send(()).unwrap();
 
         let mut buf = [0; 48];
-        let RecvResult {
-            bytes_read: size,
-            timestamp,
+        let network = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(network.bytes_read, 48);
+        let first_deadline = poll_send.deadline().unwrap();
+
+        // The poll is not answered yet, so a new one has to wait for it
+        poll_now_sender.send(()).unwrap();
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(50)) => {/* expected */},
+            _ = socket.recv(&mut buf) => { unreachable!("poll should be deferred") }
+        }
+
+        // but happens as soon as the response window closes, rather than at
+        // the next regular poll
+        let deadline = poll_send.deadline().unwrap();
+        assert!(deadline < first_deadline);
+        assert!(deadline <= Instant::now() + RESPONSE_WINDOW);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_poll_now_respects_min_gap() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, mut socket, mut msg_recv) = test_startup(8040).await;
+        let min_gap = Duration::from_millis(300);
+        process.min_poll_gap = min_gap;
+
+        let (poll_now_sender, poll_now_receiver) = tokio::sync::watch::channel(());
+        process.channels.poll_now_receiver = poll_now_receiver;
+
+        let system = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let clock = TestClock::default();
+
+        // A real timer, so that the deferred poll actually fires
+        let poll_wait = tokio::time::sleep(Duration::from_secs(3600));
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_now_sender.send(()).unwrap();
+
+        let mut buf = [0; 48];
+        let RecvResult {
+            bytes_read: size,
+            remote_addr,
+            ..
+        } = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(size, 48);
+        let first_sent = Instant::now();
+
+        // Answer it, so the next poll is not held back as being in flight
+        let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        let send_packet =
+            NtpPacket::timestamp_response(&system, rec_packet, clock.now().unwrap(), &clock);
+        let serialized = serialize_packet_unencryped(&send_packet);
+        socket.send_to(&serialized, remote_addr).await.unwrap();
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::NewMeasurement(_, _, _)));
+
+        poll_now_sender.send(()).unwrap();
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(50)) => {/* expected */},
+            _ = socket.recv(&mut buf) => { unreachable!("poll should be deferred") }
+        }
+
+        // The poll is sent once the minimum gap has passed
+        socket.recv(&mut buf).await.unwrap();
+        assert!(first_sent.elapsed() >= min_gap - Duration::from_millis(10));
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_reset_reachability() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, mut socket, mut msg_recv) = test_startup(8050).await;
+        let source_addr = process.source_addr;
+
+        let (poll_now_sender, poll_now_receiver) = tokio::sync::watch::channel(());
+        process.channels.poll_now_receiver = poll_now_receiver;
+        let (reset_sender, reset_receiver) = tokio::sync::broadcast::channel(4);
+        process.channels.reset_reachability_receiver = reset_receiver;
+
+        let system = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let clock = TestClock::default();
+
+        // The regular poll schedule never fires in this test
+        let (poll_wait, _poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_now_sender.send(()).unwrap();
+
+        let mut buf = [0; 48];
+        let RecvResult {
+            bytes_read: size,
+            remote_addr,
+            ..
+        } = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(size, 48);
+
+        let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        let send_packet =
+            NtpPacket::timestamp_response(&system, rec_packet, clock.now().unwrap(), &clock);
+        let serialized = serialize_packet_unencryped(&send_packet);
+        socket.send_to(&serialized, remote_addr).await.unwrap();
+        let msg = msg_recv.recv().await.unwrap();
+        let MsgForSystem::NewMeasurement(_, snapshot, _) = msg else {
+            panic!("Expected a measurement");
+        };
+        assert!(snapshot.reach.is_reachable());
+
+        let other_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 1));
+
+        // A reset of another peer is ignored
+        reset_sender.send(other_addr).unwrap();
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(50)) => {/* expected */},
+            _ = msg_recv.recv() => { unreachable!("other peers should not be reset") }
+        }
+
+        // A reset of another peer right after ours does not hide ours
+        reset_sender.send(source_addr).unwrap();
+        reset_sender.send(other_addr).unwrap();
+        let msg = msg_recv.recv().await.unwrap();
+        let MsgForSystem::UpdatedSnapshot(_, snapshot) = msg else {
+            panic!("Expected an updated snapshot");
+        };
+        assert!(!snapshot.reach.is_reachable());
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_read_variables() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, mut socket, _) = test_startup(8051).await;
+        let source_addr = process.source_addr;
+
+        let (read_variables_sender, read_variables_receiver) = tokio::sync::watch::channel(None);
+        process.channels.read_variables_receiver = read_variables_receiver;
+
+        // The regular poll schedule never fires in this test
+        let (poll_wait, _poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        let (reply, mut response) = tokio::sync::mpsc::channel(1);
+        read_variables_sender
+            .send(Some(ReadVariablesRequest {
+                addr: source_addr,
+                reply,
+            }))
+            .unwrap();
+
+        let mut buf = [0; 48];
+        let RecvResult {
+            bytes_read: size,
+            remote_addr,
+            ..
+        } = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(size, 12);
+        // version 2, mode 6, read variables
+        assert_eq!(buf[0], 0x16);
+        assert_eq!(buf[1], 2);
+
+        // Answer with a canned read variables response
+        let variables = b"version=\"ntpd 4.2.8p15\", stratum=2, refid=192.0.2.1";
+        let mut packet = vec![0x16, 0x82, buf[2], buf[3], 0, 0, 0, 0, 0, 0];
+        packet.extend((variables.len() as u16).to_be_bytes());
+        packet.extend(variables);
+        socket.send_to(&packet, remote_addr).await.unwrap();
+
+        let variables = response.recv().await.unwrap().unwrap();
+        assert_eq!(variables["version"], "ntpd 4.2.8p15");
+        assert_eq!(variables["stratum"], "2");
+        assert_eq!(variables["refid"], "192.0.2.1");
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_response_processed_before_poll() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, mut socket, mut msg_recv) = test_startup(8042).await;
+
+        let system = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let clock = TestClock::default();
+
+        let (poll_wait, poll_send) = TestWait::new();
+        tokio::pin!(poll_wait);
+
+        assert!(matches!(
+            process.handle_poll(&mut poll_wait).await,
+            PollResult::Ok
+        ));
+
+        let mut buf = [0; 48];
+        let RecvResult {
+            bytes_read: size,
+            remote_addr,
+            ..
+        } = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(size, 48);
+
+        let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        let send_packet =
+            NtpPacket::timestamp_response(&system, rec_packet, clock.now().unwrap(), &clock);
+        let serialized = serialize_packet_unencryped(&send_packet);
+        socket.send_to(&serialized, remote_addr).await.unwrap();
+
+        // Give the runtime a chance to see the response arrive, then make
+        // the next poll due at the same moment
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        process.last_poll_sent = process.last_poll_sent.checked_sub(RESPONSE_WINDOW).unwrap();
+        poll_send.notify();
+
+        // The response is matched against the poll it answers, instead of
+        // being discarded after a new poll was sent
+        tokio::select! {
+            _ = process.run(poll_wait) => unreachable!("peer task should keep running"),
+            msg = msg_recv.recv() => {
+                assert!(matches!(msg, Some(MsgForSystem::NewMeasurement(_, _, _))));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mac_key() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, mut socket, mut msg_recv) = test_startup(8044).await;
+        let key = MacKey::new(42, b"secret".to_vec());
+        process.peer = Peer::new_with_mac_key(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 8044)),
+            SourceDefaultsConfig::default(),
+            ProtocolVersion::V4,
+            key.clone(),
+        );
+
+        let system = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let (poll_wait, poll_send) = TestWait::new();
+        let clock = TestClock::default();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        // The poll carries a MAC made with our key
+        let mut buf = [0; 1024];
+        let RecvResult {
+            bytes_read: size,
+            remote_addr,
+            ..
+        } = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(size, 48 + MacKey::MAC_SIZE);
+        assert!(key.verify(&buf[..size]));
+
+        let rec_packet = NtpPacket::deserialize(&buf[..size], &NoCipher).unwrap().0;
+        let send_packet =
+            NtpPacket::timestamp_response(&system, rec_packet, clock.now().unwrap(), &clock);
+        let serialized = serialize_packet_unencryped(&send_packet);
+
+        // A response signed with another key is rejected
+        let mut response = serialized.to_vec();
+        MacKey::new(42, b"wrong".to_vec())
+            .sign(&serialized, &mut response)
+            .unwrap();
+        socket.send_to(&response, remote_addr).await.unwrap();
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(50)) => {/* expected */},
+            _ = msg_recv.recv() => { unreachable!("response with a bad MAC should be ignored") }
+        }
+
+        // While a correctly signed one is accepted
+        let mut response = serialized.to_vec();
+        key.sign(&serialized, &mut response).unwrap();
+        socket.send_to(&response, remote_addr).await.unwrap();
+
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::NewMeasurement(_, _, _)));
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_measurement_recording() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, mut socket, mut msg_recv) = test_startup(8046).await;
+        let (record_sender, record_receiver) = mpsc::channel(1);
+        process.channels.measurement_recorder = MeasurementRecorder::from_sender(record_sender);
+
+        let system = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let (poll_wait, poll_send) = TestWait::new();
+        let clock = TestClock::default();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let RecvResult {
+            bytes_read: size,
+            remote_addr,
+            ..
+        } = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(size, 48);
+
+        let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        let send_packet =
+            NtpPacket::timestamp_response(&system, rec_packet, clock.now().unwrap(), &clock);
+        let serialized = serialize_packet_unencryped(&send_packet);
+        socket.send_to(&serialized, remote_addr).await.unwrap();
+
+        let MsgForSystem::NewMeasurement(_, _, measurement) = msg_recv.recv().await.unwrap() else {
+            panic!("expected a new measurement");
+        };
+
+        // Stopping the task drops its recorder, so writing the records ends
+        handle.abort();
+        let _ = handle.await;
+
+        let mut output = vec![];
+        write_records(record_receiver, &mut output).await;
+
+        let output = String::from_utf8(output).unwrap();
+        let mut lines = output.lines();
+        let record: serde_json::Value = serde_json::from_str(lines.next().unwrap()).unwrap();
+        assert_eq!(lines.next(), None);
+
+        assert_eq!(record["peer"], "127.0.0.1:8046");
+        // The json round-trip need not preserve the last bits of the floats
+        assert!(
+            (record["offset"].as_f64().unwrap() - measurement.offset.to_seconds()).abs() < 1e-12
+        );
+        assert!((record["delay"].as_f64().unwrap() - measurement.delay.to_seconds()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_recv_timestamp_policy() {
+        let buf = [0; 48];
+        let kernel_timestamp = Timestamp {
+            seconds: 1,
+            nanos: 0,
+        };
+        let received = |timestamp| {
+            Ok(RecvResult {
+                bytes_read: 48,
+                remote_addr: SocketAddr::from((Ipv4Addr::LOCALHOST, 123)),
+                timestamp,
+            })
+        };
+        let kernel = convert_net_timestamp(kernel_timestamp);
+
+        // Prefer uses the kernel timestamp, falling back to the clock
+        let AcceptResult::Accept(_, ts) = accept_packet(
+            received(Some(kernel_timestamp)),
+            &buf,
+            &TestClock::default(),
+            RecvTimestampPolicy::Prefer,
+            None,
+        ) else {
+            panic!("packet should be accepted");
+        };
+        assert_eq!(ts, kernel);
+        let AcceptResult::Accept(_, ts) = accept_packet(
+            received(None),
+            &buf,
+            &TestClock::default(),
+            RecvTimestampPolicy::Prefer,
+            None,
+        ) else {
+            panic!("packet should be accepted");
+        };
+        assert_ne!(ts, kernel);
+
+        // Require ignores packets without a kernel timestamp
+        let AcceptResult::Accept(_, ts) = accept_packet(
+            received(Some(kernel_timestamp)),
+            &buf,
+            &TestClock::default(),
+            RecvTimestampPolicy::Require,
+            None,
+        ) else {
+            panic!("packet should be accepted");
+        };
+        assert_eq!(ts, kernel);
+        assert!(matches!(
+            accept_packet(
+                received(None),
+                &buf,
+                &TestClock::default(),
+                RecvTimestampPolicy::Require,
+                None
+            ),
+            AcceptResult::Ignore
+        ));
+
+        // SoftwareOnly always reads the clock
+        let AcceptResult::Accept(_, ts) = accept_packet(
+            received(Some(kernel_timestamp)),
+            &buf,
+            &TestClock::default(),
+            RecvTimestampPolicy::SoftwareOnly,
+            None,
+        ) else {
+            panic!("packet should be accepted");
+        };
+        assert_ne!(ts, kernel);
+
+        // Without a timestamp or a working clock the packet can't be used
+        assert!(matches!(
+            accept_packet(
+                received(None),
+                &buf,
+                &FailingClock::default(),
+                RecvTimestampPolicy::Prefer,
+                None
+            ),
+            AcceptResult::Ignore
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_socket_setup_retry() {
+        // transient failures are retried
+        let mut tries = 0;
+        let result = retry_socket_setup(Duration::ZERO, || {
+            tries += 1;
+            if tries < 3 {
+                Err(std::io::Error::from(std::io::ErrorKind::AddrNotAvailable))
+            } else {
+                Ok(())
+            }
+        })
+        .await;
+        assert!(result.is_ok());
+        assert_eq!(tries, 3);
+
+        // but not indefinitely
+        let mut tries = 0;
+        let result: std::io::Result<()> = retry_socket_setup(Duration::ZERO, || {
+            tries += 1;
+            Err(std::io::Error::from(std::io::ErrorKind::AddrNotAvailable))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(tries, SOCKET_SETUP_TRIES);
+    }
+
+    #[tokio::test]
+    async fn test_send_failure() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, _, _) = test_startup::<TestWait>(8032).await;
+
+        // an IPv4 socket can't send to an IPv6 address
+        let socket = open_ip(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            GeneralTimestampMode::SoftwareRecv,
+        )
+        .unwrap();
+        process.socket = Some(PeerSocket::Unconnected(
+            socket,
+            SocketAddr::from((Ipv6Addr::LOCALHOST, 8032)),
+        ));
+
+        let result = process.send_packet(&[0; 48]).await;
+        assert!(matches!(result, PollResult::Ok));
+        // a packet that wasn't sent can't have a response
+        assert!(process.last_send_timestamp.is_none());
+        assert!(process.response_deadline.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_report_send_error() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, _, mut msg_recv) = test_startup::<TestWait>(8036).await;
+
+        // an IPv4 socket can't send to an IPv6 address
+        let socket = open_ip(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            GeneralTimestampMode::SoftwareRecv,
+        )
+        .unwrap();
+        process.socket = Some(PeerSocket::Unconnected(
+            socket,
+            SocketAddr::from((Ipv6Addr::LOCALHOST, 8036)),
+        ));
+
+        let before = process.clock.now().unwrap();
+        process.send_packet(&[0; 48]).await;
+        let after = process.clock.now().unwrap();
+
+        let msg = msg_recv.try_recv().unwrap();
+        let MsgForSystem::Error(_, timestamp, error) = msg else {
+            panic!("Expected an error, got {msg:?}");
+        };
+        assert!(before <= timestamp && timestamp <= after);
+        assert!(error.starts_with("poll message could not be sent"));
+    }
+
+    #[tokio::test]
+    async fn test_poll_without_randomization() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, _, _) = test_startup::<Sleep>(8034).await;
+        process.randomize_poll = false;
+
+        let system = SystemSnapshot::default();
+        let poll_interval = process.peer.next_poll_interval(system).as_system_duration();
+
+        let poll_wait = tokio::time::sleep(Duration::ZERO);
+        tokio::pin!(poll_wait);
+
+        let before = Instant::now();
+        process.update_poll_wait(&mut poll_wait, system);
+        let first = poll_wait.deadline();
+        assert_close(first - before, poll_interval);
+
+        process.last_poll_sent = process.last_poll_sent + poll_interval;
+        process.update_poll_wait(&mut poll_wait, system);
+        assert_close(poll_wait.deadline() - first, poll_interval);
+    }
+
+    /// Conversions between the clocks happen at slightly different moments
+    fn assert_close(actual: Duration, expected: Duration) {
+        let difference = if actual > expected {
+            actual - expected
+        } else {
+            expected - actual
+        };
+        assert!(
+            difference < Duration::from_millis(100),
+            "{actual:?} is not close to {expected:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_poll_deadline_with_paused_time() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, _, _) = test_startup::<Sleep>(8048).await;
+        process.randomize_poll = false;
+
+        let system = SystemSnapshot::default();
+        let poll_interval = process.peer.next_poll_interval(system).as_system_duration();
+
+        let poll_wait = tokio::time::sleep(Duration::ZERO);
+        tokio::pin!(poll_wait);
+
+        // tokio's clock jumps ahead, while the poll was sent just now in
+        // real time
+        tokio::time::advance(Duration::from_secs(3600)).await;
+
+        let now = Instant::now();
+        process.update_poll_wait(&mut poll_wait, system);
+        assert_close(poll_wait.deadline() - now, poll_interval);
+    }
+
+    #[tokio::test]
+    async fn test_clock_error() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, _socket, mut msg_recv) =
+            test_startup_with_clock(8026, FailingClock {}).await;
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        // The failure is reported, and only this peer's task stops
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::Error(_, _, _)));
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::ClockError(_)));
+        handle.await.unwrap();
+    }
+
+    #[derive(Clone, Default)]
+    struct CaptureWriter(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CaptureWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wire_trace() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, _) = test_startup(8022).await;
+
+        let output = CaptureWriter::default();
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(
+            async move {
+                tokio::pin!(poll_wait);
+                process.run(poll_wait).await;
+            }
+            .with_subscriber(subscriber),
+        );
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let network = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(network.bytes_read, 48);
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains(WIRE_TRACE_TARGET));
+        assert!(output.contains(&HexDump(&buf).to_string()));
+
+        handle.abort();
+    }
+
+    fn serialize_packet_unencryped(send_packet: &NtpPacket) -> [u8; 48] {
+        let mut buf = [0; 48];
+        let mut cursor = Cursor::new(buf.as_mut_slice());
+        send_packet.serialize(&mut cursor, &NoCipher, None).unwrap();
+
+        assert_eq!(cursor.position(), 48);
+
+        buf
+    }
+
+    #[tokio::test]
+    async fn test_timeroundtrip() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, mut socket, mut msg_recv) = test_startup(8008).await;
+
+        let system = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let (poll_wait, poll_send) = TestWait::new();
+        let clock = TestClock::default();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let RecvResult {
+            bytes_read: size,
+            timestamp,
+            remote_addr,
+        } = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(size, 48);
+        let timestamp = timestamp.unwrap();
+
+        let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        let send_packet = NtpPacket::timestamp_response(
+            &system,
+            rec_packet,
+            convert_net_timestamp(timestamp),
+            &clock,
+        );
+
+        let serialized = serialize_packet_unencryped(&send_packet);
+        socket.send_to(&serialized, remote_addr).await.unwrap();
+
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::NewMeasurement(_, _, _)));
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_clock_offset() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let client_clock = TestClock {
+            offset: NtpDuration::from_seconds(0.1),
+        };
+        let (mut process, mut socket, mut msg_recv) =
+            test_startup_with_clock(8030, client_clock).await;
+        // kernel timestamps would follow the system time instead of the client clock
+        process.recv_timestamp_policy = RecvTimestampPolicy::SoftwareOnly;
+
+        let system = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let (poll_wait, poll_send) = TestWait::new();
+        let clock = TestClock::default();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let RecvResult { remote_addr, .. } = socket.recv(&mut buf).await.unwrap();
+
+        let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        let send_packet =
+            NtpPacket::timestamp_response(&system, rec_packet, clock.now().unwrap(), &clock);
+
+        let serialized = serialize_packet_unencryped(&send_packet);
+        socket.send_to(&serialized, remote_addr).await.unwrap();
+
+        let msg = msg_recv.recv().await.unwrap();
+        let MsgForSystem::NewMeasurement(_, _, measurement) = msg else {
+            panic!("Expected a measurement, got {msg:?}");
+        };
+        // the client is ahead of the server, so it should move back
+        assert!((measurement.offset.to_seconds() + 0.1).abs() < 0.01);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_deny_stops_poll() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, mut socket, mut msg_recv) = test_startup(8010).await;
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let RecvResult {
+            bytes_read: size,
+            timestamp,
+            remote_addr,
+        } = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(size, 48);
+        assert!(timestamp.is_some());
+
+        let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        let send_packet = NtpPacket::deny_response(rec_packet);
+        let serialized = serialize_packet_unencryped(&send_packet);
+
+        socket.send_to(&serialized, remote_addr).await.unwrap();
+
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::MustDemobilize(_)));
+
+        poll_send.notify();
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(10)) => {/*expected */},
+            _ = socket.recv(&mut buf) => { unreachable!("should not receive anything") }
+        }
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_rstr_stops_poll_temporarily() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, mut socket, mut msg_recv) = test_startup(8012).await;
+        process.restrict_cooldown = Duration::from_secs(60);
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let RecvResult {
+            bytes_read: size,
+            remote_addr,
+            ..
+        } = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(size, 48);
+
+        let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        let mut send_packet = NtpPacket::deny_response(rec_packet);
+        send_packet.set_reference_id(ReferenceId::KISS_RSTR);
+        let serialized = serialize_packet_unencryped(&send_packet);
+
+        socket.send_to(&serialized, remote_addr).await.unwrap();
+
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(
+            msg,
+            MsgForSystem::DemobilizeTemporary(_, cooldown) if cooldown == Duration::from_secs(60)
+        ));
+
+        poll_send.notify();
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(10)) => {/*expected */},
+            _ = socket.recv(&mut buf) => { unreachable!("should not receive anything") }
+        }
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_resend_after_long_idle() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, mut socket, mut msg_recv) = test_startup(8014).await;
+
+        let system = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // Pretend we have not polled in a long time
+        process.last_poll_sent = NtpInstant::now().checked_sub(LONG_IDLE_THRESHOLD).unwrap();
+
+        let (poll_wait, poll_send) = TestWait::new();
+        let clock = TestClock::default();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        // The first packet gets lost
+        let mut first = [0; 48];
+        let RecvResult {
+            bytes_read: size,
+            remote_addr: first_addr,
+            ..
+        } = socket.recv(&mut first).await.unwrap();
+        assert_eq!(size, 48);
+
+        // The retry is scheduled well before the next regular poll
+        let deadline = poll_send.deadline().unwrap();
+        assert!(deadline <= Instant::now() + IDLE_RETRY_TIMEOUT);
+
+        // Let some time pass, so that measuring against the send time of the
+        // resent packet instead of the original shows up in the delay
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // So the same poll is resent once the retry timeout passes
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let RecvResult {
+            bytes_read: size,
             remote_addr,
+            ..
         } = socket.recv(&mut buf).await.unwrap();
         assert_eq!(size, 48);
-        let timestamp = timestamp.unwrap();
+        assert_eq!(first, buf);
+        // from a new socket, in case a firewall forgot the mapping of the old one
+        assert_ne!(first_addr, remote_addr);
 
         let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
-        let send_packet = NtpPacket::timestamp_response(
-            &system,
-            rec_packet,
-            convert_net_timestamp(timestamp),
-            &clock,
-        );
+        let send_packet =
+            NtpPacket::timestamp_response(&system, rec_packet, clock.now().unwrap(), &clock);
 
         let serialized = serialize_packet_unencryped(&send_packet);
         socket.send_to(&serialized, remote_addr).await.unwrap();
 
+        // The response is measured against the original send time
         let msg = msg_recv.recv().await.unwrap();
-        assert!(matches!(msg, MsgForSystem::NewMeasurement(_, _, _)));
+        let MsgForSystem::NewMeasurement(_, _, measurement) = msg else {
+            panic!("expected a measurement, got {msg:?}");
+        };
+        assert!(measurement.delay >= NtpDuration::from_seconds(0.2));
 
         handle.abort();
     }
 
     #[tokio::test]
-    async fn test_deny_stops_poll() {
+    async fn test_resolve_after_long_idle() {
         // Note: Ports must be unique among tests to deal with parallelism
-        let (mut process, mut socket, mut msg_recv) = test_startup(8010).await;
+        let (mut process, socket, mut msg_recv) = test_startup(8052).await;
+
+        // The name of the peer now resolves to another address
+        process.resolve_after_idle = Some(NormalizedAddress::with_hardcoded_dns(
+            "example.com",
+            8052,
+            vec!["127.0.0.2:8052".parse().unwrap()],
+        ));
+        process.last_poll_sent = NtpInstant::now().checked_sub(LONG_IDLE_THRESHOLD).unwrap();
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        // The peer stops, so that it is recreated with the new address
+        loop {
+            match msg_recv.recv().await.unwrap() {
+                MsgForSystem::Error(..) => continue,
+                MsgForSystem::Unreachable(_) => break,
+                msg => panic!("expected the peer to stop, got {msg:?}"),
+            }
+        }
+
+        let mut buf = [0; 48];
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(10)) => {/*expected */},
+            _ = socket.recv(&mut buf) => { unreachable!("should not receive anything") }
+        }
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_resend_first_poll() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, mut socket, mut msg_recv) = test_startup(8038).await;
+        process.retry_until_response = true;
+
+        let system = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
 
         let (poll_wait, poll_send) = TestWait::new();
+        let clock = TestClock::default();
 
         let handle = tokio::spawn(async move {
             tokio::pin!(poll_wait);
@@ -672,24 +2374,76 @@ mod tests {
 
         poll_send.notify();
 
+        // The very first packet gets lost
+        let mut first = [0; 48];
+        let RecvResult {
+            bytes_read: size, ..
+        } = socket.recv(&mut first).await.unwrap();
+        assert_eq!(size, 48);
+
+        // The retry is scheduled well before the next regular poll
+        let deadline = poll_send.deadline().unwrap();
+        assert!(deadline <= Instant::now() + IDLE_RETRY_TIMEOUT);
+
+        // Let some time pass, so that measuring against the send time of the
+        // resent packet instead of the original shows up in the delay
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // The wait now ends after the short retry timeout instead of a full
+        // poll interval, and resends the same poll
+        poll_send.notify();
+
         let mut buf = [0; 48];
         let RecvResult {
             bytes_read: size,
-            timestamp,
             remote_addr,
+            ..
         } = socket.recv(&mut buf).await.unwrap();
         assert_eq!(size, 48);
-        assert!(timestamp.is_some());
+        assert_eq!(first, buf);
 
         let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
-        let send_packet = NtpPacket::deny_response(rec_packet);
-        let serialized = serialize_packet_unencryped(&send_packet);
+        let send_packet =
+            NtpPacket::timestamp_response(&system, rec_packet, clock.now().unwrap(), &clock);
 
+        let serialized = serialize_packet_unencryped(&send_packet);
         socket.send_to(&serialized, remote_addr).await.unwrap();
 
+        // The response is measured against the original send time
         let msg = msg_recv.recv().await.unwrap();
-        assert!(matches!(msg, MsgForSystem::MustDemobilize(_)));
+        let MsgForSystem::NewMeasurement(_, _, measurement) = msg else {
+            panic!("expected a measurement, got {msg:?}");
+        };
+        assert!(measurement.delay >= NtpDuration::from_seconds(0.2));
+
+        // Once answered, the next poll is a new one
+        poll_send.notify();
+        let mut next = [0; 48];
+        socket.recv(&mut next).await.unwrap();
+        assert_ne!(first, next);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_no_poll_while_in_flight() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, _) = test_startup(8016).await;
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
 
+        let mut buf = [0; 48];
+        let network = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(network.bytes_read, 48);
+
+        // The first poll has not been answered yet, so no new poll is sent
         poll_send.notify();
 
         tokio::select! {
@@ -699,4 +2453,337 @@ mod tests {
 
         handle.abort();
     }
+
+    #[tokio::test]
+    async fn test_response_timeout() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, mut msg_recv) = test_startup(8018).await;
+        process.response_timeout = Duration::from_millis(20);
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            tokio::time::timeout(Duration::from_millis(200), process.run(poll_wait))
+                .await
+                .ok();
+            process.peer.missed_responses()
+        });
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let network = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(network.bytes_read, 48);
+
+        // The system learns about the missed poll
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(
+            msg,
+            MsgForSystem::UpdatedSnapshot(_, snapshot) if snapshot.missed_responses == 1
+        ));
+
+        // We never respond, so the poll is reported missed once
+        assert_eq!(handle.await.unwrap(), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_source_port() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, _) = test_startup(8028).await;
+        process.source_port = Some(8029);
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let RecvResult { remote_addr, .. } = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(remote_addr.port(), 8029);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_accept_any_response_port() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, mut msg_recv) = test_startup(8020).await;
+        process.accept_any_port = true;
+
+        // The response is sent from a different port than the poll was sent to
+        let mut other_socket = open_ip(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 8021)),
+            GeneralTimestampMode::SoftwareRecv,
+        )
+        .unwrap();
+
+        let system = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let (poll_wait, poll_send) = TestWait::new();
+        let clock = TestClock::default();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let RecvResult {
+            bytes_read: size,
+            remote_addr,
+            ..
+        } = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(size, 48);
+
+        let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        let send_packet =
+            NtpPacket::timestamp_response(&system, rec_packet, clock.now().unwrap(), &clock);
+
+        let serialized = serialize_packet_unencryped(&send_packet);
+        other_socket
+            .send_to(&serialized, remote_addr)
+            .await
+            .unwrap();
+
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::NewMeasurement(_, _, _)));
+
+        handle.abort();
+    }
+
+    #[test]
+    fn test_accept_packet_source() {
+        let buf = [0; 48];
+        let received = |remote_addr| {
+            Ok(RecvResult {
+                bytes_read: 48,
+                remote_addr,
+                timestamp: None,
+            })
+        };
+        let expected = SocketAddr::from((Ipv4Addr::LOCALHOST, 123));
+
+        // any port of the expected address is accepted, also when IPv4-mapped
+        for remote_addr in [
+            expected,
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 1234)),
+            SocketAddr::from((Ipv4Addr::LOCALHOST.to_ipv6_mapped(), 123)),
+        ] {
+            assert!(matches!(
+                accept_packet(
+                    received(remote_addr),
+                    &buf,
+                    &TestClock::default(),
+                    RecvTimestampPolicy::Prefer,
+                    Some(expected)
+                ),
+                AcceptResult::Accept(_, _)
+            ));
+        }
+
+        let other = SocketAddr::from((Ipv4Addr::new(127, 0, 0, 2), 123));
+        assert!(matches!(
+            accept_packet(
+                received(other),
+                &buf,
+                &TestClock::default(),
+                RecvTimestampPolicy::Prefer,
+                Some(expected)
+            ),
+            AcceptResult::UnexpectedSource(addr) if addr == other
+        ));
+
+        // connected sockets are verified by the kernel
+        assert!(matches!(
+            accept_packet(
+                received(other),
+                &buf,
+                &TestClock::default(),
+                RecvTimestampPolicy::Prefer,
+                None
+            ),
+            AcceptResult::Accept(_, _)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reject_unexpected_source() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, mut msg_recv) = test_startup(8047).await;
+        process.accept_any_port = true;
+
+        // Another host on the same port tries to answer the poll
+        let mut other_socket = open_ip(
+            SocketAddr::from((Ipv4Addr::new(127, 0, 0, 2), 8047)),
+            GeneralTimestampMode::SoftwareRecv,
+        )
+        .unwrap();
+
+        let system = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let (poll_wait, poll_send) = TestWait::new();
+        let clock = TestClock::default();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let RecvResult {
+            bytes_read: size,
+            remote_addr,
+            ..
+        } = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(size, 48);
+
+        let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        let send_packet =
+            NtpPacket::timestamp_response(&system, rec_packet, clock.now().unwrap(), &clock);
+
+        let serialized = serialize_packet_unencryped(&send_packet);
+        other_socket
+            .send_to(&serialized, remote_addr)
+            .await
+            .unwrap();
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(50)) => {/* expected */},
+            _ = msg_recv.recv() => { unreachable!("packet from another address should be ignored") }
+        }
+
+        handle.abort();
+    }
+
+    struct TestRefClock {
+        measurements: mpsc::Receiver<std::io::Result<Measurement>>,
+    }
+
+    #[async_trait::async_trait]
+    impl RefClockDriver for TestRefClock {
+        fn reference_id(&self) -> ReferenceId {
+            ReferenceId::from_bytes(*b"GPS\0")
+        }
+
+        async fn next_measurement(&mut self) -> std::io::Result<Measurement> {
+            self.measurements
+                .recv()
+                .await
+                .unwrap_or_else(|| Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refclock() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, _, mut msg_recv) = test_startup(8049).await;
+        let (measurement_sender, measurements) = mpsc::channel(1);
+        process.refclock = Some(Box::new(TestRefClock { measurements }));
+
+        let (poll_wait, _poll_send) = TestWait::new();
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(1e-6),
+            offset: NtpDuration::from_seconds(0.001),
+            transmit_timestamp: NtpTimestamp::default(),
+            receive_timestamp: NtpTimestamp::default(),
+            localtime: TestClock::default().now().unwrap(),
+            monotime: NtpInstant::now(),
+            stratum: 0,
+            root_delay: NtpDuration::ZERO,
+            root_dispersion: NtpDuration::ZERO,
+            leap: NtpLeapIndicator::NoWarning,
+            precision: -20,
+        };
+
+        // Each measurement of the reference clock reaches the system
+        for _ in 0..2 {
+            measurement_sender.send(Ok(measurement)).await.unwrap();
+            let Some(MsgForSystem::NewMeasurement(_, snapshot, result)) = msg_recv.recv().await
+            else {
+                panic!("expected a new measurement");
+            };
+            assert_eq!(result.offset, measurement.offset);
+            assert_eq!(snapshot.stratum, 0);
+            assert_eq!(snapshot.reference_id, ReferenceId::from_bytes(*b"GPS\0"));
+        }
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_refclock_recovers_from_error() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, _, mut msg_recv) = test_startup(8054).await;
+        let (measurement_sender, measurements) = mpsc::channel(1);
+        process.refclock = Some(Box::new(TestRefClock { measurements }));
+
+        // skip over the retry delay
+        tokio::time::pause();
+
+        let (poll_wait, _poll_send) = TestWait::new();
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        // A failing read is reported, but does not stop the peer
+        measurement_sender
+            .send(Err(std::io::Error::from(std::io::ErrorKind::Interrupted)))
+            .await
+            .unwrap();
+        assert!(matches!(
+            msg_recv.recv().await,
+            Some(MsgForSystem::Error(_, _, _))
+        ));
+
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(1e-6),
+            offset: NtpDuration::from_seconds(0.001),
+            transmit_timestamp: NtpTimestamp::default(),
+            receive_timestamp: NtpTimestamp::default(),
+            localtime: TestClock::default().now().unwrap(),
+            monotime: NtpInstant::now(),
+            stratum: 0,
+            root_delay: NtpDuration::ZERO,
+            root_dispersion: NtpDuration::ZERO,
+            leap: NtpLeapIndicator::NoWarning,
+            precision: -20,
+        };
+
+        // once the reference clock recovers, its measurements reach the system again
+        measurement_sender.send(Ok(measurement)).await.unwrap();
+        let Some(MsgForSystem::NewMeasurement(_, _, result)) = msg_recv.recv().await else {
+            panic!("expected a new measurement");
+        };
+        assert_eq!(result.offset, measurement.offset);
+
+        handle.abort();
+    }
 }
@@ -0,0 +1,111 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::Mutex, time::Instant};
+
+/// Longest time between two polls the limiter enforces
+const MAX_REFILL_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Token bucket shared between all peer tasks, pacing the aggregate rate at
+/// which poll packets are sent so that aligned poll intervals don't result
+/// in bursts of packets.
+#[derive(Debug, Clone)]
+pub struct PollRateLimiter {
+    bucket: Option<Arc<Mutex<Bucket>>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Time between two tokens becoming available
+    refill_interval: Duration,
+    /// Moment at which the next token is available
+    next_token: Instant,
+}
+
+impl PollRateLimiter {
+    /// Create a limiter allowing at most `rate` polls per second, or no
+    /// limit at all when `rate` is `None`. The configuration only allows
+    /// positive rates; the time between polls is capped for tiny ones.
+    pub fn new(rate: Option<f64>) -> Self {
+        let bucket = rate.map(|rate| {
+            let refill_interval = Duration::try_from_secs_f64(1.0 / rate)
+                .map_or(MAX_REFILL_INTERVAL, |interval| {
+                    interval.min(MAX_REFILL_INTERVAL)
+                });
+            Arc::new(Mutex::new(Bucket {
+                refill_interval,
+                next_token: Instant::now(),
+            }))
+        });
+
+        PollRateLimiter { bucket }
+    }
+
+    /// Wait until we are allowed to send a poll
+    pub async fn acquire(&self) {
+        let Some(bucket) = &self.bucket else {
+            return;
+        };
+
+        // The lock is held while waiting, so waiters are served in order
+        let mut bucket = bucket.lock().await;
+        let now = Instant::now();
+        if bucket.next_token > now {
+            tokio::time::sleep_until(bucket.next_token).await;
+        }
+
+        // The bucket holds at most a single token, so unused time is not saved up
+        bucket.next_token = Instant::now().max(bucket.next_token) + bucket.refill_interval;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unlimited() {
+        let limiter = PollRateLimiter::new(None);
+
+        let start = Instant::now();
+        for _ in 0..100 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_spacing() {
+        let limiter = PollRateLimiter::new(Some(20.0));
+
+        // three peers that are all due at the same time
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move {
+                    limiter.acquire().await;
+                    Instant::now()
+                })
+            })
+            .collect();
+
+        let mut sends = vec![];
+        for handle in handles {
+            sends.push(handle.await.unwrap());
+        }
+        sends.sort();
+
+        for pair in sends.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_millis(45));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extreme_rates() {
+        // rates this low or invalid don't panic, but are capped
+        for rate in [1e-300, 0.0, -1.0, f64::NAN] {
+            let limiter = PollRateLimiter::new(Some(rate));
+            let bucket = limiter.bucket.unwrap();
+            assert_eq!(bucket.lock().await.refill_interval, MAX_REFILL_INTERVAL);
+        }
+    }
+}
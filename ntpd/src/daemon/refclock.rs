@@ -0,0 +1,251 @@
+use std::path::PathBuf;
+
+use ntp_proto::{
+    Measurement, NtpDuration, NtpInstant, NtpLeapIndicator, NtpTimestamp, ReferenceId,
+};
+use tokio::net::UnixDatagram;
+use tracing::debug;
+
+use super::util::EPOCH_OFFSET;
+
+/// Driver for a reference clock attached to this machine, such as a GPS
+/// receiver or a PPS signal. A peer backed by a reference clock takes its
+/// measurements from the driver instead of exchanging packets with a server.
+#[async_trait::async_trait]
+pub trait RefClockDriver: Send + Sync + 'static {
+    /// Reference id advertised for this clock, such as `GPS` or `PPS`
+    fn reference_id(&self) -> ReferenceId;
+
+    /// Wait for the next measurement of the local clock against the reference
+    /// clock. After an error, the peer calls this again after a backoff.
+    async fn next_measurement(&mut self) -> std::io::Result<Measurement>;
+}
+
+/// Magic number at the end of every sample of the SOCK protocol
+const SOCK_MAGIC: i32 = 0x534f434b;
+
+/// Size of a sample of the SOCK protocol: a `struct timeval`, the offset as a
+/// double and four ints, as laid out on 64-bit platforms
+const SOCK_SAMPLE_SIZE: usize = 40;
+
+/// Precision (log2 seconds) of the samples, which have microsecond resolution
+const SOCK_PRECISION: i8 = -20;
+
+/// Sample received over the SOCK protocol
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SockSample {
+    /// Local time of the sample, as a unix timestamp
+    seconds: i64,
+    microseconds: i64,
+    /// Offset of the reference clock from the local clock, in seconds
+    offset: f64,
+    /// Whether this is a pulse without a time of day (PPS)
+    pulse: bool,
+    leap: i32,
+}
+
+impl SockSample {
+    fn deserialize(data: &[u8]) -> Option<SockSample> {
+        if data.len() != SOCK_SAMPLE_SIZE {
+            return None;
+        }
+
+        let i64_at = |i: usize| i64::from_ne_bytes(data[i..i + 8].try_into().unwrap());
+        let i32_at = |i: usize| i32::from_ne_bytes(data[i..i + 4].try_into().unwrap());
+
+        if i32_at(36) != SOCK_MAGIC {
+            return None;
+        }
+
+        Some(SockSample {
+            seconds: i64_at(0),
+            microseconds: i64_at(8),
+            offset: f64::from_ne_bytes(data[16..24].try_into().unwrap()),
+            pulse: i32_at(24) != 0,
+            leap: i32_at(28),
+        })
+    }
+
+    fn measurement(&self) -> Measurement {
+        let localtime = NtpTimestamp::from_seconds_nanos_since_ntp_era(
+            EPOCH_OFFSET.wrapping_add(self.seconds as _),
+            (self.microseconds.clamp(0, 999_999) * 1000) as u32,
+        );
+        let offset = NtpDuration::from_seconds(self.offset);
+
+        Measurement {
+            // The clock is local, so the delay is within the resolution
+            delay: NtpDuration::from_exponent(SOCK_PRECISION),
+            offset,
+            transmit_timestamp: localtime + offset,
+            receive_timestamp: localtime + offset,
+            localtime,
+            monotime: NtpInstant::now(),
+            stratum: 0,
+            root_delay: NtpDuration::ZERO,
+            root_dispersion: NtpDuration::ZERO,
+            leap: match self.leap {
+                0 => NtpLeapIndicator::NoWarning,
+                1 => NtpLeapIndicator::Leap61,
+                2 => NtpLeapIndicator::Leap59,
+                _ => NtpLeapIndicator::Unknown,
+            },
+            precision: SOCK_PRECISION,
+        }
+    }
+}
+
+/// Reference clock fed over a unix datagram socket using the SOCK protocol of
+/// chrony, as supported by for example gpsd. Only samples carrying the time of
+/// day are used, bare pulses are ignored.
+pub struct SockRefClock {
+    path: PathBuf,
+    reference_id: ReferenceId,
+    socket: Option<UnixDatagram>,
+}
+
+impl SockRefClock {
+    /// The socket at `path` is only created once the first measurement is
+    /// requested, so that failing to create it stops the peer.
+    pub fn new(path: PathBuf, reference_id: ReferenceId) -> Self {
+        SockRefClock {
+            path,
+            reference_id,
+            socket: None,
+        }
+    }
+
+    fn bind(&self) -> std::io::Result<UnixDatagram> {
+        // A socket left behind by an earlier run would make binding fail
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        UnixDatagram::bind(&self.path)
+    }
+}
+
+impl Drop for SockRefClock {
+    fn drop(&mut self) {
+        if self.socket.is_some() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RefClockDriver for SockRefClock {
+    fn reference_id(&self) -> ReferenceId {
+        self.reference_id
+    }
+
+    async fn next_measurement(&mut self) -> std::io::Result<Measurement> {
+        if self.socket.is_none() {
+            self.socket = Some(self.bind()?);
+        }
+        let socket = self.socket.as_ref().unwrap();
+
+        // One byte extra, so that oversized datagrams are recognized
+        let mut buf = [0u8; SOCK_SAMPLE_SIZE + 1];
+        loop {
+            let size = socket.recv(&mut buf).await?;
+            match SockSample::deserialize(&buf[..size]) {
+                Some(sample) if !sample.pulse => return Ok(sample.measurement()),
+                Some(_) => debug!("ignoring pulse sample from reference clock"),
+                None => debug!(size, "ignoring malformed sample from reference clock"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_bytes(
+        seconds: i64,
+        microseconds: i64,
+        offset: f64,
+        pulse: i32,
+        leap: i32,
+    ) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend(seconds.to_ne_bytes());
+        data.extend(microseconds.to_ne_bytes());
+        data.extend(offset.to_ne_bytes());
+        data.extend(pulse.to_ne_bytes());
+        data.extend(leap.to_ne_bytes());
+        data.extend(0i32.to_ne_bytes());
+        data.extend(SOCK_MAGIC.to_ne_bytes());
+        data
+    }
+
+    #[test]
+    fn test_sock_sample() {
+        let data = sample_bytes(1_700_000_000, 250_000, 0.001, 0, 1);
+        let sample = SockSample::deserialize(&data).unwrap();
+        assert_eq!(
+            sample,
+            SockSample {
+                seconds: 1_700_000_000,
+                microseconds: 250_000,
+                offset: 0.001,
+                pulse: false,
+                leap: 1,
+            }
+        );
+
+        let measurement = sample.measurement();
+        assert_eq!(measurement.offset, NtpDuration::from_seconds(0.001));
+        assert_eq!(measurement.leap, NtpLeapIndicator::Leap61);
+        assert_eq!(measurement.stratum, 0);
+        assert_eq!(
+            measurement.localtime,
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                EPOCH_OFFSET.wrapping_add(1_700_000_000),
+                250_000_000
+            )
+        );
+
+        // Truncated or without the magic number
+        assert!(SockSample::deserialize(&data[..SOCK_SAMPLE_SIZE - 1]).is_none());
+        let mut data = data;
+        data[36] ^= 0xff;
+        assert!(SockSample::deserialize(&data).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sock_refclock() {
+        let path = std::env::temp_dir().join(format!("ntpd-rs-test-sock-{}", std::process::id()));
+        let mut refclock = SockRefClock::new(path.clone(), ReferenceId::from_bytes(*b"GPS\0"));
+
+        let sender = tokio::spawn({
+            let path = path.clone();
+            async move {
+                let socket = UnixDatagram::unbound().unwrap();
+                // Wait for the reference clock to create its socket
+                while socket.connect(&path).is_err() {
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                }
+                // Pulses and malformed samples are skipped
+                socket
+                    .send(&sample_bytes(1_700_000_000, 0, 0.5, 1, 0))
+                    .await
+                    .unwrap();
+                socket.send(&[0; 12]).await.unwrap();
+                socket
+                    .send(&sample_bytes(1_700_000_001, 0, -0.25, 0, 0))
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let measurement = refclock.next_measurement().await.unwrap();
+        assert_eq!(measurement.offset, NtpDuration::from_seconds(-0.25));
+        assert_eq!(measurement.leap, NtpLeapIndicator::NoWarning);
+        sender.await.unwrap();
+
+        drop(refclock);
+        assert!(!path.exists());
+    }
+}
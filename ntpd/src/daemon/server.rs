@@ -304,6 +304,10 @@ mod tests {
             Duration::from_secs(0),
         );
 
+        // Give the server task the opportunity to open its socket, the kernel
+        // may take a moment before it starts timestamping received packets
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
         let socket = open_ip(
             "127.0.0.1:9001".parse().unwrap(),
             GeneralTimestampMode::SoftwareRecv,
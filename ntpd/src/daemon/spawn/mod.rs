@@ -1,13 +1,19 @@
-use std::{net::SocketAddr, sync::atomic::AtomicU64};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::atomic::AtomicU64,
+};
 
-use ntp_proto::{PeerNtsData, ProtocolVersion};
+use ntp_proto::{MacKey, NtpDuration, PeerNtsData, ProtocolVersion};
 use serde::{Deserialize, Serialize};
 use tokio::{
     sync::mpsc,
     time::{timeout, Instant},
 };
 
-use super::{config::NormalizedAddress, system::NETWORK_WAIT_PERIOD};
+use super::{
+    config::{NormalizedAddress, SockPeerConfig},
+    system::NETWORK_WAIT_PERIOD,
+};
 
 #[cfg(test)]
 pub mod dummy;
@@ -15,6 +21,7 @@ pub mod nts;
 #[cfg(feature = "unstable_nts-pool")]
 pub mod nts_pool;
 pub mod pool;
+pub mod sock;
 pub mod standard;
 
 /// Unique identifier for a spawner.
@@ -101,6 +108,7 @@ pub struct PeerRemovedEvent {
 #[derive(Debug, PartialEq, Eq)]
 pub enum PeerRemovalReason {
     Demobilized,
+    Restricted,
     NetworkIssue,
     Unreachable,
 }
@@ -120,6 +128,8 @@ impl SpawnAction {
         normalized_addr: NormalizedAddress,
         protocol_version: ProtocolVersion,
         nts: Option<Box<PeerNtsData>>,
+        prefer: bool,
+        mac_key: Option<MacKey>,
     ) -> SpawnAction {
         SpawnAction::Create(PeerCreateParameters {
             id,
@@ -127,6 +137,32 @@ impl SpawnAction {
             normalized_addr,
             protocol_version,
             nts,
+            prefer,
+            mac_key,
+            source_port: None,
+            delay_asymmetry_correction: NtpDuration::ZERO,
+            resolve_after_idle: false,
+            refclock: None,
+        })
+    }
+
+    pub fn create_refclock(id: PeerId, config: SockPeerConfig) -> SpawnAction {
+        SpawnAction::Create(PeerCreateParameters {
+            id,
+            // A reference clock has no network address
+            addr: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            normalized_addr: NormalizedAddress::new_unchecked(
+                &config.path.display().to_string(),
+                0,
+            ),
+            protocol_version: ProtocolVersion::default(),
+            nts: None,
+            prefer: config.prefer,
+            mac_key: None,
+            source_port: None,
+            delay_asymmetry_correction: NtpDuration::ZERO,
+            resolve_after_idle: false,
+            refclock: Some(config),
         })
     }
 }
@@ -138,6 +174,18 @@ pub struct PeerCreateParameters {
     pub normalized_addr: NormalizedAddress,
     pub protocol_version: ProtocolVersion,
     pub nts: Option<Box<PeerNtsData>>,
+    pub prefer: bool,
+    pub mac_key: Option<MacKey>,
+    /// Local port to send polls from, or an ephemeral port when not set
+    pub source_port: Option<u16>,
+    /// How much longer packets take to come back from the source than to
+    /// reach it
+    pub delay_asymmetry_correction: NtpDuration,
+    /// Whether `normalized_addr` names just this peer, so that it can be
+    /// resolved again after a long idle period to notice an address change
+    pub resolve_after_idle: bool,
+    /// Reference clock to take measurements from instead of a server
+    pub refclock: Option<SockPeerConfig>,
 }
 
 #[cfg(test)]
@@ -158,6 +206,12 @@ impl PeerCreateParameters {
             .unwrap(),
             protocol_version: ProtocolVersion::default(),
             nts: None,
+            prefer: false,
+            mac_key: None,
+            source_port: None,
+            delay_asymmetry_correction: NtpDuration::ZERO,
+            resolve_after_idle: false,
+            refclock: None,
         }
     }
 
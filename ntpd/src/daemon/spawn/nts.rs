@@ -88,6 +88,8 @@ impl BasicSpawner for NtsSpawner {
                                 self.config.address.deref().clone(),
                                 ke.protocol_version,
                                 Some(ke.nts),
+                                false,
+                                None,
                             ),
                         ))
                         .await?;
@@ -96,6 +96,8 @@ impl BasicSpawner for NtsPoolSpawner {
                                     self.config.addr.deref().clone(),
                                     ke.protocol_version,
                                     Some(ke.nts),
+                                    false,
+                                    None,
                                 ),
                             ))
                             .await?;
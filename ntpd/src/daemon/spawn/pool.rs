@@ -85,6 +85,8 @@ impl BasicSpawner for PoolSpawner {
                     self.config.addr.deref().clone(),
                     ProtocolVersion::default(),
                     None,
+                    false,
+                    None,
                 );
                 tracing::debug!(?action, "intending to spawn new pool peer at");
 
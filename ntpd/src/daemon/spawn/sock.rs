@@ -0,0 +1,137 @@
+use std::fmt::Display;
+
+use tokio::sync::mpsc;
+
+use super::super::config::SockPeerConfig;
+
+use super::{
+    BasicSpawner, PeerId, PeerRemovalReason, PeerRemovedEvent, SpawnAction, SpawnEvent, SpawnerId,
+};
+
+pub struct SockSpawner {
+    id: SpawnerId,
+    config: SockPeerConfig,
+    has_spawned: bool,
+}
+
+#[derive(Debug)]
+pub enum SockSpawnError {
+    SendError(mpsc::error::SendError<SpawnEvent>),
+}
+
+impl Display for SockSpawnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SendError(e) => write!(f, "Channel send error: {e}"),
+        }
+    }
+}
+
+impl From<mpsc::error::SendError<SpawnEvent>> for SockSpawnError {
+    fn from(value: mpsc::error::SendError<SpawnEvent>) -> Self {
+        Self::SendError(value)
+    }
+}
+
+impl std::error::Error for SockSpawnError {}
+
+impl SockSpawner {
+    pub fn new(config: SockPeerConfig) -> SockSpawner {
+        SockSpawner {
+            id: Default::default(),
+            config,
+            has_spawned: false,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl BasicSpawner for SockSpawner {
+    type Error = SockSpawnError;
+
+    async fn try_spawn(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), SockSpawnError> {
+        action_tx
+            .send(SpawnEvent::new(
+                self.id,
+                SpawnAction::create_refclock(PeerId::new(), self.config.clone()),
+            ))
+            .await?;
+        self.has_spawned = true;
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.has_spawned
+    }
+
+    async fn handle_peer_removed(
+        &mut self,
+        removed_peer: PeerRemovedEvent,
+    ) -> Result<(), SockSpawnError> {
+        if removed_peer.reason != PeerRemovalReason::Demobilized {
+            self.has_spawned = false;
+        }
+        Ok(())
+    }
+
+    fn get_id(&self) -> SpawnerId {
+        self.id
+    }
+
+    fn get_addr_description(&self) -> String {
+        self.config.path.display().to_string()
+    }
+
+    fn get_description(&self) -> &str {
+        "sock"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntp_proto::ReferenceId;
+    use tokio::sync::mpsc;
+
+    use crate::daemon::{
+        config::SockPeerConfig,
+        spawn::{
+            sock::SockSpawner, tests::get_create_params, BasicSpawner, PeerRemovalReason,
+            PeerRemovedEvent,
+        },
+        system::MESSAGE_BUFFER_SIZE,
+    };
+
+    #[tokio::test]
+    async fn creates_a_refclock_peer() {
+        let config = SockPeerConfig {
+            path: "/run/ntpd-rs/gps.sock".into(),
+            reference_id: ReferenceId::from_bytes(*b"GPS\0"),
+            prefer: true,
+        };
+        let mut spawner = SockSpawner::new(config.clone());
+        let spawner_id = spawner.get_id();
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap();
+        assert_eq!(res.id, spawner_id);
+        let params = get_create_params(res);
+        assert_eq!(params.refclock, Some(config));
+        assert!(params.prefer);
+        assert!(spawner.is_complete());
+
+        // A failing reference clock is not restarted
+        spawner
+            .handle_peer_removed(PeerRemovedEvent {
+                id: params.id,
+                reason: PeerRemovalReason::Demobilized,
+            })
+            .await
+            .unwrap();
+        assert!(spawner.is_complete());
+    }
+}
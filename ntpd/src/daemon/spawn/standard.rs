@@ -8,7 +8,8 @@ use tracing::warn;
 use super::super::config::StandardPeerConfig;
 
 use super::{
-    BasicSpawner, PeerId, PeerRemovalReason, PeerRemovedEvent, SpawnAction, SpawnEvent, SpawnerId,
+    BasicSpawner, PeerCreateParameters, PeerId, PeerRemovalReason, PeerRemovedEvent, SpawnAction,
+    SpawnEvent, SpawnerId,
 };
 
 pub struct StandardSpawner {
@@ -87,13 +88,19 @@ impl BasicSpawner for StandardSpawner {
         action_tx
             .send(SpawnEvent::new(
                 self.id,
-                SpawnAction::create(
-                    PeerId::new(),
+                SpawnAction::Create(PeerCreateParameters {
+                    id: PeerId::new(),
                     addr,
-                    self.config.address.deref().clone(),
-                    ProtocolVersion::default(),
-                    None,
-                ),
+                    normalized_addr: self.config.address.deref().clone(),
+                    protocol_version: ProtocolVersion::default(),
+                    nts: None,
+                    prefer: self.config.prefer,
+                    mac_key: self.config.key.clone(),
+                    source_port: self.config.source_port,
+                    delay_asymmetry_correction: self.config.delay_asymmetry_correction,
+                    resolve_after_idle: true,
+                    refclock: None,
+                }),
             ))
             .await?;
         self.has_spawned = true;
@@ -133,6 +140,7 @@ impl BasicSpawner for StandardSpawner {
 
 #[cfg(test)]
 mod tests {
+    use ntp_proto::NtpDuration;
     use tokio::sync::mpsc::{self, error::TryRecvError};
 
     use crate::daemon::{
@@ -153,6 +161,10 @@ mod tests {
                 vec!["127.0.0.1:123".parse().unwrap()],
             )
             .into(),
+            prefer: false,
+            key: None,
+            source_port: None,
+            delay_asymmetry_correction: NtpDuration::ZERO,
         });
         let spawner_id = spawner.get_id();
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
@@ -168,6 +180,31 @@ mod tests {
         assert!(spawner.is_complete());
     }
 
+    #[tokio::test]
+    async fn passes_source_options() {
+        let mut spawner = StandardSpawner::new(StandardPeerConfig {
+            address: NormalizedAddress::with_hardcoded_dns(
+                "example.com",
+                123,
+                vec!["127.0.0.1:123".parse().unwrap()],
+            )
+            .into(),
+            prefer: false,
+            key: None,
+            source_port: Some(1234),
+            delay_asymmetry_correction: NtpDuration::from_seconds(0.01),
+        });
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let params = get_create_params(action_rx.try_recv().unwrap());
+        assert_eq!(params.source_port, Some(1234));
+        assert_eq!(
+            params.delay_asymmetry_correction,
+            NtpDuration::from_seconds(0.01)
+        );
+    }
+
     #[tokio::test]
     async fn recreates_a_peer() {
         let mut spawner = StandardSpawner::new(StandardPeerConfig {
@@ -177,6 +214,10 @@ mod tests {
                 vec!["127.0.0.1:123".parse().unwrap()],
             )
             .into(),
+            prefer: false,
+            key: None,
+            source_port: None,
+            delay_asymmetry_correction: NtpDuration::ZERO,
         });
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
 
@@ -214,6 +255,10 @@ mod tests {
                 addresses.to_vec(),
             )
             .into(),
+            prefer: false,
+            key: None,
+            source_port: None,
+            delay_asymmetry_correction: NtpDuration::ZERO,
         });
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
 
@@ -266,6 +311,10 @@ mod tests {
     async fn works_if_address_does_not_resolve() {
         let mut spawner = StandardSpawner::new(StandardPeerConfig {
             address: NormalizedAddress::with_hardcoded_dns("does.not.resolve", 123, vec![]).into(),
+            prefer: false,
+            key: None,
+            source_port: None,
+            delay_asymmetry_correction: NtpDuration::ZERO,
         });
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
 
@@ -1,32 +1,93 @@
 #[cfg(feature = "unstable_nts-pool")]
 use super::spawn::nts_pool::NtsPoolSpawner;
 use super::{
-    config::{ClockConfig, NormalizedAddress, PeerConfig, ServerConfig, TimestampMode},
-    peer::{MsgForSystem, PeerChannels, PeerTask, Wait},
+    config::{
+        ClockConfig, NormalizedAddress, PeerConfig, RecvTimestampPolicy, ServerConfig,
+        TimestampMode,
+    },
+    measurement_log::MeasurementRecorder,
+    peer::{MsgForSystem, PeerChannels, PeerTask, ReadVariablesRequest, Wait},
+    poll_limiter::PollRateLimiter,
     server::{ServerStats, ServerTask},
     spawn::{
-        nts::NtsSpawner, pool::PoolSpawner, standard::StandardSpawner, PeerCreateParameters,
-        PeerId, PeerRemovalReason, SpawnAction, SpawnEvent, Spawner, SpawnerId, SystemEvent,
+        nts::NtsSpawner, pool::PoolSpawner, sock::SockSpawner, standard::StandardSpawner,
+        PeerCreateParameters, PeerId, PeerRemovalReason, SpawnAction, SpawnEvent, Spawner,
+        SpawnerId, SystemEvent,
     },
     ObservablePeerState, ObservedPeerState,
 };
 
 use std::{
-    collections::HashMap, future::Future, marker::PhantomData, net::IpAddr, pin::Pin, sync::Arc,
+    collections::HashMap,
+    future::Future,
+    marker::PhantomData,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
     time::Duration,
 };
 
 use ntp_proto::{
-    KeySet, NtpClock, SourceDefaultsConfig, SynchronizationConfig, System, SystemSnapshot,
+    KeySet, NtpClock, NtpTimestamp, SourceDefaultsConfig, SynchronizationConfig, System,
+    SystemSnapshot,
 };
+#[cfg(target_os = "linux")]
+use timestamped_socket::interface::interfaces;
 use timestamped_socket::interface::InterfaceName;
 use tokio::{sync::mpsc, task::JoinHandle};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 pub const NETWORK_WAIT_PERIOD: std::time::Duration = std::time::Duration::from_secs(1);
 
 pub const MESSAGE_BUFFER_SIZE: usize = 32;
 
+/// How long to wait before restarting a peer that could not read the clock
+const CLOCK_ERROR_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// How long to wait before first checking again whether a vanished interface
+/// came back, doubling for every check after up to the maximum
+#[cfg(target_os = "linux")]
+const INTERFACE_CHECK_INITIAL_DELAY: Duration = Duration::from_secs(1);
+#[cfg(target_os = "linux")]
+const INTERFACE_CHECK_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Whether the system currently has a network interface named `interface`
+#[cfg(target_os = "linux")]
+fn interface_present(interface: InterfaceName) -> bool {
+    match interfaces() {
+        Ok(interfaces) => interfaces.iter().any(|(name, _)| *name == interface),
+        Err(error) => {
+            // Without a list of interfaces, just try to use it
+            warn!(?error, "Could not list network interfaces");
+            true
+        }
+    }
+}
+
+/// Wait until `present` holds, checking again after `delay` and doubling the
+/// delay after every check, up to `max_delay`
+#[cfg(target_os = "linux")]
+async fn wait_until_present(
+    mut present: impl FnMut() -> bool,
+    delay: Duration,
+    max_delay: Duration,
+) {
+    if present() {
+        return;
+    }
+
+    info!("Network interface is gone, waiting for it to return");
+    let mut delay = delay;
+    loop {
+        tokio::time::sleep(delay).await;
+        if present() {
+            info!("Network interface returned");
+            return;
+        }
+        delay = std::cmp::min(delay * 2, max_delay);
+    }
+}
+
 struct SingleshotSleep<T> {
     enabled: bool,
     sleep: Pin<Box<T>>,
@@ -74,6 +135,12 @@ pub struct DaemonChannels {
     pub peer_snapshots_receiver: tokio::sync::watch::Receiver<Vec<ObservablePeerState>>,
     pub server_data_receiver: tokio::sync::watch::Receiver<Vec<ServerData>>,
     pub system_snapshot_receiver: tokio::sync::watch::Receiver<SystemSnapshot>,
+    /// Trigger an immediate poll of all peers
+    pub poll_now_sender: tokio::sync::watch::Sender<()>,
+    /// Reset the reachability of the peer with the given address
+    pub reset_reachability_sender: tokio::sync::broadcast::Sender<SocketAddr>,
+    /// Read the variables of the peer with the given address
+    pub read_variables_sender: tokio::sync::watch::Sender<Option<ReadVariablesRequest>>,
 }
 
 /// Spawn the NTP daemon
@@ -84,6 +151,7 @@ pub async fn spawn(
     peer_configs: &[PeerConfig],
     server_configs: &[ServerConfig],
     keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+    measurement_recorder: MeasurementRecorder,
 ) -> std::io::Result<(JoinHandle<std::io::Result<()>>, DaemonChannels)> {
     let ip_list = super::local_ip_provider::spawn()?;
 
@@ -91,11 +159,13 @@ pub async fn spawn(
         clock_config.clock,
         clock_config.interface,
         clock_config.timestamp_mode,
+        clock_config.recv_timestamp_policy,
         synchronization_config,
         peer_defaults_config,
         keyset,
         ip_list,
     );
+    system.peer_channels.measurement_recorder = measurement_recorder;
 
     for peer_config in peer_configs {
         match peer_config {
@@ -123,6 +193,14 @@ pub async fn spawn(
                         std::io::Error::new(std::io::ErrorKind::Other, e)
                     })?;
             }
+            PeerConfig::Sock(cfg) => {
+                system
+                    .add_spawner(SockSpawner::new(cfg.clone()))
+                    .map_err(|e| {
+                        tracing::error!("Could not spawn peer: {}", e);
+                        std::io::Error::new(std::io::ErrorKind::Other, e)
+                    })?;
+            }
             #[cfg(feature = "unstable_nts-pool")]
             PeerConfig::NtsPool(cfg) => {
                 system
@@ -170,6 +248,9 @@ struct SystemTask<C: NtpClock, T: Wait> {
     spawn_rx: mpsc::Receiver<SpawnEvent>,
 
     peers: HashMap<PeerId, PeerState>,
+    /// Most recent error of each source, and when it occurred. Kept apart from
+    /// the peers, as a peer is often restarted because of the error.
+    last_errors: HashMap<(SpawnerId, SocketAddr), (NtpTimestamp, String)>,
     servers: Vec<ServerData>,
     spawners: Vec<SystemSpawnerData>,
 
@@ -178,6 +259,8 @@ struct SystemTask<C: NtpClock, T: Wait> {
 
     // which timestamps to use (this is a hint, OS or hardware may ignore)
     timestamp_mode: TimestampMode,
+    // which receive timestamps to accept
+    recv_timestamp_policy: RecvTimestampPolicy,
 
     // bind the socket to a specific interface. This is relevant for hardware timestamping,
     // because the interface determines which clock is used to produce the timestamps.
@@ -185,10 +268,12 @@ struct SystemTask<C: NtpClock, T: Wait> {
 }
 
 impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         clock: C,
         interface: Option<InterfaceName>,
         timestamp_mode: TimestampMode,
+        recv_timestamp_policy: RecvTimestampPolicy,
         synchronization_config: SynchronizationConfig,
         peer_defaults_config: SourceDefaultsConfig,
         keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
@@ -209,6 +294,10 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
         let (msg_for_system_sender, msg_for_system_receiver) =
             tokio::sync::mpsc::channel(MESSAGE_BUFFER_SIZE);
         let (spawn_tx, spawn_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        let (poll_now_sender, poll_now_receiver) = tokio::sync::watch::channel(());
+        let (reset_reachability_sender, reset_reachability_receiver) =
+            tokio::sync::broadcast::channel(MESSAGE_BUFFER_SIZE);
+        let (read_variables_sender, read_variables_receiver) = tokio::sync::watch::channel(None);
 
         // Build System and its channels
         (
@@ -228,20 +317,30 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
                 spawn_tx,
 
                 peers: Default::default(),
+                last_errors: Default::default(),
                 servers: Default::default(),
                 spawners: Default::default(),
                 peer_channels: PeerChannels {
                     msg_for_system_sender,
                     system_snapshot_receiver: system_snapshot_receiver.clone(),
+                    poll_limiter: PollRateLimiter::new(peer_defaults_config.poll_rate_limit),
+                    poll_now_receiver,
+                    reset_reachability_receiver,
+                    read_variables_receiver,
+                    measurement_recorder: MeasurementRecorder::disabled(),
                 },
                 clock,
                 timestamp_mode,
+                recv_timestamp_policy,
                 interface,
             },
             DaemonChannels {
                 peer_snapshots_receiver,
                 server_data_receiver,
                 system_snapshot_receiver,
+                poll_now_sender,
+                reset_reachability_sender,
+                read_variables_sender,
             },
         )
     }
@@ -330,6 +429,11 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
                     unreachable!("Could not demobilize peer: {}", e);
                 };
             }
+            MsgForSystem::DemobilizeTemporary(index, cooldown) => {
+                if let Err(e) = self.handle_peer_demobilize_temporary(index, cooldown) {
+                    unreachable!("Could not demobilize peer: {}", e);
+                };
+            }
             MsgForSystem::NewMeasurement(index, snapshot, measurement) => {
                 match self
                     .system
@@ -350,6 +454,23 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
             MsgForSystem::Unreachable(index) => {
                 self.handle_peer_unreachable(index).await?;
             }
+            MsgForSystem::ClockError(index) => {
+                // Most likely the clock is unreadable for everyone, don't
+                // restart the peer right away to keep it from spinning
+                warn!(
+                    cooldown = ?CLOCK_ERROR_COOLDOWN,
+                    "Peer could not read the clock, restarting it later"
+                );
+                if let Err(e) = self.handle_peer_demobilize_temporary(index, CLOCK_ERROR_COOLDOWN) {
+                    unreachable!("Could not demobilize peer: {}", e);
+                };
+            }
+            MsgForSystem::Error(index, timestamp, error) => {
+                if let Some(state) = self.peers.get(&index) {
+                    self.last_errors
+                        .insert((state.spawner_id, state.addr), (timestamp, error));
+                }
+            }
         }
 
         // Don't care if there is no receiver for peer snapshots (which might happen if
@@ -372,12 +493,28 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
         let source_id = state.source_id;
         let opt_spawner = self.spawners.iter().find(|s| s.id == spawner_id);
         if let Some(spawner) = opt_spawner {
+            let removed = SystemEvent::peer_removed(source_id, PeerRemovalReason::NetworkIssue);
+
+            // Restarting the peer is pointless while the interface it binds
+            // to is gone, so only let the spawner know once it is back.
+            #[cfg(target_os = "linux")]
+            if let Some(interface) = self.interface.filter(|i| !interface_present(*i)) {
+                let notify_tx = spawner.notify_tx.clone();
+                tokio::spawn(async move {
+                    wait_until_present(
+                        || interface_present(interface),
+                        INTERFACE_CHECK_INITIAL_DELAY,
+                        INTERFACE_CHECK_MAX_DELAY,
+                    )
+                    .await;
+                    let _ = notify_tx.send(removed).await;
+                });
+                return Ok(());
+            }
+
             spawner
                 .notify_tx
-                .send(SystemEvent::peer_removed(
-                    source_id,
-                    PeerRemovalReason::NetworkIssue,
-                ))
+                .send(removed)
                 .await
                 .expect("Could not notify spawner");
         }
@@ -414,6 +551,8 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
 
         // Restart the peer reusing its configuration.
         let state = self.peers.remove(&index).unwrap();
+        // The source is gone for good, and so is its last error
+        self.last_errors.remove(&(state.spawner_id, state.addr));
         let spawner_id = state.spawner_id;
         let source_id = state.source_id;
         let opt_spawner = self.spawners.iter().find(|s| s.id == spawner_id);
@@ -430,6 +569,35 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
         Ok(())
     }
 
+    fn handle_peer_demobilize_temporary(
+        &mut self,
+        index: PeerId,
+        cooldown: Duration,
+    ) -> Result<(), C::Error> {
+        self.system.handle_peer_remove(index)?;
+
+        // Only tell the spawner once the cooldown has passed, so that it
+        // doesn't restart the peer before then.
+        let state = self.peers.remove(&index).unwrap();
+        let spawner_id = state.spawner_id;
+        let source_id = state.source_id;
+        let opt_spawner = self.spawners.iter().find(|s| s.id == spawner_id);
+        if let Some(spawner) = opt_spawner {
+            let notify_tx = spawner.notify_tx.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(cooldown).await;
+                // The spawner may have stopped in the meantime, which is fine
+                let _ = notify_tx
+                    .send(SystemEvent::peer_removed(
+                        source_id,
+                        PeerRemovalReason::Restricted,
+                    ))
+                    .await;
+            });
+        }
+        Ok(())
+    }
+
     async fn create_peer(
         &mut self,
         spawner_id: SpawnerId,
@@ -441,11 +609,12 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
             source_id,
             PeerState {
                 peer_address: params.normalized_addr.clone(),
+                addr: params.addr,
                 source_id,
                 spawner_id,
             },
         );
-        self.system.handle_peer_create(source_id)?;
+        self.system.handle_peer_create(source_id, params.prefer)?;
 
         PeerTask::spawn(
             source_id,
@@ -453,10 +622,18 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
             self.interface,
             self.clock.clone(),
             self.timestamp_mode,
+            self.recv_timestamp_policy,
             self.peer_channels.clone(),
             params.protocol_version,
             self.peer_defaults_config,
             params.nts.take(),
+            params.mac_key.take(),
+            params.source_port,
+            params.delay_asymmetry_correction,
+            params
+                .resolve_after_idle
+                .then(|| params.normalized_addr.clone()),
+            params.refclock.clone(),
         );
 
         // Don't care if there is no receiver
@@ -510,6 +687,10 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
                     name: data.peer_address.to_string(),
                     address: snapshot.source_addr.to_string(),
                     id: data.source_id,
+                    last_error: self.last_errors.get(&(data.spawner_id, data.addr)).cloned(),
+                    last_measurement: snapshot.last_measurement_time,
+                    missed_responses: snapshot.missed_responses,
+                    state: snapshot.state,
                 })
             } else {
                 ObservablePeerState::Nothing
@@ -521,6 +702,7 @@ impl<C: NtpClock + Sync, T: Wait> SystemTask<C, T> {
 #[derive(Debug)]
 struct PeerState {
     peer_address: NormalizedAddress,
+    addr: SocketAddr,
     spawner_id: SpawnerId,
     source_id: PeerId,
 }
@@ -588,6 +770,7 @@ mod tests {
             TestClock {},
             None,
             TimestampMode::KernelRecv,
+            RecvTimestampPolicy::Prefer,
             SynchronizationConfig::default(),
             SourceDefaultsConfig::default(),
             keyset,
@@ -732,5 +915,219 @@ mod tests {
                 .sum::<i32>(),
             1
         );
+
+        system
+            .handle_peer_update(
+                MsgForSystem::DemobilizeTemporary(indices[2], Duration::from_secs(3600)),
+                &mut wait,
+            )
+            .await
+            .unwrap();
+        assert!(!system.peers.contains_key(&indices[2]));
+        assert_eq!(system.peers.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_peer_last_error() {
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+        let (_, ip_list) = tokio::sync::watch::channel([].into_iter().collect());
+
+        let (mut system, _) = SystemTask::new(
+            TestClock {},
+            None,
+            TimestampMode::KernelRecv,
+            RecvTimestampPolicy::Prefer,
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            keyset,
+            ip_list,
+        );
+        let wait =
+            SingleshotSleep::new_disabled(tokio::time::sleep(std::time::Duration::from_secs(0)));
+        tokio::pin!(wait);
+
+        let id = system.add_spawner(DummySpawner::empty()).unwrap();
+        let index = system
+            .create_peer(
+                id,
+                PeerCreateParameters::from_new_ip_and_port("127.0.0.1", 123),
+            )
+            .await
+            .unwrap();
+        let last_error = |system: &SystemTask<_, _>, index| {
+            system.observe_peers().find_map(|peer| match peer {
+                ObservablePeerState::Observable(state) if state.id == index => state.last_error,
+                _ => None,
+            })
+        };
+        assert!(system.last_errors.is_empty());
+
+        // A peer reports the error that makes it stop, and is then restarted
+        let timestamp = NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0);
+        system
+            .handle_peer_update(
+                MsgForSystem::Error(
+                    index,
+                    timestamp,
+                    "could not receive packet: network is unreachable".into(),
+                ),
+                &mut wait,
+            )
+            .await
+            .unwrap();
+        system
+            .handle_peer_update(MsgForSystem::NetworkIssue(index), &mut wait)
+            .await
+            .unwrap();
+        assert!(!system.peers.contains_key(&index));
+
+        let index = system
+            .create_peer(
+                id,
+                PeerCreateParameters::from_new_ip_and_port("127.0.0.1", 123),
+            )
+            .await
+            .unwrap();
+        system
+            .handle_peer_update(
+                MsgForSystem::NewMeasurement(
+                    index,
+                    peer_snapshot(),
+                    Measurement {
+                        delay: NtpDuration::from_seconds(0.1),
+                        offset: NtpDuration::from_seconds(0.),
+                        transmit_timestamp: NtpTimestamp::default(),
+                        receive_timestamp: NtpTimestamp::default(),
+                        localtime: NtpTimestamp::from_seconds_nanos_since_ntp_era(0, 0),
+                        monotime: NtpInstant::now(),
+
+                        stratum: 0,
+                        root_delay: NtpDuration::default(),
+                        root_dispersion: NtpDuration::default(),
+                        leap: NtpLeapIndicator::NoWarning,
+                        precision: 0,
+                    },
+                ),
+                &mut wait,
+            )
+            .await
+            .unwrap();
+
+        // The restarted peer still shows the error
+        let (error_timestamp, error) = last_error(&system, index).unwrap();
+        assert_eq!(error_timestamp, timestamp);
+        assert_eq!(error, "could not receive packet: network is unreachable");
+
+        // Until the source is removed for good
+        system
+            .handle_peer_update(MsgForSystem::MustDemobilize(index), &mut wait)
+            .await
+            .unwrap();
+        assert!(system.last_errors.is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_network_issue_waits_for_interface() {
+        use std::str::FromStr;
+
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+        let (_, ip_list) = tokio::sync::watch::channel([].into_iter().collect());
+
+        let (mut system, _) = SystemTask::new(
+            TestClock {},
+            Some(InterfaceName::from_str("ntpd-test-gone").unwrap()),
+            TimestampMode::KernelRecv,
+            RecvTimestampPolicy::Prefer,
+            SynchronizationConfig::default(),
+            SourceDefaultsConfig::default(),
+            keyset,
+            ip_list,
+        );
+        let wait =
+            SingleshotSleep::new_disabled(tokio::time::sleep(std::time::Duration::from_secs(0)));
+        tokio::pin!(wait);
+
+        let (notify_tx, mut notify_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        let id = SpawnerId::new();
+        system.spawners.push(SystemSpawnerData { id, notify_tx });
+        let index = system
+            .create_peer(
+                id,
+                PeerCreateParameters::from_new_ip_and_port("127.0.0.1", 123),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            notify_rx.try_recv(),
+            Ok(SystemEvent::PeerRegistered(_))
+        ));
+
+        // The peer is gone from the system right away, but the spawner only
+        // hears about it once the interface returns
+        system
+            .handle_peer_update(MsgForSystem::NetworkIssue(index), &mut wait)
+            .await
+            .unwrap();
+        assert!(!system.peers.contains_key(&index));
+        assert!(system.observe_peers().next().is_none());
+        assert!(notify_rx.try_recv().is_err());
+
+        // Without a vanished interface the spawner is told immediately
+        system.interface = None;
+        let index = system
+            .create_peer(
+                id,
+                PeerCreateParameters::from_new_ip_and_port("127.0.0.1", 123),
+            )
+            .await
+            .unwrap();
+        assert!(notify_rx.try_recv().is_ok());
+        system
+            .handle_peer_update(MsgForSystem::NetworkIssue(index), &mut wait)
+            .await
+            .unwrap();
+        match notify_rx.try_recv() {
+            Ok(SystemEvent::PeerRemoved(removed)) => {
+                assert_eq!(removed.id, index);
+                assert_eq!(removed.reason, PeerRemovalReason::NetworkIssue);
+            }
+            other => panic!("Unexpected notification {other:?}"),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_for_interface() {
+        // A present interface is used right away
+        let start = tokio::time::Instant::now();
+        wait_until_present(|| true, Duration::from_secs(1), Duration::from_secs(60)).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // An absent one is checked with backoff until it returns
+        let mut checks = 0;
+        let start = tokio::time::Instant::now();
+        wait_until_present(
+            || {
+                checks += 1;
+                checks > 4
+            },
+            Duration::from_secs(1),
+            Duration::from_secs(3),
+        )
+        .await;
+        assert_eq!(checks, 5);
+        assert_eq!(start.elapsed(), Duration::from_secs(1 + 2 + 3 + 3));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_interface_present() {
+        use std::str::FromStr;
+
+        assert!(interface_present(InterfaceName::from_str("lo").unwrap()));
+        assert!(!interface_present(
+            InterfaceName::from_str("ntpd-test-gone").unwrap()
+        ));
     }
 }